@@ -1,23 +1,20 @@
 #![feature(test)]
 extern crate test;
 
-use std::io;
-
 use test::Bencher;
 
 use simd_playground as simd;
 
 use simd::{
-    test_util::{test, FilterType},
-    ConvProcessor,
+    backend::*,
+    test_util::{test, Check, FilterType},
 };
 
 macro_rules! bench {
-    ($bencher:ident, $const_filter_type:expr, $method:ident) => {{
+    ($bencher:ident, $const_filter_type:expr, $backend:expr) => {{
         const FIL_TY: FilterType = $const_filter_type;
         const K: usize = FIL_TY.size();
-        // run test with assertion disabled
-        test(Some($bencher), false, FIL_TY, ConvProcessor::<K>::$method)
+        test::<K, _>(Some($bencher), Check::None, FIL_TY, &$backend)
     }};
 }
 
@@ -27,7 +24,7 @@ mod image {
     use simd::{consts::*, image::RgbImage};
 
     #[bench]
-    fn load(b: &mut Bencher) -> io::Result<()> {
+    fn load(b: &mut Bencher) -> simd::Result<()> {
         b.iter(|| {
             RgbImage::load(ORIGINAL).expect("cannot load");
         });
@@ -35,7 +32,7 @@ mod image {
     }
 
     #[bench]
-    fn save(b: &mut Bencher) -> io::Result<()> {
+    fn save(b: &mut Bencher) -> simd::Result<()> {
         let img = RgbImage::load(ORIGINAL)?;
         b.iter(|| {
             img.save(BACKUP).expect("cannot save");
@@ -48,48 +45,48 @@ mod naive_benches {
     use super::*;
 
     #[bench]
-    fn box3_naive2(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(3), naive2)
+    fn box3_naive2(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(3), Naive2)
     }
 
     #[bench]
-    fn box5_naive2(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(5), naive2)
+    fn box5_naive2(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(5), Naive2)
     }
 
     #[bench]
-    fn box7_naive2(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(7), naive2)
+    fn box7_naive2(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(7), Naive2)
     }
 
     #[bench]
-    fn box9_naive2(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(9), naive2)
+    fn box9_naive2(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(9), Naive2)
     }
 
     #[bench]
-    fn box11_naive2(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(11), naive2)
+    fn box11_naive2(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(11), Naive2)
     }
 
     #[bench]
-    fn box13_naive2(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(13), naive2)
+    fn box13_naive2(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(13), Naive2)
     }
 
     #[bench]
-    fn box15_naive2(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(15), naive2)
+    fn box15_naive2(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(15), Naive2)
     }
 
     #[bench]
-    fn box17_naive2(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(17), naive2)
+    fn box17_naive2(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(17), Naive2)
     }
 
     #[bench]
-    fn box19_naive2(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(19), naive2)
+    fn box19_naive2(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(19), Naive2)
     }
 }
 
@@ -98,137 +95,739 @@ mod simd_benches {
     use super::*;
 
     #[bench]
-    fn box3_simd1(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(3), simd1)
+    fn box3_simd1(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(3), Simd1)
+    }
+
+    #[bench]
+    fn box5_simd1(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(5), Simd1)
+    }
+
+    #[bench]
+    fn box7_simd1(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(7), Simd1)
+    }
+
+    #[bench]
+    fn box9_simd1(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(9), Simd1)
+    }
+
+    #[bench]
+    fn box11_simd1(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(11), Simd1)
+    }
+
+    #[bench]
+    fn box13_simd1(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(13), Simd1)
+    }
+
+    #[bench]
+    fn box15_simd1(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(15), Simd1)
+    }
+
+    #[bench]
+    fn box17_simd1(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(17), Simd1)
+    }
+
+    #[bench]
+    fn box19_simd1(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(19), Simd1)
+    }
+
+    #[bench]
+    fn box3_simd2(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(3), Simd2)
+    }
+
+    #[bench]
+    fn box5_simd2(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(5), Simd2)
+    }
+
+    #[bench]
+    fn box7_simd2(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(7), Simd2)
+    }
+
+    #[bench]
+    fn box9_simd2(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(9), Simd2)
+    }
+
+    #[bench]
+    fn box11_simd2(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(11), Simd2)
+    }
+
+    #[bench]
+    fn box13_simd2(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(13), Simd2)
+    }
+
+    #[bench]
+    fn box15_simd2(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(15), Simd2)
+    }
+
+    #[bench]
+    fn box17_simd2(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(17), Simd2)
+    }
+
+    #[bench]
+    fn box19_simd2(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(19), Simd2)
+    }
+
+    #[bench]
+    fn box3_simd3(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(3), Simd3)
+    }
+
+    #[bench]
+    fn box5_simd3(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(5), Simd3)
+    }
+
+    #[bench]
+    fn box7_simd3(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(7), Simd3)
+    }
+
+    #[bench]
+    fn box9_simd3(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(9), Simd3)
+    }
+
+    #[bench]
+    fn box11_simd3(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(11), Simd3)
+    }
+
+    #[bench]
+    fn box13_simd3(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(13), Simd3)
+    }
+
+    #[bench]
+    fn box15_simd3(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(15), Simd3)
+    }
+
+    #[bench]
+    fn box17_simd3(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(17), Simd3)
+    }
+
+    #[bench]
+    fn box19_simd3(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(19), Simd3)
+    }
+
+    #[bench]
+    fn box3_simd_wide32(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(3), SimdWide::<2>)
+    }
+
+    #[bench]
+    fn box5_simd_wide32(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(5), SimdWide::<2>)
+    }
+
+    #[bench]
+    fn box7_simd_wide32(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(7), SimdWide::<2>)
+    }
+
+    #[bench]
+    fn box9_simd_wide32(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(9), SimdWide::<2>)
+    }
+
+    #[bench]
+    fn box11_simd_wide32(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(11), SimdWide::<2>)
+    }
+
+    #[bench]
+    fn box13_simd_wide32(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(13), SimdWide::<2>)
+    }
+
+    #[bench]
+    fn box15_simd_wide32(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(15), SimdWide::<2>)
+    }
+
+    #[bench]
+    fn box17_simd_wide32(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(17), SimdWide::<2>)
+    }
+
+    #[bench]
+    fn box19_simd_wide32(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(19), SimdWide::<2>)
+    }
+
+    #[bench]
+    fn box3_simd_wide64(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(3), SimdWide::<4>)
+    }
+
+    #[bench]
+    fn box5_simd_wide64(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(5), SimdWide::<4>)
+    }
+
+    #[bench]
+    fn box7_simd_wide64(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(7), SimdWide::<4>)
+    }
+
+    #[bench]
+    fn box9_simd_wide64(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(9), SimdWide::<4>)
+    }
+
+    #[bench]
+    fn box11_simd_wide64(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(11), SimdWide::<4>)
+    }
+
+    #[bench]
+    fn box13_simd_wide64(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(13), SimdWide::<4>)
+    }
+
+    #[bench]
+    fn box15_simd_wide64(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(15), SimdWide::<4>)
+    }
+
+    #[bench]
+    fn box17_simd_wide64(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(17), SimdWide::<4>)
+    }
+
+    #[bench]
+    fn box19_simd_wide64(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(19), SimdWide::<4>)
+    }
+
+    #[bench]
+    fn box3_simd_int(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(3), SimdInt)
+    }
+
+    #[bench]
+    fn box5_simd_int(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(5), SimdInt)
+    }
+
+    #[bench]
+    fn box7_simd_int(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(7), SimdInt)
+    }
+
+    #[bench]
+    fn box9_simd_int(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(9), SimdInt)
+    }
+
+    #[bench]
+    fn box11_simd_int(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(11), SimdInt)
+    }
+
+    #[bench]
+    fn box13_simd_int(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(13), SimdInt)
+    }
+
+    #[bench]
+    fn box15_simd_int(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(15), SimdInt)
+    }
+
+    #[bench]
+    fn box17_simd_int(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(17), SimdInt)
+    }
+
+    #[bench]
+    fn box19_simd_int(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(19), SimdInt)
+    }
+
+    #[bench]
+    fn box3_simd4(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(3), Simd4)
+    }
+
+    #[bench]
+    fn box5_simd4(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(5), Simd4)
+    }
+
+    #[bench]
+    fn box7_simd4(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(7), Simd4)
+    }
+
+    #[bench]
+    fn box9_simd4(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(9), Simd4)
+    }
+
+    #[bench]
+    fn box3_simd_ring(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(3), SimdRing)
+    }
+
+    #[bench]
+    fn box5_simd_ring(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(5), SimdRing)
+    }
+
+    #[bench]
+    fn box7_simd_ring(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(7), SimdRing)
+    }
+
+    #[bench]
+    fn box9_simd_ring(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(9), SimdRing)
+    }
+
+    #[bench]
+    fn box3_simd3x3(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(3), Simd3x3)
+    }
+
+    #[bench]
+    fn sobel_simd3(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Sobel, Simd3)
+    }
+
+    #[bench]
+    fn sobel_simd3x3(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Sobel, Simd3x3)
+    }
+
+    #[bench]
+    fn box5_simd5x5(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(5), Simd5x5)
+    }
+
+    #[bench]
+    fn box3_simd_winograd(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(3), SimdWinograd)
+    }
+
+    #[bench]
+    fn sobel_simd_winograd(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Sobel, SimdWinograd)
+    }
+}
+
+// 4K image, where whole source rows of a kernel do not stay in L2
+#[cfg(all(any(target_arch = "aarch64"), all(target_feature = "neon")))]
+mod large {
+    use super::*;
+
+    use simd::{
+        consts::*, image::RgbImage, test_util::misaligned, ConvKernel, ConvProcessor, Traversal,
+    };
+
+    const H: usize = 2160;
+    const W: usize = 3840;
+
+    fn image() -> simd::Result<RgbImage> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let content = (0..H * W * 3)
+            .map(|i| img.content()[i % img.content().len()])
+            .collect();
+        RgbImage::from_raw(content, H, W)
+    }
+
+    // blocks of rows and pixels within the backend
+    fn blocked<B: ConvBackend<5>>(b: &mut Bencher, backend: B) -> simd::Result<()> {
+        let img = image()?;
+        let kernel = ConvKernel::<5>::new(&[1.; 25], true)?;
+        let mut dst = RgbImage::from_raw(vec![0; H * W * 3], H, W)?;
+        b.iter(|| backend.convolve(&kernel, &img, &mut dst).unwrap());
+        Ok(())
+    }
+
+    // one row per call, i.e. whole rows without blocking
+    fn row_by_row<B: ConvBackend<5>>(b: &mut Bencher, backend: B) -> simd::Result<()> {
+        let img = image()?;
+        let kernel = ConvKernel::<5>::new(&[1.; 25], true)?;
+        let mut dst = vec![0; W * 3];
+        b.iter(|| {
+            for y in 2..H - 2 {
+                backend.convolve_rows(&kernel, &img, y..y + 1, &mut dst);
+            }
+        });
+        Ok(())
+    }
+
+    #[bench]
+    fn box5_simd2_4k_blocked(b: &mut Bencher) -> simd::Result<()> {
+        blocked(b, Simd2)
     }
 
     #[bench]
-    fn box5_simd1(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(5), simd1)
+    fn box5_simd2_4k_row_by_row(b: &mut Bencher) -> simd::Result<()> {
+        row_by_row(b, Simd2)
     }
 
     #[bench]
-    fn box7_simd1(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(7), simd1)
+    fn box5_simd3_4k_blocked(b: &mut Bencher) -> simd::Result<()> {
+        blocked(b, Simd3)
     }
 
     #[bench]
-    fn box9_simd1(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(9), simd1)
+    fn box5_simd3_4k_row_by_row(b: &mut Bencher) -> simd::Result<()> {
+        row_by_row(b, Simd3)
     }
 
     #[bench]
-    fn box11_simd1(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(11), simd1)
+    fn box5_simd4_4k_blocked(b: &mut Bencher) -> simd::Result<()> {
+        blocked(b, Simd4)
     }
 
     #[bench]
-    fn box13_simd1(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(13), simd1)
+    fn box5_simd_ring_4k(b: &mut Bencher) -> simd::Result<()> {
+        blocked(b, SimdRing)
+    }
+
+    // tiles of a 15x15 kernel in each order, whose halo is 14 rows for 64 output rows
+    fn traversal(b: &mut Bencher, traversal: Traversal) -> simd::Result<()> {
+        let img = image()?;
+        let layer = ConvProcessor::<15>::builder()
+            .kernel(&[1.; 225])
+            .normalize(true)
+            .tile_size(64, 256)
+            .traversal(traversal)
+            .build()?;
+        let mut dst = RgbImage::from_raw(vec![0; H * W * 3], H, W)?;
+        b.iter(|| layer.convolve_into(&img, &mut dst).unwrap());
+        Ok(())
     }
 
     #[bench]
-    fn box15_simd1(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(15), simd1)
+    fn box15_4k_tiles_rows(b: &mut Bencher) -> simd::Result<()> {
+        traversal(b, Traversal::Rows)
     }
 
     #[bench]
-    fn box17_simd1(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(17), simd1)
+    fn box15_4k_tiles_columns(b: &mut Bencher) -> simd::Result<()> {
+        traversal(b, Traversal::Columns)
+    }
+
+    // pixels 4 bytes past a 16-byte boundary, computed by Simd3 from the first aligned pixel of
+    // each row or from the first pixel, and the counts of rows computed on each path are printed
+    fn alignment(b: &mut Bencher, align: bool) -> simd::Result<()> {
+        let img = misaligned(&image()?, 4);
+        let kernel = ConvKernel::<5>::new(&[1.; 25], true)?;
+        let mut dst = RgbImage::from_raw(vec![0; H * W * 3], H, W)?;
+        let default = align_loads();
+        set_align_loads(align);
+        reset_alignment_counts();
+        b.iter(|| Simd3.convolve(&kernel, &img, &mut dst).unwrap());
+        eprintln!("{:?}", alignment_counts());
+        set_align_loads(default);
+        Ok(())
     }
 
     #[bench]
-    fn box19_simd1(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(19), simd1)
+    fn box5_simd3_4k_aligned(b: &mut Bencher) -> simd::Result<()> {
+        alignment(b, true)
     }
 
     #[bench]
-    fn box3_simd2(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(3), simd2)
+    fn box5_simd3_4k_unaligned(b: &mut Bencher) -> simd::Result<()> {
+        alignment(b, false)
     }
 
+    // compare with `box5_simd3_4k_blocked`, which prefetches with the default distance
+    #[cfg(feature = "prefetch")]
     #[bench]
-    fn box5_simd2(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(5), simd2)
+    fn box5_simd3_4k_no_prefetch(b: &mut Bencher) -> simd::Result<()> {
+        let default = prefetch_distance();
+        set_prefetch_distance(0);
+        let result = blocked(b, Simd3);
+        set_prefetch_distance(default);
+        result
     }
+}
+
+// 9x9 Gaussian as a KxK kernel and as a column and a row
+mod separable {
+    use super::*;
+
+    use simd::{consts::*, image::RgbImage, separable::SeparableProcessor, ConvKernel};
 
     #[bench]
-    fn box7_simd2(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(7), simd2)
+    fn gaussian9_2d_naive2(b: &mut Bencher) -> simd::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let kernel = ConvKernel::<9>::gaussian(2.)?;
+        let mut dst = RgbImage::from_raw(vec![0; img.content().len()], img.height(), img.width())?;
+        b.iter(|| Naive2.convolve(&kernel, &img, &mut dst).unwrap());
+        Ok(())
     }
 
     #[bench]
-    fn box9_simd2(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(9), simd2)
+    fn gaussian9_separable_naive(b: &mut Bencher) -> simd::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let layer = SeparableProcessor::<9>::gaussian(2.)?;
+        b.iter(|| layer.naive(&img).unwrap());
+        Ok(())
     }
 
+    #[cfg(all(any(target_arch = "aarch64"), all(target_feature = "neon")))]
     #[bench]
-    fn box11_simd2(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(11), simd2)
+    fn gaussian9_2d_simd3(b: &mut Bencher) -> simd::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let kernel = ConvKernel::<9>::gaussian(2.)?;
+        let mut dst = RgbImage::from_raw(vec![0; img.content().len()], img.height(), img.width())?;
+        b.iter(|| Simd3.convolve(&kernel, &img, &mut dst).unwrap());
+        Ok(())
     }
 
+    #[cfg(all(any(target_arch = "aarch64"), all(target_feature = "neon")))]
     #[bench]
-    fn box13_simd2(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(13), simd2)
+    fn gaussian9_separable_simd(b: &mut Bencher) -> simd::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let layer = SeparableProcessor::<9>::gaussian(2.)?;
+        b.iter(|| layer.simd(&img).unwrap());
+        Ok(())
     }
+}
+
+// Gaussian of sigma 8 as a 49-tap separable kernel and as a recursive filter, whose cost does not
+// grow with sigma
+mod recursive {
+    use super::*;
+
+    use simd::{
+        consts::*, image::RgbImage, recursive::RecursiveGaussian, separable::SeparableProcessor,
+    };
 
     #[bench]
-    fn box15_simd2(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(15), simd2)
+    fn gaussian_sigma8_separable_naive(b: &mut Bencher) -> simd::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let layer = SeparableProcessor::<49>::gaussian(8.)?;
+        b.iter(|| layer.naive(&img).unwrap());
+        Ok(())
+    }
+
+    #[bench]
+    fn gaussian_sigma8_recursive_naive(b: &mut Bencher) -> simd::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let layer = RecursiveGaussian::new(8.)?;
+        b.iter(|| layer.naive(&img).unwrap());
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), all(target_feature = "neon")))]
+    #[bench]
+    fn gaussian_sigma8_separable_simd(b: &mut Bencher) -> simd::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let layer = SeparableProcessor::<49>::gaussian(8.)?;
+        b.iter(|| layer.simd(&img).unwrap());
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), all(target_feature = "neon")))]
+    #[bench]
+    fn gaussian_sigma8_recursive_simd(b: &mut Bencher) -> simd::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let layer = RecursiveGaussian::new(8.)?;
+        b.iter(|| layer.simd(&img).unwrap());
+        Ok(())
+    }
+}
+
+// stack blur of radius 19, with about the same standard deviation as the Gaussians of `recursive`
+mod stack_blur {
+    use super::*;
+
+    use simd::{consts::*, image::RgbImage, stack_blur::StackBlur};
+
+    #[bench]
+    fn radius19_naive(b: &mut Bencher) -> simd::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let layer = StackBlur::new(19)?;
+        b.iter(|| layer.naive(&img).unwrap());
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), all(target_feature = "neon")))]
+    #[bench]
+    fn radius19_simd(b: &mut Bencher) -> simd::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let layer = StackBlur::new(19)?;
+        b.iter(|| layer.simd(&img).unwrap());
+        Ok(())
+    }
+}
+
+// 3 box blurs approximating the Gaussian of sigma 8 of `recursive`
+mod box_blur {
+    use super::*;
+
+    use simd::{box_blur::gaussian_approx, consts::*, image::RgbImage};
+
+    #[bench]
+    fn gaussian_sigma8_approx_naive(b: &mut Bencher) -> simd::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let layer = gaussian_approx(8., 3)?;
+        b.iter(|| layer.naive(&img).unwrap());
+        Ok(())
     }
 
+    #[cfg(all(any(target_arch = "aarch64"), all(target_feature = "neon")))]
     #[bench]
-    fn box17_simd2(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(17), simd2)
+    fn gaussian_sigma8_approx_simd(b: &mut Bencher) -> simd::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let layer = gaussian_approx(8., 3)?;
+        b.iter(|| layer.simd(&img).unwrap());
+        Ok(())
     }
+}
+
+// lowered to matrix multiplication, one kernel as a backend or a bank of kernels at once
+mod gemm {
+    use super::*;
+
+    use simd::{consts::*, gemm::convolve_many, image::RgbImage, ConvKernel};
 
     #[bench]
-    fn box19_simd2(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(19), simd2)
+    fn box3_gemm(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(3), Gemm)
     }
 
     #[bench]
-    fn box3_simd3(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(3), simd3)
+    fn box9_gemm(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(9), Gemm)
     }
 
     #[bench]
-    fn box5_simd3(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(5), simd3)
+    fn box19_gemm(b: &mut Bencher) -> simd::Result<()> {
+        bench!(b, FilterType::Box(19), Gemm)
+    }
+
+    // 4 different 7x7 kernels
+    fn bank() -> simd::Result<[ConvKernel<7>; 4]> {
+        Ok([
+            ConvKernel::new(&[1.; 49], true)?,
+            ConvKernel::gaussian(1.)?,
+            ConvKernel::gaussian(2.)?,
+            ConvKernel::new(
+                &(0..49).map(|i| (i % 7) as f32 - 3.).collect::<Vec<_>>(),
+                false,
+            )?,
+        ])
     }
 
     #[bench]
-    fn box7_simd3(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(7), simd3)
+    fn bank4_7x7_direct(b: &mut Bencher) -> simd::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let kernels = bank()?;
+        let backend = select::<7>(img.width());
+        let mut dst = RgbImage::from_raw(vec![0; img.content().len()], img.height(), img.width())?;
+        b.iter(|| {
+            for kernel in kernels.iter() {
+                backend.convolve(kernel, &img, &mut dst).unwrap();
+            }
+        });
+        Ok(())
     }
 
     #[bench]
-    fn box9_simd3(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(9), simd3)
+    fn bank4_7x7_gemm(b: &mut Bencher) -> simd::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let kernels = bank()?;
+        b.iter(|| convolve_many(&kernels, &img).unwrap());
+        Ok(())
     }
+}
+
+mod hsv {
+    use super::*;
+
+    use simd::{consts::*, image::RgbImage};
 
     #[bench]
-    fn box11_simd3(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(11), simd3)
+    fn adjust_hsv(b: &mut Bencher) -> simd::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let mut dst = RgbImage::from_raw(img.content().to_vec(), img.height(), img.width())?;
+        b.iter(|| dst.adjust_hsv(30., 1.2));
+        Ok(())
     }
+}
+
+mod pipeline {
+    use super::*;
+
+    use simd::{consts::*, image::RgbImage, pipeline::Pipeline, ConvKernel, ConvProcessor};
+
+    const SHARPEN: [f32; 9] = [0., -1., 0., -1., 5., -1., 0., -1., 0.];
 
     #[bench]
-    fn box13_simd3(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(13), simd3)
+    fn sequential(b: &mut Bencher) -> simd::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let blur = ConvProcessor::<5>::new(&[1.; 25], true)?;
+        let sharpen = ConvProcessor::<3>::new(&SHARPEN, false)?;
+        b.iter(|| {
+            let processed = sharpen.convolve(&blur.convolve(&img).unwrap()).unwrap();
+            processed
+                .content()
+                .iter()
+                .map(|&v| if v >= 64 { 255 } else { 0 })
+                .collect::<Vec<u8>>()
+        });
+        Ok(())
     }
 
     #[bench]
-    fn box15_simd3(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(15), simd3)
+    fn fused(b: &mut Bencher) -> simd::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let pipeline = Pipeline::new()
+            .convolve(ConvKernel::<5>::new(&[1.; 25], true)?)
+            .convolve(ConvKernel::<3>::new(&SHARPEN, false)?)
+            .threshold(64);
+        b.iter(|| pipeline.run(&img).unwrap());
+        Ok(())
     }
+}
+
+#[cfg(feature = "rayon")]
+mod par {
+    use super::*;
+
+    use simd::{consts::*, image::RgbImage, ConvProcessor};
 
     #[bench]
-    fn box17_simd3(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(17), simd3)
+    fn box7x7_sequential(b: &mut Bencher) -> simd::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let blur = ConvProcessor::<7>::new(&[1.; 49], true)?;
+        b.iter(|| blur.convolve(&img).unwrap());
+        Ok(())
     }
 
     #[bench]
-    fn box19_simd3(b: &mut Bencher) -> io::Result<()> {
-        bench!(b, FilterType::Box(19), simd3)
+    fn box7x7_rayon(b: &mut Bencher) -> simd::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let blur = ConvProcessor::<7>::new(&[1.; 49], true)?;
+        b.iter(|| blur.par_convolve(&img).unwrap());
+        Ok(())
     }
 }
@@ -0,0 +1,97 @@
+//! Throughput of every backend over kernel sizes and image sizes, with Criterion.
+//!
+//! Unlike `benches/main.rs`, which uses the unstable `test` crate and reports ns/iter, results
+//! are reported in pixels per second (`Melem/s`), so that they can be compared across image sizes
+//! and machines. With `BENCH_REPORT=<path>`, the mean time of the measured (not warm-up)
+//! iterations of every benchmark is also written to `<path>` as CSV if it ends with `.csv`, or as
+//! JSON otherwise (see `test_util::BenchReport`).
+
+use std::{
+    env,
+    time::{Duration, Instant},
+};
+
+use criterion::{black_box, BenchmarkId, Criterion, Throughput};
+
+use simd_playground as simd;
+
+use simd::{
+    backend::*,
+    image::RgbImage,
+    test_util::{photo_image, BenchRecord, BenchReport},
+    ConvKernel,
+};
+
+// (width, height) of the images, generated by `photo_image` with the same seed on every run
+const SIZES: [(usize, usize); 3] = [(512, 512), (1920, 1080), (3840, 2160)];
+
+// samples of each benchmark
+const SAMPLES: usize = 10;
+
+// a KxK box filter by every backend on every size, as a group `box{K}`, recorded in `$report`
+macro_rules! box_filter {
+    ($c:expr, $report:expr, $k:literal) => {{
+        const K: usize = $k;
+        let kernel = ConvKernel::<K>::new(&[1.; K * K], true).expect("invalid kernel");
+        let mut group = $c.benchmark_group(format!("box{}", K));
+        // naive backends take seconds per 4K image
+        group.sample_size(SAMPLES);
+        for &(w, h) in SIZES.iter() {
+            let img = photo_image(h, w, 0);
+            let mut dst = RgbImage::from_raw(vec![0; h * w * 3], h, w).expect("invalid size");
+            group.throughput(Throughput::Elements((w * h) as u64));
+            for backend in available::<K>() {
+                let id = BenchmarkId::new(backend.name(), format!("{}x{}", w, h));
+                // iterations and time of every call, the warm-up ones first
+                let mut calls: Vec<(u64, Duration)> = vec![];
+                group.bench_with_input(id, &img, |b, img| {
+                    b.iter_custom(|n| {
+                        let start = Instant::now();
+                        for _ in 0..n {
+                            backend
+                                .convolve(&kernel, black_box(img), &mut dst)
+                                .expect("cannot process");
+                        }
+                        let time = start.elapsed();
+                        calls.push((n, time));
+                        time
+                    })
+                });
+                // Criterion measures a sample per call after warming up, so only the last
+                // `SAMPLES` calls are measured
+                let measured = &calls[calls.len().saturating_sub(SAMPLES)..];
+                let iters: u64 = measured.iter().map(|&(n, _)| n).sum();
+                let elapsed: Duration = measured.iter().map(|&(_, time)| time).sum();
+                // skipped by a filter
+                if iters > 0 {
+                    $report.push(BenchRecord {
+                        backend: backend.name().to_string(),
+                        k: K,
+                        width: w,
+                        height: h,
+                        ns: elapsed.as_nanos() as f64 / iters as f64,
+                    });
+                }
+            }
+        }
+        group.finish();
+    }};
+}
+
+fn kernels(c: &mut Criterion, report: &mut BenchReport) {
+    box_filter!(c, report, 3);
+    box_filter!(c, report, 5);
+    box_filter!(c, report, 7);
+    box_filter!(c, report, 9);
+}
+
+// `criterion_main!` without `criterion_group!`, to get the report back
+fn main() {
+    let mut c = Criterion::default().configure_from_args();
+    let mut report = BenchReport::default();
+    kernels(&mut c, &mut report);
+    c.final_summary();
+    if let Some(path) = env::var_os("BENCH_REPORT") {
+        report.save(path).expect("cannot save report");
+    }
+}
@@ -0,0 +1,85 @@
+#ifndef SIMD_PLAYGROUND_H
+#define SIMD_PLAYGROUND_H
+
+/* Generated by cbindgen from capi/src/lib.rs, do not edit. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+// Result of C API calls.
+typedef enum ConvStatus {
+  // Succeeded.
+  CONV_STATUS_OK = 0,
+  // A pointer argument is null.
+  CONV_STATUS_NULL_POINTER = 1,
+  // Images do not have the same size.
+  CONV_STATUS_SIZE_MISMATCH = 2,
+  // Image is smaller than the kernel.
+  CONV_STATUS_IMAGE_TOO_SMALL = 3,
+  // Stride is less than `width * 3`.
+  CONV_STATUS_INVALID_STRIDE = 4,
+  // Unexpected failure.
+  CONV_STATUS_INTERNAL_ERROR = 5,
+} ConvStatus;
+
+// Convolution created by [`conv_create`], opaque to C.
+typedef struct ConvHandle ConvHandle;
+
+// Read-only view of an image owned by the caller.
+typedef struct ConvImageRef {
+  // First pixel of the first row.
+  const uint8_t *data;
+  // Number of rows.
+  size_t height;
+  // Number of pixels in a row.
+  size_t width;
+  // Bytes from the start of a row to the start of the next row.
+  size_t stride;
+} ConvImageRef;
+
+// Writable view of an image owned by the caller.
+typedef struct ConvImageMut {
+  // First pixel of the first row.
+  uint8_t *data;
+  // Number of rows.
+  size_t height;
+  // Number of pixels in a row.
+  size_t width;
+  // Bytes from the start of a row to the start of the next row.
+  size_t stride;
+} ConvImageMut;
+
+// Creates a `k`x`k` convolution with `k * k` weights in row-major order.
+// If `normalize` is set, results are divided by the sum of the weights.
+//
+// Returns null if `weights` is null, `k` is not an odd number in `3..=19`,
+// or `normalize` is set for weights summing up to 0.
+// The result must be released with [`conv_destroy`].
+//
+// # Safety
+// `weights` must point to `k * k` floats.
+struct ConvHandle *conv_create(const float *weights, size_t k, bool normalize);
+
+// Convolves `src` into `dst`, which must have the same size.
+// Pixels closer than `k / 2` to the border are filled with 0.
+//
+// Images are copied into and out of internal buffers, so `src` and `dst` may overlap.
+//
+// # Safety
+// `handle` must be created by [`conv_create`] and not destroyed yet. `src` and `dst` must
+// describe valid images: `height` rows of `width * 3` bytes, `stride` bytes apart.
+enum ConvStatus conv_run(const struct ConvHandle *handle,
+                         const struct ConvImageRef *src,
+                         const struct ConvImageMut *dst);
+
+// Releases a convolution created by [`conv_create`]. Does nothing for null.
+//
+// # Safety
+// `handle` must be null or created by [`conv_create`], and must not be used afterwards.
+void conv_destroy(struct ConvHandle *handle);
+
+// Version of the C API, bumped on incompatible changes.
+int conv_api_version(void);
+
+#endif /* SIMD_PLAYGROUND_H */
@@ -0,0 +1,272 @@
+//! C API of `simd_playground`, declared in `include/simd_playground.h`.
+//!
+//! Images are described by a pointer to the first row, the size in pixels and the stride
+//! (distance between rows in bytes), so that rows of a larger buffer can be processed.
+//! Pixels are interleaved 8-bit RGB.
+#![allow(incomplete_features)]
+#![feature(generic_const_exprs)]
+#![warn(missing_docs)]
+
+use std::{
+    os::raw::c_int,
+    panic::{catch_unwind, AssertUnwindSafe},
+    slice,
+};
+
+use simd_playground::{ConvProcessor, Error, Result, RgbImage};
+
+// number of channels in RgbImage
+const C: usize = 3;
+
+/// Result of C API calls.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvStatus {
+    /// Succeeded.
+    Ok = 0,
+    /// A pointer argument is null.
+    NullPointer = 1,
+    /// Images do not have the same size.
+    SizeMismatch = 2,
+    /// Image is smaller than the kernel.
+    ImageTooSmall = 3,
+    /// Stride is less than `width * 3`.
+    InvalidStride = 4,
+    /// Unexpected failure.
+    InternalError = 5,
+}
+
+/// Read-only view of an image owned by the caller.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ConvImageRef {
+    /// First pixel of the first row.
+    pub data: *const u8,
+    /// Number of rows.
+    pub height: usize,
+    /// Number of pixels in a row.
+    pub width: usize,
+    /// Bytes from the start of a row to the start of the next row.
+    pub stride: usize,
+}
+
+/// Writable view of an image owned by the caller.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ConvImageMut {
+    /// First pixel of the first row.
+    pub data: *mut u8,
+    /// Number of rows.
+    pub height: usize,
+    /// Number of pixels in a row.
+    pub width: usize,
+    /// Bytes from the start of a row to the start of the next row.
+    pub stride: usize,
+}
+
+/// Convolution created by [`conv_create`], opaque to C.
+pub struct ConvHandle {
+    inner: Box<dyn Convolve>,
+}
+
+// ConvProcessor with the kernel size erased
+trait Convolve {
+    fn convolve_into(&self, src: &RgbImage, dst: &mut RgbImage) -> Result<()>;
+}
+
+impl<const K: usize> Convolve for ConvProcessor<K>
+where
+    [(); (K / 2 + 1) / 2 + 1]: Sized,
+{
+    fn convolve_into(&self, src: &RgbImage, dst: &mut RgbImage) -> Result<()> {
+        ConvProcessor::convolve_into(self, src, dst)
+    }
+}
+
+fn create<const K: usize>(weights: &[f32], normalize: bool) -> Option<Box<dyn Convolve>>
+where
+    [(); (K / 2 + 1) / 2 + 1]: Sized,
+{
+    let processor = ConvProcessor::<K>::new(weights, normalize).ok()?;
+    Some(Box::new(processor))
+}
+
+macro_rules! dispatch_create {
+    ($k:expr, $weights:expr, $normalize:expr, $($n:literal)*) => {
+        match $k {
+            $($n => create::<$n>($weights, $normalize),)*
+            _ => None,
+        }
+    };
+}
+
+/// Creates a `k`x`k` convolution with `k * k` weights in row-major order.
+/// If `normalize` is set, results are divided by the sum of the weights.
+///
+/// Returns null if `weights` is null, `k` is not an odd number in `3..=19`,
+/// or `normalize` is set for weights summing up to 0.
+/// The result must be released with [`conv_destroy`].
+///
+/// # Safety
+/// `weights` must point to `k * k` floats.
+#[no_mangle]
+pub unsafe extern "C" fn conv_create(
+    weights: *const f32,
+    k: usize,
+    normalize: bool,
+) -> *mut ConvHandle {
+    if weights.is_null() || k > 19 {
+        return std::ptr::null_mut();
+    }
+    let weights = slice::from_raw_parts(weights, k * k);
+    let created = catch_unwind(|| dispatch_create!(k, weights, normalize, 3 5 7 9 11 13 15 17 19));
+    match created {
+        Ok(Some(inner)) => Box::into_raw(Box::new(ConvHandle { inner })),
+        _ => std::ptr::null_mut(),
+    }
+}
+
+/// Convolves `src` into `dst`, which must have the same size.
+/// Pixels closer than `k / 2` to the border are filled with 0.
+///
+/// Images are copied into and out of internal buffers, so `src` and `dst` may overlap.
+///
+/// # Safety
+/// `handle` must be created by [`conv_create`] and not destroyed yet. `src` and `dst` must
+/// describe valid images: `height` rows of `width * 3` bytes, `stride` bytes apart.
+#[no_mangle]
+pub unsafe extern "C" fn conv_run(
+    handle: *const ConvHandle,
+    src: *const ConvImageRef,
+    dst: *const ConvImageMut,
+) -> ConvStatus {
+    if handle.is_null() || src.is_null() || dst.is_null() {
+        return ConvStatus::NullPointer;
+    }
+    let (handle, src, dst) = (&*handle, *src, *dst);
+    if src.data.is_null() || dst.data.is_null() {
+        return ConvStatus::NullPointer;
+    }
+    if src.stride < src.width * C || dst.stride < dst.width * C {
+        return ConvStatus::InvalidStride;
+    }
+    if (src.height, src.width) != (dst.height, dst.width) {
+        return ConvStatus::SizeMismatch;
+    }
+
+    let (h, w) = (src.height, src.width);
+    let row = w * C;
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        let mut content = Vec::with_capacity(h * row);
+        for y in 0..h {
+            content.extend_from_slice(slice::from_raw_parts(src.data.add(y * src.stride), row));
+        }
+        let src = RgbImage::from_raw(content, h, w)?;
+        let mut out = RgbImage::from_raw(vec![0; h * row], h, w)?;
+        handle.inner.convolve_into(&src, &mut out)?;
+        for (y, r) in out.content().chunks_exact(row).enumerate() {
+            slice::from_raw_parts_mut(dst.data.add(y * dst.stride), row).copy_from_slice(r);
+        }
+        Ok(())
+    }));
+    match result {
+        Ok(Ok(())) => ConvStatus::Ok,
+        Ok(Err(Error::ImageTooSmall { .. })) => ConvStatus::ImageTooSmall,
+        Ok(Err(Error::SizeMismatch { .. })) => ConvStatus::SizeMismatch,
+        _ => ConvStatus::InternalError,
+    }
+}
+
+/// Releases a convolution created by [`conv_create`]. Does nothing for null.
+///
+/// # Safety
+/// `handle` must be null or created by [`conv_create`], and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn conv_destroy(handle: *mut ConvHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Version of the C API, bumped on incompatible changes.
+#[no_mangle]
+pub extern "C" fn conv_api_version() -> c_int {
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simd_playground::consts::*;
+
+    #[test]
+    fn run_with_stride() -> Result<()> {
+        // tests run in capi/
+        let img = RgbImage::load(format!("{}/../{}", env!("CARGO_MANIFEST_DIR"), ORIGINAL))?;
+        let expected = ConvProcessor::<3>::new(&SOBEL_FILTER, false)?.naive2(&img)?;
+        let (h, w) = (img.height(), img.width());
+        let stride = w * C + 7;
+        // padded rows on both sides
+        let mut src = vec![0xaa; h * stride];
+        for (y, r) in img.content().chunks_exact(w * C).enumerate() {
+            src[y * stride..y * stride + w * C].copy_from_slice(r);
+        }
+        let mut dst = vec![0xbb; h * stride];
+
+        unsafe {
+            let handle = conv_create(SOBEL_FILTER.as_ptr(), 3, false);
+            assert!(!handle.is_null());
+            let src_ref = ConvImageRef {
+                data: src.as_ptr(),
+                height: h,
+                width: w,
+                stride,
+            };
+            let dst_mut = ConvImageMut {
+                data: dst.as_mut_ptr(),
+                height: h,
+                width: w,
+                stride,
+            };
+            assert_eq!(conv_run(handle, &src_ref, &dst_mut), ConvStatus::Ok);
+
+            let small = ConvImageMut {
+                height: 2,
+                ..dst_mut
+            };
+            assert_eq!(conv_run(handle, &src_ref, &small), ConvStatus::SizeMismatch);
+            let narrow = ConvImageRef {
+                stride: w * C - 1,
+                ..src_ref
+            };
+            assert_eq!(
+                conv_run(handle, &narrow, &dst_mut),
+                ConvStatus::InvalidStride
+            );
+            assert_eq!(
+                conv_run(std::ptr::null(), &src_ref, &dst_mut),
+                ConvStatus::NullPointer
+            );
+            conv_destroy(handle);
+        }
+
+        for (y, r) in dst.chunks_exact(stride).enumerate() {
+            assert_eq!(r[..w * C], expected.content()[y * w * C..(y + 1) * w * C]);
+            // padding is untouched
+            assert!(r[w * C..].iter().all(|&v| v == 0xbb));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn create_fails() {
+        let weights = [1.; 16];
+        unsafe {
+            assert!(conv_create(weights.as_ptr(), 4, true).is_null());
+            assert!(conv_create(weights.as_ptr(), 1, true).is_null());
+            assert!(conv_create(std::ptr::null(), 3, true).is_null());
+            assert!(conv_create([0.; 9].as_ptr(), 3, true).is_null());
+            conv_destroy(std::ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Every backend on images of arbitrary sizes and contents, with arbitrary kernels.
+//!
+//! Backends must not panic (nor read out of bounds, with `-s address`), must fail like `Naive2`
+//! on images smaller than the kernel and outputs of another size, and must otherwise give exactly
+//! the result of `Naive2`. Weights are multiples of 1/4, so that sums are exact whether backends
+//! fuse multiplications and additions or not.
+#![no_main]
+
+use libfuzzer_sys::{
+    arbitrary::{Arbitrary, Result, Unstructured},
+    fuzz_target,
+};
+use simd_playground::{
+    backend::{available, ConvBackend, Naive2},
+    ConvKernel, RgbImage,
+};
+
+// up to a block of SIMD backends (1024 pixels) and a bit more, and a few blocks of rows
+const MAX_WIDTH: usize = 1100;
+const MAX_HEIGHT: usize = 40;
+
+// `height` x `width` pixels, taken from the input as long as it lasts and 0 afterwards
+fn image(u: &mut Unstructured, height: usize, width: usize) -> Result<RgbImage> {
+    let mut content = vec![0; height * width * 3];
+    u.fill_buffer(&mut content)?;
+    Ok(RgbImage::from_raw(content, height, width).expect("consistent length"))
+}
+
+macro_rules! check {
+    ($u:expr, $k:literal) => {{
+        const K: usize = $k;
+        let u: &mut Unstructured = $u;
+        let mut weights = [0.; K * K];
+        for w in weights.iter_mut() {
+            *w = i8::arbitrary(u)? as f32 / 4.;
+        }
+        let kernel = match ConvKernel::<K>::new(&weights, bool::arbitrary(u)?) {
+            Ok(kernel) => kernel,
+            // averaging weights summing up to 0
+            Err(_) => return Ok(()),
+        };
+        let (h, w) = (u.int_in_range(0..=MAX_HEIGHT)?, u.int_in_range(0..=MAX_WIDTH)?);
+        // outputs of another size now and then
+        let (dh, dw) = match u.int_in_range(0..=15)? {
+            0 => (u.int_in_range(0..=MAX_HEIGHT)?, u.int_in_range(0..=MAX_WIDTH)?),
+            _ => (h, w),
+        };
+        let src = image(u, h, w)?;
+
+        let mut expected = RgbImage::from_raw(vec![0; dh * dw * 3], dh, dw).unwrap();
+        let expected_result = Naive2.convolve(&kernel, &src, &mut expected);
+        for backend in available::<K>() {
+            let mut dst = RgbImage::from_raw(vec![0; dh * dw * 3], dh, dw).unwrap();
+            let result = backend.convolve(&kernel, &src, &mut dst);
+            assert_eq!(
+                format!("{:?}", result),
+                format!("{:?}", expected_result),
+                "{} on {}x{}",
+                backend.name(),
+                h,
+                w
+            );
+            assert!(dst == expected, "{} on {}x{}", backend.name(), h, w);
+        }
+        Ok(())
+    }};
+}
+
+fn run(data: &[u8]) -> Result<()> {
+    let u = &mut Unstructured::new(data);
+    match u.int_in_range(0..=4)? {
+        0 => check!(u, 3),
+        1 => check!(u, 5),
+        2 => check!(u, 7),
+        3 => check!(u, 9),
+        _ => check!(u, 11),
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    // inputs too short to decide on a kernel are skipped
+    let _ = run(data);
+});
@@ -0,0 +1,79 @@
+//! `ConvProcessor` with arbitrary borders, tiles and threads, on images of arbitrary sizes.
+//!
+//! Padding the border and splitting rows into tiles and threads must not panic, and must give
+//! exactly the result of `Naive2` on whole rows of the image.
+#![no_main]
+
+use libfuzzer_sys::{
+    arbitrary::{Arbitrary, Result, Unstructured},
+    fuzz_target,
+};
+use simd_playground::{
+    backend::{available, Naive2},
+    BorderMode, ConvProcessor, RgbImage,
+};
+
+const MAX_WIDTH: usize = 300;
+const MAX_HEIGHT: usize = 40;
+
+macro_rules! check {
+    ($u:expr, $k:literal) => {{
+        const K: usize = $k;
+        let u: &mut Unstructured = $u;
+        let mut weights = [0.; K * K];
+        for w in weights.iter_mut() {
+            *w = i8::arbitrary(u)? as f32 / 4.;
+        }
+        let normalize = bool::arbitrary(u)?;
+        let border = *u.choose(&[BorderMode::Zero, BorderMode::Replicate, BorderMode::Reflect])?;
+        let reference = match ConvProcessor::<K>::builder()
+            .kernel(&weights)
+            .normalize(normalize)
+            .border(border)
+            .build()
+        {
+            Ok(reference) => reference,
+            // averaging weights summing up to 0
+            Err(_) => return Ok(()),
+        };
+
+        let backends = available::<K>();
+        let mut builder = ConvProcessor::<K>::builder()
+            .kernel(&weights)
+            .normalize(normalize)
+            .border(border)
+            .backend(*u.choose(&backends)?)
+            .threads(u.int_in_range(1..=3)?);
+        if bool::arbitrary(u)? {
+            builder = builder.tile_size(u.int_in_range(0..=40)?, u.int_in_range(0..=300)?);
+        }
+        let layer = builder.build().expect("valid kernel");
+
+        let (h, w) = (u.int_in_range(0..=MAX_HEIGHT)?, u.int_in_range(0..=MAX_WIDTH)?);
+        let mut content = vec![0; h * w * 3];
+        u.fill_buffer(&mut content)?;
+        let src = RgbImage::from_raw(content, h, w).expect("consistent length");
+
+        let expected = reference.apply(&Naive2, &src);
+        let result = layer.convolve(&src);
+        match (result, expected) {
+            (Ok(result), Ok(expected)) => assert!(result == expected, "{:?} on {}x{}", layer, h, w),
+            (result, expected) => assert_eq!(format!("{:?}", result), format!("{:?}", expected)),
+        }
+        Ok(())
+    }};
+}
+
+fn run(data: &[u8]) -> Result<()> {
+    let u = &mut Unstructured::new(data);
+    match u.int_in_range(0..=2)? {
+        0 => check!(u, 3),
+        1 => check!(u, 5),
+        _ => check!(u, 7),
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    // inputs too short to decide on a kernel are skipped
+    let _ = run(data);
+});
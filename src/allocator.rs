@@ -0,0 +1,185 @@
+//! Caller-supplied memory for the pixels of [`RgbImage`](crate::RgbImage)s and the scratch
+//! buffers of [`ConvProcessor`](crate::ConvProcessor)s, e.g. a bump arena or a region of pinned
+//! hugepages.
+//!
+//! Images allocate through an [`ImageAllocator`], a shared handle to any [`Allocator`] which is
+//! the global allocator by default. Buffers of the global allocator are wrapped and unwrapped
+//! without copying, see [`RgbImage::from_raw`](crate::RgbImage::from_raw) and
+//! [`RgbImage::into_raw_ordered`](crate::RgbImage::into_raw_ordered).
+
+use alloc::{
+    alloc::{AllocError, Allocator, Global, Layout},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{fmt, mem::ManuallyDrop, ptr::NonNull};
+
+/// Allocator of the pixels of [`RgbImage`](crate::RgbImage)s: the global one, or a shared
+/// custom [`Allocator`].
+///
+/// Clones refer to the same allocator, and buffers are freed by the allocator which allocated
+/// them.
+#[derive(Clone, Default)]
+pub struct ImageAllocator(Option<Arc<dyn Allocator + Send + Sync>>);
+
+impl ImageAllocator {
+    /// The global allocator.
+    pub const fn global() -> Self {
+        Self(None)
+    }
+
+    /// Allocates with `allocator`, e.g. a bump arena or an allocator of pinned memory.
+    pub fn new<A: Allocator + Send + Sync + 'static>(allocator: A) -> Self {
+        Self(Some(Arc::new(allocator)))
+    }
+
+    /// Whether this is the global allocator.
+    pub fn is_global(&self) -> bool {
+        self.0.is_none()
+    }
+}
+
+// the same allocator, not an equal one
+impl PartialEq for ImageAllocator {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (None, None) => true,
+            (Some(a), Some(b)) => Arc::as_ptr(a) as *const u8 == Arc::as_ptr(b) as *const u8,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for ImageAllocator {}
+
+impl fmt::Debug for ImageAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self.0 {
+            None => "ImageAllocator(Global)",
+            Some(_) => "ImageAllocator(Custom)",
+        })
+    }
+}
+
+unsafe impl Allocator for ImageAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        match &self.0 {
+            None => Global.allocate(layout),
+            Some(a) => a.allocate(layout),
+        }
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        match &self.0 {
+            None => Global.allocate_zeroed(layout),
+            Some(a) => a.allocate_zeroed(layout),
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        match &self.0 {
+            None => Global.deallocate(ptr, layout),
+            Some(a) => a.deallocate(ptr, layout),
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        match &self.0 {
+            None => Global.grow(ptr, old_layout, new_layout),
+            Some(a) => a.grow(ptr, old_layout, new_layout),
+        }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        match &self.0 {
+            None => Global.shrink(ptr, old_layout, new_layout),
+            Some(a) => a.shrink(ptr, old_layout, new_layout),
+        }
+    }
+}
+
+/// Bytes allocated by an [`ImageAllocator`].
+pub type ImageBuffer = Vec<u8, ImageAllocator>;
+
+// `buf` of the global allocator as an `ImageBuffer`, without copying
+pub(crate) fn from_global(buf: Vec<u8>) -> ImageBuffer {
+    let mut buf = ManuallyDrop::new(buf);
+    // SAFETY: the buffer is freed by the global allocator either way
+    unsafe {
+        Vec::from_raw_parts_in(
+            buf.as_mut_ptr(),
+            buf.len(),
+            buf.capacity(),
+            ImageAllocator::global(),
+        )
+    }
+}
+
+// `buf` as a buffer of the global allocator, copied if another allocator allocated it
+pub(crate) fn into_global(buf: ImageBuffer) -> Vec<u8> {
+    if !buf.allocator().is_global() {
+        return buf.to_vec();
+    }
+    let mut buf = ManuallyDrop::new(buf);
+    // SAFETY: the global allocator allocated the buffer
+    unsafe { Vec::from_raw_parts(buf.as_mut_ptr(), buf.len(), buf.capacity()) }
+}
+
+// `len` zeros allocated by `allocator`
+pub(crate) fn zeroed_in(len: usize, allocator: ImageAllocator) -> ImageBuffer {
+    let mut buf = Vec::with_capacity_in(len, allocator);
+    buf.resize(len, 0);
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    // global allocator counting the bytes it holds
+    #[derive(Default)]
+    struct Counting(AtomicUsize);
+
+    unsafe impl Allocator for &'static Counting {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            self.0.fetch_add(layout.size(), Ordering::Relaxed);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.0.fetch_sub(layout.size(), Ordering::Relaxed);
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    #[test]
+    fn buffers() {
+        let buf = vec![1, 2, 3];
+        let ptr = buf.as_ptr();
+        let buf = from_global(buf);
+        assert!(buf.allocator().is_global());
+        let buf = into_global(buf);
+        assert_eq!(buf.as_ptr(), ptr);
+
+        let counting: &'static Counting = Box::leak(Box::default());
+        let allocator = ImageAllocator::new(counting);
+        let buf = zeroed_in(100, allocator.clone());
+        assert_eq!(counting.0.load(Ordering::Relaxed), 100);
+        assert!(buf.iter().all(|&v| v == 0));
+        // copied to the global allocator
+        let global = into_global(buf);
+        assert_eq!(global, vec![0; 100]);
+        assert_eq!(counting.0.load(Ordering::Relaxed), 0);
+    }
+}
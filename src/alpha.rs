@@ -0,0 +1,347 @@
+//! Premultiplied alpha for interleaved RGBA buffers, e.g. to blur transparent images.
+//!
+//! [`RgbImage`](crate::RgbImage) has no alpha channel, so these operate on `[r, g, b, a, ...]`
+//! bytes. Blurring straight (not premultiplied) RGBA mixes the colors of transparent pixels
+//! into their neighbors, so images are premultiplied before filtering and unpremultiplied after.
+//! NEON handles 16 pixels at a time with `vld4q_u8`/`vst4q_u8`, with the same results as the
+//! serial loops.
+//!
+//! [`rgba_to_gray`] converts to gray by weights of the channels, optionally keeping the alpha.
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    color_matrix::weighted,
+    error::{Error, Result},
+    kernel::Rounding,
+    C,
+};
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::{
+    color_matrix::weighted16,
+    simd,
+    util::{f32x4x4_to_u8x16, u8x16_to_f32x4x4},
+};
+
+/// Number of channels of an RGBA pixel.
+pub const RGBA: usize = 4;
+
+/// Multiplies the color of every pixel by its alpha, `c * a / 255` rounded to the nearest
+/// integer.
+///
+/// Fails with [`Error::InvalidLength`] if `rgba` is not whole pixels, `expected` being its
+/// length rounded down to them.
+pub fn premultiply(rgba: &mut [u8]) -> Result<()> {
+    check_len(rgba.len())?;
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let rgba = {
+        let simd_end = rgba.len() - rgba.len() % (16 * RGBA);
+        for i in (0..simd_end).step_by(16 * RGBA) {
+            let [r, g, b, a] = simd::deinterleave_rgba16(rgba, i);
+            let v = unsafe { [r, g, b].map(|c| mul_div255_u8x16(c, a)) };
+            simd::interleave_rgba16(rgba, i, [v[0], v[1], v[2], a]);
+        }
+        &mut rgba[simd_end..]
+    };
+    for pixel in rgba.chunks_exact_mut(RGBA) {
+        let a = pixel[3];
+        for c in &mut pixel[..3] {
+            *c = mul_div255(*c, a);
+        }
+    }
+    Ok(())
+}
+
+/// Divides the color of every pixel by its alpha, `c * 255 / a` rounded to the nearest integer
+/// and saturated, the reverse of [`premultiply`] up to its rounding. Colors of fully transparent
+/// pixels become 0.
+///
+/// Fails with [`Error::InvalidLength`] like [`premultiply`].
+pub fn unpremultiply(rgba: &mut [u8]) -> Result<()> {
+    check_len(rgba.len())?;
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let rgba = {
+        let simd_end = rgba.len() - rgba.len() % (16 * RGBA);
+        for i in (0..simd_end).step_by(16 * RGBA) {
+            let [r, g, b, a] = simd::deinterleave_rgba16(rgba, i);
+            let v = unsafe {
+                let transparent = vceqq_u8(a, vdupq_n_u8(0));
+                let af = u8x16_to_f32x4x4(a);
+                [r, g, b].map(|c| {
+                    let mut cf = u8x16_to_f32x4x4(c);
+                    for (c, &a) in cf.iter_mut().zip(af.iter()) {
+                        *c = vdivq_f32(vmulq_f32(*c, vdupq_n_f32(255.)), a);
+                    }
+                    // 0 instead of dividing by 0
+                    let c = f32x4x4_to_u8x16(cf, Rounding::Nearest);
+                    vbslq_u8(transparent, vdupq_n_u8(0), c)
+                })
+            };
+            simd::interleave_rgba16(rgba, i, [v[0], v[1], v[2], a]);
+        }
+        &mut rgba[simd_end..]
+    };
+    for pixel in rgba.chunks_exact_mut(RGBA) {
+        let a = pixel[3];
+        for c in &mut pixel[..3] {
+            *c = if a == 0 {
+                0
+            } else {
+                Rounding::Nearest.to_u8(*c as f32 * 255. / a as f32)
+            };
+        }
+    }
+    Ok(())
+}
+
+/// Composites premultiplied `src` over premultiplied `dst` in place, i.e. every channel of
+/// `dst` (alpha included) becomes `s + d * (255 - sa) / 255`, rounded to the nearest integer
+/// and saturated.
+///
+/// Fails with [`Error::InvalidLength`] if `dst` is not whole pixels like [`premultiply`], or
+/// `src` does not have the same length.
+pub fn over(src: &[u8], dst: &mut [u8]) -> Result<()> {
+    check_len(dst.len())?;
+    if src.len() != dst.len() {
+        return Err(Error::InvalidLength {
+            expected: dst.len(),
+            actual: src.len(),
+        });
+    }
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let (src, dst) = {
+        let simd_end = dst.len() - dst.len() % (16 * RGBA);
+        for i in (0..simd_end).step_by(16 * RGBA) {
+            let s = simd::deinterleave_rgba16(src, i);
+            let d = simd::deinterleave_rgba16(dst, i);
+            let v = unsafe {
+                let transparency = vmvnq_u8(s[3]);
+                [0, 1, 2, 3].map(|c| vqaddq_u8(s[c], mul_div255_u8x16(d[c], transparency)))
+            };
+            simd::interleave_rgba16(dst, i, v);
+        }
+        (&src[simd_end..], &mut dst[simd_end..])
+    };
+    for (s, d) in src.chunks_exact(RGBA).zip(dst.chunks_exact_mut(RGBA)) {
+        let transparency = u8::MAX - s[3];
+        for (&s, d) in s.iter().zip(d.iter_mut()) {
+            *d = s.saturating_add(mul_div255(*d, transparency));
+        }
+    }
+    Ok(())
+}
+
+/// Gray value of every pixel by the weights of its color like
+/// [`RgbImage::to_gray`](crate::RgbImage::to_gray), followed by its alpha if `keep_alpha`
+/// (`[gray, a, gray, a, ...]`), e.g. for 8-bit gray and alpha PNGs.
+///
+/// Fails with [`Error::InvalidLength`] like [`premultiply`].
+pub fn rgba_to_gray(rgba: &[u8], weights: [f32; C], keep_alpha: bool) -> Result<Vec<u8>> {
+    check_len(rgba.len())?;
+    let pixels = rgba.len() / RGBA;
+    let step = if keep_alpha { 2 } else { 1 };
+    let mut dst = vec![0; pixels * step];
+    // pixels from `start` are left to the serial loop
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let start = {
+        let w = weights.map(simd::splat);
+        let simd_end = pixels - pixels % 16;
+        for x in (0..simd_end).step_by(16) {
+            let [r, g, b, a] = simd::deinterleave_rgba16(rgba, x * RGBA);
+            unsafe {
+                let v = [r, g, b].map(|c| u8x16_to_f32x4x4(c));
+                let gray = f32x4x4_to_u8x16(weighted16(&v, &w), Rounding::Nearest);
+                if keep_alpha {
+                    let dst = &mut dst[2 * x..2 * x + 32];
+                    vst2q_u8(dst.as_mut_ptr(), uint8x16x2_t(gray, a));
+                } else {
+                    simd::store_bytes16(&mut dst, x, gray);
+                }
+            }
+        }
+        simd_end
+    };
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    let start = 0;
+    for (d, s) in dst[start * step..]
+        .chunks_exact_mut(step)
+        .zip(rgba[start * RGBA..].chunks_exact(RGBA))
+    {
+        d[0] = weighted(s, weights);
+        if keep_alpha {
+            d[1] = s[3];
+        }
+    }
+    Ok(dst)
+}
+
+fn check_len(len: usize) -> Result<()> {
+    if len % RGBA != 0 {
+        return Err(Error::InvalidLength {
+            expected: len - len % RGBA,
+            actual: len,
+        });
+    }
+    Ok(())
+}
+
+// `a * b / 255` rounded to the nearest integer, without division
+fn mul_div255(a: u8, b: u8) -> u8 {
+    let t = a as u32 * b as u32;
+    ((t + ((t + 128) >> 8) + 128) >> 8) as u8
+}
+
+// `mul_div255` of 16 lanes, the shifts rounding with `vrshrq` and `vraddhn`
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[inline]
+unsafe fn mul_div255_u8x16(a: uint8x16_t, b: uint8x16_t) -> uint8x16_t {
+    let lo = vmull_u8(vget_low_u8(a), vget_low_u8(b));
+    let hi = vmull_high_u8(a, b);
+    vraddhn_high_u16(
+        vraddhn_u16(lo, vrshrq_n_u16::<8>(lo)),
+        hi,
+        vrshrq_n_u16::<8>(hi),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color_matrix::LUMA_BT601;
+
+    #[test]
+    fn premultiplied() -> Result<()> {
+        // every color with every alpha
+        let straight: Vec<u8> = (0..=255u8)
+            .flat_map(|a| (0..=255u8).flat_map(move |c| [c, 255 - c, c / 2, a]))
+            .collect();
+        let mut rgba = straight.clone();
+        premultiply(&mut rgba)?;
+        for (p, s) in rgba.chunks_exact(RGBA).zip(straight.chunks_exact(RGBA)) {
+            let a = s[3] as f32;
+            let expected = [0, 1, 2].map(|c| (s[c] as f32 * a / 255.).round() as u8);
+            assert_eq!(p[..3], expected, "{:?}", s);
+            assert_eq!(p[3], s[3]);
+        }
+
+        unpremultiply(&mut rgba)?;
+        for (p, s) in rgba.chunks_exact(RGBA).zip(straight.chunks_exact(RGBA)) {
+            match s[3] {
+                0 => assert_eq!(p[..3], [0; 3]),
+                // premultiplying keeps about `a` levels of each color
+                a => {
+                    for c in 0..3 {
+                        let error = (p[c] as i16 - s[c] as i16).unsigned_abs();
+                        assert!(error as f32 <= 127.5 / a as f32 + 0.5, "{:?} {:?}", s, p);
+                    }
+                }
+            }
+        }
+
+        let mut rgba = vec![0; 7];
+        assert!(matches!(
+            premultiply(&mut rgba),
+            Err(Error::InvalidLength {
+                expected: 4,
+                actual: 7
+            })
+        ));
+        assert!(unpremultiply(&mut rgba).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn composite() -> Result<()> {
+        // longer than a vector of 16 pixels, with some left over
+        let dst: Vec<u8> = [200, 100, 50, 255].repeat(19);
+        let mut opaque = dst.clone();
+        over(&[10, 20, 30, 255].repeat(19), &mut opaque)?;
+        assert_eq!(opaque, [10, 20, 30, 255].repeat(19));
+        let mut transparent = dst.clone();
+        over(&[0; 19 * RGBA], &mut transparent)?;
+        assert_eq!(transparent, dst);
+
+        // half-transparent red over the straight color, i.e. premultiplied by 128
+        let mut mixed = dst.clone();
+        over(&[128, 0, 0, 128].repeat(19), &mut mixed)?;
+        assert_eq!(mixed, [228, 50, 25, 255].repeat(19));
+
+        assert!(matches!(
+            over(&dst[4..], &mut mixed),
+            Err(Error::InvalidLength {
+                expected: 76,
+                actual: 72
+            })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn rgba_to_gray() -> Result<()> {
+        // the gray of the color, over vectors of 16 pixels with some left over
+        let rgba: Vec<u8> = (0..37 * RGBA).map(|i| (i * 89 % 256) as u8).collect();
+        let rgb: Vec<u8> = rgba
+            .chunks_exact(RGBA)
+            .flat_map(|p| [p[0], p[1], p[2]])
+            .collect();
+        let rgb = crate::RgbImage::from_raw(rgb, 1, 37)?;
+        for weights in [LUMA_BT601, [-1., 2., -1.]] {
+            let gray = super::rgba_to_gray(&rgba, weights, false)?;
+            assert_eq!(gray, rgb.to_gray(weights));
+            let gray_alpha = super::rgba_to_gray(&rgba, weights, true)?;
+            for ((ga, &g), p) in gray_alpha
+                .chunks_exact(2)
+                .zip(&gray)
+                .zip(rgba.chunks_exact(RGBA))
+            {
+                assert_eq!(ga, [g, p[3]]);
+            }
+        }
+        assert!(matches!(
+            super::rgba_to_gray(&rgba[1..], LUMA_BT601, true),
+            Err(Error::InvalidLength { .. })
+        ));
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    #[test]
+    fn simd() -> Result<()> {
+        // vectors of 16 pixels, and some left over
+        let src: Vec<u8> = (0..1000 * RGBA).map(|i| (i * 37 % 256) as u8).collect();
+        let dst: Vec<u8> = (0..1000 * RGBA).map(|i| (i * 11 % 253) as u8).collect();
+
+        let mut rgba = src.clone();
+        premultiply(&mut rgba)?;
+        for (p, s) in rgba.chunks_exact(RGBA).zip(src.chunks_exact(RGBA)) {
+            assert_eq!(p[..3], [0, 1, 2].map(|c| mul_div255(s[c], s[3])), "{:?}", s);
+        }
+        let premultiplied = rgba.clone();
+        unpremultiply(&mut rgba)?;
+        for (p, s) in rgba
+            .chunks_exact(RGBA)
+            .zip(premultiplied.chunks_exact(RGBA))
+        {
+            let expected = [0, 1, 2].map(|c| match s[3] {
+                0 => 0,
+                a => Rounding::Nearest.to_u8(s[c] as f32 * 255. / a as f32),
+            });
+            assert_eq!(p[..3], expected, "{:?}", s);
+        }
+
+        let mut composited = dst.clone();
+        over(&src, &mut composited)?;
+        for ((p, s), d) in composited
+            .chunks_exact(RGBA)
+            .zip(src.chunks_exact(RGBA))
+            .zip(dst.chunks_exact(RGBA))
+        {
+            let expected = [0, 1, 2, 3].map(|c| s[c].saturating_add(mul_div255(d[c], 255 - s[3])));
+            assert_eq!(p, expected, "{:?} {:?}", s, d);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+//! Measurement of backends, thread counts, tile sizes and traversals for
+//! [`ConvProcessorBuilder::autotune`](crate::processor::ConvProcessorBuilder::autotune), and of
+//! backends alone with a [`Tuner`].
+//!
+//! The fastest combination depends on the CPU (number of cores, cache sizes, NEON throughput)
+//! rather than on the code, so it is measured instead of guessed.
+
+use std::{collections::HashMap, iter, sync::Mutex, thread, time::Duration};
+
+use crate::{
+    backend::{available, select, ConvBackend},
+    bench,
+    error::{Error, Result},
+    image::RgbImage,
+    kernel::ConvKernel,
+    processor::{Traversal, Tuning},
+    C,
+};
+
+// rows of the image on which candidates are measured
+pub(crate) const SAMPLE_ROWS: usize = 64;
+// measurements per candidate, of which the fastest counts: the first one warms up caches
+const RUNS: usize = 2;
+// `(height, width)` of the tiles tried besides whole rows
+const TILES: [(usize, usize); 2] = [(16, 256), (64, 1024)];
+
+// combinations of `backends`, powers of 2 threads up to the number of CPUs, tile sizes
+// narrower than `width` and traversals
+pub(crate) fn candidates<const K: usize>(
+    backends: &[&'static dyn ConvBackend<K>],
+    width: usize,
+) -> Vec<Tuning<K>> {
+    let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut threads: Vec<_> = (0..).map(|i| 1 << i).take_while(|&n| n < cpus).collect();
+    threads.push(cpus);
+    let tiles = iter::once(None).chain(TILES.iter().filter(|t| t.1 < width).map(|&t| Some(t)));
+
+    let mut candidates = vec![];
+    for tile in tiles {
+        for &traversal in [Traversal::Rows, Traversal::Columns].iter() {
+            for &threads in &threads {
+                for &backend in backends {
+                    candidates.push(Tuning {
+                        backend,
+                        threads,
+                        tile,
+                        traversal,
+                    });
+                }
+            }
+        }
+    }
+    candidates
+}
+
+// backends measured unless one is configured: the available ones, or the one `select` picks for
+// `width` if every `impl-*` feature is off
+pub(crate) fn backends<const K: usize>(width: usize) -> Vec<&'static dyn ConvBackend<K>>
+where
+    [(); (K / 2 + 1) / 2 + 1]: Sized,
+{
+    let backends = available::<K>();
+    if backends.is_empty() {
+        vec![select(width)]
+    } else {
+        backends
+    }
+}
+
+// the candidate for which `run` takes the least time
+pub(crate) fn fastest<T: Copy>(
+    candidates: Vec<T>,
+    mut run: impl FnMut(&T) -> Result<()>,
+) -> Result<T> {
+    let mut best: Option<(T, Duration)> = None;
+    for t in candidates {
+        let elapsed = bench::time(RUNS, 0, || run(&t))?.min;
+        if best.map_or(true, |(_, min)| elapsed < min) {
+            best = Some((t, elapsed));
+        }
+    }
+    Ok(best.expect("no candidate").0)
+}
+
+/// Picks the fastest backend for each image size and kernel size, by running every
+/// [`available`] backend (or the one [`select`] picks, if none is) on the first rows of the first
+/// such image and caching the decision. [`SimdWinograd`](crate::backend::SimdWinograd), which
+/// may round differently, is never picked.
+///
+/// A tuner is shared among processors with
+/// [`ConvProcessorBuilder::tuner`](crate::processor::ConvProcessorBuilder::tuner), e.g. for the
+/// lifetime of an application, so that each size is measured once.
+#[derive(Debug, Default)]
+pub struct Tuner {
+    // name of the fastest backend for each `(height, width, K)`
+    chosen: Mutex<HashMap<(usize, usize, usize), &'static str>>,
+}
+
+impl Tuner {
+    /// Creates a tuner which has not measured anything yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fastest backend for images of the size of `src` and `kernel`, measured on `src` if this
+    /// is the first such image.
+    ///
+    /// Fails with [`Error::ImageTooSmall`] if `src` is smaller than the kernel.
+    pub fn backend<const K: usize>(
+        &self,
+        kernel: &ConvKernel<K>,
+        src: &RgbImage,
+    ) -> Result<&'static dyn ConvBackend<K>>
+    where
+        [(); (K / 2 + 1) / 2 + 1]: Sized,
+    {
+        src.check_min_size(K)?;
+        let backends = backends::<K>(src.width);
+        // held while measuring, so that other threads neither measure again nor disturb it
+        let mut chosen = self.chosen.lock().unwrap();
+        let key = (src.height, src.width, K);
+        if let Some(&name) = chosen.get(&key) {
+            if let Some(&backend) = backends.iter().find(|b| b.name() == name) {
+                return Ok(backend);
+            }
+        }
+
+        let half = K / 2;
+        let rows = half..(half + SAMPLE_ROWS).min(src.height - half);
+        let mut dst = vec![0; rows.len() * src.width * C];
+        let backend = fastest(backends, |backend| {
+            backend.convolve_rows(kernel, src, rows.clone(), &mut dst);
+            Ok::<_, Error>(())
+        })?;
+        chosen.insert(key, backend.name());
+        Ok(backend)
+    }
+
+    /// Name of the backend chosen for images of `height` x `width` pixels and KxK kernels, or
+    /// `None` if no such image has been measured.
+    pub fn chosen(&self, height: usize, width: usize, k: usize) -> Option<&'static str> {
+        self.chosen
+            .lock()
+            .unwrap()
+            .get(&(height, width, k))
+            .copied()
+    }
+}
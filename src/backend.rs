@@ -0,0 +1,2483 @@
+//! Interchangeable implementations of [`ConvProcessor`](crate::ConvProcessor).
+//!
+//! Every backend gives the same result: each channel is convolved separately, by fused
+//! multiply-adds (rounded once, in scalar code too) in the row-major order of the weights,
+//! divided by [`ConvKernel::div`] if any, then saturated to `u8` with [`ConvKernel::rounding`].
+//! Only [`SimdWinograd`] may round some pixels differently, see there, so it is not among the
+//! [`available`] backends and is used only if configured with
+//! [`ConvProcessorBuilder::backend`](crate::processor::ConvProcessorBuilder::backend).
+
+use alloc::{vec, vec::Vec};
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    any(
+        feature = "impl-simd1",
+        feature = "impl-simd2",
+        feature = "impl-simd3",
+        feature = "impl-simd4",
+        feature = "impl-wide"
+    )
+))]
+use core::arch::aarch64::*;
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    any(feature = "impl-simd2", feature = "impl-simd3", feature = "impl-wide")
+))]
+use core::mem;
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd3"
+))]
+use core::sync::atomic::AtomicBool;
+#[cfg(any(
+    feature = "prefetch",
+    all(
+        any(target_arch = "aarch64"),
+        target_feature = "neon",
+        feature = "impl-simd3"
+    )
+))]
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{fmt::Debug, ops::Range};
+
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    any(feature = "impl-simd4", feature = "impl-int")
+))]
+use crate::kernel::Rounding;
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    any(
+        feature = "impl-simd3",
+        feature = "impl-simd4",
+        feature = "impl-wide",
+        feature = "impl-ring"
+    )
+))]
+use crate::simd;
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-unrolled"
+))]
+use crate::simd::Rgb16;
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd4"
+))]
+use crate::simd::Rgb8;
+#[cfg(feature = "stats")]
+use crate::stats;
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    any(feature = "impl-simd3", feature = "impl-wide")
+))]
+use crate::util::{vec_mut, vec_ref};
+use crate::{
+    error::{Error, Result},
+    image::RgbImage,
+    kernel::{fma, ConvKernel},
+    C,
+};
+
+/// Implementation of KxK convolution.
+///
+/// Backends are shared among threads, see [`ConvProcessorBuilder::threads`](crate::processor::ConvProcessorBuilder::threads).
+pub trait ConvBackend<const K: usize>: Debug + Sync {
+    /// Short name of the implementation, e.g. for test and benchmark reports.
+    fn name(&self) -> &'static str;
+
+    /// Computes output rows in `rows` into `dst`, which holds exactly those rows.
+    ///
+    /// `rows` must lie in `K / 2..src.height() - K / 2`. Pixels closer than `K / 2` to the
+    /// left and right borders are left untouched.
+    ///
+    /// # Panics
+    /// Panics if `src` is smaller than KxK, `rows` is out of range or `dst` has another length.
+    fn convolve_rows(
+        &self,
+        kernel: &ConvKernel<K>,
+        src: &RgbImage,
+        rows: Range<usize>,
+        dst: &mut [u8],
+    );
+
+    /// Convolves `src` into `dst`, filling pixels closer than `K / 2` to the border with 0.
+    ///
+    /// Fails with [`Error::ImageTooSmall`] if `src` is smaller than the kernel,
+    /// and with [`Error::SizeMismatch`] if `dst` does not have the same size as `src`.
+    fn convolve(&self, kernel: &ConvKernel<K>, src: &RgbImage, dst: &mut RgbImage) -> Result<()> {
+        src.check_min_size(K)?;
+        let (h, w) = (src.height, src.width);
+        if (dst.height, dst.width) != (h, w) {
+            return Err(Error::SizeMismatch {
+                expected: (h, w),
+                actual: (dst.height, dst.width),
+            });
+        }
+        let half = K / 2;
+        // dst may hold a previous result, and backends only write the inner part
+        clear_border(&mut dst.inner, h, w, half);
+        let row = w * C;
+        self.convolve_rows(
+            kernel,
+            src,
+            half..h - half,
+            &mut dst.inner[half * row..(h - half) * row],
+        );
+        Ok(())
+    }
+}
+
+/// Straightforward scalar implementation, looping over channels outermost.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Naive1;
+
+/// Scalar implementation accumulating all channels of a pixel at once.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Naive2;
+
+/// NEON implementation computing 4 pixels at once, gathering each input lane separately. Two such
+/// vectors are computed side by side, so that their fused multiply-adds do not wait for each other.
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd1"
+))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Simd1;
+
+/// NEON implementation computing 4 pixels at once, sharing loaded rows among kernel columns with `vext`.
+/// Two such vectors are computed side by side, like [`Simd1`].
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd2"
+))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Simd2;
+
+/// NEON implementation computing 16 pixels at once with deinterleaving loads and stores.
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd3"
+))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Simd3;
+
+/// Same as [`Simd3`], computing `16 * B` pixels at once with `4 * B` accumulators per channel, to
+/// find how much instruction-level parallelism pays for the register pressure on big cores.
+/// `SimdWide<2>` (32 pixels) keeps its accumulators in 24 of the 32 NEON registers, while those of
+/// `SimdWide<4>` (64 pixels) take 48 and spill to the stack. Only these two are backends, see
+/// [`WideBlocks`].
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-wide"
+))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SimdWide<const B: usize>;
+
+/// Widths of [`SimdWide`] which implement [`ConvBackend`]: `SimdWide<2>` and `SimdWide<4>`.
+///
+/// It is sealed, so that no other number of vectors (e.g. 0) can be computed.
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-wide"
+))]
+pub trait WideBlocks: sealed::Sealed {
+    /// Name of the backend, see [`ConvBackend::name`].
+    const NAME: &'static str;
+}
+
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-wide"
+))]
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for super::SimdWide<2> {}
+    impl Sealed for super::SimdWide<4> {}
+}
+
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-wide"
+))]
+impl WideBlocks for SimdWide<2> {
+    const NAME: &'static str = "simd_wide32";
+}
+
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-wide"
+))]
+impl WideBlocks for SimdWide<4> {
+    const NAME: &'static str = "simd_wide64";
+}
+
+/// NEON implementation computing 8 pixels of 4 output rows at once, so that each source row is
+/// loaded and converted to `f32` once for all the output rows it contributes to, rather than once
+/// per output row, and once for all the kernel columns, whose pixels are shifted by `vext`.
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd4"
+))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Simd4;
+
+/// NEON implementation converting each source row to `f32` once, into a ring buffer of the last
+/// `K` rows shared by consecutive output rows, rather than once per kernel row.
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-ring"
+))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SimdRing;
+
+/// NEON implementation specialized for 3x3 kernels, keeping the source rows and the broadcast
+/// weights in registers across pixels.
+///
+/// Other kernel sizes are computed by [`Simd3`].
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-unrolled"
+))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Simd3x3;
+
+/// NEON implementation specialized for 5x5 kernels, like [`Simd3x3`].
+///
+/// Other kernel sizes are computed by [`Simd3`].
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-unrolled"
+))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Simd5x5;
+
+/// NEON implementation computing 16 pixels at once with integer multiply-accumulate, for kernels
+/// whose weights are integers in `0..=255` (e.g. box and binomial filters). Pixels are never
+/// converted to `f32`, and sums are divided by shifts for powers of 2.
+///
+/// Other kernels are computed by [`Simd3`].
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-int"
+))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SimdInt;
+
+/// NEON implementation of 3x3 kernels by the Winograd minimal filtering algorithm F(2x2, 3x3),
+/// computing each block of 2x2 pixels from 4x4 source pixels with 16 multiplications instead of
+/// 36. The source is transformed by integer additions on 8 blocks at once, and the kernel and the
+/// products in `f32`.
+///
+/// Sums are not computed in the order of the other backends, so that pixels may differ from them
+/// by 1 after rounding, unless the weights are small multiples of a power of 2 (e.g. integers,
+/// as in box and Sobel kernels). It is therefore left out of [`available`] and of autotuning, and
+/// must be configured explicitly. Other kernel sizes are computed by [`Simd3`].
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-winograd"
+))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SimdWinograd;
+
+/// Implementation lowering tiles of output rows to a matrix multiplication (im2col and GEMM), see
+/// [`gemm`](crate::gemm), with a NEON micro-kernel if available.
+#[cfg(feature = "impl-gemm")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Gemm;
+
+/// Every backend available on the target and enabled by its `impl-*` feature, slowest first,
+/// except [`SimdWinograd`] which may round differently.
+pub fn available<const K: usize>() -> Vec<&'static dyn ConvBackend<K>>
+where
+    [(); (K / 2 + 1) / 2 + 1]: Sized,
+{
+    #[allow(unused_mut)]
+    let mut backends: Vec<&'static dyn ConvBackend<K>> = vec![];
+    #[cfg(feature = "impl-naive")]
+    {
+        backends.push(&Naive1);
+        backends.push(&Naive2);
+    }
+    #[cfg(feature = "impl-gemm")]
+    backends.push(&Gemm);
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    {
+        #[cfg(feature = "impl-simd1")]
+        backends.push(&Simd1);
+        #[cfg(feature = "impl-simd2")]
+        backends.push(&Simd2);
+        #[cfg(feature = "impl-simd3")]
+        backends.push(&Simd3);
+        #[cfg(feature = "impl-wide")]
+        {
+            backends.push(&SimdWide::<2>);
+            backends.push(&SimdWide::<4>);
+        }
+        #[cfg(feature = "impl-simd4")]
+        backends.push(&Simd4);
+        #[cfg(feature = "impl-ring")]
+        backends.push(&SimdRing);
+        #[cfg(feature = "impl-unrolled")]
+        {
+            backends.push(&Simd3x3);
+            backends.push(&Simd5x5);
+        }
+        #[cfg(feature = "impl-int")]
+        backends.push(&SimdInt);
+    }
+    backends
+}
+
+/// Fastest enabled backend on the target for images of the given width.
+///
+/// SIMD backends compute the last columns of a row by a vector overlapping the previous one,
+/// but in serial if a row has no room for a whole vector, so wider vectors are picked only if a
+/// row has room for at least one of them. [`Naive2`] is picked otherwise, whatever the features.
+pub fn select<const K: usize>(width: usize) -> &'static dyn ConvBackend<K>
+where
+    [(); (K / 2 + 1) / 2 + 1]: Sized,
+{
+    // number of pixels not on the border
+    let inner = width.saturating_sub(K / 2 * 2);
+    #[cfg(all(
+        any(target_arch = "aarch64"),
+        target_feature = "neon",
+        feature = "impl-simd3"
+    ))]
+    if inner >= 16 {
+        return &Simd3;
+    }
+    #[cfg(all(
+        any(target_arch = "aarch64"),
+        target_feature = "neon",
+        feature = "impl-simd2"
+    ))]
+    if inner >= 8 {
+        return &Simd2;
+    }
+    let _ = inner;
+    &Naive2
+}
+
+#[cfg(feature = "prefetch")]
+static PREFETCH_DISTANCE: AtomicUsize = AtomicUsize::new(64);
+
+/// Sets how many pixels ahead of the pixels being computed [`Simd3`] prefetches each source
+/// row (64 by default), with the `prefetch` feature. 0 disables prefetching.
+///
+/// The best distance depends on the core: in-order cores need hints further ahead than
+/// out-of-order ones, which may not benefit at all. It applies to every thread.
+#[cfg(feature = "prefetch")]
+pub fn set_prefetch_distance(pixels: usize) {
+    PREFETCH_DISTANCE.store(pixels, Ordering::Relaxed);
+}
+
+/// Distance set by [`set_prefetch_distance`].
+#[cfg(feature = "prefetch")]
+pub fn prefetch_distance() -> usize {
+    PREFETCH_DISTANCE.load(Ordering::Relaxed)
+}
+
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd3"
+))]
+static ALIGN_LOADS: AtomicBool = AtomicBool::new(true);
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd3"
+))]
+static ALIGNED_ROWS: AtomicUsize = AtomicUsize::new(0);
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd3"
+))]
+static UNALIGNED_ROWS: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets whether [`Simd3`] starts its vectors at the first pixel from which the source loads are
+/// aligned to 16 bytes (true by default), which it can when the rows of the source are a multiple
+/// of 16 bytes (e.g. widths multiple of 16). The pixels before it are computed by an extra vector.
+///
+/// Aligned loads read 16-byte chunks which never straddle two cache lines, unlike some chunks of
+/// unaligned loads. Buffers of the global allocator are aligned, but those of other
+/// [`ImageAllocator`]s may not be, see [`alignment_counts`]. It applies to every thread.
+///
+/// [`ImageAllocator`]: crate::allocator::ImageAllocator
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd3"
+))]
+pub fn set_align_loads(align: bool) {
+    ALIGN_LOADS.store(align, Ordering::Relaxed);
+}
+
+/// Whether set by [`set_align_loads`].
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd3"
+))]
+pub fn align_loads() -> bool {
+    ALIGN_LOADS.load(Ordering::Relaxed)
+}
+
+/// How many output rows [`Simd3`] computed with source loads aligned to 16 bytes, and with
+/// unaligned loads, see [`alignment_counts`].
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd3"
+))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AlignmentCounts {
+    /// Rows computed from the first aligned pixel.
+    pub aligned: usize,
+    /// Rows computed from the first pixel, either with [`set_align_loads`] disabled or because
+    /// the rows of the source are not a multiple of 16 bytes.
+    pub unaligned: usize,
+}
+
+/// Rows computed by [`Simd3`] on each path since the start or [`reset_alignment_counts`], over
+/// every thread, e.g. for benchmarks to report which path they measured.
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd3"
+))]
+pub fn alignment_counts() -> AlignmentCounts {
+    AlignmentCounts {
+        aligned: ALIGNED_ROWS.load(Ordering::Relaxed),
+        unaligned: UNALIGNED_ROWS.load(Ordering::Relaxed),
+    }
+}
+
+/// Sets the counts of [`alignment_counts`] to 0.
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd3"
+))]
+pub fn reset_alignment_counts() {
+    ALIGNED_ROWS.store(0, Ordering::Relaxed);
+    UNALIGNED_ROWS.store(0, Ordering::Relaxed);
+}
+
+// First x in `xs`, leaving room for a vector of 16 pixels, from which Simd3 loads source rows at
+// addresses aligned to 16 bytes. Only if source rows are a multiple of 16 bytes apart, so that
+// it is the same for every row.
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd3"
+))]
+fn aligned_start<const K: usize>(src: &RgbImage, xs: Range<usize>) -> Option<usize> {
+    if src.width * C % 16 != 0 {
+        return None;
+    }
+    // the loads for x start from pixel x - half
+    let base = src.content().as_ptr() as usize + (xs.start - K / 2) * C;
+    // 11 * C is 1 modulo 16
+    let x = xs.start + (16 - base % 16) * 11 % 16;
+    (x + 16 <= xs.end).then(|| x)
+}
+
+// fill pixels closer than `half` to the border with 0
+pub(crate) fn clear_border(dst: &mut [u8], h: usize, w: usize, half: usize) {
+    let row = w * C;
+    dst[..half * row].fill(0);
+    dst[(h - half) * row..].fill(0);
+    for y in half..h - half {
+        dst[y * row..y * row + half * C].fill(0);
+        dst[(y + 1) * row - half * C..(y + 1) * row].fill(0);
+    }
+}
+
+// SIMD backends read and write through references to single elements, so the ranges must be
+// checked up front rather than relying on slice indexing
+fn check_rows<const K: usize>(src: &RgbImage, rows: &Range<usize>, dst: &[u8]) {
+    let half = K / 2;
+    assert!(
+        src.height >= K && src.width >= K,
+        "image is smaller than the kernel"
+    );
+    assert!(
+        half <= rows.start && rows.start <= rows.end && rows.end <= src.height - half,
+        "rows {:?} out of range",
+        rows
+    );
+    assert_eq!(
+        dst.len(),
+        rows.len() * src.width * C,
+        "invalid length of output rows"
+    );
+}
+
+// Rows and pixels of the blocks in which SIMD backends compute the output. The `K - 1 + BLOCK_ROWS`
+// source rows of a block (about 64KB for 5x5) stay in cache across kernel rows and output rows
+// even when whole rows of a 4K image would not.
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    any(
+        feature = "impl-simd1",
+        feature = "impl-simd2",
+        feature = "impl-simd3",
+        feature = "impl-simd4",
+        feature = "impl-wide"
+    )
+))]
+const BLOCK_ROWS: usize = 16;
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    any(
+        feature = "impl-simd1",
+        feature = "impl-simd2",
+        feature = "impl-simd3",
+        feature = "impl-simd4",
+        feature = "impl-wide"
+    )
+))]
+const BLOCK_PIXELS: usize = 1024;
+
+// Output vectors of 4 pixels computed at once by Simd1 and Simd2, each with its own accumulators.
+// Consecutive fused multiply-adds then do not wait for each other, as those on a single
+// accumulator would for its latency (4 cycles on most cores), which in-order cores cannot hide by
+// running other instructions. Splitting the weights of a vector among several accumulators would
+// do the same, but sum them in another order than the other backends.
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    any(feature = "impl-simd1", feature = "impl-simd2")
+))]
+const CHAINS: usize = 2;
+
+// accumulators of Simd1 and Simd2, scaled and saturated into the `4 * CHAINS` pixels from x
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    any(feature = "impl-simd1", feature = "impl-simd2")
+))]
+fn store_chains<const K: usize>(
+    kernel: &ConvKernel<K>,
+    chains: &[float32x4x3_t; CHAINS],
+    x: usize,
+    dst: &mut [u8],
+) {
+    let mut t4 = [0.; 4];
+    for (v, vt) in chains.iter().enumerate() {
+        let base_index = (x + 4 * v) * C;
+        for (c, &v) in [vt.0, vt.1, vt.2].iter().enumerate() {
+            unsafe {
+                vst1q_f32(t4.as_mut_ptr(), v);
+            }
+            for z in 0..4 {
+                let mut t = t4[z];
+                if let Some(div) = kernel.div {
+                    t /= div;
+                }
+                dst[base_index + z * C + c] = kernel.rounding.to_u8(t);
+            }
+        }
+    }
+}
+
+// Calls `simd(x, y, dst)` for every `step.0`-th x in `xs` and every `step.1`-th y in `rows`, block
+// by block. `dst` holds the output rows from y (`step.1` of them, or less at the end of `rows`) of
+// `width` pixels. The pixels left at the end of `xs` are computed again by a last vector
+// overlapping the previous one, or by `peel(x, y, dst)` for each row if `xs` is narrower than a
+// vector.
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    any(
+        feature = "impl-simd1",
+        feature = "impl-simd2",
+        feature = "impl-simd3",
+        feature = "impl-simd4",
+        feature = "impl-wide"
+    )
+))]
+fn for_each_block(
+    rows: Range<usize>,
+    dst: &mut [u8],
+    width: usize,
+    xs: Range<usize>,
+    step: (usize, usize),
+    simd: impl Fn(usize, usize, &mut [u8]),
+    peel: impl Fn(usize, usize, &mut [u8]),
+) {
+    let ((step, rows_at_once), row) = (step, width * C);
+    debug_assert_eq!(BLOCK_ROWS % rows_at_once, 0);
+    // whole vectors in a block
+    let block = BLOCK_PIXELS / step * step;
+    let simd_end = xs.end - xs.len() % step;
+    for (y0, band) in rows
+        .step_by(BLOCK_ROWS)
+        .zip(dst.chunks_mut(BLOCK_ROWS * row))
+    {
+        for x0 in (xs.start..simd_end).step_by(block) {
+            let x1 = (x0 + block).min(simd_end);
+            for (y, dst) in (y0..)
+                .step_by(rows_at_once)
+                .zip(band.chunks_mut(rows_at_once * row))
+            {
+                for x in (x0..x1).step_by(step) {
+                    simd(x, y, dst);
+                }
+                #[cfg(feature = "stats")]
+                stats::simd_pixels((x1 - x0) * dst.len() / row);
+            }
+        }
+        if simd_end == xs.end {
+            continue;
+        }
+        for (y, dst) in (y0..)
+            .step_by(rows_at_once)
+            .zip(band.chunks_mut(rows_at_once * row))
+        {
+            if xs.len() >= step {
+                simd(xs.end - step, y, dst);
+                #[cfg(feature = "stats")]
+                stats::simd_pixels(step * dst.len() / row);
+            } else {
+                for (y, dst) in (y..).zip(dst.chunks_exact_mut(row)) {
+                    for x in xs.clone() {
+                        peel(x, y, dst);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// compute a pixel in serial, `dst` holds the row of the pixel
+fn peel_loop<const K: usize>(
+    kernel: &ConvKernel<K>,
+    x: usize,
+    y: usize,
+    src: &RgbImage,
+    dst: &mut [u8],
+) {
+    #[cfg(feature = "stats")]
+    stats::peel_pixels(1);
+    let w = src.width;
+    let half = K / 2;
+    let mut rgb: [f32; 3] = [0.; C];
+    for i in 0..K {
+        for j in 0..K {
+            for (c, pix) in rgb.iter_mut().enumerate() {
+                let index = (y - half + i) * w * C + (x - half + j) * C + c;
+                *pix = fma(src.content()[index] as f32, kernel.at(i, j), *pix);
+            }
+        }
+    }
+    let base_index = x * C;
+    for c in 0..C {
+        let mut t = rgb[c];
+        if let Some(div) = kernel.div {
+            t /= div;
+        }
+        dst[base_index + c] = kernel.rounding.to_u8(t);
+    }
+}
+
+impl<const K: usize> ConvBackend<K> for Naive1 {
+    fn name(&self) -> &'static str {
+        "naive1"
+    }
+
+    fn convolve_rows(
+        &self,
+        kernel: &ConvKernel<K>,
+        src: &RgbImage,
+        rows: Range<usize>,
+        dst: &mut [u8],
+    ) {
+        check_rows::<K>(src, &rows, dst);
+        let w = src.width;
+        let half = K / 2;
+        let xend = w - half;
+
+        for (y, dst) in rows.zip(dst.chunks_exact_mut(w * C)) {
+            #[cfg(feature = "stats")]
+            stats::peel_pixels(xend - half);
+            for x in half..xend {
+                for c in 0..C {
+                    // RGB
+                    let mut t: f32 = 0.;
+                    for i in 0..K {
+                        for j in 0..K {
+                            let index = (y - half + i) * w * C + (x - half + j) * C + c;
+                            t = fma(src.content()[index] as f32, kernel.at(i, j), t);
+                        }
+                    }
+                    if let Some(div) = kernel.div {
+                        t /= div;
+                    }
+                    let index = x * C + c;
+                    dst[index] = kernel.rounding.to_u8(t);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "impl-gemm")]
+impl<const K: usize> ConvBackend<K> for Gemm {
+    fn name(&self) -> &'static str {
+        "gemm"
+    }
+
+    fn convolve_rows(
+        &self,
+        kernel: &ConvKernel<K>,
+        src: &RgbImage,
+        rows: Range<usize>,
+        dst: &mut [u8],
+    ) {
+        check_rows::<K>(src, &rows, dst);
+        crate::gemm::convolve_rows(&[kernel], src, rows, &mut [dst]);
+    }
+}
+
+impl<const K: usize> ConvBackend<K> for Naive2 {
+    fn name(&self) -> &'static str {
+        "naive2"
+    }
+
+    fn convolve_rows(
+        &self,
+        kernel: &ConvKernel<K>,
+        src: &RgbImage,
+        rows: Range<usize>,
+        dst: &mut [u8],
+    ) {
+        check_rows::<K>(src, &rows, dst);
+        let w = src.width;
+        let half = K / 2;
+        let xend = w - half;
+
+        for (y, dst) in rows.zip(dst.chunks_exact_mut(w * C)) {
+            for x in half..xend {
+                peel_loop(kernel, x, y, src, dst);
+            }
+        }
+    }
+}
+
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd1"
+))]
+impl<const K: usize> ConvBackend<K> for Simd1 {
+    fn name(&self) -> &'static str {
+        "simd1"
+    }
+
+    fn convolve_rows(
+        &self,
+        kernel: &ConvKernel<K>,
+        src: &RgbImage,
+        rows: Range<usize>,
+        dst: &mut [u8],
+    ) {
+        check_rows::<K>(src, &rows, dst);
+        let w = src.width;
+        let half = K / 2;
+        let xend = w - half;
+        let weights = kernel.splats();
+
+        // calc 4 cells with simd in parallel, in CHAINS vectors at once
+        // x coordinate of center pixel will be half+0~3, +4~7, ... half+(w-half*2 - (w-half*2)%8 -8 + 0~7)
+        // remnants will be processed by a last vector overlapping the previous one
+
+        let simd_loop = |x: usize, y: usize, dst: &mut [u8]| {
+            #[cfg(feature = "stats")]
+            stats::loads(CHAINS * K * K * C);
+            let mut chains = unsafe { crate::util::init_multiple_float32x4x3::<CHAINS>(0.) };
+            for (i, weights) in weights.iter().enumerate() {
+                for (j, &kern) in weights.iter().enumerate() {
+                    for (v, vt) in chains.iter_mut().enumerate() {
+                        let base_index = (y - half + i) * w * C + (x + 4 * v - half + j) * C;
+                        let mut s4 = [0.; 4];
+                        let mut prepare = |c: usize| -> float32x4_t {
+                            // prepare simd register
+                            for (z, s) in s4.iter_mut().enumerate() {
+                                // +z in second axis and +c in third axis
+                                *s = src.content()[base_index + z * C + c] as f32;
+                            }
+                            unsafe { vld1q_f32(s4.as_ptr()) }
+                        };
+                        let vs = float32x4x3_t(prepare(0), prepare(1), prepare(2));
+
+                        unsafe {
+                            vt.0 = vfmaq_f32(vt.0, vs.0, kern);
+                            vt.1 = vfmaq_f32(vt.1, vs.1, kern);
+                            vt.2 = vfmaq_f32(vt.2, vs.2, kern);
+                        }
+                    }
+                }
+            }
+            store_chains(kernel, &chains, x, dst);
+        };
+
+        // main execution
+        let peel = |x, y, dst: &mut [u8]| peel_loop(kernel, x, y, src, dst);
+        for_each_block(rows, dst, w, half..xend, (4 * CHAINS, 1), simd_loop, peel);
+    }
+}
+
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd2"
+))]
+impl<const K: usize> ConvBackend<K> for Simd2
+where
+    [(); (K / 2 + 1) / 2 + 1]: Sized,
+{
+    fn name(&self) -> &'static str {
+        "simd2"
+    }
+
+    fn convolve_rows(
+        &self,
+        kernel: &ConvKernel<K>,
+        src: &RgbImage,
+        rows: Range<usize>,
+        dst: &mut [u8],
+    ) {
+        check_rows::<K>(src, &rows, dst);
+        let w = src.width;
+        let half = K / 2;
+        let xend = w - half;
+        let weights = kernel.splats();
+
+        // calc 4 cells with simd in parallel, in CHAINS vectors at once
+        // x coordinate of center pixel will be half+0~3, +4~7, ... half+(w-half*2 - (w-half*2)%8 -8 + 0~7)
+        // remnants will be processed by a last vector overlapping the previous one
+
+        let simd_loop = |x: usize, y: usize, dst: &mut [u8]| {
+            #[cfg(feature = "stats")]
+            stats::loads(CHAINS * K * ((K / 2 + 1) / 2 + 1) * C);
+            let mut chains = unsafe { crate::util::init_multiple_float32x4x3::<CHAINS>(0.) };
+            for (i, weights) in weights.iter().enumerate() {
+                // We process 2*half+4 elements(x3, RGB channel) in a row here for each vector
+                // then number of simd registers simd register is ceil(half/2 + 1).
+                let mut shared =
+                    unsafe { [[mem::zeroed::<float32x4x3_t>(); (K / 2 + 1) / 2 + 1]; CHAINS] };
+                let len = shared[0].len();
+                let base_index = (y - half + i) * w * C + (x - half) * C;
+                let mut s4 = [0.; 4];
+
+                let mut load = |k: usize, c: usize, ft: usize| -> float32x4_t {
+                    // ft := four or two
+                    debug_assert!(ft == 2 || ft == 4);
+                    let base_index = base_index + k * 4 * C;
+                    for (z, s) in s4.iter_mut().enumerate().take(ft) {
+                        // +z in second axis and +c in third axis
+                        *s = src.content()[base_index + z * C + c] as f32;
+                    }
+                    unsafe { vld1q_f32(s4.as_ptr()) }
+                };
+
+                // fill shared[v][k], the registers of vector v starting 4v pixels later
+                let mut make = |v: usize, k: usize, ft: usize| {
+                    shared[v][k] =
+                        float32x4x3_t(load(v + k, 0, ft), load(v + k, 1, ft), load(v + k, 2, ft))
+                };
+
+                let ft = if half % 2 == 1 { 2 } else { 4 };
+                for v in 0..CHAINS {
+                    for k in 0..len - 1 {
+                        make(v, k, 4)
+                    }
+                    // have to care about 2 elements at the tail
+                    make(v, len - 1, ft);
+                }
+
+                for (j, &kern) in weights.iter().enumerate() {
+                    let regi = j / 4;
+                    let offset = j % 4;
+                    let vext = match offset {
+                        0 => vextq_f32::<0>,
+                        1 => vextq_f32::<1>,
+                        2 => vextq_f32::<2>,
+                        3 => vextq_f32::<3>,
+                        _ => unreachable!(),
+                    };
+
+                    for (vt, shared) in chains.iter_mut().zip(shared.iter()) {
+                        let vs = if offset != 0 {
+                            // here guaranteed that regi+1 is valid for index.
+                            unsafe {
+                                float32x4x3_t(
+                                    vext(shared[regi].0, shared[regi + 1].0),
+                                    vext(shared[regi].1, shared[regi + 1].1),
+                                    vext(shared[regi].2, shared[regi + 1].2),
+                                )
+                            }
+                        } else {
+                            shared[regi]
+                        };
+
+                        unsafe {
+                            vt.0 = vfmaq_f32(vt.0, vs.0, kern);
+                            vt.1 = vfmaq_f32(vt.1, vs.1, kern);
+                            vt.2 = vfmaq_f32(vt.2, vs.2, kern);
+                        }
+                    }
+                }
+            }
+            store_chains(kernel, &chains, x, dst);
+        };
+
+        // main execution
+        let peel = |x, y, dst: &mut [u8]| peel_loop(kernel, x, y, src, dst);
+        for_each_block(rows, dst, w, half..xend, (4 * CHAINS, 1), simd_loop, peel);
+    }
+}
+
+// Helper macro to pack float32x4_t into uint8x16_t with rounding $r
+// Ugly hack: $c should be tuple indice.
+// $v is expected to be
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    any(feature = "impl-simd3", feature = "impl-wide")
+))]
+macro_rules! vec4_cvt {
+    ($v:ident, $c:tt, $r:expr) => {{
+        crate::util::f32x4x4_to_u8x16([$v[0].$c, $v[1].$c, $v[2].$c, $v[3].$c], $r)
+    }};
+}
+
+// Fused multiply-adds of Simd3 and SimdWide for the source vector `b` (pixels 4b to 4b + 3 from
+// x - half) by the broadcast `weights` of a kernel row, `pair` holding it and the vector b + 1.
+// Its shift by `offset` pixels is the source of weight j of the output vector z for every z with
+// 4z + j = 4b + offset, so it is computed once for all of them, and every accumulator still takes
+// the weights in the order of j.
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    any(feature = "impl-simd3", feature = "impl-wide")
+))]
+#[inline(always)]
+fn simd3_taps<const K: usize, const B: usize>(
+    weights: &[float32x4_t; K],
+    b: usize,
+    pair: (float32x4x3_t, float32x4x3_t),
+    vts: &mut [[float32x4x3_t; 4]; B],
+) {
+    for offset in 0..4 {
+        let s = b * 4 + offset;
+        // past the last weight of the last output vector
+        if s >= K + 16 * B - 4 {
+            break;
+        }
+        let vs = if offset != 0 {
+            let vext = match offset {
+                1 => vextq_f32::<1>,
+                2 => vextq_f32::<2>,
+                3 => vextq_f32::<3>,
+                _ => unreachable!(),
+            };
+            unsafe {
+                float32x4x3_t(
+                    vext(pair.0 .0, pair.1 .0),
+                    vext(pair.0 .1, pair.1 .1),
+                    vext(pair.0 .2, pair.1 .2),
+                )
+            }
+        } else {
+            pair.0
+        };
+        for (z, vt) in vts.iter_mut().flatten().enumerate() {
+            if s < z * 4 || s - z * 4 >= K {
+                continue;
+            }
+            let kern = weights[s - z * 4];
+            unsafe {
+                vt.0 = vfmaq_f32(vt.0, vs.0, kern);
+                vt.1 = vfmaq_f32(vt.1, vs.1, kern);
+                vt.2 = vfmaq_f32(vt.2, vs.2, kern);
+            }
+        }
+    }
+}
+
+// Accumulates a kernel row of broadcast `weights` into `vts`, the 16 * B pixels from x of an output
+// row, from the source pixels from x - half of the row at `base_index`.
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    any(feature = "impl-simd3", feature = "impl-wide")
+))]
+#[inline(always)]
+fn simd3_row<const K: usize, const B: usize>(
+    weights: &[float32x4_t; K],
+    src: &RgbImage,
+    base_index: usize,
+    vts: &mut [[float32x4x3_t; 4]; B],
+) {
+    #[rustfmt::skip]
+    let cvt16 = |z: usize, s: uint8x16_t| -> float32x4_t {
+        unsafe {
+            match z {
+                0 => vcvtq_f32_u32(vmovl_u16(vget_low_u16(vmovl_u8(vget_low_u8(s))))),  // 0..4 th uint8 to float32
+                1 => vcvtq_f32_u32(vmovl_high_u16(        vmovl_u8(vget_low_u8(s)))),   // 4..8 th uint8 to float32
+                2 => vcvtq_f32_u32(vmovl_u16(vget_low_u16(vmovl_high_u8(       s)))),   // 9..12 th uint8 to float32
+                3 => vcvtq_f32_u32(vmovl_high_u16(        vmovl_high_u8(       s))),    // 12..15 th uint8 to float32
+                _ => unreachable!(),
+            }
+        }
+    };
+    #[rustfmt::skip]
+    let cvt8 = |z: usize, s: uint8x8_t| -> float32x4_t {
+        unsafe {
+            match z {
+                0 => vcvtq_f32_u32(vmovl_u16(vget_low_u16(vmovl_u8(s)))),  // 0..4 th uint8 to float32
+                1 => vcvtq_f32_u32(vmovl_high_u16(        vmovl_u8(s))),   // 4..8 th uint8 to float32
+                _ => unreachable!(),
+            }
+        }
+    };
+
+    // The source pixels are fed by vectors of 4, each converted when the previous one is
+    // multiplied with it, rather than all converted first: for Simd3, only the accumulators (12
+    // registers), the 2 vectors (6), a shifted vector (3), a weight and the bytes of the last load
+    // (3) are live, 25 of the 32 registers for any K. The vectors of the whole row took
+    // (K + 1) / 4 + 4 more (18 for 7x7, 21 for 11x11), so that they spilled to the stack from 7x7.
+    let mut prev = unsafe { mem::zeroed::<float32x4x3_t>() };
+    let mut fed = 0;
+    let mut push = |v: float32x4x3_t| {
+        if fed > 0 {
+            simd3_taps(weights, fed - 1, (prev, v), vts);
+        }
+        prev = v;
+        fed += 1;
+    };
+
+    let mut base = 0;
+    let mut remains = K - 1 + 16 * B; // # of loading elements
+    while remains >= 2 {
+        let base_index = base_index + base * 4 * C;
+        match remains {
+            _r @ 16.. => {
+                // deinterleaved loading
+                let sc = unsafe { vld3q_u8(vec_ref(src.content(), base_index, 16 * C)) };
+                #[cfg(feature = "stats")]
+                stats::loads(1);
+                for z in 0..4 {
+                    push(float32x4x3_t(
+                        cvt16(z, sc.0),
+                        cvt16(z, sc.1),
+                        cvt16(z, sc.2),
+                    ));
+                }
+                remains -= 16;
+                base += 4;
+            }
+            _r @ 8.. => {
+                let sc = unsafe { vld3_u8(vec_ref(src.content(), base_index, 8 * C)) };
+                #[cfg(feature = "stats")]
+                stats::loads(1);
+                for z in 0..2 {
+                    push(float32x4x3_t(cvt8(z, sc.0), cvt8(z, sc.1), cvt8(z, sc.2)));
+                }
+                remains -= 8;
+                base += 2;
+            }
+            _ => {
+                // 4 or 2 pixels, the lanes past them are never multiplied
+                let ft = remains.min(4);
+                let mut s4 = [0.; 4];
+                let mut load = |c: usize| -> float32x4_t {
+                    for (z, s) in s4.iter_mut().enumerate().take(ft) {
+                        *s = src.content()[base_index + z * C + c] as f32;
+                    }
+                    unsafe { vld1q_f32(s4.as_ptr()) }
+                };
+                push(float32x4x3_t(load(0), load(1), load(2)));
+                #[cfg(feature = "stats")]
+                stats::loads(C);
+                remains -= ft;
+                base += 1;
+            }
+        }
+    }
+    // the last vector, whose shifts would only reach past the last weight
+    simd3_taps(weights, fed - 1, (prev, prev), vts);
+}
+
+// the sums `vts` of the 16 * B pixels from x, scaled and saturated into `dst`
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    any(feature = "impl-simd3", feature = "impl-wide")
+))]
+#[inline(always)]
+fn simd3_store<const K: usize, const B: usize>(
+    kernel: &ConvKernel<K>,
+    vts: &mut [[float32x4x3_t; 4]; B],
+    x: usize,
+    dst: &mut [u8],
+) {
+    if let Some(div) = kernel.div.map(simd::Divisor::new) {
+        for vt in vts.iter_mut().flatten() {
+            vt.0 = div.divide(vt.0);
+            vt.1 = div.divide(vt.1);
+            vt.2 = div.divide(vt.2);
+        }
+    }
+    for (g, vts) in vts.iter().enumerate() {
+        let base_index = (x + 16 * g) * C;
+        unsafe {
+            vst3q_u8(
+                vec_mut(dst, base_index, 16 * C),
+                uint8x16x3_t(
+                    vec4_cvt!(vts, 0, kernel.rounding),
+                    vec4_cvt!(vts, 1, kernel.rounding),
+                    vec4_cvt!(vts, 2, kernel.rounding),
+                ),
+            );
+        }
+    }
+}
+
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd3"
+))]
+impl<const K: usize> ConvBackend<K> for Simd3 {
+    fn name(&self) -> &'static str {
+        "simd3"
+    }
+
+    fn convolve_rows(
+        &self,
+        kernel: &ConvKernel<K>,
+        src: &RgbImage,
+        rows: Range<usize>,
+        dst: &mut [u8],
+    ) {
+        check_rows::<K>(src, &rows, dst);
+        let w = src.width;
+        let half = K / 2;
+        let xend = w - half;
+        let weights = kernel.splats();
+
+        // read/write 16 elements in parallel
+        #[cfg(feature = "prefetch")]
+        let distance = prefetch_distance() * C;
+
+        let simd_loop = |x: usize, y: usize, dst: &mut [u8]| {
+            let mut vts = [unsafe { crate::util::init_multiple_float32x4x3::<4>(0.) }];
+            for (i, weights) in weights.iter().enumerate() {
+                let base_index = (y - half + i) * w * C + (x - half) * C;
+                // hints do not fault, even past the end of the image
+                #[cfg(feature = "prefetch")]
+                if distance > 0 {
+                    let p = src.content().as_ptr().wrapping_add(base_index + distance);
+                    unsafe { _prefetch::<_PREFETCH_READ, _PREFETCH_LOCALITY3>(p as *const i8) };
+                }
+                simd3_row(weights, src, base_index, &mut vts);
+            }
+            simd3_store(kernel, &mut vts, x, dst);
+        };
+
+        // main execution
+        let peel = |x, y, dst: &mut [u8]| peel_loop(kernel, x, y, src, dst);
+        let start = if align_loads() {
+            aligned_start::<K>(src, half..xend)
+        } else {
+            None
+        };
+        match start {
+            Some(start) => {
+                ALIGNED_ROWS.fetch_add(rows.len(), Ordering::Relaxed);
+                // the pixels before `start` by a vector overlapping the first aligned one
+                if start > half {
+                    for (y, dst) in rows.clone().zip(dst.chunks_exact_mut(w * C)) {
+                        simd_loop(half, y, dst);
+                    }
+                    #[cfg(feature = "stats")]
+                    stats::simd_pixels((start - half) * rows.len());
+                }
+                for_each_block(rows, dst, w, start..xend, (16, 1), simd_loop, peel);
+            }
+            None => {
+                UNALIGNED_ROWS.fetch_add(rows.len(), Ordering::Relaxed);
+                for_each_block(rows, dst, w, half..xend, (16, 1), simd_loop, peel);
+            }
+        }
+    }
+}
+
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-wide"
+))]
+impl<const K: usize, const B: usize> ConvBackend<K> for SimdWide<B>
+where
+    Self: WideBlocks,
+{
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn convolve_rows(
+        &self,
+        kernel: &ConvKernel<K>,
+        src: &RgbImage,
+        rows: Range<usize>,
+        dst: &mut [u8],
+    ) {
+        check_rows::<K>(src, &rows, dst);
+        let w = src.width;
+        let half = K / 2;
+        let xend = w - half;
+        let weights = kernel.splats();
+
+        let simd_loop = |x: usize, y: usize, dst: &mut [u8]| {
+            let mut vts = [unsafe { crate::util::init_multiple_float32x4x3::<4>(0.) }; B];
+            for (i, weights) in weights.iter().enumerate() {
+                let base_index = (y - half + i) * w * C + (x - half) * C;
+                simd3_row(weights, src, base_index, &mut vts);
+            }
+            simd3_store(kernel, &mut vts, x, dst);
+        };
+
+        let peel = |x, y, dst: &mut [u8]| peel_loop(kernel, x, y, src, dst);
+        for_each_block(rows, dst, w, half..xend, (16 * B, 1), simd_loop, peel);
+    }
+}
+
+// output rows computed at once by Simd4
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd4"
+))]
+const SIMD4_ROWS: usize = 4;
+
+// the `8 + K - 1` pixels from `src[index..]` which the 8 pixels of Simd4 take in a source row, as
+// vectors of 4 pixels followed by 0: `[g / 2][c][g % 2]` holds channel c of the pixels 4g to
+// 4g + 3. They are loaded by 8 from the first one, then by a last load ending at the last one,
+// shifted into place by `vext` if it does not start at a multiple of 4 pixels.
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd4"
+))]
+#[inline(always)]
+fn simd4_row<const K: usize>(src: &[u8], index: usize) -> [Rgb8; K] {
+    let half = K / 2;
+    let mut row: [Rgb8; K] = [[[simd::splat(0.); 2]; C]; K];
+    for (m, row) in row.iter_mut().enumerate().take(half / 4 + 1) {
+        *row = simd::load_rgb8(src, index + 8 * m * C);
+    }
+    if half % 4 != 0 {
+        let last = simd::load_rgb8(src, index + 2 * half * C);
+        for g in 2 * (half / 4 + 1)..=(7 + 2 * half) / 4 {
+            // pixel 4g is pixel p of the last load
+            let p = 4 * g - 2 * half;
+            for c in 0..C {
+                let at = |i: usize| last[c].get(i).copied().unwrap_or(simd::splat(0.));
+                row[g / 2][c][g % 2] = simd::ext(at(p / 4), at(p / 4 + 1), p % 4);
+            }
+        }
+    }
+    row
+}
+
+// computes the 8 pixels from x of the R output rows from y with Simd4, `dst` holds the R rows
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd4"
+))]
+#[inline(always)]
+fn simd4_vector<const K: usize, const R: usize>(
+    weights: &[[float32x4_t; K]],
+    vdiv: Option<simd::Divisor>,
+    rounding: Rounding,
+    src: &RgbImage,
+    x: usize,
+    y: usize,
+    dst: &mut [u8],
+) {
+    let half = K / 2;
+    #[cfg(feature = "stats")]
+    stats::loads((R + K - 1) * (half / 4 + 1 + (half % 4 != 0) as usize));
+    let row = src.width * C;
+    let mut acc: [Rgb8; R] = [[[simd::splat(0.); 2]; C]; R];
+    // each source row is loaded once for all the kernel columns and output rows
+    for r in 0..R + K - 1 {
+        let pixels = simd4_row::<K>(src.content(), (y - half + r) * row + (x - half) * C);
+        let vector = |g: usize, c: usize| pixels[g / 2][c][g % 2];
+        // the 8 pixels from x - half + j for kernel column j
+        let mut taps: [Rgb8; K] = [[[simd::splat(0.); 2]; C]; K];
+        for (j, tap) in taps.iter_mut().enumerate() {
+            let (g, n) = (j / 4, j % 4);
+            *tap = [0, 1, 2]
+                .map(|c| [0, 1].map(|z| simd::ext(vector(g + z, c), vector(g + z + 1, c), n)));
+        }
+        for (j, s) in taps.iter().enumerate() {
+            // output row o takes source row r with the kernel row r - o
+            for (o, acc) in acc.iter_mut().enumerate() {
+                if o <= r && r - o < K {
+                    let weight = weights[r - o][j];
+                    for (acc, s) in acc.iter_mut().zip(s.iter()) {
+                        acc[0] = simd::fma(acc[0], s[0], weight);
+                        acc[1] = simd::fma(acc[1], s[1], weight);
+                    }
+                }
+            }
+        }
+    }
+    for (o, acc) in acc.iter_mut().enumerate() {
+        simd::divide(acc, vdiv);
+        simd::store_rgb8(dst, o * row + x * C, acc, rounding);
+    }
+}
+
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd4"
+))]
+impl<const K: usize> ConvBackend<K> for Simd4 {
+    fn name(&self) -> &'static str {
+        "simd4"
+    }
+
+    fn convolve_rows(
+        &self,
+        kernel: &ConvKernel<K>,
+        src: &RgbImage,
+        rows: Range<usize>,
+        dst: &mut [u8],
+    ) {
+        check_rows::<K>(src, &rows, dst);
+        let w = src.width;
+        let half = K / 2;
+        let xend = w - half;
+        let row = w * C;
+
+        let weights = kernel.splats();
+        let vdiv = kernel.div.map(simd::Divisor::new);
+        let rounding = kernel.rounding;
+
+        // the rows left at the end of `rows` are computed one by one
+        let simd_loop = |x: usize, y: usize, dst: &mut [u8]| {
+            if dst.len() == SIMD4_ROWS * row {
+                simd4_vector::<K, SIMD4_ROWS>(weights, vdiv, rounding, src, x, y, dst);
+            } else {
+                for (y, dst) in (y..).zip(dst.chunks_exact_mut(row)) {
+                    simd4_vector::<K, 1>(weights, vdiv, rounding, src, x, y, dst);
+                }
+            }
+        };
+
+        // main execution
+        let peel = |x, y, dst: &mut [u8]| peel_loop(kernel, x, y, src, dst);
+        for_each_block(rows, dst, w, half..xend, (8, SIMD4_ROWS), simd_loop, peel);
+    }
+}
+
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-ring"
+))]
+impl<const K: usize> ConvBackend<K> for SimdRing {
+    fn name(&self) -> &'static str {
+        "simd_ring"
+    }
+
+    fn convolve_rows(
+        &self,
+        kernel: &ConvKernel<K>,
+        src: &RgbImage,
+        rows: Range<usize>,
+        dst: &mut [u8],
+    ) {
+        check_rows::<K>(src, &rows, dst);
+        let w = src.width;
+        let half = K / 2;
+        let xend = w - half;
+        let row = w * C;
+        if xend - half < 16 {
+            for (y, dst) in rows.zip(dst.chunks_exact_mut(row)) {
+                for x in half..xend {
+                    peel_loop(kernel, x, y, src, dst);
+                }
+            }
+            return;
+        }
+        let simd_end = xend - (xend - half) % 16;
+
+        let weights = kernel.splats();
+        let vdiv = kernel.div.map(simd::Divisor::new);
+
+        // converts source row y into a plane of `w` pixels per channel
+        let convert = |y: usize, out: &mut [f32]| {
+            let line = &src.content()[y * row..][..row];
+            let vec_end = w - w % 16;
+            #[cfg(feature = "stats")]
+            stats::loads(vec_end / 16);
+            for x in (0..vec_end).step_by(16) {
+                for (c, s) in simd::load_rgb16(line, x * C).iter().enumerate() {
+                    for (z, &v) in s.iter().enumerate() {
+                        simd::store_f32(out, c * w + x + z * 4, v);
+                    }
+                }
+            }
+            for x in vec_end..w {
+                for c in 0..C {
+                    out[c * w + x] = line[x * C + c] as f32;
+                }
+            }
+        };
+
+        // computes the 16 pixels from x, with the kernel row i on `ring[(top + i) % K]`
+        let vector = |x: usize, ring: &[Vec<f32>], top: usize, dst: &mut [u8]| {
+            #[cfg(feature = "stats")]
+            {
+                stats::simd_pixels(16);
+                stats::loads(K * K * C * 4);
+            }
+            let mut acc = [[simd::splat(0.); 4]; C];
+            for (i, weights) in weights.iter().enumerate() {
+                let line = &ring[(top + i) % K];
+                for (j, &weight) in weights.iter().enumerate() {
+                    for (c, acc) in acc.iter_mut().enumerate() {
+                        let s = &line[c * w + x - half + j..][..16];
+                        for (z, acc) in acc.iter_mut().enumerate() {
+                            *acc = simd::fma(*acc, simd::load_f32(s, z * 4), weight);
+                        }
+                    }
+                }
+            }
+            simd::divide(&mut acc, vdiv);
+            simd::store_rgb16(dst, x * C, &acc, kernel.rounding);
+        };
+
+        // source row `start - half + k` is on `ring[k % K]`
+        let start = rows.start;
+        let mut ring = vec![vec![0.; C * w]; K];
+        for (k, out) in ring.iter_mut().enumerate().take(K - 1) {
+            convert(start - half + k, out);
+        }
+        for (y, dst) in rows.zip(dst.chunks_exact_mut(row)) {
+            // the row entering the window replaces the one which left it
+            let top = (y - start) % K;
+            convert(y + half, &mut ring[(top + K - 1) % K]);
+            for x in (half..simd_end).step_by(16) {
+                vector(x, &ring, top, dst);
+            }
+            // the last pixels by a vector overlapping the previous one
+            if simd_end < xend {
+                vector(xend - 16, &ring, top, dst);
+            }
+        }
+    }
+}
+
+// Convolution for small kernels (up to 9x9) keeping source rows in registers across x: each
+// iteration converts only the next 16 pixels of each source row, and gets the pixels shifted by
+// kernel columns with `vext` among the previous, current and next vectors. Broadcast weights are
+// hoisted out of every loop.
+//
+// Accumulates in the same order as Simd3, so that the results are the same.
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-unrolled"
+))]
+fn convolve_rows_unrolled<const K: usize>(
+    kernel: &ConvKernel<K>,
+    src: &RgbImage,
+    rows: Range<usize>,
+    dst: &mut [u8],
+) {
+    debug_assert!(K <= 9, "shifts must stay within adjacent vectors");
+    check_rows::<K>(src, &rows, dst);
+    let w = src.width;
+    let half = K / 2;
+    let row = w * C;
+    let xend = w - half;
+    let simd_end = w - half - (w - 2 * half) % 16;
+
+    let weights = kernel.splats();
+    let vdiv = kernel.div.map(simd::Divisor::new);
+    let content = src.content();
+
+    // 16 pixels from x in row y, 4 vectors per channel
+    let load16 = |y: usize, x: usize| -> Rgb16 {
+        #[cfg(feature = "stats")]
+        stats::loads(1);
+        simd::load_rgb16(content, y * row + x * C)
+    };
+    // 4 pixels from x in row y, with 0 for those out of the row
+    let load4 = |y: usize, x: isize| -> [float32x4_t; C] {
+        #[cfg(feature = "stats")]
+        stats::loads(C);
+        let mut out = [simd::splat(0.); C];
+        for (c, out) in out.iter_mut().enumerate() {
+            let mut s4 = [0.; 4];
+            for (z, s) in s4.iter_mut().enumerate() {
+                let px = x + z as isize;
+                if 0 <= px && (px as usize) < w {
+                    *s = content[y * row + px as usize * C + c] as f32;
+                }
+            }
+            *out = simd::load_f32(&s4, 0);
+        }
+        out
+    };
+    // vectors of the 4 pixels before x and the 16 pixels from x in the source rows of output row y
+    let window = |y: usize, x: usize| {
+        let mut prev = [[simd::splat(0.); C]; K];
+        let mut cur = [[[simd::splat(0.); 4]; C]; K];
+        for i in 0..K {
+            prev[i] = load4(y - half + i, x as isize - 4);
+            cur[i] = load16(y - half + i, x);
+        }
+        (prev, cur)
+    };
+    // vectors of the pixels from x in the source rows of output row y, only the first 4 of them
+    // unless `whole`
+    let next = |y: usize, x: usize, whole: bool| {
+        let mut next = [[[simd::splat(0.); 4]; C]; K];
+        for (i, next) in next.iter_mut().enumerate() {
+            *next = if whole {
+                load16(y - half + i, x)
+            } else {
+                load4(y - half + i, x as isize).map(|v| [v; 4])
+            };
+        }
+        next
+    };
+    // computes the 16 pixels from x
+    let vector = |x: usize,
+                  prev: &[[float32x4_t; C]; K],
+                  cur: &[Rgb16; K],
+                  next: &[Rgb16; K],
+                  dst: &mut [u8]| {
+        #[cfg(feature = "stats")]
+        stats::simd_pixels(16);
+        // the 12 accumulators in turn for each weight, so that consecutive fused multiply-adds
+        // are independent
+        let mut acc = [[simd::splat(0.); 4]; C];
+        for (i, weights) in weights.iter().enumerate() {
+            for (j, &weight) in weights.iter().enumerate() {
+                let d = j as isize - half as isize;
+                for (c, acc) in acc.iter_mut().enumerate() {
+                    for (z, acc) in acc.iter_mut().enumerate() {
+                        let s = simd::shifted(prev[i][c], &cur[i][c], next[i][c][0], z, d);
+                        *acc = simd::fma(*acc, s, weight);
+                    }
+                }
+            }
+        }
+        simd::divide(&mut acc, vdiv);
+        simd::store_rgb16(dst, x * C, &acc, kernel.rounding);
+    };
+
+    for (y, dst) in rows.zip(dst.chunks_exact_mut(row)) {
+        if xend - half < 16 {
+            for x in half..xend {
+                peel_loop(kernel, x, y, src, dst);
+            }
+            continue;
+        }
+
+        let (mut prev, mut cur) = window(y, half);
+        for x in (half..simd_end).step_by(16) {
+            let next = next(y, x + 16, x + 16 < simd_end);
+            vector(x, &prev, &cur, &next, dst);
+            for i in 0..K {
+                for c in 0..C {
+                    prev[i][c] = cur[i][c][3];
+                }
+            }
+            cur = next;
+        }
+        // the last pixels by a vector overlapping the previous one
+        if simd_end < xend {
+            let x = xend - 16;
+            let (prev, cur) = window(y, x);
+            vector(x, &prev, &cur, &next(y, xend, false), dst);
+        }
+    }
+}
+
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-unrolled"
+))]
+impl<const K: usize> ConvBackend<K> for Simd3x3 {
+    fn name(&self) -> &'static str {
+        "simd3x3"
+    }
+
+    fn convolve_rows(
+        &self,
+        kernel: &ConvKernel<K>,
+        src: &RgbImage,
+        rows: Range<usize>,
+        dst: &mut [u8],
+    ) {
+        match K {
+            3 => convolve_rows_unrolled(kernel, src, rows, dst),
+            _ => Simd3.convolve_rows(kernel, src, rows, dst),
+        }
+    }
+}
+
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-unrolled"
+))]
+impl<const K: usize> ConvBackend<K> for Simd5x5 {
+    fn name(&self) -> &'static str {
+        "simd5x5"
+    }
+
+    fn convolve_rows(
+        &self,
+        kernel: &ConvKernel<K>,
+        src: &RgbImage,
+        rows: Range<usize>,
+        dst: &mut [u8],
+    ) {
+        match K {
+            5 => convolve_rows_unrolled(kernel, src, rows, dst),
+            _ => Simd3.convolve_rows(kernel, src, rows, dst),
+        }
+    }
+}
+
+// weights of a kernel for SimdInt, if they are integers in the range of u8 and any sum of
+// weighted pixels is exact in f32, so that the result is the same as other backends
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-int"
+))]
+fn int_weights<const K: usize>(kernel: &ConvKernel<K>) -> Option<([[u8; K]; K], u32)> {
+    let mut weights = [[0u8; K]; K];
+    let mut sum = 0u32;
+    for (i, row) in weights.iter_mut().enumerate() {
+        for (j, w) in row.iter_mut().enumerate() {
+            let weight = kernel.at(i, j);
+            // without `f32::fract`, which needs std
+            if !(0. ..=u8::MAX as f32).contains(&weight) || weight as u8 as f32 != weight {
+                return None;
+            }
+            *w = weight as u8;
+            sum += *w as u32;
+        }
+    }
+    (sum * u8::MAX as u32 <= 1 << 24).then(|| (weights, sum))
+}
+
+// how accumulated sums are brought back to the range of pixels
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-int"
+))]
+#[derive(Clone, Copy)]
+enum Scale {
+    None,
+    // division by a power of 2
+    Shift(i32),
+    // division by the sum, rounded like the other backends
+    Div(simd::Divisor),
+}
+
+// saturate 16 sums into u8 lanes after scaling with `rounding`
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-int"
+))]
+#[inline]
+unsafe fn narrow_u32x4x4(mut v: [uint32x4_t; 4], scale: Scale, rounding: Rounding) -> uint8x16_t {
+    match scale {
+        Scale::None => {}
+        Scale::Shift(shift) => {
+            // shifts with a negative count are right shifts, and rounding shifts round half up
+            let count = vdupq_n_s32(-shift);
+            for v in v.iter_mut() {
+                *v = match rounding {
+                    Rounding::Truncate => vshlq_u32(*v, count),
+                    Rounding::Nearest => vrshlq_u32(*v, count),
+                };
+            }
+        }
+        Scale::Div(div) => {
+            let f = v.map(|v| div.divide(vcvtq_f32_u32(v)));
+            return crate::util::f32x4x4_to_u8x16(f, rounding);
+        }
+    }
+    let lo = vqmovn_high_u32(vqmovn_u32(v[0]), v[1]);
+    let hi = vqmovn_high_u32(vqmovn_u32(v[2]), v[3]);
+    vqmovn_high_u16(vqmovn_u16(lo), hi)
+}
+
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-int"
+))]
+impl<const K: usize> ConvBackend<K> for SimdInt {
+    fn name(&self) -> &'static str {
+        "simd_int"
+    }
+
+    fn convolve_rows(
+        &self,
+        kernel: &ConvKernel<K>,
+        src: &RgbImage,
+        rows: Range<usize>,
+        dst: &mut [u8],
+    ) {
+        let (weights, sum) = match int_weights(kernel) {
+            Some(weights) => weights,
+            None => return Simd3.convolve_rows(kernel, src, rows, dst),
+        };
+        check_rows::<K>(src, &rows, dst);
+        let w = src.width;
+        let half = K / 2;
+        let xend = w - half;
+        let scale = match kernel.div {
+            None => Scale::None,
+            Some(_) if sum.is_power_of_two() => Scale::Shift(sum.trailing_zeros() as i32),
+            Some(div) => Scale::Div(simd::Divisor::new(div)),
+        };
+
+        // each channel of 16 pixels in 2 uint16x8_t, if no sum exceeds u16
+        let simd_loop16 = |x: usize, y: usize, dst: &mut [u8]| {
+            let mut acc = unsafe { [[vdupq_n_u16(0); 2]; C] };
+            for (i, row) in weights.iter().enumerate() {
+                for (j, &weight) in row.iter().enumerate() {
+                    if weight == 0 {
+                        continue;
+                    }
+                    #[cfg(feature = "stats")]
+                    stats::loads(1);
+                    let base_index = (y - half + i) * w * C + (x - half + j) * C;
+                    unsafe {
+                        let sc = vld3q_u8(vec_ref(src.content(), base_index, 16 * C));
+                        let (wd, wq) = (vdup_n_u8(weight), vdupq_n_u8(weight));
+                        for (a, &s) in acc.iter_mut().zip([sc.0, sc.1, sc.2].iter()) {
+                            a[0] = vmlal_u8(a[0], vget_low_u8(s), wd);
+                            a[1] = vmlal_high_u8(a[1], s, wq);
+                        }
+                    }
+                }
+            }
+            let out = acc.map(|a| unsafe {
+                let v = [
+                    vmovl_u16(vget_low_u16(a[0])),
+                    vmovl_high_u16(a[0]),
+                    vmovl_u16(vget_low_u16(a[1])),
+                    vmovl_high_u16(a[1]),
+                ];
+                narrow_u32x4x4(v, scale, kernel.rounding)
+            });
+            unsafe {
+                vst3q_u8(
+                    vec_mut(dst, x * C, 16 * C),
+                    uint8x16x3_t(out[0], out[1], out[2]),
+                );
+            }
+        };
+
+        // each channel of 16 pixels in 4 uint32x4_t
+        let simd_loop32 = |x: usize, y: usize, dst: &mut [u8]| {
+            let mut acc = unsafe { [[vdupq_n_u32(0); 4]; C] };
+            for (i, row) in weights.iter().enumerate() {
+                for (j, &weight) in row.iter().enumerate() {
+                    if weight == 0 {
+                        continue;
+                    }
+                    #[cfg(feature = "stats")]
+                    stats::loads(1);
+                    let base_index = (y - half + i) * w * C + (x - half + j) * C;
+                    unsafe {
+                        let sc = vld3q_u8(vec_ref(src.content(), base_index, 16 * C));
+                        let (wd, wq) = (vdup_n_u8(weight), vdupq_n_u8(weight));
+                        for (a, &s) in acc.iter_mut().zip([sc.0, sc.1, sc.2].iter()) {
+                            let lo = vmull_u8(vget_low_u8(s), wd);
+                            let hi = vmull_high_u8(s, wq);
+                            a[0] = vaddw_u16(a[0], vget_low_u16(lo));
+                            a[1] = vaddw_high_u16(a[1], lo);
+                            a[2] = vaddw_u16(a[2], vget_low_u16(hi));
+                            a[3] = vaddw_high_u16(a[3], hi);
+                        }
+                    }
+                }
+            }
+            let out = acc.map(|a| unsafe { narrow_u32x4x4(a, scale, kernel.rounding) });
+            unsafe {
+                vst3q_u8(
+                    vec_mut(dst, x * C, 16 * C),
+                    uint8x16x3_t(out[0], out[1], out[2]),
+                );
+            }
+        };
+
+        let wide = sum * u8::MAX as u32 > u16::MAX as u32;
+        // main execution
+        let peel = |x, y, dst: &mut [u8]| peel_loop(kernel, x, y, src, dst);
+        if wide {
+            for_each_block(rows, dst, w, half..xend, (16, 1), simd_loop32, peel);
+        } else {
+            for_each_block(rows, dst, w, half..xend, (16, 1), simd_loop16, peel);
+        }
+    }
+}
+
+// transform G g G^T of the 3x3 kernel g for SimdWinograd, with the rows of G
+// [1, 0, 0], [1/2, 1/2, 1/2], [1/2, -1/2, 1/2] and [0, 0, 1]
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-winograd"
+))]
+fn winograd_kernel<const K: usize>(kernel: &ConvKernel<K>) -> [[f32; 4]; 4] {
+    let g = |v: [f32; 3]| {
+        [
+            v[0],
+            (v[0] + v[1] + v[2]) / 2.,
+            (v[0] - v[1] + v[2]) / 2.,
+            v[2],
+        ]
+    };
+    // G g column by column, then (G g) G^T row by row
+    let cols = [0, 1, 2].map(|j| g([0, 1, 2].map(|i| kernel.at(i, j))));
+    [0, 1, 2, 3].map(|r| g([cols[0][r], cols[1][r], cols[2][r]]))
+}
+
+// computes the 16 pixels from x of 2 output rows with SimdWinograd, as 8 blocks of 2x2 pixels
+// whose source rows are `rows`. The first output row does not depend on the last source row,
+// which may be any row of the image when the second output row is not needed.
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-winograd"
+))]
+fn winograd_vector<const K: usize>(
+    kernel: &ConvKernel<K>,
+    u: &[[float32x4_t; 4]; 4],
+    src: &RgbImage,
+    x: usize,
+    rows: [usize; 4],
+) -> [[uint8x16_t; C]; 2] {
+    let row = src.width * C;
+    let vdiv = kernel.div.map(simd::Divisor::new);
+    unsafe {
+        // source rows times B = [[1, 0, 0, 0], [0, 1, -1, 1], [-1, 1, 1, 0], [0, 0, 0, -1]], exact
+        // in i16, `w[r][c][k]` holding column k of the blocks in row r
+        let mut w = [[[vdupq_n_s16(0); 4]; C]; 4];
+        for (r, &y) in rows.iter().enumerate() {
+            #[cfg(feature = "stats")]
+            stats::loads(2);
+            let a = vld3q_u8(vec_ref(src.content(), y * row + (x - 1) * C, 16 * C));
+            let b = vld3q_u8(vec_ref(src.content(), y * row + (x + 1) * C, 16 * C));
+            let (a, b) = ([a.0, a.1, a.2], [b.0, b.1, b.2]);
+            for c in 0..C {
+                // columns x - 1 + 2t and x + 2t of the blocks t, then x + 1 + 2t and x + 2 + 2t
+                let (even, odd) = (vuzp1q_u8(a[c], b[c]), vuzp2q_u8(a[c], b[c]));
+                let d = [
+                    vmovl_u8(vget_low_u8(even)),
+                    vmovl_u8(vget_low_u8(odd)),
+                    vmovl_high_u8(even),
+                    vmovl_high_u8(odd),
+                ]
+                .map(|v| vreinterpretq_s16_u16(v));
+                w[r][c] = [
+                    vsubq_s16(d[0], d[2]),
+                    vaddq_s16(d[1], d[2]),
+                    vsubq_s16(d[2], d[1]),
+                    vsubq_s16(d[1], d[3]),
+                ];
+            }
+        }
+
+        let mut out = [[vdupq_n_u8(0); C]; 2];
+        for c in 0..C {
+            // B^T times the rows, multiplied by the transformed kernel in f32
+            let mut m = [[[simd::splat(0.); 2]; 4]; 4];
+            for k in 0..4 {
+                let v = [
+                    vsubq_s16(w[0][c][k], w[2][c][k]),
+                    vaddq_s16(w[1][c][k], w[2][c][k]),
+                    vsubq_s16(w[2][c][k], w[1][c][k]),
+                    vsubq_s16(w[1][c][k], w[3][c][k]),
+                ];
+                for (i, &v) in v.iter().enumerate() {
+                    let lo = vcvtq_f32_s32(vmovl_s16(vget_low_s16(v)));
+                    let hi = vcvtq_f32_s32(vmovl_high_s16(v));
+                    m[i][k] = [simd::mul(lo, u[i][k]), simd::mul(hi, u[i][k])];
+                }
+            }
+            // A^T m A with A^T = [[1, 1, 1, 0], [0, 1, -1, -1]], giving the even and odd pixels
+            // of each output row
+            let t = [
+                [0, 1, 2, 3].map(|k| {
+                    [0, 1].map(|h| simd::add(simd::add(m[0][k][h], m[1][k][h]), m[2][k][h]))
+                }),
+                [0, 1, 2, 3].map(|k| {
+                    [0, 1].map(|h| vsubq_f32(vsubq_f32(m[1][k][h], m[2][k][h]), m[3][k][h]))
+                }),
+            ];
+            for (out, t) in out.iter_mut().zip(t.iter()) {
+                let mut even = [0, 1].map(|h| simd::add(simd::add(t[0][h], t[1][h]), t[2][h]));
+                let mut odd = [0, 1].map(|h| vsubq_f32(vsubq_f32(t[1][h], t[2][h]), t[3][h]));
+                if let Some(div) = vdiv {
+                    even = even.map(|v| div.divide(v));
+                    odd = odd.map(|v| div.divide(v));
+                }
+                let even = crate::util::f32x4x2_to_u8x8(even, kernel.rounding);
+                let odd = crate::util::f32x4x2_to_u8x8(odd, kernel.rounding);
+                out[c] = vcombine_u8(vzip1_u8(even, odd), vzip2_u8(even, odd));
+            }
+        }
+        out
+    }
+}
+
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-winograd"
+))]
+impl<const K: usize> ConvBackend<K> for SimdWinograd {
+    fn name(&self) -> &'static str {
+        "simd_winograd"
+    }
+
+    fn convolve_rows(
+        &self,
+        kernel: &ConvKernel<K>,
+        src: &RgbImage,
+        rows: Range<usize>,
+        dst: &mut [u8],
+    ) {
+        if K != 3 {
+            return Simd3.convolve_rows(kernel, src, rows, dst);
+        }
+        check_rows::<K>(src, &rows, dst);
+        let (h, w) = (src.height, src.width);
+        let u = winograd_kernel(kernel).map(|row| row.map(simd::splat));
+
+        // 2 output rows at once, or the last one with any row below it
+        let simd_loop = |x: usize, y: usize, dst: &mut [u8]| {
+            let out = winograd_vector(kernel, &u, src, x, [y - 1, y, y + 1, (y + 2).min(h - 1)]);
+            for (out, dst) in out.iter().zip(dst.chunks_exact_mut(w * C)) {
+                unsafe {
+                    vst3q_u8(
+                        vec_mut(dst, x * C, 16 * C),
+                        uint8x16x3_t(out[0], out[1], out[2]),
+                    );
+                }
+            }
+        };
+        let peel = |x, y, dst: &mut [u8]| peel_loop(kernel, x, y, src, dst);
+        for_each_block(rows, dst, w, 1..w - 1, (16, 2), simd_loop, peel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::consts::*;
+
+    #[test]
+    fn rows() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let kernel = ConvKernel::<5>::new(&[1.; 25], true)?;
+        let row = img.width * C;
+        for backend in available::<5>() {
+            let mut full = RgbImage::from_raw(vec![0; img.content().len()], img.height, img.width)?;
+            backend.convolve(&kernel, &img, &mut full)?;
+
+            let rows = 100..117;
+            let mut part = vec![0; rows.len() * row];
+            backend.convolve_rows(&kernel, &img, rows.clone(), &mut part);
+            assert_eq!(
+                part,
+                full.content()[rows.start * row..rows.end * row],
+                "{}",
+                backend.name()
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn small_images() -> Result<()> {
+        let kernel = ConvKernel::<5>::new(&[1.; 25], true)?;
+        for &(h, w) in [(0, 0), (1, 1), (5, 4), (4, 5), (4, 40), (40, 4)].iter() {
+            let content: Vec<u8> = (0..h * w * C).map(|i| (i * 37 % 251) as u8).collect();
+            let img = RgbImage::from_raw(content, h, w)?;
+            for backend in available::<5>() {
+                let mut dst = RgbImage::from_raw(vec![0; h * w * C], h, w)?;
+                assert!(
+                    matches!(
+                        backend.convolve(&kernel, &img, &mut dst),
+                        Err(Error::ImageTooSmall { min: 5, .. })
+                    ),
+                    "{} on {}x{}",
+                    backend.name(),
+                    h,
+                    w
+                );
+            }
+        }
+
+        // no pixel but the center one is 2 pixels away from the border
+        let content: Vec<u8> = (0..5 * 5 * C).map(|i| (i * 37 % 251) as u8).collect();
+        let img = RgbImage::from_raw(content, 5, 5)?;
+        let mut expected = RgbImage::from_raw(vec![0; 5 * 5 * C], 5, 5)?;
+        Naive2.convolve(&kernel, &img, &mut expected)?;
+        for backend in available::<5>() {
+            let mut dst = RgbImage::from_raw(vec![255; 5 * 5 * C], 5, 5)?;
+            backend.convolve(&kernel, &img, &mut dst)?;
+            assert!(dst == expected, "{}", backend.name());
+        }
+        Ok(())
+    }
+
+    // vectors running past the end of a slice are caught even though their first element is in it
+    #[cfg(all(
+        any(target_arch = "aarch64"),
+        target_feature = "neon",
+        any(debug_assertions, feature = "audit")
+    ))]
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn audit() {
+        let content = [0u8; 16 * C - 1];
+        unsafe { core::arch::aarch64::vld3q_u8(crate::util::vec_ref(&content, 0, 16 * C)) };
+    }
+
+    #[test]
+    fn blocks() -> Result<()> {
+        // wider and taller than a block, with partial blocks at the ends
+        let img = RgbImage::load(ORIGINAL)?;
+        let (h, w) = (37, 4 * img.width + 13);
+        let content = (0..h * w * C)
+            .map(|i| img.content()[i % img.content().len()])
+            .collect();
+        let wide = RgbImage::from_raw(content, h, w)?;
+        let kernel = ConvKernel::<5>::new(&[1.; 25], true)?;
+        let mut expected = RgbImage::from_raw(vec![0; h * w * C], h, w)?;
+        Naive2.convolve(&kernel, &wide, &mut expected)?;
+        for backend in available::<5>() {
+            let mut dst = RgbImage::from_raw(vec![0; h * w * C], h, w)?;
+            backend.convolve(&kernel, &wide, &mut dst)?;
+            assert!(dst == expected, "{}", backend.name());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tails() -> Result<()> {
+        // narrower than a vector, and with the last vector overlapping the previous one
+        for w in (5..48).chain([1024 + 4 + 7, 1024 + 4 + 16]) {
+            let content: Vec<u8> = (0..7 * w * C).map(|i| (i * 37 % 251) as u8).collect();
+            let img = RgbImage::from_raw(content, 7, w)?;
+            let kernel = ConvKernel::<5>::new(&[1.; 25], true)?;
+            let mut expected = RgbImage::from_raw(vec![0; 7 * w * C], 7, w)?;
+            Naive2.convolve(&kernel, &img, &mut expected)?;
+            for backend in available::<5>() {
+                let mut dst = RgbImage::from_raw(vec![0; 7 * w * C], 7, w)?;
+                backend.convolve(&kernel, &img, &mut dst)?;
+                assert!(dst == expected, "{} with width {}", backend.name(), w);
+            }
+        }
+        Ok(())
+    }
+
+    // groups of output rows and the rows left after them, and rows shared by output rows
+    #[cfg(all(
+        any(target_arch = "aarch64"),
+        target_feature = "neon",
+        feature = "impl-simd3",
+        feature = "impl-simd4",
+        feature = "impl-ring"
+    ))]
+    #[test]
+    fn multi_row() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let kernel = ConvKernel::<7>::gaussian(1.5)?;
+        for h in 7..16 {
+            let w = img.width;
+            let img = RgbImage::from_raw(img.content()[..h * w * C].to_vec(), h, w)?;
+            let mut expected = RgbImage::from_raw(vec![0; h * w * C], h, w)?;
+            Simd3.convolve(&kernel, &img, &mut expected)?;
+            let backends: [&dyn ConvBackend<7>; 2] = [&Simd4, &SimdRing];
+            for backend in backends.iter() {
+                let mut dst = RgbImage::from_raw(vec![0; h * w * C], h, w)?;
+                backend.convolve(&kernel, &img, &mut dst)?;
+                assert!(dst == expected, "{} with height {}", backend.name(), h);
+            }
+        }
+        Ok(())
+    }
+
+    // vectors of 32 and 64 pixels, with pixels left over or rows narrower than a vector
+    #[cfg(all(
+        any(target_arch = "aarch64"),
+        target_feature = "neon",
+        feature = "impl-simd3",
+        feature = "impl-wide"
+    ))]
+    #[test]
+    fn wide() -> Result<()> {
+        use crate::test_util::photo_image;
+
+        let kernel = ConvKernel::<7>::gaussian(1.5)?;
+        for w in [7, 37, 70, 100, 150] {
+            let img = photo_image(9, w, 59);
+            let mut expected = RgbImage::from_raw(vec![0; 9 * w * C], 9, w)?;
+            Simd3.convolve(&kernel, &img, &mut expected)?;
+            let backends: [&dyn ConvBackend<7>; 2] = [&SimdWide::<2>, &SimdWide::<4>];
+            for backend in backends.iter() {
+                let mut dst = RgbImage::from_raw(vec![0; 9 * w * C], 9, w)?;
+                backend.convolve(&kernel, &img, &mut dst)?;
+                assert!(dst == expected, "{} with width {}", backend.name(), w);
+            }
+        }
+        Ok(())
+    }
+
+    // two vectors side by side, with widths around them and weights whose products and sums are
+    // rounded, so that summing them in another order than Naive2 would round some pixels apart
+    #[cfg(all(
+        any(target_arch = "aarch64"),
+        target_feature = "neon",
+        feature = "impl-simd1",
+        feature = "impl-simd2"
+    ))]
+    #[test]
+    fn chains() -> Result<()> {
+        fn check<const K: usize>(kernel: &ConvKernel<K>) -> Result<()>
+        where
+            [(); (K / 2 + 1) / 2 + 1]: Sized,
+        {
+            for w in K..K + 4 * CHAINS * 3 {
+                let content: Vec<u8> = (0..9 * w * C).map(|i| (i * 37 % 251) as u8).collect();
+                let img = RgbImage::from_raw(content, 9, w)?;
+                let mut expected = RgbImage::from_raw(vec![0; 9 * w * C], 9, w)?;
+                Naive2.convolve(kernel, &img, &mut expected)?;
+                let backends: [&dyn ConvBackend<K>; 2] = [&Simd1, &Simd2];
+                for backend in backends.iter() {
+                    let mut dst = RgbImage::from_raw(vec![0; 9 * w * C], 9, w)?;
+                    backend.convolve(kernel, &img, &mut dst)?;
+                    assert!(dst == expected, "{} with width {}", backend.name(), w);
+                }
+            }
+            Ok(())
+        }
+        let weights: Vec<f32> = (0..25).map(|i| (i * 7 % 11) as f32 * 0.3 + 0.1).collect();
+        check(&ConvKernel::<5>::new(&weights, true)?)?;
+        check(&ConvKernel::<3>::gaussian(0.8)?)?;
+        check(&ConvKernel::<7>::gaussian(1.5)?)
+    }
+
+    #[cfg(all(
+        any(target_arch = "aarch64"),
+        target_feature = "neon",
+        feature = "impl-simd2",
+        feature = "impl-simd3"
+    ))]
+    #[test]
+    fn select() {
+        assert_eq!(super::select::<3>(18).name(), "simd3");
+        assert_eq!(super::select::<3>(17).name(), "simd2");
+        assert_eq!(super::select::<5>(12).name(), "simd2");
+        assert_eq!(super::select::<5>(11).name(), "naive2");
+    }
+
+    // integer kernels of SimdInt, and others computed by its fallback
+    #[test]
+    fn integer_weights() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let binomial = [1., 4., 6., 4., 1.];
+        let binomial: Vec<f32> = (0..25).map(|i| binomial[i / 5] * binomial[i % 5]).collect();
+        let large: Vec<f32> = (0..25).map(|i| (200 + i) as f32).collect();
+        let mut odd = [3.; 25];
+        odd[12] = 0.5;
+        let cases: [(&[f32], bool); 6] = [
+            (&binomial, true),
+            (&[1.; 25], true),
+            (&[1.; 25], false),
+            (&large, true),
+            (&large, false),
+            (&odd, true),
+        ];
+        for &(filter, avg) in cases.iter() {
+            let kernel = ConvKernel::<5>::new(filter, avg)?;
+            let mut expected =
+                RgbImage::from_raw(vec![0; img.content().len()], img.height, img.width)?;
+            Naive2.convolve(&kernel, &img, &mut expected)?;
+            for backend in available::<5>() {
+                let mut dst =
+                    RgbImage::from_raw(vec![0; img.content().len()], img.height, img.width)?;
+                backend.convolve(&kernel, &img, &mut dst)?;
+                assert!(
+                    dst == expected,
+                    "{} with {:?}",
+                    backend.name(),
+                    &filter[..3]
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // widths around the vector boundaries, where the pixels next to a block are gathered
+    #[cfg(all(
+        any(target_arch = "aarch64"),
+        target_feature = "neon",
+        feature = "impl-unrolled"
+    ))]
+    #[test]
+    fn unrolled() -> Result<()> {
+        fn check<const K: usize, B: ConvBackend<K>>(backend: B) -> Result<()> {
+            let weights: Vec<f32> = (0..K * K)
+                .map(|i| (i * 7 % 11) as f32 * 0.3 + 0.1)
+                .collect();
+            let kernel = ConvKernel::<K>::new(&weights, true)?;
+            for w in (K..40).chain([1023, 1024, 1025]) {
+                let content: Vec<u8> = (0..7 * w * C).map(|i| (i * 37 % 251) as u8).collect();
+                let img = RgbImage::from_raw(content, 7, w)?;
+                let mut expected = RgbImage::from_raw(vec![0; 7 * w * C], 7, w)?;
+                Simd3.convolve(&kernel, &img, &mut expected)?;
+                let mut dst = RgbImage::from_raw(vec![0; 7 * w * C], 7, w)?;
+                backend.convolve(&kernel, &img, &mut dst)?;
+                assert!(dst == expected, "{} with width {}", backend.name(), w);
+            }
+            Ok(())
+        }
+        check::<3, _>(Simd3x3)?;
+        check::<5, _>(Simd5x5)
+    }
+
+    // rows in pairs with one left over, widths around the vector boundaries, and weights whose
+    // sums are rounded in another order than Naive2 unless they are integers
+    #[cfg(all(
+        any(target_arch = "aarch64"),
+        target_feature = "neon",
+        feature = "impl-winograd"
+    ))]
+    #[test]
+    fn winograd() -> Result<()> {
+        let weights: Vec<f32> = (0..9).map(|i| (i * 7 % 11) as f32 * 0.3 + 0.1).collect();
+        let kernels = [
+            (ConvKernel::<3>::new(&weights, true)?, 1),
+            (ConvKernel::<3>::new(&SOBEL_FILTER, false)?, 0),
+            (ConvKernel::<3>::new(&[1.; 9], true)?, 0),
+        ];
+        for (h, w) in [(7, 3), (8, 20)]
+            .iter()
+            .copied()
+            .chain((3..40).map(|w| (7, w)))
+            .chain([(6, 1025)])
+        {
+            let content: Vec<u8> = (0..h * w * C).map(|i| (i * 37 % 251) as u8).collect();
+            let img = RgbImage::from_raw(content, h, w)?;
+            for (kernel, tolerance) in kernels.iter() {
+                let mut expected = RgbImage::from_raw(vec![0; h * w * C], h, w)?;
+                Naive2.convolve(kernel, &img, &mut expected)?;
+                let mut dst = RgbImage::from_raw(vec![0; h * w * C], h, w)?;
+                SimdWinograd.convolve(kernel, &img, &mut dst)?;
+                assert!(dst.approx_eq(&expected, *tolerance, 0), "{}x{}", h, w);
+            }
+        }
+        Ok(())
+    }
+
+    // height, width, pixels, weights and whether to normalize for a KxK kernel, with widths
+    // around multiples of a vector (8 or 16 pixels) and of a block. Weights are any values in
+    // `-8..8`, whose products and sums are rounded, or small non-negative integers for SimdInt.
+    fn random_case(k: usize) -> impl Strategy<Value = (usize, usize, Vec<u8>, Vec<f32>, bool)> {
+        let inner = vec![1, 4, 7, 8, 9, 15, 16, 17, 31, 32, 33, 1023, 1024, 1025];
+        let widths = prop_oneof![
+            k..k + 48,
+            prop::sample::select(inner).prop_map(move |w| w + k - 1),
+        ];
+        let weights = prop_oneof![
+            prop::collection::vec(-8f32..8., k * k),
+            prop::collection::vec((0..=8).prop_map(|n| n as f32), k * k),
+        ];
+        (k..k + 16, widths, weights, any::<bool>()).prop_flat_map(|(h, w, weights, normalize)| {
+            let pixels = prop::collection::vec(any::<u8>(), h * w * C);
+            (Just(h), Just(w), pixels, Just(weights), Just(normalize))
+        })
+    }
+
+    fn same_as_naive2<const K: usize>(
+        (h, w, pixels, weights, normalize): (usize, usize, Vec<u8>, Vec<f32>, bool),
+    ) -> core::result::Result<(), TestCaseError>
+    where
+        [(); (K / 2 + 1) / 2 + 1]: Sized,
+    {
+        prop_assume!(!normalize || weights.iter().sum::<f32>() != 0.);
+        let kernel = ConvKernel::<K>::new(&weights, normalize).unwrap();
+        let img = RgbImage::from_raw(pixels, h, w).unwrap();
+        let mut expected = RgbImage::from_raw(vec![0; h * w * C], h, w).unwrap();
+        Naive2.convolve(&kernel, &img, &mut expected).unwrap();
+        for backend in available::<K>() {
+            let mut dst = RgbImage::from_raw(vec![0; h * w * C], h, w).unwrap();
+            backend.convolve(&kernel, &img, &mut dst).unwrap();
+            prop_assert!(dst == expected, "{} on {}x{}", backend.name(), h, w);
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn random_3x3(case in random_case(3)) {
+            same_as_naive2::<3>(case)?;
+        }
+
+        #[test]
+        fn random_5x5(case in random_case(5)) {
+            same_as_naive2::<5>(case)?;
+        }
+
+        #[test]
+        fn random_7x7(case in random_case(7)) {
+            same_as_naive2::<7>(case)?;
+        }
+
+        #[test]
+        fn random_9x9(case in random_case(9)) {
+            same_as_naive2::<9>(case)?;
+        }
+    }
+
+    // from the first aligned pixel or not, on rows of 48 * 3 bytes then not a multiple of 16, whose
+    // pixels start anywhere in a 16-byte chunk
+    #[cfg(all(
+        any(target_arch = "aarch64"),
+        target_feature = "neon",
+        feature = "impl-simd3"
+    ))]
+    #[test]
+    fn alignment() -> Result<()> {
+        use crate::test_util::misaligned;
+
+        let kernel = ConvKernel::<5>::new(&[1.; 25], true)?;
+        for w in [48, 47] {
+            let content: Vec<u8> = (0..9 * w * C).map(|i| (i * 37 % 251) as u8).collect();
+            let img = RgbImage::from_raw(content, 9, w)?;
+            let mut expected = RgbImage::from_raw(vec![0; 9 * w * C], 9, w)?;
+            Naive2.convolve(&kernel, &img, &mut expected)?;
+            for offset in [0, 1, 4, 8, 13] {
+                let img = misaligned(&img, offset);
+                if let Some(start) = aligned_start::<5>(&img, 2..w - 2) {
+                    let base = img.content().as_ptr() as usize + (start - 2) * C;
+                    assert_eq!(base % 16, 0, "width {} offset {}", w, offset);
+                }
+                let mut results = vec![];
+                for align in [true, false] {
+                    set_align_loads(align);
+                    // other tests may run meanwhile, but only add to the counts
+                    let before = alignment_counts();
+                    let mut dst = RgbImage::from_raw(vec![0; 9 * w * C], 9, w)?;
+                    Simd3.convolve(&kernel, &img, &mut dst)?;
+                    let after = alignment_counts();
+                    set_align_loads(true);
+                    if align && w == 48 {
+                        assert!(after.aligned >= before.aligned + 5);
+                    } else {
+                        assert!(after.unaligned >= before.unaligned + 5);
+                    }
+                    results.push(dst);
+                }
+                assert!(results[0] == results[1], "width {} offset {}", w, offset);
+                assert!(results[0] == expected, "width {} offset {}", w, offset);
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "prefetch")]
+    #[test]
+    fn prefetch() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let kernel = ConvKernel::<5>::new(&[1.; 25], true)?;
+        let mut expected = RgbImage::from_raw(vec![0; img.content().len()], img.height, img.width)?;
+        Naive2.convolve(&kernel, &img, &mut expected)?;
+        let default = prefetch_distance();
+        // past the end of rows and of the image
+        for &distance in [0, 1, 16, 1 << 20].iter() {
+            set_prefetch_distance(distance);
+            for backend in available::<5>() {
+                let mut dst =
+                    RgbImage::from_raw(vec![0; img.content().len()], img.height, img.width)?;
+                backend.convolve(&kernel, &img, &mut dst)?;
+                assert!(dst == expected, "{} with {}", backend.name(), distance);
+            }
+        }
+        set_prefetch_distance(default);
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic]
+    fn rows_out_of_range() {
+        let img = RgbImage::from_raw_unchecked(vec![0; 8 * 8 * C], 8, 8);
+        let kernel = ConvKernel::<3>::new(&[1.; 9], true).unwrap();
+        let mut dst = vec![0; 8 * 8 * C];
+        Naive2.convolve_rows(&kernel, &img, 0..8, &mut dst);
+    }
+}
@@ -0,0 +1,159 @@
+//! Timing of backends and processors from code, e.g. to calibrate an application at startup with
+//! the same measurements as the benchmarks.
+//!
+//! [`backend`] and [`processor`] convolve given images a given number of times after a warm-up
+//! run, and give [`Timing`] statistics of the runs. [`time`] does the same for any closure.
+//!
+//! ```no_run
+//! use simd_playground::{backend::available, bench, test_util::photo_image, ConvKernel};
+//!
+//! # fn main() -> simd_playground::Result<()> {
+//! let kernel = ConvKernel::<3>::new(&[1.; 9], true)?;
+//! let inputs = [photo_image(1080, 1920, 0)];
+//! for backend in available::<3>() {
+//!     let timing = bench::backend(backend, &kernel, &inputs, 10)?;
+//!     println!("{}: {:.1} Mpix/s", backend.name(), timing.mpix_per_s(1080 * 1920));
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    backend::ConvBackend, error::Result, image::RgbImage, kernel::ConvKernel,
+    processor::ConvProcessor,
+};
+
+/// Statistics of the time taken by repeated runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    /// Number of measured runs.
+    pub runs: usize,
+    /// Fastest run, the least disturbed by other processes.
+    pub min: Duration,
+    /// Median run.
+    pub median: Duration,
+    /// Mean of the runs.
+    pub mean: Duration,
+    /// Slowest run.
+    pub max: Duration,
+}
+
+impl Timing {
+    /// Millions of pixels per second at the [`median`](Self::median), for runs producing
+    /// `pixels` pixels each.
+    pub fn mpix_per_s(&self, pixels: usize) -> f64 {
+        pixels as f64 / self.median.as_nanos() as f64 * 1e3
+    }
+}
+
+/// Times `runs` calls of `run` (at least one) after `warmup` unmeasured ones, returning the first
+/// error of any call.
+pub fn time(runs: usize, warmup: usize, mut run: impl FnMut() -> Result<()>) -> Result<Timing> {
+    for _ in 0..warmup {
+        run()?;
+    }
+    let mut times = Vec::with_capacity(runs.max(1));
+    for _ in 0..runs.max(1) {
+        let start = Instant::now();
+        run()?;
+        times.push(start.elapsed());
+    }
+    times.sort_unstable();
+    Ok(Timing {
+        runs: times.len(),
+        min: times[0],
+        median: times[times.len() / 2],
+        mean: times.iter().sum::<Duration>() / times.len() as u32,
+        max: times[times.len() - 1],
+    })
+}
+
+/// Times `backend` convolving every image of `inputs` with `kernel`, once to warm up caches then
+/// `runs` times. A run convolves all the images, whose pixels add up for
+/// [`Timing::mpix_per_s`].
+///
+/// Fails like [`ConvBackend::convolve`] if an image is smaller than the kernel.
+pub fn backend<const K: usize, B: ConvBackend<K> + ?Sized>(
+    backend: &B,
+    kernel: &ConvKernel<K>,
+    inputs: &[RgbImage],
+    runs: usize,
+) -> Result<Timing> {
+    let mut outputs: Vec<_> = inputs.iter().map(empty_like).collect();
+    time(runs, 1, || {
+        for (src, dst) in inputs.iter().zip(&mut outputs) {
+            backend.convolve(kernel, src, dst)?;
+        }
+        Ok(())
+    })
+}
+
+/// Same as [`backend`], but with [`ConvProcessor::convolve_into`], i.e. with the threads, tiles
+/// and border of `processor`.
+///
+/// Fails like [`ConvProcessor::convolve_into`] if an image is smaller than the kernel.
+pub fn processor<const K: usize>(
+    processor: &ConvProcessor<K>,
+    inputs: &[RgbImage],
+    runs: usize,
+) -> Result<Timing>
+where
+    [(); (K / 2 + 1) / 2 + 1]: Sized,
+{
+    let mut outputs: Vec<_> = inputs.iter().map(empty_like).collect();
+    time(runs, 1, || {
+        for (src, dst) in inputs.iter().zip(&mut outputs) {
+            processor.convolve_into(src, dst)?;
+        }
+        Ok(())
+    })
+}
+
+// black image of the size of `img`
+fn empty_like(img: &RgbImage) -> RgbImage {
+    RgbImage::from_raw_unchecked(vec![0; img.inner.len()], img.height, img.width)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::{backend::Naive1, error::Error, test_util::photo_image};
+
+    #[test]
+    fn time() -> Result<()> {
+        let calls = Cell::new(0);
+        let timing = super::time(5, 2, || {
+            calls.set(calls.get() + 1);
+            std::thread::sleep(Duration::from_micros(100 * calls.get()));
+            Ok(())
+        })?;
+        assert_eq!((calls.get(), timing.runs), (7, 5));
+        assert!(timing.min >= Duration::from_micros(300));
+        assert!(timing.min <= timing.median && timing.median <= timing.max);
+        assert!(timing.min <= timing.mean && timing.mean <= timing.max);
+        assert_eq!(super::time(0, 0, || Ok(()))?.runs, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn convolutions() -> Result<()> {
+        let kernel = ConvKernel::<3>::new(&[1.; 9], true)?;
+        let inputs = [photo_image(16, 24, 22), photo_image(5, 40, 23)];
+        let timing = backend(&Naive1, &kernel, &inputs, 3)?;
+        assert_eq!(timing.runs, 3);
+        assert!(timing.mpix_per_s(16 * 24 + 5 * 40) > 0.);
+        let blur = ConvProcessor::<3>::new(&[1.; 9], true)?;
+        assert_eq!(processor(&blur, &inputs, 2)?.runs, 2);
+
+        let small = [photo_image(2, 40, 24)];
+        assert!(matches!(
+            backend(&Naive1, &kernel, &small, 3),
+            Err(Error::ImageTooSmall { .. })
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,321 @@
+//! Applies a filter to a PNG image.
+//!
+//! ```text
+//! simdconv --filter gaussian --sigma 2.0 --impl simd3 in.png out.png
+//! ```
+//!
+//! Run with `--help` for all options.
+#![allow(incomplete_features)]
+#![feature(generic_const_exprs)]
+
+use std::{env, error::Error, process, str::FromStr, time::Instant};
+
+use simd_playground::{
+    backend::{available, ConvBackend},
+    consts::SOBEL_FILTER,
+    BorderMode, ConvKernel, ConvProcessor, RgbImage,
+};
+
+const USAGE: &str = "\
+usage: simdconv [options] <input.png> <output.png>
+
+options:
+    --filter <name>     box, gaussian, sobel or sharpen (default: box)
+    --size <k>          odd kernel size in 3..=19 for box and gaussian
+                        (default: 3, or 2 * ceil(3 * sigma) + 1 for gaussian)
+    --sigma <sigma>     standard deviation of gaussian, at most 3 without
+                        --size (default: 1.0)
+    --impl <name>       naive1, naive2, gemm, simd1, simd2, simd3,
+                        simd_wide32, simd_wide64, simd4, simd_ring,
+                        simd3x3, simd5x5, simd_int, simd_winograd or auto
+                        (default: auto)
+    --border <mode>     zero, replicate or reflect (default: zero)
+    --effect <name>     invert, posterize or sepia, applied after the filter
+    --levels <n>        levels per channel for posterize (default: 4)
+    --threads <n>       number of threads, 0 for all CPUs (default: 1)
+    --bench             print timings of every implementation available
+    --iters <n>         iterations for --bench (default: 10)
+    -h, --help          print this message";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Filter {
+    Box,
+    Gaussian,
+    Sobel,
+    Sharpen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Effect {
+    Invert,
+    Posterize,
+    Sepia,
+}
+
+#[derive(Debug, PartialEq)]
+struct Args {
+    filter: Filter,
+    size: Option<usize>,
+    sigma: f32,
+    imp: String,
+    border: BorderMode,
+    effect: Option<Effect>,
+    levels: u8,
+    threads: usize,
+    bench: bool,
+    iters: usize,
+    input: String,
+    output: String,
+}
+
+fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+    fn value<T: FromStr, I: Iterator<Item = String>>(
+        args: &mut I,
+        name: &str,
+    ) -> Result<T, String> {
+        let v = args.next().ok_or(format!("missing value for {}", name))?;
+        v.parse()
+            .map_err(|_| format!("invalid value for {}: {}", name, v))
+    }
+
+    let mut parsed = Args {
+        filter: Filter::Box,
+        size: None,
+        sigma: 1.,
+        imp: "auto".to_string(),
+        border: BorderMode::Zero,
+        effect: None,
+        levels: 4,
+        threads: 1,
+        bench: false,
+        iters: 10,
+        input: String::new(),
+        output: String::new(),
+    };
+    let mut paths = vec![];
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--filter" => {
+                parsed.filter = match value::<String, _>(&mut args, &arg)?.as_str() {
+                    "box" => Filter::Box,
+                    "gaussian" => Filter::Gaussian,
+                    "sobel" => Filter::Sobel,
+                    "sharpen" => Filter::Sharpen,
+                    other => return Err(format!("unknown filter: {}", other)),
+                }
+            }
+            "--size" => parsed.size = Some(value(&mut args, &arg)?),
+            "--sigma" => parsed.sigma = value(&mut args, &arg)?,
+            "--impl" => parsed.imp = value(&mut args, &arg)?,
+            "--border" => {
+                parsed.border = match value::<String, _>(&mut args, &arg)?.as_str() {
+                    "zero" => BorderMode::Zero,
+                    "replicate" => BorderMode::Replicate,
+                    "reflect" => BorderMode::Reflect,
+                    other => return Err(format!("unknown border mode: {}", other)),
+                }
+            }
+            "--effect" => {
+                parsed.effect = Some(match value::<String, _>(&mut args, &arg)?.as_str() {
+                    "invert" => Effect::Invert,
+                    "posterize" => Effect::Posterize,
+                    "sepia" => Effect::Sepia,
+                    other => return Err(format!("unknown effect: {}", other)),
+                })
+            }
+            "--levels" => parsed.levels = value(&mut args, &arg)?,
+            "--threads" => parsed.threads = value(&mut args, &arg)?,
+            "--bench" => parsed.bench = true,
+            "--iters" => parsed.iters = value(&mut args, &arg)?,
+            "-h" | "--help" => return Err(String::new()),
+            s if s.starts_with('-') => return Err(format!("unknown option: {}", s)),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.len() != 2 {
+        return Err("expected an input and an output path".to_string());
+    }
+    parsed.output = paths.pop().unwrap();
+    parsed.input = paths.pop().unwrap();
+    match parsed.filter {
+        Filter::Sobel | Filter::Sharpen if parsed.size.is_some() => {
+            return Err("--size does not apply to the 3x3 sobel and sharpen filters".to_string());
+        }
+        Filter::Gaussian if parsed.sigma <= 0. || parsed.sigma.is_nan() => {
+            return Err(format!("sigma must be positive, got {}", parsed.sigma));
+        }
+        // 2 * ceil(3 * sigma) + 1 <= 19
+        Filter::Gaussian if parsed.size.is_none() && parsed.sigma > 3. => {
+            return Err(format!(
+                "sigma {} needs a kernel larger than 19, give --size or a sigma of at most 3",
+                parsed.sigma
+            ));
+        }
+        _ => {}
+    }
+    Ok(parsed)
+}
+
+impl Args {
+    fn kernel_size(&self) -> usize {
+        match (self.filter, self.size) {
+            (Filter::Sobel | Filter::Sharpen, _) => 3,
+            (_, Some(size)) => size,
+            (Filter::Gaussian, None) => 2 * (3. * self.sigma).ceil() as usize + 1,
+            (Filter::Box, None) => 3,
+        }
+    }
+}
+
+// the available backends, and SimdWinograd which must be chosen explicitly
+fn backends<const K: usize>() -> Vec<&'static dyn ConvBackend<K>>
+where
+    [(); (K / 2 + 1) / 2 + 1]: Sized,
+{
+    #[allow(unused_mut)]
+    let mut backends = available::<K>();
+    #[cfg(all(
+        any(target_arch = "aarch64"),
+        target_feature = "neon",
+        feature = "impl-winograd"
+    ))]
+    backends.push(&simd_playground::backend::SimdWinograd);
+    backends
+}
+
+fn run<const K: usize>(args: &Args) -> Result<(), Box<dyn Error>>
+where
+    [(); (K / 2 + 1) / 2 + 1]: Sized,
+{
+    let kernel = match args.filter {
+        Filter::Box => ConvKernel::<K>::new(&vec![1.; K * K], true)?,
+        Filter::Gaussian => ConvKernel::<K>::gaussian(args.sigma)?,
+        Filter::Sobel => ConvKernel::<K>::new(&SOBEL_FILTER, false)?,
+        Filter::Sharpen => ConvKernel::<K>::new(&[0., -1., 0., -1., 5., -1., 0., -1., 0.], false)?,
+    };
+    let mut builder = ConvProcessor::<K>::builder()
+        .kernel(kernel.weights())
+        .normalize(kernel.div().is_some())
+        .border(args.border)
+        .threads(args.threads);
+    if args.imp != "auto" {
+        let backend = backends::<K>()
+            .into_iter()
+            .find(|b| b.name() == args.imp)
+            .ok_or(format!("implementation {} is not available", args.imp))?;
+        builder = builder.backend(backend);
+    }
+    let layer = builder.build()?;
+    let img = RgbImage::load(&args.input)?;
+
+    if args.bench {
+        println!(
+            "{}x{} image, {}x{} kernel, {} iterations",
+            img.height(),
+            img.width(),
+            K,
+            K,
+            args.iters
+        );
+        for backend in backends::<K>() {
+            let start = Instant::now();
+            for _ in 0..args.iters {
+                layer.apply(backend, &img)?;
+            }
+            let elapsed = start.elapsed() / args.iters.max(1) as u32;
+            println!(
+                "{:>8}: {:10.3} ms",
+                backend.name(),
+                elapsed.as_secs_f64() * 1e3
+            );
+        }
+    }
+
+    let mut out = layer.convolve(&img)?;
+    match args.effect {
+        None => {}
+        Some(Effect::Invert) => out.invert(),
+        Some(Effect::Posterize) => out.posterize(args.levels)?,
+        Some(Effect::Sepia) => out = out.sepia(),
+    }
+    out.save(&args.output)?;
+    Ok(())
+}
+
+macro_rules! dispatch {
+    ($args:expr, $($k:literal)*) => {
+        match $args.kernel_size() {
+            $($k => run::<$k>($args),)*
+            k => Err(format!("kernel size must be odd in 3..=19, got {}", k).into()),
+        }
+    };
+}
+
+fn main() {
+    let args = match parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("simdconv: {}\n", e);
+            }
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = dispatch!(&args, 3 5 7 9 11 13 15 17 19) {
+        eprintln!("simdconv: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Result<Args, String> {
+        parse(s.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn parse_args() -> Result<(), String> {
+        let parsed = args("--filter gaussian --sigma 2.0 --impl simd3 in.png out.png")?;
+        assert_eq!(parsed.filter, Filter::Gaussian);
+        assert_eq!(parsed.sigma, 2.);
+        assert_eq!(parsed.imp, "simd3");
+        assert_eq!(
+            (parsed.input.as_str(), parsed.output.as_str()),
+            ("in.png", "out.png")
+        );
+        assert_eq!(parsed.kernel_size(), 13);
+
+        let parsed = args("a.png --border reflect --threads 0 --bench b.png")?;
+        assert_eq!(parsed.border, BorderMode::Reflect);
+        assert_eq!(parsed.threads, 0);
+        assert!(parsed.bench);
+        assert_eq!(parsed.kernel_size(), 3);
+        assert_eq!(parsed.effect, None);
+
+        let parsed = args("--effect posterize --levels 3 a.png b.png")?;
+        assert_eq!((parsed.effect, parsed.levels), (Some(Effect::Posterize), 3));
+
+        assert!(args("--size").is_err());
+        assert!(args("--size x a.png b.png").is_err());
+        assert!(args("--filter median a.png b.png").is_err());
+        assert!(args("--effect blur a.png b.png").is_err());
+        assert!(args("a.png").is_err());
+
+        assert_eq!(
+            args("--filter gaussian --sigma 3 a.png b.png")?.kernel_size(),
+            19
+        );
+        assert!(args("--filter gaussian --sigma 3.5 a.png b.png").is_err());
+        assert_eq!(
+            args("--filter gaussian --sigma 3.5 --size 19 a.png b.png")?.kernel_size(),
+            19
+        );
+        assert!(args("--filter gaussian --sigma 0 a.png b.png").is_err());
+        assert!(args("--filter sobel --size 5 a.png b.png").is_err());
+        assert!(args("--filter sharpen --size 3 a.png b.png").is_err());
+        Ok(())
+    }
+}
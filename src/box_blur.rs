@@ -0,0 +1,339 @@
+//! Box blur by a sliding window, and Gaussian blur approximated by iterating it.
+//!
+//! The sum of the window is updated from the previous pixel by adding the pixel entering it and
+//! subtracting the one leaving it, so that a pixel takes 2 additions whatever the radius. Boxes
+//! blurred again with themselves converge to a Gaussian, and 3 passes whose widths are chosen
+//! for the variance (Kovesi, 2010) are already hard to tell from it.
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+
+use alloc::{format, string::ToString, vec, vec::Vec};
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::util::{div_u32x4x4_to_u8x16, u8x16_to_u32x4x4, vec_mut, vec_ref};
+use crate::{
+    error::{Error, Result},
+    image::RgbImage,
+    kernel::sqrt,
+    C,
+};
+
+/// Largest radius, for which the sums of a window fit in 24 bits.
+pub const MAX_RADIUS: usize = 32767;
+
+/// Box blur averaging the `(2 * radius + 1)²` pixels around each pixel, horizontally then
+/// vertically.
+///
+/// Pixels outside the image take the value of the nearest edge pixel. The result of each pass
+/// is rounded to the nearest integer, half up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoxBlur {
+    radius: usize,
+}
+
+impl BoxBlur {
+    /// Creates a blur of `radius` pixels on each side, where 0 leaves images as they are.
+    ///
+    /// Fails with [`Error::BadKernel`] if `radius` is more than [`MAX_RADIUS`].
+    pub fn new(radius: usize) -> Result<Self> {
+        if radius > MAX_RADIUS {
+            return Err(Error::BadKernel(format!(
+                "radius must be at most {}, got {}",
+                MAX_RADIUS, radius
+            )));
+        }
+        Ok(Self { radius })
+    }
+
+    /// Number of pixels on each side of the center which are averaged.
+    pub fn radius(&self) -> usize {
+        self.radius
+    }
+
+    /// Scalar implementation.
+    pub fn naive(&self, src: &RgbImage) -> Result<RgbImage> {
+        let (h, w) = (src.height, src.width);
+        let mut tmp = vec![0; src.content().len()];
+        self.horizontal(src.content(), &mut tmp, w);
+        let mut dst = vec![0; tmp.len()];
+        for k in 0..w * C {
+            self.blur_line(&tmp[k..], &mut dst[k..], h, w * C);
+        }
+        Ok(RgbImage::from_raw_unchecked(dst, h, w))
+    }
+
+    /// NEON implementation of the vertical pass, on 16 values of a row at once. The horizontal
+    /// pass is the same as [`naive`](Self::naive).
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    pub fn simd(&self, src: &RgbImage) -> Result<RgbImage> {
+        let (h, w) = (src.height, src.width);
+        let row = w * C;
+        let mut tmp = vec![0; src.content().len()];
+        self.horizontal(src.content(), &mut tmp, w);
+        let mut dst = vec![0; tmp.len()];
+        let simd_end = row - row % 16;
+        for k in (0..simd_end).step_by(16) {
+            self.vertical_simd(&tmp, &mut dst, k, h, row);
+        }
+        for k in simd_end..row {
+            self.blur_line(&tmp[k..], &mut dst[k..], h, row);
+        }
+        Ok(RgbImage::from_raw_unchecked(dst, h, w))
+    }
+
+    // every channel of every row of `src`, `width` pixels each, into `dst`
+    fn horizontal(&self, src: &[u8], dst: &mut [u8], width: usize) {
+        let row = width * C;
+        if row == 0 {
+            return;
+        }
+        for (s, d) in src.chunks_exact(row).zip(dst.chunks_exact_mut(row)) {
+            for c in 0..C {
+                self.blur_line(&s[c..], &mut d[c..], width, C);
+            }
+        }
+    }
+
+    // blurs the `len` values `src[0], src[step], ..` into the same indices of `dst`
+    fn blur_line(&self, src: &[u8], dst: &mut [u8], len: usize, step: usize) {
+        if len == 0 {
+            return;
+        }
+        let r = self.radius as isize;
+        let at = |i: isize| src[i.clamp(0, len as isize - 1) as usize * step] as u32;
+        let div = 2 * self.radius as u32 + 1;
+        // half of the divisor, so that the truncated quotient is rounded
+        let mut sum: u32 = div / 2 + (-r..=r).map(at).sum::<u32>();
+        for i in 0..len as isize {
+            dst[i as usize * step] = (sum / div) as u8;
+            sum = sum + at(i + r + 1) - at(i - r);
+        }
+    }
+
+    // `blur_line` on the 16 columns of bytes from `k`, i.e. `src[k..k + 16]` of every row
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    fn vertical_simd(&self, src: &[u8], dst: &mut [u8], k: usize, h: usize, row: usize) {
+        unsafe {
+            let r = self.radius as isize;
+            let at = |y: isize| {
+                let y = y.clamp(0, h as isize - 1) as usize;
+                u8x16_to_u32x4x4(vld1q_u8(vec_ref(src, y * row + k, 16)))
+            };
+            let div = 2 * self.radius as u32 + 1;
+            let mut sum = [vdupq_n_u32(div / 2); 4];
+            for d in -r..=r {
+                let v = at(d);
+                for z in 0..4 {
+                    sum[z] = vaddq_u32(sum[z], v[z]);
+                }
+            }
+            // sums are below 2^24 and divisors below 2^16
+            let div = vdupq_n_f32(div as f32);
+            for y in 0..h as isize {
+                vst1q_u8(
+                    vec_mut(dst, y as usize * row + k, 16),
+                    div_u32x4x4_to_u8x16(sum, div),
+                );
+                let (enter, leave) = (at(y + r + 1), at(y - r));
+                for z in 0..4 {
+                    sum[z] = vsubq_u32(vaddq_u32(sum[z], enter[z]), leave[z]);
+                }
+            }
+        }
+    }
+}
+
+/// Gaussian blur approximated by box blurs, see [`gaussian_approx`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoxGaussian {
+    boxes: Vec<BoxBlur>,
+}
+
+impl BoxGaussian {
+    /// Box blurs in the order they are applied.
+    pub fn boxes(&self) -> &[BoxBlur] {
+        &self.boxes
+    }
+
+    /// Runs [`BoxBlur::naive`] of every box.
+    pub fn naive(&self, src: &RgbImage) -> Result<RgbImage> {
+        self.run(src, BoxBlur::naive)
+    }
+
+    /// Runs [`BoxBlur::simd`] of every box.
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    pub fn simd(&self, src: &RgbImage) -> Result<RgbImage> {
+        self.run(src, BoxBlur::simd)
+    }
+
+    fn run(
+        &self,
+        src: &RgbImage,
+        blur: impl Fn(&BoxBlur, &RgbImage) -> Result<RgbImage>,
+    ) -> Result<RgbImage> {
+        let mut boxes = self.boxes.iter();
+        // there is at least 1 box
+        let mut dst = blur(boxes.next().unwrap(), src)?;
+        for b in boxes {
+            dst = blur(b, &dst)?;
+        }
+        Ok(dst)
+    }
+}
+
+/// Gaussian blur with standard deviation `sigma` approximated by `passes` box blurs, whose
+/// widths are the 2 odd integers around the ideal one for `passes` equal boxes, as many of
+/// each as make up the variance `sigma²` best. 3 passes give near-Gaussian quality, and cost
+/// the same whatever the sigma.
+///
+/// Fails with [`Error::BadKernel`] if `sigma` is not positive, `passes` is 0, or the radius
+/// would be more than [`MAX_RADIUS`].
+pub fn gaussian_approx(sigma: f32, passes: usize) -> Result<BoxGaussian> {
+    if sigma.is_nan() || sigma <= 0. {
+        return Err(Error::BadKernel(format!(
+            "sigma must be positive, got {}",
+            sigma
+        )));
+    }
+    if passes == 0 {
+        return Err(Error::BadKernel("passes must be positive".to_string()));
+    }
+    let (var, n) = (12. * sigma * sigma, passes as f32);
+    // variance of a box of width `w` is `(w² - 1) / 12`
+    let ideal = sqrt(var / n + 1.);
+    let mut lower = ideal as usize;
+    if lower % 2 == 0 {
+        lower = lower.saturating_sub(1).max(1);
+    }
+    let l = lower as f32;
+    // number of passes with the lower width
+    let m = (var - n * l * l - 4. * n * l - 3. * n) / (-4. * l - 4.);
+    let m = ((m + 0.5).max(0.) as usize).min(passes);
+    let boxes = (0..passes)
+        .map(|i| BoxBlur::new(if i < m { lower / 2 } else { lower / 2 + 1 }))
+        .collect::<Result<_>>()?;
+    Ok(BoxGaussian { boxes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "impl-separable")]
+    use crate::separable::SeparableProcessor;
+    use crate::test_util::photo_image;
+
+    // every output computed directly from the window, with an intermediate `u8` image
+    fn reference(radius: usize, src: &RgbImage) -> RgbImage {
+        let (h, w) = (src.height as isize, src.width as isize);
+        let r = radius as isize;
+        let div = 2 * radius as u32 + 1;
+        let pass = |src: &[u8], horizontal: bool| -> Vec<u8> {
+            let mut dst = vec![0; src.len()];
+            for y in 0..h {
+                for x in 0..w {
+                    for c in 0..C {
+                        let sum: u32 = (-r..=r)
+                            .map(|d| {
+                                let (sy, sx) = if horizontal {
+                                    (y, (x + d).clamp(0, w - 1))
+                                } else {
+                                    ((y + d).clamp(0, h - 1), x)
+                                };
+                                src[((sy * w + sx) as usize) * C + c] as u32
+                            })
+                            .sum();
+                        dst[((y * w + x) as usize) * C + c] = ((sum + div / 2) / div) as u8;
+                    }
+                }
+            }
+            dst
+        };
+        let tmp = pass(src.content(), true);
+        RgbImage::from_raw_unchecked(pass(&tmp, false), src.height, src.width)
+    }
+
+    #[test]
+    fn same_as_window() -> Result<()> {
+        for (h, w) in [(1, 1), (5, 9), (17, 4)] {
+            let img = photo_image(h, w, 1);
+            for radius in [0, 1, 2, 7] {
+                let blur = BoxBlur::new(radius)?;
+                assert_eq!(
+                    blur.naive(&img)?,
+                    reference(radius, &img),
+                    "{:?} {}",
+                    (h, w),
+                    radius
+                );
+            }
+        }
+        let img = RgbImage::from_raw(vec![255; 3 * 4 * C], 3, 4)?;
+        assert_eq!(BoxBlur::new(MAX_RADIUS)?.naive(&img)?, img);
+        assert!(BoxBlur::new(MAX_RADIUS + 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn gaussian() -> Result<()> {
+        // ideal width sqrt(101) ~ 10.05, so that 2 boxes of 9 and 1 of 11 give a variance of
+        // (2 * 80 + 120) / 12 ~ 23.3
+        let radii = |sigma, passes| -> Result<Vec<usize>> {
+            Ok(gaussian_approx(sigma, passes)?
+                .boxes()
+                .iter()
+                .map(BoxBlur::radius)
+                .collect())
+        };
+        assert_eq!(radii(5., 3)?, [4, 4, 5]);
+        assert_eq!(radii(5., 1)?, [8]);
+        assert_eq!(radii(0.1, 3)?, [0, 0, 0]);
+        assert!(gaussian_approx(0., 3).is_err());
+        assert!(gaussian_approx(5., 0).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "impl-separable")]
+    #[test]
+    fn close_to_fir() -> Result<()> {
+        // 3 sigmas from the border, where the FIR kernel sees no zero padding
+        let (h, w) = (80, 90);
+        let img = photo_image(h, w, 7);
+        let fir = SeparableProcessor::<31>::gaussian(5.)?.naive(&img)?;
+        let approx = gaussian_approx(5., 3)?.naive(&img)?;
+        let mut max = 0;
+        for y in 15..h - 15 {
+            for x in 15..w - 15 {
+                for c in 0..C {
+                    let i = (y * w + x) * C + c;
+                    let d = (fir.content()[i] as i16 - approx.content()[i] as i16).unsigned_abs();
+                    max = max.max(d);
+                }
+            }
+        }
+        assert!(max <= 4, "max difference {}", max);
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    #[test]
+    fn simd() -> Result<()> {
+        // columns of bytes left over by vectors of 16
+        for (h, w) in [(1, 1), (9, 6), (40, 23)] {
+            let img = photo_image(h, w, 2);
+            for radius in [0, 1, 4, 30] {
+                let blur = BoxBlur::new(radius)?;
+                assert_eq!(
+                    blur.simd(&img)?,
+                    blur.naive(&img)?,
+                    "{:?} {}",
+                    (h, w),
+                    radius
+                );
+            }
+            let approx = gaussian_approx(3., 3)?;
+            assert_eq!(approx.simd(&img)?, approx.naive(&img)?);
+        }
+        Ok(())
+    }
+}
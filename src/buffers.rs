@@ -0,0 +1,151 @@
+//! Buffers reused among the calls of a [`ConvProcessor`](crate::ConvProcessor).
+//!
+//! Each convolution of a large image needs an output, plus a padded copy of the source and
+//! its output rows for borders other than [`BorderMode::Zero`](crate::BorderMode::Zero):
+//! megabytes per call in tight loops, e.g. on every frame of a video. The pool keeps the scratch
+//! buffers of finished calls, and the outputs given back by
+//! [`ConvProcessor::recycle`](crate::ConvProcessor::recycle), for the next calls to take
+//! instead of allocating. Without `std` (no `Mutex`), it keeps nothing.
+//!
+//! New buffers come from the [`ImageAllocator`] of the processor, and outputs of other
+//! allocators are not kept.
+
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::sync::Mutex;
+
+use crate::{
+    allocator::{self, ImageAllocator, ImageBuffer},
+    processor::Scratch,
+};
+
+// buffers of each kind kept at most, so that images of many sizes do not pile up
+#[cfg(feature = "std")]
+const MAX_BUFFERS: usize = 4;
+
+#[derive(Debug, Default)]
+pub(crate) struct BufferPool {
+    allocator: ImageAllocator,
+    #[cfg(feature = "std")]
+    scratch: Mutex<Vec<Scratch>>,
+    #[cfg(feature = "std")]
+    outputs: Mutex<Vec<ImageBuffer>>,
+}
+
+impl BufferPool {
+    pub(crate) fn new(allocator: ImageAllocator) -> Self {
+        Self {
+            allocator,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn allocator(&self) -> &ImageAllocator {
+        &self.allocator
+    }
+
+    // scratch buffers of a finished call, or empty ones
+    pub(crate) fn scratch(&self) -> Scratch {
+        #[cfg(feature = "std")]
+        if let Some(scratch) = self.scratch.lock().unwrap().pop() {
+            return scratch;
+        }
+        Scratch::new_in(self.allocator.clone())
+    }
+
+    // keeps `scratch` for the next calls unless the pool is full
+    pub(crate) fn put_scratch(&self, scratch: Scratch) {
+        #[cfg(feature = "std")]
+        {
+            let mut pool = self.scratch.lock().unwrap();
+            if pool.len() < MAX_BUFFERS {
+                pool.push(scratch);
+            }
+        }
+        #[cfg(not(feature = "std"))]
+        drop(scratch);
+    }
+
+    // a buffer of `len` bytes with unspecified values: the smallest kept one whose capacity is
+    // large enough, or a new one
+    pub(crate) fn output(&self, len: usize) -> ImageBuffer {
+        #[cfg(feature = "std")]
+        {
+            let mut pool = self.outputs.lock().unwrap();
+            let best = (0..pool.len())
+                .filter(|&i| pool[i].capacity() >= len)
+                .min_by_key(|&i| pool[i].capacity());
+            if let Some(i) = best {
+                let mut buf = pool.swap_remove(i);
+                buf.resize(len, 0);
+                return buf;
+            }
+        }
+        allocator::zeroed_in(len, self.allocator.clone())
+    }
+
+    // keeps `buf` for `output`, in place of the smallest kept buffer if the pool is full
+    pub(crate) fn put_output(&self, buf: ImageBuffer) {
+        #[cfg(feature = "std")]
+        if *buf.allocator() == self.allocator {
+            let mut pool = self.outputs.lock().unwrap();
+            if pool.len() < MAX_BUFFERS {
+                pool.push(buf);
+            } else if let Some(smallest) = pool.iter_mut().min_by_key(|b| b.capacity()) {
+                if smallest.capacity() < buf.capacity() {
+                    *smallest = buf;
+                }
+            }
+        }
+        #[cfg(not(feature = "std"))]
+        drop(buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outputs() {
+        let pool = BufferPool::default();
+        let (small, large) = (
+            allocator::from_global(vec![1; 100]),
+            allocator::from_global(vec![2; 1000]),
+        );
+        let (small_ptr, large_ptr) = (small.as_ptr(), large.as_ptr());
+        pool.put_output(large);
+        pool.put_output(small);
+
+        // the smallest buffer large enough, whatever its values
+        let buf = pool.output(80);
+        assert_eq!((buf.len(), buf.as_ptr()), (80, small_ptr));
+        let buf = pool.output(120);
+        assert_eq!((buf.len(), buf.as_ptr()), (120, large_ptr));
+        assert!(buf[100..].iter().all(|&v| v == 2));
+        assert!(pool.outputs.lock().unwrap().is_empty());
+
+        // the largest buffers are kept
+        for len in 1..=MAX_BUFFERS + 1 {
+            pool.put_output(allocator::zeroed_in(len * 10, ImageAllocator::global()));
+        }
+        let mut lens: Vec<_> = pool.outputs.lock().unwrap().iter().map(Vec::len).collect();
+        lens.sort_unstable();
+        assert_eq!(
+            lens,
+            (2..=MAX_BUFFERS + 1).map(|n| n * 10).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn scratch() {
+        let pool = BufferPool::default();
+        for _ in 0..MAX_BUFFERS + 1 {
+            pool.put_scratch(Scratch::default());
+        }
+        assert_eq!(pool.scratch.lock().unwrap().len(), MAX_BUFFERS);
+        pool.scratch();
+        assert_eq!(pool.scratch.lock().unwrap().len(), MAX_BUFFERS - 1);
+    }
+}
@@ -0,0 +1,35 @@
+//! Cooperative cancellation of running operations.
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Flag shared among threads to abort an operation in progress.
+///
+/// Clones refer to the same flag: keep one and give another to the operation, e.g. with
+/// [`ConvProcessorBuilder::cancel_token`](crate::processor::ConvProcessorBuilder::cancel_token).
+/// Operations check the flag between rows and fail with
+/// [`Error::Cancelled`](crate::Error::Cancelled) once it is set.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Creates a token which is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests operations using this token to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether [`cancel`](Self::cancel) has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Clears the flag so that the token can be reused.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
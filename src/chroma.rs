@@ -0,0 +1,404 @@
+//! Chroma subsampling (4:2:2 and 4:2:0) of YCbCr images, for planar video buffers such as I420.
+//!
+//! [`YCbCrImage::subsample`] averages the chroma over blocks of 2x1 or 2x2 pixels, i.e. chroma
+//! sited at the centers of the blocks as in JPEG, with NEON pairwise widening adds and rounding
+//! narrows on 16 pixels of a row at a time. [`SubsampledImage::upsample`] interpolates it back by
+//! the triangle filter, weighing the nearest chroma sample by 3/4 and the next one by 1/4 in each
+//! subsampled direction, with NEON on 8 chroma samples (16 pixels) of a row at a time. Odd sizes
+//! repeat the last column or row.
+
+use alloc::{vec, vec::Vec};
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+
+use crate::{
+    error::{Error, Result},
+    image::RgbImage,
+    ycbcr::YCbCrImage,
+    C,
+};
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::{
+    simd,
+    util::{vec_mut, vec_ref},
+};
+
+/// Resolution of the chroma planes of a [`SubsampledImage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsampling {
+    /// Half the width (4:2:2), e.g. I422.
+    Yuv422,
+    /// Half the width and half the height (4:2:0), e.g. I420.
+    Yuv420,
+}
+
+impl Subsampling {
+    /// Height and width of each chroma plane of an image of `height * width` pixels, rounded up.
+    pub fn chroma_size(self, height: usize, width: usize) -> (usize, usize) {
+        ((height + self.rows() - 1) / self.rows(), (width + 1) / 2)
+    }
+
+    // rows of pixels per chroma sample
+    fn rows(self) -> usize {
+        match self {
+            Subsampling::Yuv422 => 1,
+            Subsampling::Yuv420 => 2,
+        }
+    }
+}
+
+/// YCbCr image with subsampled chroma, stored as a Y plane of `height * width` bytes followed by
+/// the Cb and Cr planes of [`Subsampling::chroma_size`], i.e. the I420 or I422 layout.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubsampledImage {
+    planes: Vec<u8>,
+    height: usize,
+    width: usize,
+    subsampling: Subsampling,
+}
+
+impl SubsampledImage {
+    /// Wraps the Y, Cb and Cr planes of a buffer in the I420 or I422 layout.
+    ///
+    /// Fails with [`Error::InvalidLength`] if the length of `planes` is inconsistent.
+    pub fn from_raw(
+        planes: Vec<u8>,
+        height: usize,
+        width: usize,
+        subsampling: Subsampling,
+    ) -> Result<Self> {
+        let (ch, cw) = subsampling.chroma_size(height, width);
+        let expected = height * width + 2 * ch * cw;
+        if planes.len() != expected {
+            return Err(Error::InvalidLength {
+                expected,
+                actual: planes.len(),
+            });
+        }
+        Ok(Self {
+            planes,
+            height,
+            width,
+            subsampling,
+        })
+    }
+
+    /// Interpolates the chroma back to full resolution.
+    pub fn upsample(&self) -> YCbCrImage {
+        let len = self.height * self.width;
+        let mut planes = vec![0; len * C];
+        planes[..len].copy_from_slice(self.plane(0));
+        for c in 1..C {
+            upsample_plane(
+                self.plane(c),
+                self.height,
+                self.width,
+                self.subsampling,
+                &mut planes[c * len..(c + 1) * len],
+            );
+        }
+        YCbCrImage {
+            planes,
+            height: self.height,
+            width: self.width,
+        }
+    }
+
+    /// Converts back to RGB, by [`upsample`](Self::upsample) and [`YCbCrImage::to_rgb`].
+    pub fn to_rgb(&self) -> RgbImage {
+        self.upsample().to_rgb()
+    }
+
+    /// The planes one after another, e.g. to write an I420 frame.
+    pub fn content(&self) -> &[u8] {
+        &self.planes
+    }
+
+    /// Height in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Width in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Resolution of the chroma planes.
+    pub fn subsampling(&self) -> Subsampling {
+        self.subsampling
+    }
+
+    /// Plane `c` (0 for Y, 1 for Cb and 2 for Cr), row by row.
+    ///
+    /// # Panics
+    ///
+    /// If `c` is 3 or more.
+    pub fn plane(&self, c: usize) -> &[u8] {
+        let range = self.plane_range(c);
+        &self.planes[range]
+    }
+
+    /// Mutable plane `c`, see [`plane`](Self::plane).
+    pub fn plane_mut(&mut self, c: usize) -> &mut [u8] {
+        let range = self.plane_range(c);
+        &mut self.planes[range]
+    }
+
+    fn plane_range(&self, c: usize) -> core::ops::Range<usize> {
+        assert!(c < C, "no plane {}", c);
+        let (ch, cw) = self.subsampling.chroma_size(self.height, self.width);
+        let (luma, chroma) = (self.height * self.width, ch * cw);
+        match c {
+            0 => 0..luma,
+            _ => luma + (c - 1) * chroma..luma + c * chroma,
+        }
+    }
+}
+
+impl YCbCrImage {
+    /// Keeps the luma and averages the chroma over blocks of pixels, rounded to the nearest
+    /// integer.
+    pub fn subsample(&self, subsampling: Subsampling) -> SubsampledImage {
+        let (ch, cw) = subsampling.chroma_size(self.height, self.width);
+        let len = self.height * self.width;
+        let mut planes = vec![0; len + 2 * ch * cw];
+        planes[..len].copy_from_slice(self.plane(0));
+        for c in 1..C {
+            let start = len + (c - 1) * ch * cw;
+            subsample_plane(
+                self.plane(c),
+                self.height,
+                self.width,
+                subsampling,
+                &mut planes[start..start + ch * cw],
+            );
+        }
+        SubsampledImage {
+            planes,
+            height: self.height,
+            width: self.width,
+            subsampling,
+        }
+    }
+}
+
+impl RgbImage {
+    /// Converts to YCbCr with subsampled chroma, by [`YCbCrImage::from_rgb`] and
+    /// [`YCbCrImage::subsample`].
+    pub fn to_subsampled(&self, subsampling: Subsampling) -> SubsampledImage {
+        YCbCrImage::from_rgb(self).subsample(subsampling)
+    }
+}
+
+// averages of the plane `src` of `height * width` bytes over the blocks of `subsampling` into
+// `dst`, repeating the last column and row of odd sizes
+fn subsample_plane(
+    src: &[u8],
+    height: usize,
+    width: usize,
+    subsampling: Subsampling,
+    dst: &mut [u8],
+) {
+    if dst.is_empty() {
+        return;
+    }
+    let (rows, cw) = (subsampling.rows(), (width + 1) / 2);
+    for (cy, dst) in dst.chunks_exact_mut(cw).enumerate() {
+        // 4:2:2 averages the same row twice
+        let y0 = cy * rows;
+        let y1 = (y0 + rows - 1).min(height - 1);
+        let (r0, r1) = (
+            &src[y0 * width..(y0 + 1) * width],
+            &src[y1 * width..(y1 + 1) * width],
+        );
+        // samples from `start` are left to the serial loop
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let start = {
+            let end = width - width % 16;
+            for x in (0..end).step_by(16) {
+                unsafe {
+                    let sums = vpadalq_u8(
+                        vpaddlq_u8(simd::load_bytes16(r0, x)),
+                        simd::load_bytes16(r1, x),
+                    );
+                    vst1_u8(vec_mut(dst, x / 2, 8), vrshrn_n_u16::<2>(sums));
+                }
+            }
+            end / 2
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let start = 0;
+        for (cx, d) in dst.iter_mut().enumerate().skip(start) {
+            let (x0, x1) = (2 * cx, (2 * cx + 1).min(width - 1));
+            let sum = r0[x0] as u16 + r0[x1] as u16 + r1[x0] as u16 + r1[x1] as u16;
+            *d = ((sum + 2) >> 2) as u8;
+        }
+    }
+}
+
+// the chroma plane `src` interpolated to `height * width` bytes into `dst` by the triangle filter:
+// each pixel takes 3/4 of the nearest sample and 1/4 of the next one on the other side of the
+// pixel, vertically for 4:2:0 and then horizontally, in 16 times the values
+fn upsample_plane(
+    src: &[u8],
+    height: usize,
+    width: usize,
+    subsampling: Subsampling,
+    dst: &mut [u8],
+) {
+    if dst.is_empty() {
+        return;
+    }
+    let rows = subsampling.rows();
+    let (ch, cw) = subsampling.chroma_size(height, width);
+    // 4 times the rows interpolated vertically
+    let mut sums = vec![0u16; cw];
+    for (y, dst) in dst.chunks_exact_mut(width).enumerate() {
+        let cy = y / rows;
+        // the same row without vertical subsampling, else the row above even rows and below odd
+        // ones
+        let far = match (rows, y % 2) {
+            (1, _) => cy,
+            (_, 0) => cy.saturating_sub(1),
+            _ => (cy + 1).min(ch - 1),
+        };
+        let (near, far) = (&src[cy * cw..(cy + 1) * cw], &src[far * cw..(far + 1) * cw]);
+        // samples from `start` are left to the serial loop
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let start = {
+            let end = cw - cw % 8;
+            for i in (0..end).step_by(8) {
+                unsafe {
+                    let (n, f) = (vld1_u8(vec_ref(near, i, 8)), vld1_u8(vec_ref(far, i, 8)));
+                    let v = vmlaq_n_u16(vmovl_u8(f), vmovl_u8(n), 3);
+                    vst1q_u16(vec_mut(&mut sums, i, 8), v);
+                }
+            }
+            end
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let start = 0;
+        for i in start..cw {
+            sums[i] = 3 * near[i] as u16 + far[i] as u16;
+        }
+
+        // samples 1 to `end` (pixels 2 to `2 * end`) have both neighbors in NEON, the others are
+        // left to the serial loop
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let end = {
+            let end = 1 + cw.saturating_sub(2) / 8 * 8;
+            for i in (1..end).step_by(8) {
+                unsafe {
+                    let v = vld1q_u16(vec_ref(&sums, i, 8));
+                    let prev = vld1q_u16(vec_ref(&sums, i - 1, 8));
+                    let next = vld1q_u16(vec_ref(&sums, i + 1, 8));
+                    let even = vrshrn_n_u16::<4>(vmlaq_n_u16(prev, v, 3));
+                    let odd = vrshrn_n_u16::<4>(vmlaq_n_u16(next, v, 3));
+                    vst2_u8(vec_mut(dst, 2 * i, 16), uint8x8x2_t(even, odd));
+                }
+            }
+            end
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let end = 1;
+        for x in (0..2.min(width)).chain(2 * end..width) {
+            let i = x / 2;
+            let next = match x % 2 {
+                0 => i.saturating_sub(1),
+                _ => (i + 1).min(cw - 1),
+            };
+            dst[x] = ((3 * sums[i] + sums[next] + 8) >> 4) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::photo_image;
+
+    #[test]
+    fn subsample() -> Result<()> {
+        // vectors of 16 pixels with some left over, and odd sizes
+        let img = photo_image(7, 37, 55);
+        let ycbcr = YCbCrImage::from_rgb(&img);
+        for subsampling in [Subsampling::Yuv422, Subsampling::Yuv420] {
+            let sub = ycbcr.subsample(subsampling);
+            let (ch, cw) = subsampling.chroma_size(7, 37);
+            let rows = match subsampling {
+                Subsampling::Yuv422 => 7,
+                Subsampling::Yuv420 => 4,
+            };
+            assert_eq!((ch, cw), (rows, 19));
+            assert_eq!(sub.content().len(), 7 * 37 + 2 * ch * cw);
+            assert_eq!(sub.plane(0), ycbcr.plane(0));
+            for c in 1..C {
+                let full = ycbcr.plane(c);
+                let at = |y: usize, x: usize| full[y.min(6) * 37 + x.min(36)] as u16;
+                for cy in 0..ch {
+                    for cx in 0..cw {
+                        let (y, x) = (cy * subsampling.rows(), 2 * cx);
+                        let (y1, x1) = (y + subsampling.rows() - 1, x + 1);
+                        let sum = at(y, x) + at(y, x1) + at(y1, x) + at(y1, x1);
+                        assert_eq!(sub.plane(c)[cy * cw + cx] as u16, (sum + 2) / 4);
+                    }
+                }
+            }
+            let raw = SubsampledImage::from_raw(sub.content().to_vec(), 7, 37, subsampling)?;
+            assert_eq!(raw, sub);
+        }
+        assert!(matches!(
+            SubsampledImage::from_raw(vec![0; 7 * 37 * 3 / 2], 7, 37, Subsampling::Yuv420),
+            Err(Error::InvalidLength { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn upsample() -> Result<()> {
+        // the triangle filter of a known plane, in 16 times the values
+        let (h, w) = (5, 35);
+        for subsampling in [Subsampling::Yuv422, Subsampling::Yuv420] {
+            let (ch, cw) = subsampling.chroma_size(h, w);
+            let mut sub =
+                SubsampledImage::from_raw(vec![0; h * w + 2 * ch * cw], h, w, subsampling)?;
+            for (i, v) in sub.plane_mut(1).iter_mut().enumerate() {
+                *v = (i * 37 % 256) as u8;
+            }
+            sub.plane_mut(2).fill(99);
+            let up = sub.upsample();
+            let chroma =
+                |y: usize, x: usize| sub.plane(1)[y.min(ch - 1) * cw + x.min(cw - 1)] as u16;
+            for y in 0..h {
+                for x in 0..w {
+                    let (cy, cx) = (y / subsampling.rows(), x / 2);
+                    let fy = match (subsampling, y % 2) {
+                        (Subsampling::Yuv422, _) => cy,
+                        (_, 0) => cy.saturating_sub(1),
+                        _ => cy + 1,
+                    };
+                    let fx = if x % 2 == 0 {
+                        cx.saturating_sub(1)
+                    } else {
+                        cx + 1
+                    };
+                    let col = |x| 3 * chroma(cy, x) + chroma(fy, x);
+                    let expected = (3 * col(cx) + col(fx) + 8) / 16;
+                    assert_eq!(up.plane(1)[y * w + x] as u16, expected, "{} {}", y, x);
+                }
+            }
+            assert!(up.plane(2).iter().all(|&v| v == 99));
+        }
+
+        // flat chroma comes back exactly, up to the conversions
+        let mut img = photo_image(9, 40, 56);
+        for p in img.content_mut().chunks_exact_mut(C) {
+            p[0] = p[1];
+            p[2] = p[1];
+        }
+        let back = img.to_subsampled(Subsampling::Yuv420).to_rgb();
+        assert!(back.approx_eq(&img, 1, 0));
+        Ok(())
+    }
+}
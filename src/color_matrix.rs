@@ -0,0 +1,184 @@
+//! Linear color transforms by a 3x3 matrix, e.g. sepia, channel mixing or the color correction
+//! matrix of a camera, and conversions to gray by weights of the channels.
+
+use alloc::{vec, vec::Vec};
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::float32x4_t;
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{
+    image::RgbImage,
+    kernel::{fma, Rounding},
+    C,
+};
+
+/// Matrix of the usual sepia tone.
+#[rustfmt::skip]
+pub const SEPIA: [[f32; C]; C] = [
+    [0.393, 0.769, 0.189],
+    [0.349, 0.686, 0.168],
+    [0.272, 0.534, 0.131],
+];
+
+/// Luma weights of the channels in BT.601 (standard definition video and JPEG).
+pub const LUMA_BT601: [f32; C] = [0.299, 0.587, 0.114];
+
+/// Luma weights of the channels in BT.709 (high definition video and sRGB).
+pub const LUMA_BT709: [f32; C] = [0.2126, 0.7152, 0.0722];
+
+/// Matrix converting to gray with the luma weights of BT.601, the same in every channel.
+pub const GRAYSCALE: [[f32; C]; C] = [LUMA_BT601; C];
+
+impl RgbImage {
+    /// Image whose pixels are `m` times the pixels of this one, i.e. channel `c` is
+    /// `m[c][0] * r + m[c][1] * g + m[c][2] * b`, rounded to the nearest integer and saturated
+    /// to `[0, 255]`. NEON computes 16 pixels at a time with the same fused multiply-adds.
+    pub fn apply_color_matrix(&self, m: [[f32; C]; C]) -> RgbImage {
+        let src = self.content();
+        let mut dst = vec![0; src.len()];
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let start = {
+            let m = m.map(|row| row.map(simd::splat));
+            let simd_end = src.len() - src.len() % (16 * C);
+            for i in (0..simd_end).step_by(16 * C) {
+                let v = simd::load_rgb16(src, i);
+                let out = [0, 1, 2].map(|c| weighted16(&v, &m[c]));
+                simd::store_rgb16(&mut dst, i, &out, Rounding::Nearest);
+            }
+            simd_end
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let start = 0;
+        for (s, d) in src[start..]
+            .chunks_exact(C)
+            .zip(dst[start..].chunks_exact_mut(C))
+        {
+            for (d, &m) in d.iter_mut().zip(m.iter()) {
+                *d = weighted(s, m);
+            }
+        }
+        RgbImage::from_raw_unchecked(dst, self.height, self.width)
+    }
+
+    /// Gray value `weights[0] * r + weights[1] * g + weights[2] * b` of every pixel, rounded to
+    /// the nearest integer and saturated to `[0, 255]`, as a plane of bytes row by row.
+    ///
+    /// Weights are e.g. [`LUMA_BT601`] or [`LUMA_BT709`] for the luminance, or
+    /// `[-1., 2., -1.]` for the excess green index of vegetation. The values are those of the
+    /// channels of [`apply_color_matrix`](RgbImage::apply_color_matrix) with `[weights; 3]`.
+    pub fn to_gray(&self, weights: [f32; C]) -> Vec<u8> {
+        let src = self.content();
+        let mut dst = vec![0; src.len() / C];
+        // pixels from `start` are left to the serial loop
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let start = {
+            let w = weights.map(simd::splat);
+            let simd_end = dst.len() - dst.len() % 16;
+            for x in (0..simd_end).step_by(16) {
+                let v = weighted16(&simd::load_rgb16(src, x * C), &w);
+                simd::store_u8x16(&mut dst, x, &v, Rounding::Nearest);
+            }
+            simd_end
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let start = 0;
+        for (d, s) in dst[start..]
+            .iter_mut()
+            .zip(src[start * C..].chunks_exact(C))
+        {
+            *d = weighted(s, weights);
+        }
+        dst
+    }
+}
+
+// `w[0] * r + w[1] * g + w[2] * b` of `pixel`, rounded to the nearest integer and saturated
+pub(crate) fn weighted(pixel: &[u8], w: [f32; C]) -> u8 {
+    let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|v| v as f32);
+    Rounding::Nearest.to_u8(fma(b, w[2], fma(g, w[1], r * w[0])))
+}
+
+// `weighted` for 16 pixels, with the same fused multiply-adds
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+pub(crate) fn weighted16(v: &simd::Rgb16, w: &[float32x4_t; C]) -> [float32x4_t; 4] {
+    let mut out = [simd::splat(0.); 4];
+    for (z, out) in out.iter_mut().enumerate() {
+        let acc = simd::mul(v[0][z], w[0]);
+        let acc = simd::fma(acc, v[1][z], w[1]);
+        *out = simd::fma(acc, v[2][z], w[2]);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Result, test_util::photo_image, PixelOrder};
+
+    #[test]
+    fn matrices() -> Result<()> {
+        // longer than a vector of 16 pixels, with some left over
+        let img = photo_image(3, 19, 9);
+        let identity = [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]];
+        assert_eq!(img.apply_color_matrix(identity), img);
+
+        let swapped = img.apply_color_matrix([[0., 0., 1.], [0., 1., 0.], [1., 0., 0.]]);
+        let bgr =
+            RgbImage::from_raw(img.content().to_vec(), 3, 19)?.into_raw_ordered(PixelOrder::Bgr);
+        assert_eq!(swapped.content(), &bgr[..]);
+
+        let gray = img.apply_color_matrix(GRAYSCALE);
+        assert!(gray
+            .content()
+            .chunks_exact(C)
+            .all(|p| p[0] == p[1] && p[1] == p[2]));
+
+        let pixels = RgbImage::from_raw(vec![100, 150, 210, 255, 255, 255], 1, 2)?;
+        // 0.393 * 100 + 0.769 * 150 + 0.189 * 210 = 194.34 and so on, and white saturated
+        assert_eq!(
+            pixels.apply_color_matrix(SEPIA).content(),
+            [194, 173, 135, 255, 255, 239]
+        );
+        let mixed = pixels.apply_color_matrix([[-1., 0., 0.], [0., 2., 0.], [0., 0., 1.]]);
+        assert_eq!(mixed.content(), [0, 255, 210, 0, 255, 255]);
+        Ok(())
+    }
+
+    #[test]
+    fn to_gray() -> Result<()> {
+        // the channels of the matrix with the same rows, over vectors of 16 pixels with some
+        // left over
+        let img = photo_image(5, 21, 51);
+        for weights in [LUMA_BT601, LUMA_BT709, [-1., 2., -1.], [0., 1., 0.]] {
+            let gray = img.to_gray(weights);
+            let matrix = img.apply_color_matrix([weights; C]);
+            let expected: Vec<u8> = matrix.content().iter().step_by(C).copied().collect();
+            assert_eq!(gray, expected);
+        }
+        // excess green of a leaf, a red brick and the gray sky
+        let pixels = RgbImage::from_raw(vec![60, 140, 50, 170, 70, 60, 200, 200, 200], 1, 3)?;
+        assert_eq!(pixels.to_gray([-1., 2., -1.]), [170, 0, 0]);
+        assert_eq!(pixels.to_gray([0., 1., 0.]), [140, 70, 200]);
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    #[test]
+    fn simd() {
+        // vectors of 16 pixels, and some left over
+        let img = photo_image(13, 21, 10);
+        let mixer = [[0.8, 0.3, -0.1], [-0.2, 1.4, -0.2], [0.05, -0.35, 1.3]];
+        for m in [SEPIA, GRAYSCALE, mixer] {
+            let expected: Vec<u8> = img
+                .content()
+                .chunks_exact(C)
+                .flat_map(|p| {
+                    let [r, g, b] = [p[0], p[1], p[2]].map(|v| v as f32);
+                    m.map(|m| Rounding::Nearest.to_u8(fma(b, m[2], fma(g, m[1], r * m[0]))))
+                })
+                .collect();
+            assert_eq!(img.apply_color_matrix(m).content(), &expected[..]);
+        }
+    }
+}
@@ -0,0 +1,334 @@
+//! Connected-component labeling of thresholded images, e.g. to find blobs after
+//! [`Pipeline::threshold`](crate::pipeline::Pipeline::threshold).
+//!
+//! [`label`] works on runs of foreground pixels rather than on pixels: the foreground of each row
+//! is found by NEON 16 pixels at a time, skipping empty vectors, then every run is merged with the
+//! runs it touches in the row above by union-find, and a second pass gives every run the label of
+//! its component.
+
+use alloc::{vec, vec::Vec};
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+use core::ops::Range;
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{image::RgbImage, C};
+
+/// Pixels which are neighbours, and connect components.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connectivity {
+    /// Pixels sharing a side.
+    Four,
+    /// Pixels sharing a side or a corner.
+    Eight,
+}
+
+/// Size and bounding box of a component.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Component {
+    /// Number of pixels.
+    pub area: usize,
+    /// Top row of the bounding box.
+    pub y: usize,
+    /// Left column of the bounding box.
+    pub x: usize,
+    /// Height of the bounding box.
+    pub height: usize,
+    /// Width of the bounding box.
+    pub width: usize,
+}
+
+/// Label of every pixel found by [`label`], 0 for the background and `i + 1` for the pixels of
+/// the component `i`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Labels {
+    labels: Vec<u32>,
+    height: usize,
+    width: usize,
+    components: Vec<Component>,
+}
+
+impl Labels {
+    /// Height in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Width in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Labels of all the pixels, row by row.
+    pub fn labels(&self) -> &[u32] {
+        &self.labels
+    }
+
+    /// Label of the pixel at `(y, x)`.
+    pub fn at(&self, y: usize, x: usize) -> u32 {
+        self.labels[y * self.width + x]
+    }
+
+    /// Components in the order of their first pixel, row by row.
+    pub fn components(&self) -> &[Component] {
+        &self.components
+    }
+}
+
+/// Labels the components of the foreground of `mask`, the pixels with any non-zero channel, in
+/// the order of their first pixel row by row.
+pub fn label(mask: &RgbImage, connectivity: Connectivity) -> Labels {
+    let (h, w) = (mask.height, mask.width);
+    // runs of every row, and the index of the first run of every row in `runs`
+    let mut runs: Vec<(usize, Range<usize>)> = Vec::new();
+    let mut rows = Vec::with_capacity(h + 1);
+    let mut fg = vec![0; w];
+    for y in 0..h {
+        rows.push(runs.len());
+        foreground(&mask.inner[y * w * C..][..w * C], &mut fg);
+        let mut x = 0;
+        while let Some(start) = next_foreground(&fg, x) {
+            let end = fg[start..]
+                .iter()
+                .position(|&v| v == 0)
+                .map_or(w, |n| start + n);
+            runs.push((y, start..end));
+            x = end;
+        }
+    }
+    rows.push(runs.len());
+
+    // runs touch those of the row above overlapping them, or only diagonally adjacent to them
+    // with 8-connectivity
+    let reach = match connectivity {
+        Connectivity::Four => 0,
+        Connectivity::Eight => 1,
+    };
+    let mut parents: Vec<usize> = (0..runs.len()).collect();
+    for y in 1..h {
+        let above = rows[y - 1]..rows[y];
+        let mut j = above.start;
+        for i in rows[y]..rows[y + 1] {
+            let run = &runs[i].1;
+            // runs above ending too far left of this run cannot touch the next ones either
+            while j < above.end && runs[j].1.end + reach <= run.start {
+                j += 1;
+            }
+            let mut k = j;
+            while k < above.end && runs[k].1.start < run.end + reach {
+                union(&mut parents, i, k);
+                k += 1;
+            }
+        }
+    }
+
+    // roots are the first runs of their component, so components are numbered in order
+    let mut labels = vec![0; h * w];
+    let mut components: Vec<Component> = Vec::new();
+    let mut run_labels = vec![0; runs.len()];
+    for (i, (y, xs)) in runs.iter().enumerate() {
+        let root = find(&mut parents, i);
+        let label = if root == i {
+            components.push(Component {
+                area: 0,
+                y: *y,
+                x: xs.start,
+                height: 1,
+                width: xs.len(),
+            });
+            components.len() as u32
+        } else {
+            run_labels[root]
+        };
+        run_labels[i] = label;
+        labels[y * w + xs.start..y * w + xs.end].fill(label);
+        let c = &mut components[label as usize - 1];
+        c.area += xs.len();
+        let right = (c.x + c.width).max(xs.end);
+        c.x = c.x.min(xs.start);
+        c.width = right - c.x;
+        c.height = y + 1 - c.y;
+    }
+    Labels {
+        labels,
+        height: h,
+        width: w,
+        components,
+    }
+}
+
+// root of the set of `i`, halving the paths on the way
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+// merges the sets of `a` and `b`, keeping the smaller root
+fn union(parents: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parents, a), find(parents, b));
+    parents[a.max(b)] = a.min(b);
+}
+
+// `fg[x]` 0xff where the pixel `x` of `row` has any non-zero channel, 0 elsewhere
+fn foreground(row: &[u8], fg: &mut [u8]) {
+    // pixels from `start` are left to the serial loop
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let start = unsafe {
+        let end = fg.len() - fg.len() % 16;
+        for x in (0..end).step_by(16) {
+            let [r, g, b] = simd::deinterleave16(row, x * C);
+            let any = vorrq_u8(vorrq_u8(r, g), b);
+            simd::store_bytes16(fg, x, vtstq_u8(any, any));
+        }
+        end
+    };
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    let start = 0;
+    for (f, pixel) in fg[start..].iter_mut().zip(row[start * C..].chunks_exact(C)) {
+        *f = if pixel.iter().any(|&v| v != 0) {
+            0xff
+        } else {
+            0
+        };
+    }
+}
+
+// first foreground pixel of `fg` from `x`
+fn next_foreground(fg: &[u8], x: usize) -> Option<usize> {
+    // skip empty vectors of 16 pixels
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let x = {
+        let mut x = x;
+        while x + 16 <= fg.len() && unsafe { vmaxvq_u8(simd::load_bytes16(fg, x)) } == 0 {
+            x += 16;
+        }
+        x
+    };
+    fg[x..].iter().position(|&v| v != 0).map(|n| x + n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Result, test_util::photo_image};
+
+    // mask of `h` x `w` pixels from the rows of `pattern`, `#` being the foreground
+    fn mask(pattern: &[&str]) -> Result<RgbImage> {
+        let content = pattern
+            .iter()
+            .flat_map(|row| row.bytes())
+            .flat_map(|b| [if b == b'#' { 255 } else { 0 }; C])
+            .collect();
+        RgbImage::from_raw(content, pattern.len(), pattern[0].len())
+    }
+
+    #[test]
+    fn label() -> Result<()> {
+        // a U merged only at its bottom, and a square with pixels touching it by their corners
+        let img = mask(&["#..#..##.", "#..#..##.", "####....#", ".......#."])?;
+        let four = super::label(&img, Connectivity::Four);
+        assert_eq!(four.components().len(), 4);
+        assert_eq!(
+            four.components()[0],
+            Component {
+                area: 8,
+                y: 0,
+                x: 0,
+                height: 3,
+                width: 4
+            }
+        );
+        assert_eq!(
+            (four.at(0, 3), four.at(1, 6), four.at(2, 8), four.at(3, 7)),
+            (1, 2, 3, 4)
+        );
+        assert_eq!(four.at(0, 1), 0);
+        let eight = super::label(&img, Connectivity::Eight);
+        assert_eq!(eight.components().len(), 2);
+        assert_eq!(
+            eight.components()[1],
+            Component {
+                area: 6,
+                y: 0,
+                x: 6,
+                height: 4,
+                width: 3
+            }
+        );
+
+        // the same labels as a flood fill from the first pixels of components, row by row, over
+        // vectors of 16 pixels with some left over
+        let img = photo_image(19, 37, 33);
+        let content = img
+            .content()
+            .chunks_exact(C)
+            .flat_map(|p| [if p[1] > 120 { 1 } else { 0 }, 0, 0])
+            .collect();
+        let img = RgbImage::from_raw(content, 19, 37)?;
+        for connectivity in [Connectivity::Four, Connectivity::Eight] {
+            let labels = super::label(&img, connectivity);
+            let expected = flood_fill(&img, connectivity);
+            assert_eq!(labels.labels(), &expected[..]);
+            for (i, c) in labels.components().iter().enumerate() {
+                let pixels: Vec<_> = (0..19 * 37)
+                    .filter(|&p| expected[p] == i as u32 + 1)
+                    .collect();
+                assert_eq!(c.area, pixels.len());
+                assert_eq!(c.y, pixels.iter().map(|p| p / 37).min().unwrap());
+                assert_eq!(
+                    c.x + c.width - 1,
+                    pixels.iter().map(|p| p % 37).max().unwrap()
+                );
+            }
+        }
+        assert!(super::label(&mask(&["...", "..."])?, Connectivity::Four)
+            .components()
+            .is_empty());
+        Ok(())
+    }
+
+    fn flood_fill(img: &RgbImage, connectivity: Connectivity) -> Vec<u32> {
+        let (h, w) = (img.height as isize, img.width as isize);
+        let fg = |y: isize, x: isize| {
+            (0..h).contains(&y)
+                && (0..w).contains(&x)
+                && img.content()[((y * w + x) as usize) * C] != 0
+        };
+        let mut labels = vec![0; (h * w) as usize];
+        let mut next = 0;
+        for i in 0..h * w {
+            if !fg(i / w, i % w) || labels[i as usize] != 0 {
+                continue;
+            }
+            next += 1;
+            let mut stack = vec![(i / w, i % w)];
+            labels[i as usize] = next;
+            while let Some((y, x)) = stack.pop() {
+                for (dy, dx) in [
+                    (-1, -1),
+                    (-1, 0),
+                    (-1, 1),
+                    (0, -1),
+                    (0, 1),
+                    (1, -1),
+                    (1, 0),
+                    (1, 1),
+                ] {
+                    if connectivity == Connectivity::Four && dy != 0 && dx != 0 {
+                        continue;
+                    }
+                    let (ny, nx) = (y + dy, x + dx);
+                    if fg(ny, nx) && labels[(ny * w + nx) as usize] == 0 {
+                        labels[(ny * w + nx) as usize] = next;
+                        stack.push((ny, nx));
+                    }
+                }
+            }
+        }
+        labels
+    }
+}
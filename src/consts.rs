@@ -1,5 +1,14 @@
+//! Paths of sample images and well-known filters.
+
+/// Sample image used by tests and benchmarks.
 pub const ORIGINAL: &str = "img/Lenna.png";
+/// Destination of the image saving benchmark.
 pub const BACKUP: &str = "img/Lenna_backup.png";
+/// Where a wrong result is saved when a test fails.
 pub const DEBUG: &str = "img/Lenna_debug.png";
+/// Where the heatmap of the differences of a wrong result is saved, see [`crate::diff`].
+pub const DEBUG_DIFF: &str = "img/Lenna_diff.png";
+/// Expected result of [`SOBEL_FILTER`] on [`ORIGINAL`].
 pub const SOBEL_ANS: &str = "img/sobel_ans.png";
+/// 3x3 Sobel filter detecting horizontal edges.
 pub const SOBEL_FILTER: [f32; 9] = [-1., -2., -1., 0., 0., 0., 1., 2., 1.];
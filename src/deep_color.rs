@@ -0,0 +1,233 @@
+//! Images with more than 8 bits per sample, e.g. 10 or 12-bit HDR video frames, stored in `u16`.
+//!
+//! [`ConvProcessor::convolve_u16`] filters them without crushing them to 8 bits first: samples
+//! are widened from `u16` to `f32` by NEON 8 at a time, and results are saturated to the range of
+//! the bit depth rather than to `[0, 255]`, by the [`Sample`](crate::sample::Sample)
+//! implementation of `u16`.
+
+use alloc::{format, vec, vec::Vec};
+
+use crate::{
+    error::{Error, Result},
+    image::RgbImage,
+    processor::ConvProcessor,
+    C,
+};
+
+/// RGB image of interleaved samples of `bits` bits (8 to 16), each in a `u16`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbImage16 {
+    inner: Vec<u16>,
+    height: usize,
+    width: usize,
+    bits: u32,
+}
+
+impl RgbImage16 {
+    /// Black image of `height` x `width` pixels.
+    ///
+    /// Fails with [`Error::UnsupportedFormat`] if `bits` is not in `8..=16`.
+    pub fn new(height: usize, width: usize, bits: u32) -> Result<Self> {
+        Self::from_raw(vec![0; height * width * C], height, width, bits)
+    }
+
+    /// Wraps interleaved samples of `height * width` pixels, which should not exceed
+    /// [`max`](Self::max).
+    ///
+    /// Fails with [`Error::UnsupportedFormat`] if `bits` is not in `8..=16`, or
+    /// [`Error::InvalidLength`] if the length of `content` is inconsistent.
+    pub fn from_raw(content: Vec<u16>, height: usize, width: usize, bits: u32) -> Result<Self> {
+        if !(8..=16).contains(&bits) {
+            return Err(Error::UnsupportedFormat(format!(
+                "{} bits per sample",
+                bits
+            )));
+        }
+        if content.len() != height * width * C {
+            return Err(Error::InvalidLength {
+                expected: height * width * C,
+                actual: content.len(),
+            });
+        }
+        Ok(Self {
+            inner: content,
+            height,
+            width,
+            bits,
+        })
+    }
+
+    /// Converts `src` to `bits` bits, 255 becoming [`max`](Self::max).
+    ///
+    /// Fails with [`Error::UnsupportedFormat`] if `bits` is not in `8..=16`.
+    pub fn from_rgb(src: &RgbImage, bits: u32) -> Result<Self> {
+        let max = (1u32 << bits.min(16)) - 1;
+        let content = src
+            .content()
+            .iter()
+            .map(|&v| ((v as u32 * max + 127) / 255) as u16)
+            .collect();
+        Self::from_raw(content, src.height, src.width, bits)
+    }
+
+    /// Converts the image to 8 bits, [`max`](Self::max) becoming 255, rounded to the nearest
+    /// integer.
+    pub fn to_rgb(&self) -> RgbImage {
+        let max = self.max() as u32;
+        let content = self
+            .inner
+            .iter()
+            .map(|&v| ((v.min(self.max()) as u32 * 255 + max / 2) / max) as u8)
+            .collect();
+        RgbImage::from_raw_unchecked(content, self.height, self.width)
+    }
+
+    /// Height in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Width in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Bits per sample.
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// Largest sample, `2^bits - 1`.
+    pub fn max(&self) -> u16 {
+        ((1u32 << self.bits) - 1) as u16
+    }
+
+    /// Samples of all the pixels, row by row.
+    pub fn content(&self) -> &[u16] {
+        &self.inner
+    }
+
+    /// Mutable samples of all the pixels, row by row.
+    pub fn content_mut(&mut self) -> &mut [u16] {
+        &mut self.inner
+    }
+}
+
+impl<const K: usize> ConvProcessor<K> {
+    /// Convolves `src` like [`convolve`](Self::convolve), but converts the results with the
+    /// [`Rounding`](crate::Rounding) of the kernel to samples of the bit depth of `src`,
+    /// saturated to `[0, src.max()]`. Pixels of the border are 0 with [`BorderMode::Zero`].
+    ///
+    /// Runs on the current thread with its own NEON implementation if available, whatever the
+    /// backend, threads and tiles of the processor.
+    ///
+    /// Fails with [`Error::ImageTooSmall`] if `src` is smaller than
+    /// [`min_size`](Self::min_size).
+    pub fn convolve_u16(&self, src: &RgbImage16) -> Result<RgbImage16> {
+        let content = self.convolve_samples(&src.inner, src.height, src.width, src.max())?;
+        RgbImage16::from_raw(content, src.height, src.width, src.bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consts::SOBEL_FILTER,
+        kernel::fma,
+        processor::{border_index, BorderMode},
+        test_util::photo_image,
+        Rounding,
+    };
+
+    #[test]
+    fn conversions() -> Result<()> {
+        let img = photo_image(5, 7, 17);
+        for bits in [8, 10, 12, 16] {
+            let deep = RgbImage16::from_rgb(&img, bits)?;
+            assert_eq!(deep.bits(), bits);
+            assert!(deep.content().iter().all(|&v| v <= deep.max()));
+            assert_eq!(deep.to_rgb(), img);
+        }
+        let white = RgbImage::from_raw(vec![255, 128, 0], 1, 1)?;
+        assert_eq!(RgbImage16::from_rgb(&white, 10)?.content(), [1023, 514, 0]);
+
+        assert!(matches!(
+            RgbImage16::new(2, 2, 17),
+            Err(Error::UnsupportedFormat(_))
+        ));
+        assert!(matches!(
+            RgbImage16::from_raw(vec![0; 11], 2, 2, 10),
+            Err(Error::InvalidLength {
+                expected: 12,
+                actual: 11
+            })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn convolve_u16() -> Result<()> {
+        // samples over the whole range, over vectors of 8 values with some left over
+        let (h, w) = (6, 11);
+        for bits in [10, 12] {
+            let max = (1 << bits) - 1;
+            let content = (0..h * w * C)
+                .map(|i| (i * 389 % (max + 1)) as u16)
+                .collect();
+            let img = RgbImage16::from_raw(content, h, w, bits)?;
+            for border in [BorderMode::Zero, BorderMode::Reflect] {
+                for rounding in [Rounding::Truncate, Rounding::Nearest] {
+                    // negative and larger than `max` results, saturated
+                    for (filter, normalize) in [(&SOBEL_FILTER, false), (&[1.; 9], true)] {
+                        let processor = ConvProcessor::<3>::builder()
+                            .kernel(filter)
+                            .normalize(normalize)
+                            .border(border)
+                            .rounding(rounding)
+                            .build()?;
+                        let convolved = processor.convolve_u16(&img)?;
+                        assert_eq!(convolved.bits(), bits);
+                        assert_eq!(convolved.content(), &reference(&processor, &img)[..]);
+                    }
+                }
+            }
+        }
+
+        // the same as with 8 bits, for 8-bit samples
+        let img = photo_image(9, 21, 18);
+        let blur = ConvProcessor::<3>::new(&[1.; 9], true)?;
+        let deep = blur.convolve_u16(&RgbImage16::from_rgb(&img, 8)?)?;
+        assert_eq!(deep.to_rgb(), blur.convolve(&img)?);
+        Ok(())
+    }
+
+    // every value computed independently, with `border_index` for those out of `src`
+    fn reference<const K: usize>(processor: &ConvProcessor<K>, src: &RgbImage16) -> Vec<u16> {
+        let (h, w) = (src.height, src.width);
+        let (kernel, border, half) = (processor.kernel(), processor.border(), K / 2);
+        (0..h * w * C)
+            .map(|index| {
+                let (y, x, c) = (index / C / w, index / C % w, index % C);
+                if border == BorderMode::Zero
+                    && (y < half || x < half || y >= h - half || x >= w - half)
+                {
+                    return 0;
+                }
+                let mut t = 0.;
+                for i in 0..K {
+                    for j in 0..K {
+                        let sy = border_index((y + i) as isize - half as isize, h, border);
+                        let sx = border_index((x + j) as isize - half as isize, w, border);
+                        let v = src.content()[(sy * w + sx) * C + c] as f32;
+                        t = fma(v, kernel.at(i, j), t);
+                    }
+                }
+                if let Some(div) = kernel.div() {
+                    t /= div;
+                }
+                kernel.rounding.to_u16(t, src.max())
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,210 @@
+//! Depthwise-separable convolution, the building block of mobile CNNs.
+
+use alloc::vec;
+
+use crate::{error::Result, image::RgbImage, kernel::ConvKernel, C};
+
+/// Depthwise-separable convolution: an independent KxK kernel for each channel (depthwise)
+/// followed by a 1x1 kernel mixing channels of each pixel (pointwise).
+#[derive(Debug)]
+pub struct DepthwiseSeparableProcessor<const K: usize> {
+    depthwise: [ConvKernel<K>; C],
+    pointwise: [[f32; C]; C],
+}
+
+impl<const K: usize> DepthwiseSeparableProcessor<K> {
+    /// `filters[c]` is applied to channel `c`, and `pointwise[o][c]` is the weight of
+    /// depthwise result of channel `c` on output channel `o`.
+    pub fn new(filters: [&[f32]; C], avg: bool, pointwise: [[f32; C]; C]) -> Result<Self> {
+        Ok(Self {
+            depthwise: [
+                ConvKernel::<K>::new(filters[0], avg)?,
+                ConvKernel::<K>::new(filters[1], avg)?,
+                ConvKernel::<K>::new(filters[2], avg)?,
+            ],
+            pointwise,
+        })
+    }
+
+    /// Scalar implementation.
+    pub fn naive(&self, src: &RgbImage) -> Result<RgbImage> {
+        src.check_min_size(K)?;
+        let h = src.height;
+        let w = src.width;
+        let half = K / 2;
+        let xend = w - half;
+        let yend = h - half;
+        let mut dst = vec![0u8; h * w * C]; // 0 padding
+
+        for y in half..yend {
+            for x in half..xend {
+                self.peel_loop(x, y, src, &mut dst);
+            }
+        }
+        Ok(RgbImage::from_raw_unchecked(dst, h, w))
+    }
+
+    /// NEON implementation computing 16 pixels at once with deinterleaving loads and stores.
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    pub fn simd(&self, src: &RgbImage) -> Result<RgbImage> {
+        src.check_min_size(K)?;
+        use crate::{kernel::Rounding, simd};
+
+        let h = src.height;
+        let w = src.width;
+        let half = K / 2;
+        let xend = w - half;
+        let yend = h - half;
+        let mut dst = vec![0u8; h * w * C]; // 0 padding
+
+        // read/write 16 elements in parallel
+        let simd_end = w - half - (w - 2 * half) % 16;
+
+        let simd_loop = |x: usize, y: usize, dst: &mut [u8]| {
+            // acc[c][z] holds channel c of (4z)..(4z+3) th pixels
+            let mut acc = [[simd::splat(0.); 4]; C];
+            for i in 0..K {
+                for j in 0..K {
+                    let base_index = (y - half + i) * w * C + (x - half + j) * C;
+                    // deinterleaved loading, each channel has its own kernel
+                    let sc = simd::load_rgb16(src.content(), base_index);
+                    for (c, vs) in sc.iter().enumerate() {
+                        let kern = simd::splat(self.depthwise[c].at(i, j));
+                        for (vt, &v) in acc[c].iter_mut().zip(vs.iter()) {
+                            *vt = simd::fma(*vt, v, kern);
+                        }
+                    }
+                }
+            }
+            for (c, vts) in acc.iter_mut().enumerate() {
+                if let Some(div) = self.depthwise[c].div.map(simd::Divisor::new) {
+                    for vt in vts.iter_mut() {
+                        *vt = div.divide(*vt);
+                    }
+                }
+            }
+
+            // pointwise: mul and add separately to get the same rounding as peel_loop
+            let mut out = [[simd::splat(0.); 4]; C];
+            for (o, vts) in out.iter_mut().enumerate() {
+                for (z, vt) in vts.iter_mut().enumerate() {
+                    for (c, channel) in acc.iter().enumerate() {
+                        let weight = simd::splat(self.pointwise[o][c]);
+                        *vt = simd::add(*vt, simd::mul(channel[z], weight));
+                    }
+                }
+            }
+
+            simd::store_rgb16(dst, y * w * C + x * C, &out, Rounding::Truncate);
+        };
+
+        // main execution
+        for y in half..yend {
+            for x in (half..simd_end).step_by(16) {
+                simd_loop(x, y, &mut dst);
+            }
+
+            for x in simd_end..xend {
+                self.peel_loop(x, y, src, &mut dst);
+            }
+        }
+        Ok(RgbImage::from_raw_unchecked(dst, h, w))
+    }
+
+    fn peel_loop(&self, x: usize, y: usize, src: &RgbImage, dst: &mut [u8]) {
+        let w = src.width;
+        let half = K / 2;
+        let mut rgb: [f32; 3] = [0.; C];
+        for i in 0..K {
+            for j in 0..K {
+                for (c, pix) in rgb.iter_mut().enumerate() {
+                    let index = (y - half + i) * w * C + (x - half + j) * C + c;
+                    *pix += src.content()[index] as f32 * self.depthwise[c].at(i, j);
+                }
+            }
+        }
+        for (c, pix) in rgb.iter_mut().enumerate() {
+            if let Some(div) = self.depthwise[c].div {
+                *pix /= div;
+            }
+        }
+
+        let base_index = y * w * C + x * C;
+        for (o, weights) in self.pointwise.iter().enumerate() {
+            let mut t: f32 = 0.;
+            for (&weight, &pix) in weights.iter().zip(rgb.iter()) {
+                t += pix * weight;
+            }
+            dst[base_index + o] = t.clamp(u8::MIN as f32, u8::MAX as f32) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{consts::*, ConvProcessor};
+
+    const IDENTITY: [[f32; C]; C] = [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]];
+
+    #[test]
+    fn same_filters_with_identity() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let boxf = [1.; 9];
+        let layer = DepthwiseSeparableProcessor::<3>::new([&boxf; C], true, IDENTITY)?;
+        assert_eq!(
+            layer.naive(&img)?,
+            ConvProcessor::<3>::new(&boxf, true)?.naive2(&img)?
+        );
+
+        let layer = DepthwiseSeparableProcessor::<3>::new([&SOBEL_FILTER; C], false, IDENTITY)?;
+        assert_eq!(
+            layer.naive(&img)?,
+            ConvProcessor::<3>::new(&SOBEL_FILTER, false)?.naive2(&img)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pointwise_mixes_channels() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let mut center = [0.; 9];
+        center[4] = 1.;
+        // BGR order and gray in the middle
+        let pointwise = [[0., 0., 1.], [1. / 3., 1. / 3., 1. / 3.], [1., 0., 0.]];
+        let layer = DepthwiseSeparableProcessor::<3>::new([&center; C], false, pointwise)?;
+        let processed = layer.naive(&img)?;
+
+        let (h, w) = (img.height, img.width);
+        for y in 1..h - 1 {
+            for x in 1..w - 1 {
+                let i = (y * w + x) * C;
+                let (r, g, b) = (
+                    img.inner[i] as f32,
+                    img.inner[i + 1] as f32,
+                    img.inner[i + 2] as f32,
+                );
+                let gray = r * (1. / 3.) + g * (1. / 3.) + b * (1. / 3.);
+                assert_eq!(processed.inner[i..i + C], [b as u8, gray as u8, r as u8]);
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), all(target_feature = "neon")))]
+    #[test]
+    fn simd() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let boxf = [1.; 25];
+        let laplacian: Vec<f32> = (0..25).map(|i| if i == 12 { -24. } else { 1. }).collect();
+        let emboss: Vec<f32> = (0..25).map(|i| (i % 5) as f32 - 2.).collect();
+        let pointwise = [[0.5, 0.25, 0.25], [-1., 2., 0.], [0., 1., 1.]];
+        let layer =
+            DepthwiseSeparableProcessor::<5>::new([&boxf, &laplacian, &emboss], false, pointwise)?;
+        assert_eq!(layer.simd(&img)?, layer.naive(&img)?);
+
+        let layer = DepthwiseSeparableProcessor::<5>::new([&boxf; C], true, IDENTITY)?;
+        assert_eq!(layer.simd(&img)?, layer.naive(&img)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,432 @@
+//! Differences between two images, to find out where and how a result is wrong.
+//!
+//! [`diff`] compares a result with the expected image, e.g. of [`Naive1`](crate::backend::Naive1),
+//! and gives a heatmap of the differing pixels with a [`DiffSummary`]. SIMD backends compute 16
+//! pixels per vector, so a miscomputed lane shows up as a column every 16 pixels in the heatmap,
+//! and in [`DiffSummary::lanes`].
+//!
+//! Results of lossy backends are rather compared with [`RgbImage::approx_eq`], which tolerates
+//! small differences in a few pixels, or by their mean errors ([`RgbImage::mse`] and
+//! [`RgbImage::mae`]), which also measure the quality of denoisers. Errors are accumulated with
+//! NEON absolute differences and widening multiplies 16 pixels at a time.
+
+use alloc::vec::Vec;
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+use core::fmt;
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{
+    error::{Error, Result},
+    image::RgbImage,
+    C,
+};
+
+/// Pixels per NEON vector of `u8` lanes, by which differing pixels are counted.
+pub const LANES: usize = 16;
+
+// vectors of 16 pixels whose squared errors are added up in 32-bit lanes before moving their sums
+// to `u64`: a lane gains at most 4 * 255^2 per vector
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+const FLUSH: usize = 1 << 14;
+
+/// Statistics of the differences between two images.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiffSummary {
+    /// Number of pixels with any differing channel.
+    pub pixels: usize,
+    /// Number of differing bytes.
+    pub bytes: usize,
+    /// Largest absolute difference of a byte.
+    pub max: u8,
+    /// Mean absolute difference over all bytes.
+    pub mean: f64,
+    /// `(y, x)` of the first differing pixel in row-major order, if any.
+    pub first: Option<(usize, usize)>,
+    /// Number of differing pixels at each `x % 16`, i.e. in each lane of the vectors of 16
+    /// pixels computed by SIMD backends from the left border.
+    pub lanes: [usize; LANES],
+}
+
+impl DiffSummary {
+    /// Whether the images are the same.
+    pub fn is_empty(&self) -> bool {
+        self.bytes == 0
+    }
+}
+
+impl fmt::Display for DiffSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} pixels ({} bytes) differ, max {}, mean {:.4}",
+            self.pixels, self.bytes, self.max, self.mean
+        )?;
+        if let Some((y, x)) = self.first {
+            write!(
+                f,
+                ", first at (y, x) = ({}, {}), by x % 16: {:?}",
+                y, x, self.lanes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Differences of `actual` from `expected`.
+#[derive(Debug)]
+pub struct Diff {
+    /// Statistics of the differences.
+    pub summary: DiffSummary,
+    /// Image of the same size, where pixels which are the same in both images are a dark gray
+    /// version of `expected`, and differing ones go from red to yellow as the largest difference
+    /// among their channels grows to [`DiffSummary::max`].
+    pub heatmap: RgbImage,
+}
+
+/// Compares `actual` with `expected` byte by byte.
+///
+/// Fails with [`Error::SizeMismatch`] if they do not have the same size.
+pub fn diff(actual: &RgbImage, expected: &RgbImage) -> Result<Diff> {
+    if (actual.height, actual.width) != (expected.height, expected.width) {
+        return Err(Error::SizeMismatch {
+            expected: (expected.height, expected.width),
+            actual: (actual.height, actual.width),
+        });
+    }
+    let w = expected.width;
+    // largest difference among the channels of each pixel
+    let mut pixel_diffs = Vec::with_capacity(expected.height * w);
+    let mut summary = DiffSummary {
+        pixels: 0,
+        bytes: 0,
+        max: 0,
+        mean: 0.,
+        first: None,
+        lanes: [0; LANES],
+    };
+    let mut sum = 0u64;
+    for (i, (a, e)) in actual
+        .inner
+        .chunks_exact(C)
+        .zip(expected.inner.chunks_exact(C))
+        .enumerate()
+    {
+        let mut pixel_diff = 0;
+        for (&a, &e) in a.iter().zip(e) {
+            let d = (a as i16 - e as i16).unsigned_abs() as u8;
+            if d > 0 {
+                summary.bytes += 1;
+                sum += d as u64;
+                pixel_diff = pixel_diff.max(d);
+            }
+        }
+        if pixel_diff > 0 {
+            summary.pixels += 1;
+            summary.first.get_or_insert((i / w, i % w));
+            summary.lanes[i % w % LANES] += 1;
+            summary.max = summary.max.max(pixel_diff);
+        }
+        pixel_diffs.push(pixel_diff);
+    }
+    if !expected.inner.is_empty() {
+        summary.mean = sum as f64 / expected.inner.len() as f64;
+    }
+
+    let mut heatmap = Vec::with_capacity(expected.inner.len());
+    for (&d, e) in pixel_diffs.iter().zip(expected.inner.chunks_exact(C)) {
+        let pixel = if d == 0 {
+            let gray = (e.iter().map(|&v| v as u16).sum::<u16>() / (C as u16 * 4)) as u8;
+            [gray; C]
+        } else {
+            [255, (d as u16 * 255 / summary.max as u16) as u8, 0]
+        };
+        heatmap.extend_from_slice(&pixel);
+    }
+    Ok(Diff {
+        summary,
+        heatmap: RgbImage::from_raw_unchecked(heatmap, expected.height, expected.width),
+    })
+}
+
+impl RgbImage {
+    /// Whether `other` has the same size, and at most `max_diff_count` pixels with a channel
+    /// differing by more than `max_per_channel_diff`, comparing 16 pixels at a time with NEON.
+    ///
+    /// `approx_eq(other, 0, 0)` is the same as `==`.
+    pub fn approx_eq(
+        &self,
+        other: &RgbImage,
+        max_per_channel_diff: u8,
+        max_diff_count: usize,
+    ) -> bool {
+        (self.height, self.width) == (other.height, other.width)
+            && count_outliers(&self.inner, &other.inner, max_per_channel_diff) <= max_diff_count
+    }
+}
+
+/// Mean errors between two images, over all values and per channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErrorMetrics {
+    /// Mean squared error over all values.
+    pub mse: f64,
+    /// Mean absolute error over all values.
+    pub mae: f64,
+    /// Mean squared error of each channel.
+    pub channel_mse: [f64; C],
+    /// Mean absolute error of each channel.
+    pub channel_mae: [f64; C],
+}
+
+impl RgbImage {
+    /// Mean squared and absolute errors of `self` from `other`.
+    ///
+    /// Fails with [`Error::SizeMismatch`] if they do not have the same size, and with
+    /// [`Error::ImageTooSmall`] if they are empty.
+    pub fn error_metrics(&self, other: &RgbImage) -> Result<ErrorMetrics> {
+        if (self.height, self.width) != (other.height, other.width) {
+            return Err(Error::SizeMismatch {
+                expected: (other.height, other.width),
+                actual: (self.height, self.width),
+            });
+        }
+        self.check_min_size(1)?;
+        let (abs, squares) = error_sums(&self.inner, &other.inner);
+        let pixels = (self.height * self.width) as f64;
+        let values = pixels * C as f64;
+        Ok(ErrorMetrics {
+            mse: squares.iter().sum::<u64>() as f64 / values,
+            mae: abs.iter().sum::<u64>() as f64 / values,
+            channel_mse: squares.map(|sum| sum as f64 / pixels),
+            channel_mae: abs.map(|sum| sum as f64 / pixels),
+        })
+    }
+
+    /// Mean squared error of `self` from `other` over all values.
+    ///
+    /// Fails as [`error_metrics`](RgbImage::error_metrics) does.
+    pub fn mse(&self, other: &RgbImage) -> Result<f64> {
+        Ok(self.error_metrics(other)?.mse)
+    }
+
+    /// Mean absolute error of `self` from `other` over all values.
+    ///
+    /// Fails as [`error_metrics`](RgbImage::error_metrics) does.
+    pub fn mae(&self, other: &RgbImage) -> Result<f64> {
+        Ok(self.error_metrics(other)?.mae)
+    }
+}
+
+// sums of the absolute and squared differences of each channel between `a` and `b`
+fn error_sums(a: &[u8], b: &[u8]) -> ([u64; C], [u64; C]) {
+    let (mut abs, mut squares) = ([0; C], [0; C]);
+    // bytes from `start` are left to the serial loop
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let start = unsafe {
+        let end = a.len() - a.len() % (16 * C);
+        for chunk in (0..end).step_by(FLUSH * 16 * C) {
+            let mut abs_accs = [vdupq_n_u32(0); C];
+            let mut square_accs = [vdupq_n_u32(0); C];
+            for i in (chunk..end.min(chunk + FLUSH * 16 * C)).step_by(16 * C) {
+                let (va, vb) = (simd::deinterleave16(a, i), simd::deinterleave16(b, i));
+                for c in 0..C {
+                    let d = vabdq_u8(va[c], vb[c]);
+                    abs_accs[c] = vpadalq_u16(abs_accs[c], vpaddlq_u8(d));
+                    let lo = vmull_u8(vget_low_u8(d), vget_low_u8(d));
+                    square_accs[c] = vpadalq_u16(square_accs[c], lo);
+                    square_accs[c] = vpadalq_u16(square_accs[c], vmull_high_u8(d, d));
+                }
+            }
+            for c in 0..C {
+                abs[c] += vaddlvq_u32(abs_accs[c]);
+                squares[c] += vaddlvq_u32(square_accs[c]);
+            }
+        }
+        end
+    };
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    let start = 0;
+    for (a, b) in a[start..].chunks_exact(C).zip(b[start..].chunks_exact(C)) {
+        for c in 0..C {
+            let d = (a[c] as i16 - b[c] as i16).unsigned_abs() as u64;
+            abs[c] += d;
+            squares[c] += d * d;
+        }
+    }
+    (abs, squares)
+}
+
+// number of pixels with a channel differing by more than `tolerance` between `a` and `b`
+fn count_outliers(a: &[u8], b: &[u8], tolerance: u8) -> usize {
+    // bytes from `start` are left to the serial loop
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let (start, mut count) = {
+        let simd_end = a.len() - a.len() % (16 * C);
+        let mut count = 0;
+        for i in (0..simd_end).step_by(16 * C) {
+            let (va, vb) = (simd::deinterleave16(a, i), simd::deinterleave16(b, i));
+            unsafe {
+                let d = vmaxq_u8(
+                    vmaxq_u8(vabdq_u8(va[0], vb[0]), vabdq_u8(va[1], vb[1])),
+                    vabdq_u8(va[2], vb[2]),
+                );
+                let outliers = vcgtq_u8(d, simd::splat_u8(tolerance));
+                count += vaddvq_u8(vshrq_n_u8::<7>(outliers)) as usize;
+            }
+        }
+        (simd_end, count)
+    };
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    let (start, mut count) = (0, 0);
+    for (a, b) in a[start..].chunks_exact(C).zip(b[start..].chunks_exact(C)) {
+        if a.iter()
+            .zip(b)
+            .any(|(&a, &b)| (a as i16 - b as i16).unsigned_abs() > tolerance as u16)
+        {
+            count += 1;
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::test_util::photo_image;
+
+    #[test]
+    fn diff() -> Result<()> {
+        let (h, w) = (3, 20);
+        let expected = RgbImage::from_raw(vec![100; h * w * C], h, w)?;
+        let same = super::diff(&expected, &expected)?;
+        assert!(same.summary.is_empty());
+        assert_eq!(same.summary.first, None);
+        assert_eq!(same.heatmap.content(), &vec![25; h * w * C][..]);
+
+        // the 3rd lane is wrong in every vector
+        let mut actual = expected.content().to_vec();
+        for y in 0..h {
+            for x in [2, 18] {
+                actual[(y * w + x) * C + 1] = 104;
+            }
+        }
+        actual[(w + 5) * C] = 98;
+        actual[(w + 5) * C + 2] = 99;
+        let actual = RgbImage::from_raw(actual, h, w)?;
+        let Diff { summary, heatmap } = super::diff(&actual, &expected)?;
+        assert_eq!((summary.pixels, summary.bytes, summary.max), (7, 8, 4));
+        assert_eq!(summary.mean, (6. * 4. + 2. + 1.) / (h * w * C) as f64);
+        assert_eq!(summary.first, Some((0, 2)));
+        assert_eq!(summary.lanes[2], 6);
+        assert_eq!(summary.lanes[5], 1);
+        assert_eq!(&heatmap.content()[2 * C..3 * C], &[255, 255, 0]);
+        assert_eq!(&heatmap.content()[(w + 5) * C..(w + 6) * C], &[255, 127, 0]);
+
+        assert!(matches!(
+            super::diff(&actual, &RgbImage::from_raw(vec![0; w * h * C], w, h)?),
+            Err(Error::SizeMismatch { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn approx_eq() -> Result<()> {
+        // 6 pixels off by 4 and one by 2
+        let (h, w) = (3, 20);
+        let expected = RgbImage::from_raw(vec![100; h * w * C], h, w)?;
+        let mut actual = expected.content().to_vec();
+        for y in 0..h {
+            for x in [2, 18] {
+                actual[(y * w + x) * C + 1] = 104;
+            }
+        }
+        actual[(w + 5) * C + 2] = 98;
+        let actual = RgbImage::from_raw(actual, h, w)?;
+        assert!(expected.approx_eq(&expected, 0, 0));
+        assert!(!actual.approx_eq(&expected, 0, 0));
+        assert!(actual.approx_eq(&expected, 4, 0));
+        assert!(actual.approx_eq(&expected, 3, 6));
+        assert!(!actual.approx_eq(&expected, 3, 5));
+        assert!(actual.approx_eq(&expected, 1, 7));
+        assert!(!actual.approx_eq(&expected, 1, 6));
+        let transposed = RgbImage::from_raw(vec![100; h * w * C], w, h)?;
+        assert!(!transposed.approx_eq(&expected, 255, usize::MAX));
+        Ok(())
+    }
+
+    #[test]
+    fn error_metrics() -> Result<()> {
+        // vectors of 16 pixels with some left over, against the definitions
+        let a = photo_image(9, 37, 43);
+        let b = photo_image(9, 37, 44);
+        let metrics = a.error_metrics(&b)?;
+        let diffs = || {
+            a.content()
+                .iter()
+                .zip(b.content())
+                .map(|(&a, &b)| a as f64 - b as f64)
+        };
+        let n = (9 * 37 * C) as f64;
+        assert_eq!(metrics.mse, diffs().map(|d| d * d).sum::<f64>() / n);
+        assert_eq!(metrics.mae, diffs().map(f64::abs).sum::<f64>() / n);
+        for c in 0..C {
+            let channel = || diffs().skip(c).step_by(C);
+            let pixels = (9 * 37) as f64;
+            let mse = channel().map(|d| d * d).sum::<f64>() / pixels;
+            assert_eq!(metrics.channel_mse[c], mse);
+            assert_eq!(
+                metrics.channel_mae[c],
+                channel().map(f64::abs).sum::<f64>() / pixels
+            );
+        }
+        assert_eq!((a.mse(&b)?, b.mae(&a)?), (metrics.mse, metrics.mae));
+        assert_eq!(a.mse(&a)?, 0.);
+
+        // only the blue channel off by 255
+        let black = RgbImage::from_raw(vec![0; 2 * 3 * C], 2, 3)?;
+        let blue = RgbImage::from_raw([0, 0, 255].repeat(6), 2, 3)?;
+        let metrics = blue.error_metrics(&black)?;
+        assert_eq!(metrics.channel_mse, [0., 0., 65025.]);
+        assert_eq!(metrics.mae, 85.);
+        assert!(matches!(
+            black.mse(&RgbImage::from_raw(vec![0; 3 * 2 * C], 3, 2)?),
+            Err(Error::SizeMismatch { .. })
+        ));
+        let empty = RgbImage::from_raw(vec![], 0, 4)?;
+        assert!(matches!(
+            empty.mae(&empty),
+            Err(Error::ImageTooSmall { .. })
+        ));
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    #[test]
+    fn simd() {
+        // differences in every channel and lane, over vectors of 16 pixels with some left over
+        let a: Vec<u8> = (0..37 * C).map(|i| (i * 97 % 256) as u8).collect();
+        let b: Vec<u8> = (0..37 * C).map(|i| (i * 89 % 256) as u8).collect();
+        for tolerance in [0, 7, 100, 255] {
+            let expected = a
+                .chunks_exact(C)
+                .zip(b.chunks_exact(C))
+                .filter(|(a, b)| {
+                    a.iter()
+                        .zip(*b)
+                        .any(|(&a, &b)| (a as i16 - b as i16).unsigned_abs() > tolerance as u16)
+                })
+                .count();
+            assert_eq!(count_outliers(&a, &b, tolerance), expected);
+        }
+
+        // largest errors over several flushes of the 32-bit lanes, with some left over
+        let pixels = 2 * FLUSH * 16 + 5;
+        let (black, white) = (vec![0; pixels * C], vec![255; pixels * C]);
+        let (abs, squares) = error_sums(&black, &white);
+        assert_eq!(abs, [255 * pixels as u64; C]);
+        assert_eq!(squares, [255 * 255 * pixels as u64; C]);
+    }
+}
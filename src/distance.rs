@@ -0,0 +1,201 @@
+//! Euclidean distance transform of binary images, e.g. for morphology with round structuring
+//! elements, masks keeping away from obstacles, or soft edges of masks for compositing.
+//!
+//! [`distance_transform`] is exact, by the two passes of Felzenszwalb and Huttenlocher: distances
+//! to the nearest feature of the same column first, by a forward and a backward scan over the rows
+//! relaxing 4 columns at a time with NEON, then the lower envelope of the parabolas they give
+//! along every row.
+
+use alloc::{vec, vec::Vec};
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{image::RgbImage, kernel::sqrt, C};
+
+/// Distances of every pixel to the nearest feature pixel, in pixels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistanceMap {
+    distances: Vec<f32>,
+    height: usize,
+    width: usize,
+}
+
+impl DistanceMap {
+    /// Height in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Width in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Distances of all the pixels, row by row.
+    pub fn distances(&self) -> &[f32] {
+        &self.distances
+    }
+
+    /// Distance of the pixel at `(y, x)`.
+    pub fn at(&self, y: usize, x: usize) -> f32 {
+        self.distances[y * self.width + x]
+    }
+}
+
+/// Euclidean distances of every pixel of `mask` to the nearest feature pixel, those with any
+/// non-zero channel. Distances are 0 at features, and infinite everywhere if there is none.
+pub fn distance_transform(mask: &RgbImage) -> DistanceMap {
+    let (h, w) = (mask.height, mask.width);
+    let mut g: Vec<f32> = mask
+        .inner
+        .chunks_exact(C)
+        .map(|p| {
+            if p.iter().any(|&v| v != 0) {
+                0.
+            } else {
+                f32::INFINITY
+            }
+        })
+        .collect();
+    for y in 1..h {
+        relax(&mut g, (y - 1) * w, y * w, w);
+    }
+    for y in (0..h.saturating_sub(1)).rev() {
+        relax(&mut g, (y + 1) * w, y * w, w);
+    }
+
+    let mut distances = vec![0.; h * w];
+    let (mut parabolas, mut bounds) = (Vec::with_capacity(w), Vec::with_capacity(w));
+    let mut f = vec![0.; w];
+    // no rows at all if the image is 0 pixels wide
+    for (g, d) in g
+        .chunks_exact(w.max(1))
+        .zip(distances.chunks_exact_mut(w.max(1)))
+    {
+        for (f, &g) in f.iter_mut().zip(g) {
+            *f = g * g;
+        }
+        envelope(&f, d, &mut parabolas, &mut bounds);
+        for d in d.iter_mut() {
+            *d = sqrt(*d);
+        }
+    }
+    DistanceMap {
+        distances,
+        height: h,
+        width: w,
+    }
+}
+
+// `g[to..to + w]` relaxed by `g[from..from + w]`, the row next to it: the smallest of each value
+// and the value next to it plus 1
+fn relax(g: &mut [f32], from: usize, to: usize, w: usize) {
+    // values from `start` are left to the serial loop
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let start = {
+        let one = simd::splat(1.);
+        let mut x = 0;
+        while x + 4 <= w {
+            let next = simd::add(simd::load_f32(g, from + x), one);
+            let v = unsafe { vminq_f32(simd::load_f32(g, to + x), next) };
+            simd::store_f32(g, to + x, v);
+            x += 4;
+        }
+        x
+    };
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    let start = 0;
+    for x in start..w {
+        g[to + x] = g[to + x].min(g[from + x] + 1.);
+    }
+}
+
+// `d[x]` the smallest `(x - q)^2 + f[q]` over all `q`, infinite if every `f[q]` is. The lower
+// envelope of the parabolas is built in `parabolas`, their vertices `q` from left to right, and
+// `bounds`, where each of them starts being the lowest.
+fn envelope(f: &[f32], d: &mut [f32], parabolas: &mut Vec<usize>, bounds: &mut Vec<f32>) {
+    parabolas.clear();
+    bounds.clear();
+    for (q, &fq) in f.iter().enumerate().filter(|(_, fq)| fq.is_finite()) {
+        // intersection with the parabola of `p`, hiding it if it is left of where it starts
+        let mut start = f32::NEG_INFINITY;
+        while let Some(&p) = parabolas.last() {
+            let (qf, pf) = (q as f32, p as f32);
+            start = ((fq + qf * qf) - (f[p] + pf * pf)) / (2. * (qf - pf));
+            if start > *bounds.last().unwrap() {
+                break;
+            }
+            parabolas.pop();
+            bounds.pop();
+            start = f32::NEG_INFINITY;
+        }
+        parabolas.push(q);
+        bounds.push(start);
+    }
+    if parabolas.is_empty() {
+        d.fill(f32::INFINITY);
+        return;
+    }
+    let mut k = 0;
+    for (x, d) in d.iter_mut().enumerate() {
+        while k + 1 < parabolas.len() && bounds[k + 1] < x as f32 {
+            k += 1;
+        }
+        let dx = x as f32 - parabolas[k] as f32;
+        *d = dx * dx + f[parabolas[k]];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Result, test_util::photo_image};
+
+    #[test]
+    fn distance_transform() -> Result<()> {
+        // scattered features, over rows of vectors of 4 values with some left over
+        let img = photo_image(17, 23, 32);
+        let content = img
+            .content()
+            .chunks_exact(C)
+            .flat_map(|p| if p[0] > 170 { [255; C] } else { [0; C] })
+            .collect();
+        let mask = RgbImage::from_raw(content, 17, 23)?;
+        let features: Vec<(usize, usize)> = (0..17 * 23)
+            .filter(|&i| mask.content()[i * C] != 0)
+            .map(|i| (i / 23, i % 23))
+            .collect();
+        assert!(features.len() > 3 && features.len() < 17 * 23 / 4);
+        let map = super::distance_transform(&mask);
+        assert_eq!((map.height(), map.width()), (17, 23));
+        for y in 0..17 {
+            for x in 0..23 {
+                let squared = features
+                    .iter()
+                    .map(|&(fy, fx)| {
+                        let (dy, dx) = (fy as f32 - y as f32, fx as f32 - x as f32);
+                        dy * dy + dx * dx
+                    })
+                    .fold(f32::INFINITY, f32::min);
+                assert_eq!(map.at(y, x), sqrt(squared), "{} {}", y, x);
+            }
+        }
+
+        // a single feature, and none
+        let mut dot = RgbImage::from_raw(vec![0; 5 * 9 * C], 5, 9)?;
+        dot.content_mut()[(2 * 9 + 6) * C + 1] = 1;
+        let map = super::distance_transform(&dot);
+        assert_eq!(
+            (map.at(2, 6), map.at(2, 0), map.at(0, 8)),
+            (0., 6., sqrt(8.))
+        );
+        let black = RgbImage::from_raw(vec![0; 4 * 6 * C], 4, 6)?;
+        let map = super::distance_transform(&black);
+        assert!(map.distances().iter().all(|d| d.is_infinite()));
+        let empty = RgbImage::from_raw(vec![], 3, 0)?;
+        assert!(super::distance_transform(&empty).distances().is_empty());
+        Ok(())
+    }
+}
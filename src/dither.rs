@@ -0,0 +1,414 @@
+//! Dithering when reducing the number of levels per channel, e.g. down to RGB565 for a display.
+//!
+//! Rounding every value to the nearest level turns smooth gradients into visible bands.
+//! [`ordered`] adds a threshold from a Bayer matrix depending only on the position of the pixel,
+//! so NEON computes 16 pixels at a time. [`floyd_steinberg`] instead diffuses the error of every
+//! pixel into its neighbors, which looks better but runs pixel by pixel.
+//!
+//! Values of the results are levels expanded back to `[0, 255]`, i.e. `q * 255 / (2^bits - 1)`
+//! rounded, whose top `bits` bits are `q`, e.g. for [`pack_rgb565`].
+//!
+//! RGB565 framebuffers of small displays are written by [`pack_rgb565_into`], dithering on the
+//! way if asked, and read back by [`unpack_rgb565`], both with NEON on 16 pixels at a time.
+
+use alloc::{format, vec, vec::Vec};
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+use core::mem;
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{
+    error::{Error, Result},
+    image::RgbImage,
+    kernel::{round, Rounding},
+    C,
+};
+
+/// Bits of red, green and blue in RGB565.
+pub const RGB565: [u32; C] = [5, 6, 5];
+
+// 8x8 Bayer matrix, each of 0..64 once
+#[rustfmt::skip]
+const BAYER: [[u8; 8]; 8] = [
+    [ 0, 32,  8, 40,  2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44,  4, 36, 14, 46,  6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [ 3, 35, 11, 43,  1, 33,  9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47,  7, 39, 13, 45,  5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// Reduces channel `c` to `bits[c]` bits with ordered dithering by an 8x8 Bayer matrix.
+///
+/// Fails with [`Error::BadKernel`] if bits are not in `1..=8`.
+pub fn ordered(img: &RgbImage, bits: [u32; C]) -> Result<RgbImage> {
+    let top = top_levels(bits)?;
+    let (h, w) = (img.height, img.width);
+    let row = w * C;
+    let mut dst = vec![0; img.content().len()];
+    for y in 0..h {
+        let src = &img.content()[y * row..(y + 1) * row];
+        let dst = &mut dst[y * row..(y + 1) * row];
+        // pixels from `start` are left to the serial loop
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let start = {
+            // the thresholds of 16 pixels from a multiple of 8 are the row of the matrix twice
+            let thresholds = [0, 4, 8, 12].map(|x| {
+                let t = [0, 1, 2, 3].map(|l| threshold(y, x + l));
+                simd::load_f32(&t, 0)
+            });
+            let scale = top.map(|top| simd::splat(top / 255.));
+            let expand = top.map(|top| simd::splat(255. / top));
+            let top = top.map(simd::splat);
+            let simd_end = w - w % 16;
+            for x in (0..simd_end).step_by(16) {
+                let mut v = simd::load_rgb16(src, x * C);
+                for (c, v) in v.iter_mut().enumerate() {
+                    for (v, &t) in v.iter_mut().zip(thresholds.iter()) {
+                        let q = unsafe {
+                            let q = simd::add(simd::mul(*v, scale[c]), t);
+                            vminq_f32(vcvtq_f32_u32(vcvtq_u32_f32(q)), top[c])
+                        };
+                        *v = simd::mul(q, expand[c]);
+                    }
+                }
+                simd::store_rgb16(dst, x * C, &v, Rounding::Nearest);
+            }
+            simd_end
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let start = 0;
+        for x in start..w {
+            let t = threshold(y, x);
+            for c in 0..C {
+                dst[x * C + c] = quantize(src[x * C + c], t, top[c]);
+            }
+        }
+    }
+    Ok(RgbImage::from_raw_unchecked(dst, h, w))
+}
+
+/// Reduces channel `c` to `bits[c]` bits with Floyd–Steinberg error diffusion: the difference
+/// between a value and its level goes to the next pixel (7/16) and the 3 below (3/16, 5/16 and
+/// 1/16), left to right in every row.
+///
+/// Fails with [`Error::BadKernel`] if bits are not in `1..=8`.
+pub fn floyd_steinberg(img: &RgbImage, bits: [u32; C]) -> Result<RgbImage> {
+    let top = top_levels(bits)?;
+    let (h, w) = (img.height, img.width);
+    let row = w * C;
+    let mut dst = vec![0; img.content().len()];
+    // errors for this row and the next, with a pixel of margin on each side
+    let mut errors = vec![0f32; (w + 2) * C];
+    let mut next = vec![0f32; (w + 2) * C];
+    for y in 0..h {
+        next.fill(0.);
+        for x in 0..w {
+            for c in 0..C {
+                let i = (x + 1) * C + c;
+                let v = img.content()[y * row + x * C + c] as f32 + errors[i];
+                let q = round(v * (top[c] / 255.)).clamp(0., top[c]);
+                let out = Rounding::Nearest.to_u8(q * (255. / top[c]));
+                dst[y * row + x * C + c] = out;
+                let e = v - out as f32;
+                errors[i + C] += e * (7. / 16.);
+                next[i - C] += e * (3. / 16.);
+                next[i] += e * (5. / 16.);
+                next[i + C] += e * (1. / 16.);
+            }
+        }
+        mem::swap(&mut errors, &mut next);
+    }
+    Ok(RgbImage::from_raw_unchecked(dst, h, w))
+}
+
+/// How [`pack_rgb565_into`] reduces values to the bits of RGB565.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    /// Values are truncated to their top bits.
+    None,
+    /// With [`ordered`].
+    Ordered,
+    /// With [`floyd_steinberg`].
+    FloydSteinberg,
+}
+
+/// Packs every pixel into 16 bits, the top 5 bits of red, 6 of green and 5 of blue, e.g. after
+/// dithering with [`RGB565`].
+pub fn pack_rgb565(img: &RgbImage) -> Vec<u16> {
+    let mut dst = vec![0; img.height * img.width];
+    pack_pixels(img.content(), &mut dst);
+    dst
+}
+
+/// Packs every pixel of `img` into `dst` like [`pack_rgb565`] after dithering them with
+/// `dither`, e.g. into the framebuffer of a display.
+///
+/// Fails with [`Error::InvalidLength`] if `dst` does not have a value per pixel.
+pub fn pack_rgb565_into(img: &RgbImage, dither: Dither, dst: &mut [u16]) -> Result<()> {
+    if dst.len() != img.height * img.width {
+        return Err(Error::InvalidLength {
+            expected: img.height * img.width,
+            actual: dst.len(),
+        });
+    }
+    match dither {
+        Dither::None => pack_pixels(img.content(), dst),
+        Dither::Ordered => pack_pixels(ordered(img, RGB565)?.content(), dst),
+        Dither::FloydSteinberg => pack_pixels(floyd_steinberg(img, RGB565)?.content(), dst),
+    }
+    Ok(())
+}
+
+/// Image of `height` x `width` pixels from RGB565 values, their levels expanded to `[0, 255]`
+/// like the results of [`ordered`], so that packing it again gives back `src`.
+///
+/// Fails with [`Error::InvalidLength`] if `src` does not have `height * width` values.
+pub fn unpack_rgb565(src: &[u16], height: usize, width: usize) -> Result<RgbImage> {
+    if src.len() != height * width {
+        return Err(Error::InvalidLength {
+            expected: height * width,
+            actual: src.len(),
+        });
+    }
+    let mut dst = vec![0; src.len() * C];
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let start = {
+        let simd_end = src.len() - src.len() % 16;
+        for i in (0..simd_end).step_by(16) {
+            let [lo, hi] = simd::load_u16x8x2(src, i);
+            let rgb = unsafe {
+                let [lo, hi] = [lo, hi].map(|v| unpack8(v));
+                [0, 1, 2].map(|c| vmovn_high_u16(vmovn_u16(lo[c]), hi[c]))
+            };
+            simd::interleave16(&mut dst, i * C, rgb);
+        }
+        simd_end
+    };
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    let start = 0;
+    for (&v, d) in src[start..]
+        .iter()
+        .zip(dst[start * C..].chunks_exact_mut(C))
+    {
+        d.copy_from_slice(&unpack(v));
+    }
+    Ok(RgbImage::from_raw_unchecked(dst, height, width))
+}
+
+// `pack_rgb565` of the pixels of `src` into `dst`, which has one value per pixel
+fn pack_pixels(src: &[u8], dst: &mut [u16]) {
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let start = {
+        let simd_end = dst.len() - dst.len() % 16;
+        for i in (0..simd_end).step_by(16) {
+            let [r, g, b] = simd::deinterleave16(src, i * C);
+            // every channel at the top of 16 bits, then shifted into place under the previous one
+            let v = unsafe {
+                let lo = [r, g, b].map(|c| vshll_n_u8::<8>(vget_low_u8(c)));
+                let hi = [r, g, b].map(|c| vshll_high_n_u8::<8>(c));
+                [lo, hi].map(|[r, g, b]| vsriq_n_u16::<11>(vsriq_n_u16::<5>(r, g), b))
+            };
+            simd::store_u16x8x2(dst, i, v);
+        }
+        simd_end
+    };
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    let start = 0;
+    for (p, d) in src[start * C..].chunks_exact(C).zip(&mut dst[start..]) {
+        *d = pack(p[0], p[1], p[2]);
+    }
+}
+
+fn pack(r: u8, g: u8, b: u8) -> u16 {
+    (r as u16 >> 3) << 11 | (g as u16 >> 2) << 5 | b as u16 >> 3
+}
+
+// levels of an RGB565 value expanded like `quantize`, `(q * 527 + 23) >> 6` being
+// `q * 255 / 31` rounded for 5 bits and `(q * 259 + 33) >> 6` being `q * 255 / 63` for 6 bits
+fn unpack(v: u16) -> [u8; C] {
+    let [r, g, b] = [v >> 11, v >> 5 & 0x3f, v & 0x1f];
+    [
+        ((r * 527 + 23) >> 6) as u8,
+        ((g * 259 + 33) >> 6) as u8,
+        ((b * 527 + 23) >> 6) as u8,
+    ]
+}
+
+// `unpack` of 8 values, still in 16-bit lanes
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[inline]
+unsafe fn unpack8(v: uint16x8_t) -> [uint16x8_t; C] {
+    let r = vshrq_n_u16::<11>(v);
+    let g = vandq_u16(vshrq_n_u16::<5>(v), vdupq_n_u16(0x3f));
+    let b = vandq_u16(v, vdupq_n_u16(0x1f));
+    let expand = |q, k, c| vshrq_n_u16::<6>(vmlaq_n_u16(vdupq_n_u16(c), q, k));
+    [expand(r, 527, 23), expand(g, 259, 33), expand(b, 527, 23)]
+}
+
+// the highest level of each channel, `2^bits - 1`
+fn top_levels(bits: [u32; C]) -> Result<[f32; C]> {
+    if let Some(b) = bits.iter().find(|b| !(1..=8).contains(*b)) {
+        return Err(Error::BadKernel(format!(
+            "bits must be in 1..=8, got {}",
+            b
+        )));
+    }
+    Ok(bits.map(|b| ((1 << b) - 1) as f32))
+}
+
+// offset added before truncating to a level, in `(0, 1)`
+fn threshold(y: usize, x: usize) -> f32 {
+    (BAYER[y % 8][x % 8] as f32 + 0.5) / 64.
+}
+
+// `v` truncated to a level after adding `t`, and expanded back
+fn quantize(v: u8, t: f32, top: f32) -> u8 {
+    let q = ((v as f32 * (top / 255.) + t) as u32 as f32).min(top);
+    Rounding::Nearest.to_u8(q * (255. / top))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::photo_image;
+
+    // mean of channel `c`
+    fn mean(img: &RgbImage, c: usize) -> f32 {
+        let sum: u32 = img
+            .content()
+            .iter()
+            .skip(c)
+            .step_by(C)
+            .map(|&v| v as u32)
+            .sum();
+        sum as f32 / (img.height * img.width) as f32
+    }
+
+    #[test]
+    fn levels() -> Result<()> {
+        // vectors of 16 pixels, and some left over
+        let img = photo_image(11, 37, 11);
+        for dither in [ordered, floyd_steinberg] {
+            assert_eq!(dither(&img, [8; C])?, img);
+            let dithered = dither(&img, RGB565)?;
+            for (i, &v) in dithered.content().iter().enumerate() {
+                let bits = RGB565[i % C];
+                let q = v >> (8 - bits);
+                let top = (1 << bits) - 1;
+                assert_eq!(v, (q as f32 * 255. / top as f32).round() as u8);
+            }
+            let packed = pack_rgb565(&dithered);
+            assert_eq!(packed.len(), 11 * 37);
+            assert!(matches!(dither(&img, [5, 9, 5]), Err(Error::BadKernel(_))));
+            assert!(matches!(dither(&img, [0, 6, 5]), Err(Error::BadKernel(_))));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn means() -> Result<()> {
+        // flat colors keep their means with 1 bit, instead of all rounding the same way
+        for v in [40, 100, 128, 200] {
+            let img = RgbImage::from_raw(vec![v; 16 * 40 * C], 16, 40)?;
+            let thresholded = ordered(&img, [1; C])?;
+            assert!((mean(&thresholded, 0) - v as f32).abs() < 4., "{}", v);
+            let diffused = floyd_steinberg(&img, [1; C])?;
+            assert!((mean(&diffused, 1) - v as f32).abs() < 4., "{}", v);
+        }
+        // every threshold once in an 8x8 block
+        let gray = RgbImage::from_raw(vec![128; 8 * 8 * C], 8, 8)?;
+        let dithered = ordered(&gray, [1; C])?;
+        assert_eq!(
+            dithered.content().iter().filter(|&&v| v == 255).count(),
+            32 * C
+        );
+
+        assert_eq!(
+            pack_rgb565(&RgbImage::from_raw(vec![255, 0, 8, 8, 4, 255], 1, 2)?),
+            [0xf801, 0x083f]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rgb565() -> Result<()> {
+        // vectors of 16 pixels, and some left over
+        let img = photo_image(7, 23, 19);
+        let mut framebuffer = vec![0; 7 * 23];
+        pack_rgb565_into(&img, Dither::None, &mut framebuffer)?;
+        assert_eq!(framebuffer, pack_rgb565(&img));
+        let unpacked = unpack_rgb565(&framebuffer, 7, 23)?;
+        for (i, (&u, &v)) in unpacked.content().iter().zip(img.content()).enumerate() {
+            // truncated to 5 or 6 bits
+            let step = if i % C == 1 { 4 } else { 8 };
+            assert!((u as i32 - v as i32).abs() < step, "{} {}", u, v);
+        }
+        assert_eq!(pack_rgb565(&unpacked), framebuffer);
+
+        // levels of the dithered images are exactly those of the framebuffer
+        for (dither, dithered) in [
+            (Dither::Ordered, ordered(&img, RGB565)?),
+            (Dither::FloydSteinberg, floyd_steinberg(&img, RGB565)?),
+        ] {
+            pack_rgb565_into(&img, dither, &mut framebuffer)?;
+            assert_eq!(unpack_rgb565(&framebuffer, 7, 23)?, dithered);
+        }
+
+        // every 5 and 6-bit level
+        let levels: Vec<u16> = (0..64)
+            .map(|q| (q & 0x1f) << 11 | q << 5 | (31 - (q & 0x1f)))
+            .collect();
+        let unpacked = unpack_rgb565(&levels, 4, 16)?;
+        for (p, q) in unpacked.content().chunks_exact(C).zip(0..) {
+            let expand = |q: u16, top: f32| (q as f32 * 255. / top).round() as u8;
+            assert_eq!(
+                p,
+                [
+                    expand(q & 0x1f, 31.),
+                    expand(q, 63.),
+                    expand(31 - (q & 0x1f), 31.)
+                ]
+            );
+        }
+
+        assert!(matches!(
+            pack_rgb565_into(&img, Dither::None, &mut framebuffer[1..]),
+            Err(Error::InvalidLength {
+                expected: 161,
+                actual: 160
+            })
+        ));
+        assert!(unpack_rgb565(&framebuffer, 7, 22).is_err());
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    #[test]
+    fn simd() -> Result<()> {
+        // vectors of 16 pixels, and some left over
+        let img = photo_image(19, 45, 12);
+        for bits in [RGB565, [1, 2, 3], [8, 7, 4]] {
+            let dithered = ordered(&img, bits)?;
+            let top = top_levels(bits)?;
+            for (i, (&d, &v)) in dithered.content().iter().zip(img.content()).enumerate() {
+                let (y, x) = (i / C / img.width, i / C % img.width);
+                assert_eq!(d, quantize(v, threshold(y, x), top[i % C]), "{:?}", (y, x));
+            }
+        }
+
+        // every RGB565 value
+        let all: Vec<u16> = (0..=u16::MAX).collect();
+        let unpacked = unpack_rgb565(&all, 256, 256)?;
+        for (p, &v) in unpacked.content().chunks_exact(C).zip(&all) {
+            assert_eq!(p, unpack(v), "{:x}", v);
+        }
+        for (p, v) in img.content().chunks_exact(C).zip(pack_rgb565(&img)) {
+            assert_eq!(v, pack(p[0], p[1], p[2]), "{:?}", p);
+        }
+        Ok(())
+    }
+}
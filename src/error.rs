@@ -0,0 +1,110 @@
+//! Error type shared by the whole crate.
+
+use alloc::string::String;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io;
+
+/// Errors returned by kernels, processors and image IO.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Kernel (or pooling window) parameters are invalid.
+    BadKernel(String),
+    /// Image or buffer does not have the expected `(height, width)`.
+    SizeMismatch {
+        /// Required `(height, width)`.
+        expected: (usize, usize),
+        /// Given `(height, width)`.
+        actual: (usize, usize),
+    },
+    /// Raw buffer does not have the expected length.
+    InvalidLength {
+        /// Required length.
+        expected: usize,
+        /// Given length.
+        actual: usize,
+    },
+    /// Image is smaller than the kernel in either dimension.
+    ImageTooSmall {
+        /// Height of the image.
+        height: usize,
+        /// Width of the image.
+        width: usize,
+        /// Minimum height and width required.
+        min: usize,
+    },
+    /// Operation was aborted with a [`CancelToken`](crate::cancel::CancelToken).
+    Cancelled {
+        /// Number of output rows computed before the cancellation.
+        completed_rows: usize,
+    },
+    /// Image file has a format which is not supported.
+    UnsupportedFormat(String),
+    /// IO or PNG encoding/decoding failed.
+    #[cfg(feature = "std")]
+    Io(io::Error),
+}
+
+/// Result type with [`enum@Error`].
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BadKernel(msg) => write!(f, "bad kernel: {}", msg),
+            Error::SizeMismatch { expected, actual } => write!(
+                f,
+                "size mismatch: expected {}x{} but got {}x{}",
+                expected.0, expected.1, actual.0, actual.1
+            ),
+            Error::InvalidLength { expected, actual } => write!(
+                f,
+                "invalid length: expected {} but got {}",
+                expected, actual
+            ),
+            Error::ImageTooSmall { height, width, min } => write!(
+                f,
+                "image too small: {}x{} is smaller than {}x{}",
+                height, width, min, min
+            ),
+            Error::Cancelled { completed_rows } => {
+                write!(f, "cancelled after {} rows", completed_rows)
+            }
+            Error::UnsupportedFormat(msg) => write!(f, "unsupported format: {}", msg),
+            #[cfg(feature = "std")]
+            Error::Io(e) => write!(f, "io error: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<png::DecodingError> for Error {
+    fn from(e: png::DecodingError) -> Self {
+        Error::Io(e.into())
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<png::EncodingError> for Error {
+    fn from(e: png::EncodingError) -> Self {
+        Error::Io(e.into())
+    }
+}
@@ -0,0 +1,375 @@
+//! FAST corner detection, e.g. to pick keypoints to track or to match between frames.
+//!
+//! A pixel is a FAST-`n` corner if `n` contiguous pixels of the ring of 16 pixels at a distance of
+//! 3 around it (a Bresenham circle) are all brighter than it plus a threshold, or all darker than
+//! it minus the threshold. Corners are detected on the luma of the image, with NEON testing 16
+//! pixels of a row at once: each pixel of the ring is compared with a single vector comparison,
+//! and runs of contiguous comparisons are counted lane by lane.
+
+use alloc::{format, vec, vec::Vec};
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{
+    error::{Error, Result},
+    image::RgbImage,
+    C,
+};
+
+// ring of 16 pixels around the center as `(dy, dx)`, clockwise from the top
+const RING: [(isize, isize); 16] = [
+    (-3, 0),
+    (-3, 1),
+    (-2, 2),
+    (-1, 3),
+    (0, 3),
+    (1, 3),
+    (2, 2),
+    (3, 1),
+    (3, 0),
+    (3, -1),
+    (2, -2),
+    (1, -3),
+    (0, -3),
+    (-1, -3),
+    (-2, -2),
+    (-3, -1),
+];
+
+// luma weights of BT.601 with 8 fractional bits, adding up to 256
+const LUMA_Q8: [u8; C] = [77, 150, 29];
+
+/// Corner found by [`Fast::detect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keypoint {
+    /// Row of the pixel.
+    pub y: usize,
+    /// Column of the pixel.
+    pub x: usize,
+    /// Strength of the corner: the sum of the differences beyond the threshold between the ring
+    /// and the pixel, over the brighter or the darker pixels of the ring, whichever is larger.
+    pub score: u32,
+}
+
+/// FAST-`n` corner detector.
+#[derive(Debug, Clone, Copy)]
+pub struct Fast {
+    threshold: u8,
+    arc: usize,
+    nonmax: bool,
+}
+
+impl Fast {
+    /// Creates a detector of corners with `arc` contiguous pixels of the ring (9 for FAST-9, 12
+    /// for FAST-12) differing from them by more than `threshold`, without non-maximum
+    /// suppression.
+    ///
+    /// Fails with [`Error::BadKernel`] if `arc` is not in `9..=16`.
+    pub fn new(threshold: u8, arc: usize) -> Result<Self> {
+        if !(9..=16).contains(&arc) {
+            return Err(Error::BadKernel(format!(
+                "arc of {} pixels, expected 9 to 16",
+                arc
+            )));
+        }
+        Ok(Self {
+            threshold,
+            arc,
+            nonmax: false,
+        })
+    }
+
+    /// Keeps only the corners whose score is the largest of their 3x3 neighbourhood, the first in
+    /// row order on ties, so that a corner is not found at several adjacent pixels.
+    pub fn nonmax(mut self, nonmax: bool) -> Self {
+        self.nonmax = nonmax;
+        self
+    }
+
+    /// Corners of `img` in row order, at least 3 pixels away from its border.
+    ///
+    /// Fails with [`Error::ImageTooSmall`] if `img` is smaller than the ring, 7x7 pixels.
+    pub fn detect(&self, img: &RgbImage) -> Result<Vec<Keypoint>> {
+        img.check_min_size(7)?;
+        let (h, w) = (img.height, img.width);
+        let gray = luma(img);
+        let offsets = RING.map(|(dy, dx)| dy * w as isize + dx);
+        let mut keypoints = Vec::new();
+        for y in 3..h - 3 {
+            let row = y * w;
+            // pixels from `start` are left to the serial loop
+            #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+            let start = {
+                let mut x = 3;
+                while x + 16 <= w - 3 {
+                    let found =
+                        unsafe { corners16(&gray, row + x, &offsets, self.threshold, self.arc) };
+                    if unsafe { vmaxvq_u8(found) } != 0 {
+                        let mut lanes = [0; 16];
+                        simd::store_bytes16(&mut lanes, 0, found);
+                        for (lane, _) in lanes.iter().enumerate().filter(|&(_, &l)| l != 0) {
+                            let score = score(&gray, row + x + lane, &offsets, self.threshold);
+                            keypoints.push(Keypoint {
+                                y,
+                                x: x + lane,
+                                score,
+                            });
+                        }
+                    }
+                    x += 16;
+                }
+                x
+            };
+            #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+            let start = 3;
+            for x in start..w - 3 {
+                if is_corner(&gray, row + x, &offsets, self.threshold, self.arc) {
+                    let score = score(&gray, row + x, &offsets, self.threshold);
+                    keypoints.push(Keypoint { y, x, score });
+                }
+            }
+        }
+        if self.nonmax {
+            keypoints = suppress(&keypoints, h, w);
+        }
+        Ok(keypoints)
+    }
+}
+
+// whether the pixel at `i` of `gray` is a corner, `offsets` being the indices of the ring
+// relative to it
+fn is_corner(gray: &[u8], i: usize, offsets: &[isize; 16], threshold: u8, arc: usize) -> bool {
+    let c = gray[i];
+    let (hi, lo) = (c.saturating_add(threshold), c.saturating_sub(threshold));
+    let (mut bright, mut dark) = (0, 0);
+    // runs may wrap around the ring
+    for k in 0..16 + arc - 1 {
+        let p = gray[(i as isize + offsets[k % 16]) as usize];
+        bright = if p > hi { bright + 1 } else { 0 };
+        dark = if p < lo { dark + 1 } else { 0 };
+        if bright >= arc || dark >= arc {
+            return true;
+        }
+    }
+    false
+}
+
+// lanes of the pixels from `i` to `i + 16` of `gray` which are corners set to 0xff, the same as
+// `is_corner` with runs of every lane counted in `u8` lanes and reset by the comparison masks
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+unsafe fn corners16(
+    gray: &[u8],
+    i: usize,
+    offsets: &[isize; 16],
+    threshold: u8,
+    arc: usize,
+) -> uint8x16_t {
+    let c = simd::load_bytes16(gray, i);
+    let hi = vqaddq_u8(c, vdupq_n_u8(threshold));
+    let lo = vqsubq_u8(c, vdupq_n_u8(threshold));
+    let ring = offsets.map(|o| simd::load_bytes16(gray, (i as isize + o) as usize));
+    let (one, n) = (vdupq_n_u8(1), vdupq_n_u8(arc as u8));
+    let (mut bright, mut dark, mut found) = (vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0));
+    for k in 0..16 + arc - 1 {
+        let p = ring[k % 16];
+        bright = vandq_u8(vaddq_u8(bright, one), vcgtq_u8(p, hi));
+        dark = vandq_u8(vaddq_u8(dark, one), vcltq_u8(p, lo));
+        found = vorrq_u8(found, vcgeq_u8(vmaxq_u8(bright, dark), n));
+    }
+    found
+}
+
+// score of the corner at `i` of `gray`, see `Keypoint::score`
+fn score(gray: &[u8], i: usize, offsets: &[isize; 16], threshold: u8) -> u32 {
+    let (c, t) = (gray[i] as i32, threshold as i32);
+    let (mut bright, mut dark) = (0, 0);
+    for &o in offsets {
+        let p = gray[(i as isize + o) as usize] as i32;
+        bright += (p - c - t).max(0) as u32;
+        dark += (c - t - p).max(0) as u32;
+    }
+    bright.max(dark)
+}
+
+// `keypoints` of an image of `h` x `w` pixels, in row order, whose score is larger than those of
+// the keypoints before them in their 3x3 neighbourhood and not smaller than those after them
+fn suppress(keypoints: &[Keypoint], h: usize, w: usize) -> Vec<Keypoint> {
+    // scores plus one, 0 where there is no keypoint
+    let mut scores = vec![0; h * w];
+    for k in keypoints {
+        scores[k.y * w + k.x] = k.score + 1;
+    }
+    keypoints
+        .iter()
+        .filter(|k| {
+            let s = k.score + 1;
+            (k.y - 1..=k.y + 1).all(|y| {
+                (k.x - 1..=k.x + 1).all(|x| {
+                    let other = scores[y * w + x];
+                    if (y, x) < (k.y, k.x) {
+                        other < s
+                    } else {
+                        other <= s
+                    }
+                })
+            })
+        })
+        .copied()
+        .collect()
+}
+
+// luma of every pixel of `img` with the weights of `LUMA_Q8`, rounded to the nearest integer
+pub(crate) fn luma(img: &RgbImage) -> Vec<u8> {
+    let bytes = &img.inner[..];
+    let mut gray = vec![0; bytes.len() / C];
+    // pixels from `start` are left to the serial loop
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let start = unsafe {
+        let end = gray.len() - gray.len() % 16;
+        let [wr, wg, wb] = LUMA_Q8.map(|w| vdup_n_u8(w));
+        for i in (0..end).step_by(16) {
+            let [r, g, b] = simd::deinterleave16(bytes, i * C);
+            let lo = vmull_u8(vget_low_u8(r), wr);
+            let lo = vmlal_u8(vmlal_u8(lo, vget_low_u8(g), wg), vget_low_u8(b), wb);
+            let hi = vmull_high_u8(r, vdupq_n_u8(LUMA_Q8[0]));
+            let hi = vmlal_high_u8(hi, g, vdupq_n_u8(LUMA_Q8[1]));
+            let hi = vmlal_high_u8(hi, b, vdupq_n_u8(LUMA_Q8[2]));
+            let v = vcombine_u8(vrshrn_n_u16::<8>(lo), vrshrn_n_u16::<8>(hi));
+            simd::store_bytes16(&mut gray, i, v);
+        }
+        end
+    };
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    let start = 0;
+    for (g, pixel) in gray[start..]
+        .iter_mut()
+        .zip(bytes[start * C..].chunks_exact(C))
+    {
+        let sum: u32 = pixel
+            .iter()
+            .zip(&LUMA_Q8)
+            .map(|(&v, &w)| v as u32 * w as u32)
+            .sum();
+        *g = ((sum + 128) >> 8) as u8;
+    }
+    gray
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color_matrix::GRAYSCALE, test_util::photo_image};
+
+    // white square of `size` pixels at `(top, top)` on a black image of `h` x `w` pixels
+    fn square(h: usize, w: usize, top: usize, size: usize) -> Result<RgbImage> {
+        let inside = |i: usize| (top..top + size).contains(&i);
+        let content = (0..h * w * C)
+            .map(|i| {
+                let (y, x) = (i / C / w, i / C % w);
+                if inside(y) && inside(x) {
+                    255
+                } else {
+                    0
+                }
+            })
+            .collect();
+        RgbImage::from_raw(content, h, w)
+    }
+
+    #[test]
+    fn detect() -> Result<()> {
+        // rows of a vector of 16 pixels with some left over
+        let img = square(30, 37, 10, 12)?;
+        let corners = [(10, 10), (10, 21), (21, 10), (21, 21)];
+        let all = Fast::new(50, 9)?.detect(&img)?;
+        for k in &all {
+            // near a corner of the square, not along its edges
+            let near = |&(y, x): &(usize, usize)| {
+                (k.y as isize - y as isize).abs() <= 2 && (k.x as isize - x as isize).abs() <= 2
+            };
+            assert!(corners.iter().any(near), "{:?}", k);
+        }
+        // one keypoint per corner after suppression, the corner pixel itself
+        let kept = Fast::new(50, 9)?.nonmax(true).detect(&img)?;
+        let kept: Vec<_> = kept.iter().map(|k| (k.y, k.x)).collect();
+        assert_eq!(kept, corners);
+        assert!(all.len() > kept.len());
+        // right angles leave only 11 pixels of the ring outside
+        assert!(Fast::new(50, 12)?.detect(&img)?.is_empty());
+        let dot = square(30, 37, 15, 1)?;
+        let found = Fast::new(50, 12)?.detect(&dot)?;
+        assert_eq!(
+            found,
+            [Keypoint {
+                y: 15,
+                x: 15,
+                score: 16 * 205
+            }]
+        );
+
+        let flat = RgbImage::from_raw(vec![128; 9 * 20 * C], 9, 20)?;
+        assert!(Fast::new(0, 9)?.detect(&flat)?.is_empty());
+        assert!(matches!(
+            Fast::new(20, 9)?.detect(&photo_image(6, 30, 29)),
+            Err(Error::ImageTooSmall { min: 7, .. })
+        ));
+        for arc in [8, 17] {
+            assert!(matches!(Fast::new(20, arc), Err(Error::BadKernel(_))));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn luma() {
+        // vectors of 16 pixels, and some left over
+        let img = photo_image(5, 21, 30);
+        let gray = super::luma(&img);
+        for (&g, pixel) in gray.iter().zip(img.content().chunks_exact(C)) {
+            let expected: f32 = pixel
+                .iter()
+                .zip(&GRAYSCALE[0])
+                .map(|(&v, w)| v as f32 * w)
+                .sum();
+            assert!((g as f32 - expected).abs() <= 1., "{} {:?}", g, pixel);
+        }
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    #[test]
+    fn simd() -> Result<()> {
+        // the same corners as `is_corner` pixel by pixel, on a textured image
+        let img = photo_image(24, 53, 31);
+        let (h, w) = (img.height, img.width);
+        let gray = super::luma(&img);
+        let offsets = RING.map(|(dy, dx)| dy * w as isize + dx);
+        for (threshold, arc) in [(5, 9), (10, 12), (0, 16)] {
+            let mut expected = Vec::new();
+            for y in 3..h - 3 {
+                for x in 3..w - 3 {
+                    if is_corner(&gray, y * w + x, &offsets, threshold, arc) {
+                        expected.push((y, x));
+                    }
+                }
+            }
+            let detected: Vec<_> = Fast::new(threshold, arc)?
+                .detect(&img)?
+                .iter()
+                .map(|k| (k.y, k.x))
+                .collect();
+            assert_eq!(detected, expected);
+        }
+
+        // and the same luma as the serial loop
+        for (&g, pixel) in gray.iter().zip(img.content().chunks_exact(C)) {
+            let sum: u32 = pixel
+                .iter()
+                .zip(&LUMA_Q8)
+                .map(|(&v, &w)| v as u32 * w as u32)
+                .sum();
+            assert_eq!(g as u32, (sum + 128) >> 8);
+        }
+        Ok(())
+    }
+}
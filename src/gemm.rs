@@ -0,0 +1,252 @@
+//! Convolution lowered to matrix multiplication (im2col and GEMM), for one or several kernels.
+//!
+//! Output rows are cut into tiles of [`TILE_PIXELS`] pixels. The source values under weight `k`
+//! of the kernel for every value of a tile are a contiguous run of the interleaved source row, so
+//! the lowered matrix of a tile (`K * K` rows of `3 * TILE_PIXELS` values) is a copy of runs
+//! converted to `f32`. It is multiplied by the matrix of the weights of every kernel (a column
+//! per kernel), with NEON by a micro-kernel computing 16 values of 4 kernels at once with fused
+//! multiply-adds by lanes of the weights. [`convolve_many`] lowers the image once for all its
+//! kernels, e.g. for banks of filters.
+//!
+//! Products are summed in the order of the weights, row by row, with fused multiply-adds with or
+//! without NEON, so that the results are the same as those of [`Naive2`](crate::backend::Naive2).
+
+use alloc::{vec, vec::Vec};
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+use core::ops::Range;
+
+#[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+use crate::kernel::fma;
+use crate::{
+    backend::clear_border,
+    error::Result,
+    image::RgbImage,
+    kernel::{ConvKernel, Rounding},
+    C,
+};
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::{simd, util::vec_ref};
+
+/// Pixels of an output row lowered at once.
+pub const TILE_PIXELS: usize = 64;
+
+/// Convolves `src` by every kernel of `kernels`, lowering each tile of the image once for all of
+/// them. Pixels closer than `K / 2` to the border are 0, as by
+/// [`ConvBackend::convolve`](crate::backend::ConvBackend::convolve).
+///
+/// Fails with [`Error::ImageTooSmall`](crate::Error::ImageTooSmall) if `src` is smaller than
+/// the kernels.
+pub fn convolve_many<const K: usize>(
+    kernels: &[ConvKernel<K>],
+    src: &RgbImage,
+) -> Result<Vec<RgbImage>> {
+    src.check_min_size(K)?;
+    let (h, w, half) = (src.height, src.width, K / 2);
+    let row = w * C;
+    let mut dsts: Vec<Vec<u8>> = kernels.iter().map(|_| vec![0; h * row]).collect();
+    let mut rows: Vec<&mut [u8]> = dsts
+        .iter_mut()
+        .map(|dst| &mut dst[half * row..(h - half) * row])
+        .collect();
+    let kernels: Vec<&ConvKernel<K>> = kernels.iter().collect();
+    convolve_rows(&kernels, src, half..h - half, &mut rows);
+    Ok(dsts
+        .into_iter()
+        .map(|mut dst| {
+            clear_border(&mut dst, h, w, half);
+            RgbImage::from_raw_unchecked(dst, h, w)
+        })
+        .collect())
+}
+
+// Computes the output rows `rows` of every kernel into `dsts`, which hold exactly those rows,
+// leaving the pixels closer than `K / 2` to the left and right borders untouched. The sizes must
+// have been checked.
+pub(crate) fn convolve_rows<const K: usize>(
+    kernels: &[&ConvKernel<K>],
+    src: &RgbImage,
+    rows: Range<usize>,
+    dsts: &mut [&mut [u8]],
+) {
+    let (w, half) = (src.width, K / 2);
+    let row = w * C;
+    // columns of the weights, padded with zeros to whole micro-kernels
+    let n = (kernels.len() + 3) / 4 * 4;
+    let mut weights = vec![0.; K * K * n];
+    for (q, kernel) in kernels.iter().enumerate() {
+        for k in 0..K * K {
+            weights[k * n + q] = kernel.at(k / K, k % K);
+        }
+    }
+    // lowered tile and products, with rows of whole vectors of 16 values
+    let mut patches = vec![0.; K * K * TILE_PIXELS * C];
+    let mut products = vec![0.; n * TILE_PIXELS * C];
+
+    for (r, y) in rows.enumerate() {
+        for x0 in (half..w - half).step_by(TILE_PIXELS) {
+            let x1 = (x0 + TILE_PIXELS).min(w - half);
+            let values = (x1 - x0) * C;
+            let padded = (values + 15) / 16 * 16;
+            for (k, patch) in patches.chunks_exact_mut(padded).take(K * K).enumerate() {
+                let start = (y - half + k / K) * row + (x0 - half + k % K) * C;
+                lower(&src.content()[start..start + values], patch);
+            }
+            let (patches, products) = (&patches[..K * K * padded], &mut products[..n * padded]);
+            multiply(patches, &weights, (padded, n, kernels.len()), products);
+            for ((kernel, dst), products) in kernels
+                .iter()
+                .zip(dsts.iter_mut())
+                .zip(products.chunks_exact(padded))
+            {
+                let dst = &mut dst[r * row + x0 * C..r * row + x1 * C];
+                store(kernel.div, kernel.rounding, products, dst);
+            }
+        }
+    }
+}
+
+// the values `src` converted to `f32` into `dst`, padded with zeros
+fn lower(src: &[u8], dst: &mut [f32]) {
+    // values from `start` are left to the serial loop
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let start = {
+        let end = src.len() - src.len() % 16;
+        for i in (0..end).step_by(16) {
+            for (z, v) in simd::load_u8x16(src, i).iter().enumerate() {
+                simd::store_f32(dst, i + 4 * z, *v);
+            }
+        }
+        end
+    };
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    let start = 0;
+    for (d, &s) in dst.iter_mut().zip(src).skip(start) {
+        *d = s as f32;
+    }
+    dst[src.len()..].fill(0.);
+}
+
+// `products[q][v]`, the sum over k of `patches[k][v] * weights[k][q]` in the order of k, for the
+// `values` (a multiple of 16) columns of `patches` and the `n` (a multiple of 4) columns of
+// `weights`, of which the first `count` are kernels
+fn multiply(
+    patches: &[f32],
+    weights: &[f32],
+    (values, n, count): (usize, usize, usize),
+    products: &mut [f32],
+) {
+    let kk = patches.len() / values;
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    {
+        // 16 values of 4 kernels in 16 registers, each value times a lane of the weights
+        for q0 in (0..count).step_by(4) {
+            for v0 in (0..values).step_by(16) {
+                let mut acc = [[simd::splat(0.); 4]; 4];
+                for k in 0..kk {
+                    let a = [0, 1, 2, 3].map(|z| simd::load_f32(patches, k * values + v0 + 4 * z));
+                    unsafe {
+                        let b = vld1q_f32(vec_ref(weights, k * n + q0, 4));
+                        for (z, &a) in a.iter().enumerate() {
+                            acc[0][z] = vfmaq_laneq_f32::<0>(acc[0][z], a, b);
+                            acc[1][z] = vfmaq_laneq_f32::<1>(acc[1][z], a, b);
+                            acc[2][z] = vfmaq_laneq_f32::<2>(acc[2][z], a, b);
+                            acc[3][z] = vfmaq_laneq_f32::<3>(acc[3][z], a, b);
+                        }
+                    }
+                }
+                for (q, acc) in acc.iter().enumerate() {
+                    for (z, &acc) in acc.iter().enumerate() {
+                        simd::store_f32(products, (q0 + q) * values + v0 + 4 * z, acc);
+                    }
+                }
+            }
+        }
+    }
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    {
+        for (q, products) in products.chunks_exact_mut(values).take(count).enumerate() {
+            for (v, p) in products.iter_mut().enumerate() {
+                let mut t = 0.;
+                for k in 0..kk {
+                    t = fma(patches[k * values + v], weights[k * n + q], t);
+                }
+                *p = t;
+            }
+        }
+    }
+}
+
+// the sums `products` divided by `div` if any and saturated into `dst`
+fn store(div: Option<f32>, rounding: Rounding, products: &[f32], dst: &mut [u8]) {
+    // values from `start` are left to the serial loop
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let start = {
+        let end = dst.len() - dst.len() % 16;
+        let vdiv = div.map(simd::Divisor::new);
+        for i in (0..end).step_by(16) {
+            let mut v = [0, 1, 2, 3].map(|z| simd::load_f32(products, i + 4 * z));
+            if let Some(div) = vdiv {
+                v = v.map(|v| div.divide(v));
+            }
+            simd::store_u8x16(dst, i, &v, rounding);
+        }
+        end
+    };
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    let start = 0;
+    for (d, &p) in dst.iter_mut().zip(products).skip(start) {
+        let mut t = p;
+        if let Some(div) = div {
+            t /= div;
+        }
+        *d = rounding.to_u8(t);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::{ConvBackend, Naive2},
+        consts::SOBEL_FILTER,
+        error::Error,
+        test_util::photo_image,
+    };
+
+    #[test]
+    fn convolve_many() -> Result<()> {
+        // tiles with some pixels left over, and a kernel column of padding
+        let img = photo_image(9, 2 * TILE_PIXELS + 19, 57);
+        let (h, w) = (img.height, img.width);
+        let weights: Vec<f32> = (0..9).map(|i| (i * 7 % 11) as f32 * 0.25 - 1.).collect();
+        let kernels = [
+            ConvKernel::<3>::new(&[1.; 9], true)?,
+            ConvKernel::<3>::new(&SOBEL_FILTER, false)?,
+            ConvKernel::<3>::new(&weights, false)?,
+            ConvKernel::<3>::new(&[1., 2., 1., 2., 4., 2., 1., 2., 1.], true)?,
+            ConvKernel::<3>::new(&[0., -1., 0., -1., 5., -1., 0., -1., 0.], false)?,
+        ];
+        let outputs = super::convolve_many(&kernels, &img)?;
+        assert_eq!(outputs.len(), kernels.len());
+        for (kernel, output) in kernels.iter().zip(outputs.iter()) {
+            let mut expected = RgbImage::from_raw(vec![0; h * w * C], h, w)?;
+            Naive2.convolve(kernel, &img, &mut expected)?;
+            assert!(*output == expected);
+        }
+
+        // large kernels, narrower than a vector
+        let kernel = ConvKernel::<7>::new(&[1.; 49], true)?;
+        let img = photo_image(7, 8, 58);
+        let mut expected = RgbImage::from_raw(vec![0; 7 * 8 * C], 7, 8)?;
+        Naive2.convolve(&kernel, &img, &mut expected)?;
+        assert!(super::convolve_many(&[kernel], &img)?[0] == expected);
+
+        assert!(super::convolve_many::<3>(&[], &img)?.is_empty());
+        assert!(matches!(
+            super::convolve_many(&[ConvKernel::<9>::new(&[1.; 81], true)?], &img),
+            Err(Error::ImageTooSmall { .. })
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+//! Seeded generators of images and kernels, so that benchmarks can sweep any resolution without
+//! [`ORIGINAL`](crate::consts::ORIGINAL) and give the same input on every run and machine.
+
+use crate::{image::RgbImage, kernel::ConvKernel, C};
+
+/// SplitMix64 pseudo-random generator, small and the same everywhere unlike an external crate.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    /// Generator whose sequence only depends on `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// Next 64 random bits.
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn next_f32(&mut self) -> f32 {
+        // 24 bits fill the mantissa
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// Image of `height` x `width` pixels with independent uniform bytes, the worst case for
+/// compression and caches of intermediate values.
+pub fn noise_image(height: usize, width: usize, seed: u64) -> RgbImage {
+    let mut rng = Rng::new(seed);
+    let content = (0..height * width * C)
+        .map(|_| (rng.next_u64() >> 56) as u8)
+        .collect();
+    RgbImage::from_raw_unchecked(content, height, width)
+}
+
+// sinusoids per channel in `photo_image`
+const WAVES: usize = 4;
+
+/// Image of `height` x `width` pixels with smooth shapes and gradients like a photo: a few
+/// sinusoids of at most 4 periods across the image per channel, plus a little noise.
+pub fn photo_image(height: usize, width: usize, seed: u64) -> RgbImage {
+    let mut rng = Rng::new(seed);
+    // products of a column and a row wave, so that each pixel takes no trigonometry
+    let mut wave = |len: usize| -> Vec<f32> {
+        let freq = (1. + rng.next_f32() * 3.) * 2. * core::f32::consts::PI / len.max(1) as f32;
+        let phase = rng.next_f32() * 2. * core::f32::consts::PI;
+        (0..len).map(|i| (i as f32 * freq + phase).cos()).collect()
+    };
+    let mut waves = Vec::with_capacity(C * WAVES);
+    for _ in 0..C * WAVES {
+        let (cols, rows) = (wave(width), wave(height));
+        waves.push((cols, rows));
+    }
+    let amps: Vec<f32> = (0..C * WAVES).map(|_| 16. + rng.next_f32() * 32.).collect();
+
+    let mut content = Vec::with_capacity(height * width * C);
+    for y in 0..height {
+        for x in 0..width {
+            for c in 0..C {
+                let mut v = 128.;
+                for w in c * WAVES..(c + 1) * WAVES {
+                    v += amps[w] * waves[w].0[x] * waves[w].1[y];
+                }
+                v += (rng.next_f32() - 0.5) * 8.;
+                content.push(v.clamp(0., 255.) as u8);
+            }
+        }
+    }
+    RgbImage::from_raw_unchecked(content, height, width)
+}
+
+/// `K` x `K` kernel of uniform weights in `[0, 1)` divided by their sum, so that the result keeps
+/// the brightness of the image without the division of `avg`.
+pub fn random_kernel<const K: usize>(seed: u64) -> ConvKernel<K> {
+    let mut rng = Rng::new(seed);
+    let mut weights: Vec<f32> = (0..K * K).map(|_| rng.next_f32()).collect();
+    let sum: f32 = weights.iter().sum();
+    for w in weights.iter_mut() {
+        *w /= sum;
+    }
+    ConvKernel::new(&weights, false).expect("weights of K x K")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // mean absolute difference between horizontally adjacent bytes
+    fn roughness(img: &RgbImage) -> f64 {
+        let content = img.content();
+        let diffs: u64 = content
+            .iter()
+            .zip(&content[C..])
+            .map(|(&a, &b)| (a as i16 - b as i16).unsigned_abs() as u64)
+            .sum();
+        diffs as f64 / (content.len() - C) as f64
+    }
+
+    #[test]
+    fn deterministic() {
+        for (h, w) in [(1, 1), (17, 33), (64, 48)] {
+            assert_eq!(noise_image(h, w, 1), noise_image(h, w, 1));
+            assert_eq!(photo_image(h, w, 1), photo_image(h, w, 1));
+            assert_eq!(photo_image(h, w, 2).height(), h);
+            assert_eq!(photo_image(h, w, 2).width(), w);
+        }
+        assert_ne!(noise_image(8, 8, 1), noise_image(8, 8, 2));
+        assert_ne!(photo_image(8, 8, 1), photo_image(8, 8, 2));
+        assert_eq!(
+            random_kernel::<3>(5).weights(),
+            random_kernel::<3>(5).weights()
+        );
+        assert_ne!(
+            random_kernel::<3>(5).weights(),
+            random_kernel::<3>(6).weights()
+        );
+        let mut rng = Rng::new(9);
+        assert!((0..1000).all(|_| (0. ..1.).contains(&rng.next_f32())));
+    }
+
+    #[test]
+    fn content() {
+        let noise = noise_image(64, 64, 3);
+        let photo = photo_image(64, 64, 3);
+        assert!(roughness(&noise) > 60., "{}", roughness(&noise));
+        assert!(roughness(&photo) < 10., "{}", roughness(&photo));
+
+        let kernel = random_kernel::<5>(3);
+        let sum: f32 = kernel.weights().iter().sum();
+        assert!((sum - 1.).abs() < 1e-5, "{}", sum);
+        assert_eq!(kernel.scale(), None);
+    }
+}
@@ -0,0 +1,271 @@
+//! Hashes of images, to snapshot results in tests and to find duplicates cheaply.
+//!
+//! [`RgbImage::content_hash`] hashes the bytes with [`xxh32`], whose 4 accumulators are the lanes
+//! of a NEON vector, so any change of a byte changes it. [`RgbImage::dhash`] and
+//! [`RgbImage::phash`] are perceptual hashes of the luma of a few cells of the image instead:
+//! similar images, e.g. recompressed, resized or slightly brighter, differ by only a few bits,
+//! which [`hamming`] counts.
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+use core::f32::consts::PI;
+
+use alloc::{vec, vec::Vec};
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{color_matrix::GRAYSCALE, error::Result, image::RgbImage, kernel::cos, C};
+
+const PRIME1: u32 = 2_654_435_761;
+const PRIME2: u32 = 2_246_822_519;
+const PRIME3: u32 = 3_266_489_917;
+const PRIME4: u32 = 668_265_263;
+const PRIME5: u32 = 374_761_393;
+
+// bytes consumed by one round of the 4 accumulators
+const STRIPE: usize = 16;
+
+// cells of the luma averaged by `phash`, of which the lowest 8 x 8 frequencies are kept
+const PHASH_CELLS: usize = 32;
+
+/// XXH32 hash of `data` with `seed`, the same as the reference implementation.
+pub fn xxh32(data: &[u8], seed: u32) -> u32 {
+    let len = data.len();
+    let end = len - len % STRIPE;
+    let mut h = if len >= STRIPE {
+        let acc = [
+            seed.wrapping_add(PRIME1).wrapping_add(PRIME2),
+            seed.wrapping_add(PRIME2),
+            seed,
+            seed.wrapping_sub(PRIME1),
+        ];
+        let [a, b, c, d] = stripes(&data[..end], acc);
+        a.rotate_left(1)
+            .wrapping_add(b.rotate_left(7))
+            .wrapping_add(c.rotate_left(12))
+            .wrapping_add(d.rotate_left(18))
+    } else {
+        seed.wrapping_add(PRIME5)
+    };
+    h = h.wrapping_add(len as u32);
+    let mut words = data[end..].chunks_exact(4);
+    for word in &mut words {
+        let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+        h = h.wrapping_add(word.wrapping_mul(PRIME3));
+        h = h.rotate_left(17).wrapping_mul(PRIME4);
+    }
+    for &byte in words.remainder() {
+        h = h.wrapping_add((byte as u32).wrapping_mul(PRIME5));
+        h = h.rotate_left(11).wrapping_mul(PRIME1);
+    }
+    h ^= h >> 15;
+    h = h.wrapping_mul(PRIME2);
+    h ^= h >> 13;
+    h = h.wrapping_mul(PRIME3);
+    h ^ (h >> 16)
+}
+
+/// Number of differing bits of two perceptual hashes, from 0 for similar images to about 32 for
+/// unrelated ones.
+pub fn hamming(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+impl RgbImage {
+    /// [`xxh32`] of the bytes, seeded with the size so that images of the same bytes but
+    /// different shapes differ.
+    pub fn content_hash(&self) -> u32 {
+        let mut size = [0; 16];
+        size[..8].copy_from_slice(&(self.height as u64).to_le_bytes());
+        size[8..].copy_from_slice(&(self.width as u64).to_le_bytes());
+        xxh32(&self.inner, xxh32(&size, 0))
+    }
+
+    /// Difference hash: bit `8 * y + x` is set if the cell at `(y, x)` of 8 x 9 cells of the luma
+    /// is darker than the one at its right.
+    ///
+    /// Fails with [`Error::ImageTooSmall`](crate::Error::ImageTooSmall) if the image is empty.
+    pub fn dhash(&self) -> Result<u64> {
+        self.check_min_size(1)?;
+        let cells = luma_cells(self, 8, 9);
+        let mut hash = 0;
+        for y in 0..8 {
+            for x in 0..8 {
+                if cells[y * 9 + x] < cells[y * 9 + x + 1] {
+                    hash |= 1 << (8 * y + x);
+                }
+            }
+        }
+        Ok(hash)
+    }
+
+    /// Perceptual hash: bit `8 * v + u` is set if the DCT coefficient of the vertical frequency
+    /// `v` and horizontal one `u` of 32 x 32 cells of the luma is above the median of the 63
+    /// lowest ones other than the mean.
+    ///
+    /// Fails with [`Error::ImageTooSmall`](crate::Error::ImageTooSmall) if the image is empty.
+    pub fn phash(&self) -> Result<u64> {
+        self.check_min_size(1)?;
+        let n = PHASH_CELLS;
+        let cells = luma_cells(self, n, n);
+        // basis[u * n + x], the DCT-II basis of the frequency `u` at `x`
+        let mut basis = vec![0.; 8 * n];
+        for (i, b) in basis.iter_mut().enumerate() {
+            let (u, x) = (i / n, i % n);
+            *b = cos(PI * (2 * x + 1) as f32 * u as f32 / (2 * n) as f32);
+        }
+        // transform the rows, then the columns of the 8 lowest frequencies
+        let mut rows = vec![0.; n * 8];
+        for y in 0..n {
+            for u in 0..8 {
+                let cells = &cells[y * n..][..n];
+                rows[y * 8 + u] = cells.iter().zip(&basis[u * n..]).map(|(c, b)| c * b).sum();
+            }
+        }
+        let mut coeffs = [0.; 64];
+        for (i, coeff) in coeffs.iter_mut().enumerate() {
+            let (v, u) = (i / 8, i % 8);
+            *coeff = (0..n).map(|y| basis[v * n + y] * rows[y * 8 + u]).sum();
+        }
+        let mut sorted = coeffs[1..].to_vec();
+        sorted.sort_unstable_by(|a: &f32, b| a.partial_cmp(b).unwrap());
+        let median = sorted[sorted.len() / 2];
+        Ok(coeffs
+            .iter()
+            .enumerate()
+            .filter(|&(_, &coeff)| coeff > median)
+            .fold(0, |hash, (i, _)| hash | 1 << i))
+    }
+}
+
+// mean luma of each of `rows` x `cols` cells splitting `img`, a pixel going to several cells if
+// the image is smaller than them
+fn luma_cells(img: &RgbImage, rows: usize, cols: usize) -> Vec<f32> {
+    let (h, w) = (img.height, img.width);
+    let bounds = |i: usize, cells: usize, len: usize| {
+        let start = i * len / cells;
+        start..((i + 1) * len / cells).max(start + 1)
+    };
+    let weights = GRAYSCALE[0];
+    let luma: Vec<f32> = img
+        .inner
+        .chunks_exact(C)
+        .map(|p| p.iter().zip(&weights).map(|(&v, w)| v as f32 * w).sum())
+        .collect();
+    let mut cells = Vec::with_capacity(rows * cols);
+    for i in 0..rows {
+        let ys = bounds(i, rows, h);
+        for j in 0..cols {
+            let xs = bounds(j, cols, w);
+            let sum: f32 = ys
+                .clone()
+                .map(|y| luma[y * w + xs.start..y * w + xs.end].iter().sum::<f32>())
+                .sum();
+            cells.push(sum / (ys.len() * xs.len()) as f32);
+        }
+    }
+    cells
+}
+
+// one round of XXH32 on `word`
+fn round(acc: u32, word: u32) -> u32 {
+    acc.wrapping_add(word.wrapping_mul(PRIME2))
+        .rotate_left(13)
+        .wrapping_mul(PRIME1)
+}
+
+// rounds of the 4 accumulators `acc` on every stripe of `data`, whose length is a multiple of
+// `STRIPE`
+fn stripes(data: &[u8], mut acc: [u32; 4]) -> [u32; 4] {
+    // stripes from `start` are left to the serial loop
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let start = unsafe {
+        let mut v = vld1q_u32(acc.as_ptr());
+        for i in (0..data.len()).step_by(STRIPE) {
+            let words = vreinterpretq_u32_u8(simd::load_bytes16(data, i));
+            v = vmlaq_u32(v, words, vdupq_n_u32(PRIME2));
+            v = vsriq_n_u32::<19>(vshlq_n_u32::<13>(v), v);
+            v = vmulq_u32(v, vdupq_n_u32(PRIME1));
+        }
+        vst1q_u32(acc.as_mut_ptr(), v);
+        data.len()
+    };
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    let start = 0;
+    for stripe in data[start..].chunks_exact(STRIPE) {
+        for (k, acc) in acc.iter_mut().enumerate() {
+            let word = &stripe[4 * k..][..4];
+            *acc = round(
+                *acc,
+                u32::from_le_bytes([word[0], word[1], word[2], word[3]]),
+            );
+        }
+    }
+    acc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::photo_image;
+
+    #[test]
+    fn content_hash() -> Result<()> {
+        // vectors of the reference implementation, shorter and longer than a stripe
+        assert_eq!(xxh32(b"", 0), 0x02cc_5d05);
+        assert_eq!(xxh32(b"abc", 0), 0x32d1_53ff);
+        assert_eq!(
+            xxh32(b"Nobody inspects the spammish repetition", 0),
+            0xe229_3b2f
+        );
+        let data: Vec<u8> = (0..=255).chain(0..7).collect();
+        assert_eq!(xxh32(&data, 0x9e37_79b1), 0x909e_19d5);
+
+        let img = photo_image(6, 8, 19);
+        assert_eq!(img.content_hash(), photo_image(6, 8, 19).content_hash());
+        let mut changed = photo_image(6, 8, 19);
+        changed.content_mut()[77] ^= 1;
+        assert_ne!(changed.content_hash(), img.content_hash());
+        let reshaped = RgbImage::from_raw(img.content().to_vec(), 8, 6)?;
+        assert_ne!(reshaped.content_hash(), img.content_hash());
+        Ok(())
+    }
+
+    #[test]
+    fn perceptual_hashes() -> Result<()> {
+        let img = photo_image(48, 64, 20);
+        let mut brighter = photo_image(48, 64, 20);
+        brighter.adjust(8., 1.05);
+        let other = photo_image(48, 64, 21);
+        for hash in [RgbImage::dhash, RgbImage::phash] {
+            assert!(hamming(hash(&img)?, hash(&brighter)?) <= 4);
+            assert!(hamming(hash(&img)?, hash(&other)?) >= 16);
+        }
+
+        // a horizontal ramp gets brighter to the right, in every cell
+        let content = (0..4 * 20 * C).map(|i| (i / C % 20 * 12) as u8).collect();
+        let ramp = RgbImage::from_raw(content, 4, 20)?;
+        assert_eq!(ramp.dhash()?, u64::MAX);
+        assert!(matches!(
+            RgbImage::from_raw(vec![], 0, 5)?.phash(),
+            Err(crate::Error::ImageTooSmall { .. })
+        ));
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    #[test]
+    fn simd() {
+        // every number of stripes up to 8, against `round` word by word
+        let data: Vec<u8> = (0..8 * STRIPE).map(|i| (i * 181 % 251) as u8).collect();
+        for stripes_len in 0..=8 {
+            let data = &data[..stripes_len * STRIPE];
+            let mut expected = [1, 2, 3, 4];
+            for (i, word) in data.chunks_exact(4).enumerate() {
+                let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+                expected[i % 4] = round(expected[i % 4], word);
+            }
+            assert_eq!(stripes(data, [1, 2, 3, 4]), expected);
+        }
+    }
+}
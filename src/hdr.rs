@@ -0,0 +1,294 @@
+//! High dynamic range images of `f32` values, and tone mapping them to 8-bit images for display.
+//!
+//! Values are linear with 1 for the white of the display, and may go far above it, e.g. sums of
+//! bright pixels. Tone mapping compresses them into `[0, 1]` before scaling to `[0, 255]`; no
+//! gamma curve is applied. The operators treat every value alike, so NEON maps 16 values at a
+//! time with the same operations as the serial loop.
+//!
+//! [`ConvProcessor::convolve_f32`] filters such images without rounding or saturating results, by the
+//! [`Sample`](crate::sample::Sample) implementation of `f32`.
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+
+use alloc::{vec, vec::Vec};
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{
+    error::{Error, Result},
+    image::RgbImage,
+    kernel::Rounding,
+    processor::ConvProcessor,
+    C,
+};
+
+/// RGB image of interleaved `f32` values, like [`RgbImage`] with bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RgbImageF32 {
+    inner: Vec<f32>,
+    height: usize,
+    width: usize,
+}
+
+/// Tone-mapping operator of [`RgbImageF32::tone_map`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMap {
+    /// Reinhard's `x / (1 + x)`, which keeps the contrast of dark values and never reaches 1.
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve, `x (2.51 x + 0.03) / (x (2.43 x + 0.59) + 0.14)`,
+    /// with more contrast and saturating the brightest values to white.
+    Aces,
+}
+
+impl RgbImageF32 {
+    /// Black image of `height` x `width` pixels.
+    pub fn new(height: usize, width: usize) -> Self {
+        Self {
+            inner: vec![0.; height * width * C],
+            height,
+            width,
+        }
+    }
+
+    /// Wraps interleaved values of `height * width` pixels.
+    ///
+    /// Fails with [`Error::InvalidLength`] if the length of `content` is inconsistent.
+    pub fn from_raw(content: Vec<f32>, height: usize, width: usize) -> Result<Self> {
+        if content.len() != height * width * C {
+            return Err(Error::InvalidLength {
+                expected: height * width * C,
+                actual: content.len(),
+            });
+        }
+        Ok(Self {
+            inner: content,
+            height,
+            width,
+        })
+    }
+
+    pub(crate) fn from_raw_unchecked(content: Vec<f32>, height: usize, width: usize) -> Self {
+        Self {
+            inner: content,
+            height,
+            width,
+        }
+    }
+
+    /// Converts `src` with 255 mapped to 1.
+    pub fn from_rgb(src: &RgbImage) -> Self {
+        Self {
+            inner: src.content().iter().map(|&v| v as f32 / 255.).collect(),
+            height: src.height,
+            width: src.width,
+        }
+    }
+
+    /// Height in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Width in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Values of all the pixels, row by row.
+    pub fn content(&self) -> &[f32] {
+        &self.inner
+    }
+
+    /// Mutable values of all the pixels, row by row.
+    pub fn content_mut(&mut self) -> &mut [f32] {
+        &mut self.inner
+    }
+
+    /// Maps every value `v` to `op(max(v * exposure, 0)) * 255`, rounded to the nearest integer
+    /// and saturated to `[0, 255]`.
+    pub fn tone_map(&self, op: ToneMap, exposure: f32) -> RgbImage {
+        let src = &self.inner[..];
+        let mut dst = vec![0; src.len()];
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let start = {
+            let simd_end = src.len() - src.len() % 16;
+            for i in (0..simd_end).step_by(16) {
+                let v = [0, 4, 8, 12].map(|z| unsafe {
+                    let x = simd::mul(simd::load_f32(src, i + z), simd::splat(exposure));
+                    let y = tone_map4(vmaxq_f32(x, simd::splat(0.)), op);
+                    simd::mul(y, simd::splat(255.))
+                });
+                simd::store_u8x16(&mut dst, i, &v, Rounding::Nearest);
+            }
+            simd_end
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let start = 0;
+        for (d, &v) in dst[start..].iter_mut().zip(&src[start..]) {
+            *d = Rounding::Nearest.to_u8(tone_map(op, (v * exposure).max(0.)) * 255.);
+        }
+        RgbImage::from_raw_unchecked(dst, self.height, self.width)
+    }
+}
+
+impl<const K: usize> ConvProcessor<K> {
+    /// Convolves `src` like [`convolve`](Self::convolve), but keeps the results in `f32` without
+    /// rounding or saturating them, e.g. HDR images for [`RgbImageF32::tone_map`]. Pixels of the
+    /// border are 0 with [`BorderMode::Zero`].
+    ///
+    /// Runs on the current thread with its own NEON implementation if available, whatever the
+    /// backend, threads and tiles of the processor.
+    ///
+    /// Fails with [`Error::ImageTooSmall`] if `src` is smaller than
+    /// [`min_size`](Self::min_size).
+    pub fn convolve_f32(&self, src: &RgbImageF32) -> Result<RgbImageF32> {
+        let (h, w) = (src.height, src.width);
+        let content = self.convolve_samples(&src.inner, h, w, f32::MAX)?;
+        Ok(RgbImageF32::from_raw_unchecked(content, h, w))
+    }
+}
+
+// coefficients of `ToneMap::Aces`
+const ACES: [f32; 5] = [2.51, 0.03, 2.43, 0.59, 0.14];
+
+// `op` of a non-negative value
+fn tone_map(op: ToneMap, x: f32) -> f32 {
+    match op {
+        ToneMap::Reinhard => x / (1. + x),
+        ToneMap::Aces => {
+            let [a, b, c, d, e] = ACES;
+            x * (a * x + b) / (x * (c * x + d) + e)
+        }
+    }
+}
+
+// `tone_map` of 4 values, without fused multiply-adds to round like it
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[inline]
+unsafe fn tone_map4(x: float32x4_t, op: ToneMap) -> float32x4_t {
+    match op {
+        ToneMap::Reinhard => vdivq_f32(x, vaddq_f32(vdupq_n_f32(1.), x)),
+        ToneMap::Aces => {
+            let [a, b, c, d, e] = ACES.map(|k| vdupq_n_f32(k));
+            let num = vmulq_f32(x, vaddq_f32(vmulq_f32(a, x), b));
+            let den = vaddq_f32(vmulq_f32(x, vaddq_f32(vmulq_f32(c, x), d)), e);
+            vdivq_f32(num, den)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        kernel::fma,
+        processor::{border_index, BorderMode},
+    };
+
+    #[test]
+    fn operators() -> Result<()> {
+        // longer than a vector of 16 values, with some left over
+        let values = [0., 1., 3., -2., 100.];
+        let content: Vec<f32> = (0..7 * C).map(|i| values[i % 5]).collect();
+        let img = RgbImageF32::from_raw(content, 1, 7)?;
+        let expect = |op, exposure, expected: [u8; 5]| {
+            let mapped = img.tone_map(op, exposure);
+            for (i, &v) in mapped.content().iter().enumerate() {
+                assert_eq!(v, expected[i % 5], "{:?} {}", op, values[i % 5]);
+            }
+        };
+        // 1 / 2, 3 / 4 and 100 / 101
+        expect(ToneMap::Reinhard, 1., [0, 128, 191, 0, 252]);
+        expect(ToneMap::Reinhard, 2., [0, 170, 219, 0, 254]);
+        // 2.54 / 3.16, 22.68 / 23.78, and saturated
+        expect(ToneMap::Aces, 1., [0, 205, 243, 0, 255]);
+
+        // increasing values stay in order
+        let ramp: Vec<f32> = (0..300 * C).map(|i| (i / C) as f32 * 0.05).collect();
+        let ramp = RgbImageF32::from_raw(ramp, 10, 30)?;
+        for op in [ToneMap::Reinhard, ToneMap::Aces] {
+            let mapped = ramp.tone_map(op, 1.);
+            assert!(mapped.content().windows(C + 1).all(|w| w[0] <= w[C]));
+        }
+
+        let rgb = RgbImage::from_raw(vec![0, 51, 255], 1, 1)?;
+        assert_eq!(RgbImageF32::from_rgb(&rgb).content(), [0., 0.2, 1.]);
+        assert!(matches!(
+            RgbImageF32::from_raw(vec![0.; 5], 1, 2),
+            Err(Error::InvalidLength {
+                expected: 6,
+                actual: 5
+            })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn convolve_f32() -> Result<()> {
+        // values above 1 and below 0, over vectors of 4 values with some left over
+        let (h, w) = (7, 13);
+        let content: Vec<f32> = (0..h * w * C)
+            .map(|i| (i * 29 % 61) as f32 * 0.1 - 1.)
+            .collect();
+        let img = RgbImageF32::from_raw(content, h, w)?;
+        let filter: Vec<f32> = (0..25).map(|i| (i % 7) as f32 - 2.).collect();
+        for border in [BorderMode::Zero, BorderMode::Replicate, BorderMode::Reflect] {
+            for normalize in [false, true] {
+                let processor = ConvProcessor::<5>::builder()
+                    .kernel(&filter)
+                    .normalize(normalize)
+                    .border(border)
+                    .build()?;
+                let convolved = processor.convolve_f32(&img)?;
+                // every value computed independently, with the same fused multiply-adds
+                let kernel = processor.kernel();
+                for (index, &v) in convolved.content().iter().enumerate() {
+                    let (y, x, c) = (index / C / w, index / C % w, index % C);
+                    if border == BorderMode::Zero && (y < 2 || x < 2 || y >= h - 2 || x >= w - 2) {
+                        assert_eq!(v, 0.);
+                        continue;
+                    }
+                    let mut t = 0.;
+                    for i in 0..5 {
+                        for j in 0..5 {
+                            let sy = border_index((y + i) as isize - 2, h, border);
+                            let sx = border_index((x + j) as isize - 2, w, border);
+                            t = fma(img.content()[(sy * w + sx) * C + c], kernel.at(i, j), t);
+                        }
+                    }
+                    if let Some(div) = kernel.div() {
+                        t /= div;
+                    }
+                    assert_eq!(v, t, "{:?} {:?}", border, (y, x, c));
+                }
+            }
+        }
+        let small = RgbImageF32::new(4, 10);
+        assert!(matches!(
+            ConvProcessor::<5>::new(&filter, false)?.convolve_f32(&small),
+            Err(Error::ImageTooSmall { min: 5, .. })
+        ));
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    #[test]
+    fn simd() -> Result<()> {
+        // vectors of 16 values, and some left over
+        let content: Vec<f32> = (0..123 * C)
+            .map(|i| (i * 37 % 101) as f32 * 0.07 - 1.)
+            .collect();
+        let img = RgbImageF32::from_raw(content, 3, 41)?;
+        for op in [ToneMap::Reinhard, ToneMap::Aces] {
+            for exposure in [0.5, 1., 3.] {
+                let mapped = img.tone_map(op, exposure);
+                for (&m, &v) in mapped.content().iter().zip(img.content()) {
+                    let expected = tone_map(op, (v * exposure).max(0.)) * 255.;
+                    assert_eq!(m, Rounding::Nearest.to_u8(expected), "{:?} {}", op, v);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+//! Hough transform, turning edge maps into straight lines.
+//!
+//! Every edge pixel `(y, x)` votes for the lines through it, `x cos θ + y sin θ = ρ` for each of
+//! the angles `θ`, with `ρ` rounded to the nearest pixel. NEON computes `ρ` for 4 angles at a
+//! time with fused multiply-adds and rounds them to indices of the accumulator, and lines are the
+//! local maxima of the votes. Edge maps are e.g. the output of a Sobel filter followed by
+//! [`Pipeline::threshold`](crate::pipeline::Pipeline::threshold).
+
+use alloc::{string::ToString, vec, vec::Vec};
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+use core::{cmp::Reverse, f32::consts::PI};
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{
+    error::{Error, Result},
+    image::RgbImage,
+    kernel::{cos, fma, round, sin, sqrt},
+    C,
+};
+
+/// Line `x cos θ + y sin θ = ρ` found by [`hough_lines`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Line {
+    /// Signed distance of the line from the top-left pixel, in pixels.
+    pub rho: f32,
+    /// Angle of the normal of the line in `[0, π)`, 0 for vertical lines and `π / 2` for
+    /// horizontal ones.
+    pub theta: f32,
+    /// Number of edge pixels on the line.
+    pub votes: u32,
+}
+
+/// Lines through at least `threshold` edge pixels of `edges`, the pixels with any non-zero
+/// channel, by decreasing number of votes. Angles are `angles` steps of `π / angles`, and
+/// distances steps of 1 pixel. Only lines with more votes than their neighbours in angle and
+/// distance are kept, the first on ties.
+///
+/// Fails with [`Error::BadKernel`] if `angles` is 0.
+pub fn hough_lines(edges: &RgbImage, angles: usize, threshold: u32) -> Result<Vec<Line>> {
+    if angles == 0 {
+        return Err(Error::BadKernel(
+            "at least one angle is required".to_string(),
+        ));
+    }
+    let (h, w) = (edges.height, edges.width);
+    let thetas: Vec<f32> = (0..angles).map(|t| t as f32 * PI / angles as f32).collect();
+    let (cosines, sines): (Vec<_>, Vec<_>) = thetas.iter().map(|&t| (cos(t), sin(t))).unzip();
+    // |ρ| is at most the diagonal
+    let diag = sqrt((h * h + w * w) as f32) as usize + 1;
+    let rhos = 2 * diag + 1;
+    let mut votes = vec![0; angles * rhos];
+    for (i, pixel) in edges.inner.chunks_exact(C).enumerate() {
+        if pixel.iter().any(|&v| v != 0) {
+            let (y, x) = ((i / w) as f32, (i % w) as f32);
+            vote(&mut votes, rhos, diag, &cosines, &sines, y, x);
+        }
+    }
+
+    let mut lines = Vec::new();
+    for t in 0..angles {
+        for r in 0..rhos {
+            let v = votes[t * rhos + r];
+            if v < threshold || v == 0 {
+                continue;
+            }
+            let is_max = (t.saturating_sub(1)..(t + 2).min(angles)).all(|nt| {
+                (r.saturating_sub(1)..(r + 2).min(rhos)).all(|nr| {
+                    let other = votes[nt * rhos + nr];
+                    if (nt, nr) < (t, r) {
+                        other < v
+                    } else {
+                        other <= v
+                    }
+                })
+            });
+            if is_max {
+                lines.push(Line {
+                    rho: r as f32 - diag as f32,
+                    theta: thetas[t],
+                    votes: v,
+                });
+            }
+        }
+    }
+    lines.sort_by_key(|line| Reverse(line.votes));
+    Ok(lines)
+}
+
+// adds the votes of the pixel at `(y, x)` to `votes`, rows of `rhos` distances from `-diag` for
+// every angle of `cosines` and `sines`
+fn vote(
+    votes: &mut [u32],
+    rhos: usize,
+    diag: usize,
+    cosines: &[f32],
+    sines: &[f32],
+    y: f32,
+    x: f32,
+) {
+    // angles from `start` are left to the serial loop
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let start = unsafe {
+        let (vx, vy) = (simd::splat(x), simd::splat(y));
+        let base = vdupq_n_s32(diag as i32);
+        let mut t = 0;
+        while t + 4 <= cosines.len() {
+            let y_sin = simd::mul(vy, simd::load_f32(sines, t));
+            let rho = simd::fma(y_sin, vx, simd::load_f32(cosines, t));
+            // rounded half away from zero like `round`
+            let mut indices = [0; 4];
+            vst1q_s32(indices.as_mut_ptr(), vaddq_s32(vcvtaq_s32_f32(rho), base));
+            for (k, &index) in indices.iter().enumerate() {
+                votes[(t + k) * rhos + index as usize] += 1;
+            }
+            t += 4;
+        }
+        t
+    };
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    let start = 0;
+    for t in start..cosines.len() {
+        let rho = round(fma(x, cosines[t], y * sines[t]));
+        votes[t * rhos + (rho as isize + diag as isize) as usize] += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // black image of `h` x `w` pixels with white pixels at `points`
+    fn edges(h: usize, w: usize, points: impl Iterator<Item = (usize, usize)>) -> Result<RgbImage> {
+        let mut content = vec![0; h * w * C];
+        for (y, x) in points {
+            content[(y * w + x) * C..][..C].fill(255);
+        }
+        RgbImage::from_raw(content, h, w)
+    }
+
+    #[test]
+    fn hough_lines() -> Result<()> {
+        // a horizontal segment and a vertical line, whose votes spread over the neighbouring
+        // angles with fewer local maxima
+        let img = edges(
+            40,
+            60,
+            (5..55).map(|x| (12, x)).chain((0..40).map(|y| (y, 45))),
+        )?;
+        let lines = super::hough_lines(&img, 180, 35)?;
+        assert_eq!(lines.len(), 2, "{:?}", lines);
+        assert_eq!((lines[0].rho, lines[0].votes), (12., 50));
+        assert!((lines[0].theta - PI / 2.).abs() < 1e-6);
+        assert_eq!(
+            lines[1],
+            Line {
+                rho: 45.,
+                theta: 0.,
+                votes: 40
+            }
+        );
+
+        // a diagonal, with only 4 angles
+        let img = edges(30, 30, (0..30).map(|i| (i, 29 - i)))?;
+        let lines = super::hough_lines(&img, 4, 20)?;
+        assert_eq!(lines.len(), 1, "{:?}", lines);
+        assert_eq!((lines[0].theta, lines[0].votes), (PI / 4., 30));
+
+        let black = edges(10, 10, core::iter::empty())?;
+        assert!(super::hough_lines(&black, 180, 0)?.is_empty());
+        assert!(matches!(
+            super::hough_lines(&black, 0, 10),
+            Err(Error::BadKernel(_))
+        ));
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    #[test]
+    fn simd() {
+        // 7 angles, one vector and some left over, against the serial formula
+        let (rhos, diag) = (2 * 50 + 1, 50);
+        let thetas: Vec<f32> = (0..7).map(|t| t as f32 * PI / 7.).collect();
+        let cosines: Vec<f32> = thetas.iter().map(|&t| cos(t)).collect();
+        let sines: Vec<f32> = thetas.iter().map(|&t| sin(t)).collect();
+        for (y, x) in [(0., 0.), (3., 17.), (33., 2.), (24., 31.)] {
+            let mut votes = vec![0; 7 * rhos];
+            vote(&mut votes, rhos, diag, &cosines, &sines, y, x);
+            let mut expected = vec![0; 7 * rhos];
+            for t in 0..7 {
+                let rho = round(fma(x, cosines[t], y * sines[t]));
+                expected[t * rhos + (rho as isize + diag as isize) as usize] += 1;
+            }
+            assert_eq!(votes, expected, "{} {}", y, x);
+        }
+    }
+}
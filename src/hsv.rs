@@ -0,0 +1,328 @@
+//! HSV color space, and hue and saturation adjustments through it.
+//!
+//! Conversions are branchless so that NEON computes 16 pixels at a time with the same
+//! operations, in the same order, as the scalar functions, and gives the same results.
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+
+use alloc::{vec, vec::Vec};
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{image::RgbImage, kernel::Rounding, C};
+
+/// Image in HSV stored as 3 planes of `height * width` values: hue in degrees in `[0, 360)`,
+/// then saturation and value in `[0, 1]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HsvImage {
+    planes: Vec<f32>,
+    height: usize,
+    width: usize,
+}
+
+impl HsvImage {
+    /// Converts every pixel of `src` with [`rgb_to_hsv`], 16 at a time with NEON.
+    pub fn from_rgb(src: &RgbImage) -> Self {
+        let len = src.height * src.width;
+        let mut planes = vec![0.; len * C];
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let start = {
+            let simd_end = len - len % 16;
+            for i in (0..simd_end).step_by(16) {
+                let [r, g, b] = simd::load_rgb16(src.content(), i * C);
+                for z in 0..4 {
+                    let hsv = unsafe { rgb_to_hsv4(r[z], g[z], b[z]) };
+                    for (c, &hsv) in hsv.iter().enumerate() {
+                        simd::store_f32(&mut planes, c * len + i + 4 * z, hsv);
+                    }
+                }
+            }
+            simd_end
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let start = 0;
+        for i in start..len {
+            let p = &src.content()[i * C..(i + 1) * C];
+            let hsv = rgb_to_hsv([p[0], p[1], p[2]]);
+            for (c, &hsv) in hsv.iter().enumerate() {
+                planes[c * len + i] = hsv;
+            }
+        }
+        Self {
+            planes,
+            height: src.height,
+            width: src.width,
+        }
+    }
+
+    /// Converts every pixel back with [`hsv_to_rgb`], 16 at a time with NEON.
+    pub fn to_rgb(&self) -> RgbImage {
+        let len = self.height * self.width;
+        let (h, s, v) = (self.plane(0), self.plane(1), self.plane(2));
+        let mut dst = vec![0; len * C];
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let start = {
+            let simd_end = len - len % 16;
+            for i in (0..simd_end).step_by(16) {
+                let mut rgb: simd::Rgb16 = [[simd::splat(0.); 4]; C];
+                let [r, g, b] = &mut rgb;
+                for z in 0..4 {
+                    [r[z], g[z], b[z]] = unsafe {
+                        hsv_to_rgb4(
+                            simd::load_f32(h, i + 4 * z),
+                            simd::load_f32(s, i + 4 * z),
+                            simd::load_f32(v, i + 4 * z),
+                        )
+                    };
+                }
+                simd::store_rgb16(&mut dst, i * C, &rgb, Rounding::Nearest);
+            }
+            simd_end
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let start = 0;
+        for i in start..len {
+            dst[i * C..(i + 1) * C].copy_from_slice(&hsv_to_rgb([h[i], s[i], v[i]]));
+        }
+        RgbImage::from_raw_unchecked(dst, self.height, self.width)
+    }
+
+    /// Height in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Width in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Plane `c` (0 for hue, 1 for saturation, 2 for value), in row-major order.
+    ///
+    /// # Panics
+    ///
+    /// If `c` is 3 or more.
+    pub fn plane(&self, c: usize) -> &[f32] {
+        let len = self.height * self.width;
+        &self.planes[c * len..][..len]
+    }
+
+    /// Mutable plane `c`, see [`plane`](Self::plane).
+    pub fn plane_mut(&mut self, c: usize) -> &mut [f32] {
+        let len = self.height * self.width;
+        &mut self.planes[c * len..][..len]
+    }
+}
+
+impl RgbImage {
+    /// Rotates the hue of every pixel by `hue` degrees and multiplies its saturation by
+    /// `saturation` (clamped to `[0, 1]`) in place, through HSV without an intermediate image,
+    /// 16 pixels at a time with NEON.
+    ///
+    /// A `hue` of 0 and a `saturation` of 1 leave the image as it is, and a `saturation` of 0
+    /// turns it into shades of gray.
+    pub fn adjust_hsv(&mut self, hue: f32, saturation: f32) {
+        let shift = hue % 360.;
+        let shift = if shift < 0. { shift + 360. } else { shift };
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let bytes = {
+            let bytes = &mut self.inner[..];
+            let simd_end = bytes.len() - bytes.len() % (16 * C);
+            for i in (0..simd_end).step_by(16 * C) {
+                let mut rgb = simd::load_rgb16(bytes, i);
+                let [r, g, b] = &mut rgb;
+                for z in 0..4 {
+                    unsafe {
+                        let [h, s, v] = rgb_to_hsv4(r[z], g[z], b[z]);
+                        let h = vaddq_f32(h, vdupq_n_f32(shift));
+                        let full = vdupq_n_f32(360.);
+                        let h = vbslq_f32(vcgeq_f32(h, full), vsubq_f32(h, full), h);
+                        let s = vmulq_f32(s, vdupq_n_f32(saturation));
+                        let s = vmaxq_f32(vminq_f32(s, vdupq_n_f32(1.)), vdupq_n_f32(0.));
+                        [r[z], g[z], b[z]] = hsv_to_rgb4(h, s, v);
+                    }
+                }
+                simd::store_rgb16(bytes, i, &rgb, Rounding::Nearest);
+            }
+            &mut bytes[simd_end..]
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let bytes = &mut self.inner[..];
+        for p in bytes.chunks_exact_mut(C) {
+            let [h, s, v] = rgb_to_hsv([p[0], p[1], p[2]]);
+            let h = h + shift;
+            let h = if h >= 360. { h - 360. } else { h };
+            let s = (s * saturation).clamp(0., 1.);
+            p.copy_from_slice(&hsv_to_rgb([h, s, v]));
+        }
+    }
+}
+
+/// Converts `[r, g, b]` to `[hue, saturation, value]`, hue in degrees in `[0, 360)` (0 for
+/// grays) and the others in `[0, 1]`.
+pub fn rgb_to_hsv(rgb: [u8; C]) -> [f32; C] {
+    let [r, g, b] = rgb.map(|v| v as f32);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let h = if delta == 0. {
+        0.
+    } else if max == r {
+        (g - b) / delta
+    } else if max == g {
+        (b - r) / delta + 2.
+    } else {
+        (r - g) / delta + 4.
+    };
+    let h = h * 60.;
+    let h = if h < 0. { h + 360. } else { h };
+    let s = if max == 0. { 0. } else { delta / max };
+    [h, s, max / 255.]
+}
+
+/// Converts `[hue, saturation, value]` back to `[r, g, b]`, rounded to the nearest integer.
+/// Hues are in degrees in `[0, 360]`, and the others in `[0, 1]`.
+pub fn hsv_to_rgb(hsv: [f32; C]) -> [u8; C] {
+    let [h, s, v] = hsv;
+    let h = h / 60.;
+    let vs = v * s;
+    // `k` is the hue in sixths of the circle, from the position of the channel `n` on it
+    [5., 3., 1.].map(|n: f32| {
+        let k = n + h;
+        let k = if k >= 6. { k - 6. } else { k };
+        let t = k.min(4. - k).clamp(0., 1.);
+        Rounding::Nearest.to_u8((v - vs * t) * 255.)
+    })
+}
+
+// `rgb_to_hsv` of 4 pixels, with values in `[0, 255]`
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[inline]
+unsafe fn rgb_to_hsv4(r: float32x4_t, g: float32x4_t, b: float32x4_t) -> [float32x4_t; C] {
+    let zero = vdupq_n_f32(0.);
+    let max = vmaxq_f32(vmaxq_f32(r, g), b);
+    let min = vminq_f32(vminq_f32(r, g), b);
+    let delta = vsubq_f32(max, min);
+    // every case is computed, those dividing by a `delta` of 0 are replaced below
+    let hr = vdivq_f32(vsubq_f32(g, b), delta);
+    let hg = vaddq_f32(vdivq_f32(vsubq_f32(b, r), delta), vdupq_n_f32(2.));
+    let hb = vaddq_f32(vdivq_f32(vsubq_f32(r, g), delta), vdupq_n_f32(4.));
+    let h = vbslq_f32(vceqq_f32(max, g), hg, hb);
+    let h = vbslq_f32(vceqq_f32(max, r), hr, h);
+    let h = vbslq_f32(vceqq_f32(delta, zero), zero, h);
+    let h = vmulq_f32(h, vdupq_n_f32(60.));
+    let h = vbslq_f32(vcltq_f32(h, zero), vaddq_f32(h, vdupq_n_f32(360.)), h);
+    let s = vbslq_f32(vceqq_f32(max, zero), zero, vdivq_f32(delta, max));
+    [h, s, vdivq_f32(max, vdupq_n_f32(255.))]
+}
+
+// `hsv_to_rgb` of 4 pixels, before rounding
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[inline]
+unsafe fn hsv_to_rgb4(h: float32x4_t, s: float32x4_t, v: float32x4_t) -> [float32x4_t; C] {
+    let h = vdivq_f32(h, vdupq_n_f32(60.));
+    let vs = vmulq_f32(v, s);
+    let six = vdupq_n_f32(6.);
+    [5., 3., 1.].map(|n| {
+        let k = vaddq_f32(vdupq_n_f32(n), h);
+        let k = vbslq_f32(vcgeq_f32(k, six), vsubq_f32(k, six), k);
+        let t = vminq_f32(vminq_f32(k, vsubq_f32(vdupq_n_f32(4.), k)), vdupq_n_f32(1.));
+        let t = vmaxq_f32(t, vdupq_n_f32(0.));
+        vmulq_f32(vsubq_f32(v, vmulq_f32(vs, t)), vdupq_n_f32(255.))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::photo_image;
+
+    #[test]
+    fn convert() {
+        let cases = [
+            ([255, 0, 0], [0., 1., 1.]),
+            ([0, 255, 0], [120., 1., 1.]),
+            ([0, 0, 255], [240., 1., 1.]),
+            ([255, 0, 255], [300., 1., 1.]),
+            ([0, 0, 0], [0., 0., 0.]),
+            ([51, 51, 51], [0., 0., 0.2]),
+            ([102, 51, 51], [0., 0.5, 0.4]),
+        ];
+        for (rgb, hsv) in cases {
+            assert_eq!(rgb_to_hsv(rgb), hsv, "{:?}", rgb);
+            assert_eq!(hsv_to_rgb(hsv), rgb, "{:?}", hsv);
+        }
+        // the end of the circle is the same red as the start
+        assert_eq!(hsv_to_rgb([360., 1., 1.]), [255, 0, 0]);
+    }
+
+    #[test]
+    fn round_trip() {
+        // vectors of 16 pixels, and some left over
+        let img = photo_image(7, 19, 4);
+        let hsv = HsvImage::from_rgb(&img);
+        assert_eq!((hsv.height(), hsv.width()), (7, 19));
+        assert!(hsv.plane(0).iter().all(|h| (0. ..360.).contains(h)));
+        assert_eq!(hsv.to_rgb(), img);
+    }
+
+    #[test]
+    fn adjust_hsv() -> crate::Result<()> {
+        let original = photo_image(7, 19, 5);
+        let mut img = RgbImage::from_raw(original.content().to_vec(), 7, 19)?;
+        img.adjust_hsv(0., 1.);
+        assert_eq!(img, original);
+        img.adjust_hsv(-360., 1.);
+        assert_eq!(img, original);
+
+        img.adjust_hsv(0., 0.);
+        for (p, o) in img
+            .content()
+            .chunks_exact(C)
+            .zip(original.content().chunks_exact(C))
+        {
+            assert_eq!(p, [*o.iter().max().unwrap(); C]);
+        }
+
+        let mut red = RgbImage::from_raw([200, 40, 40].repeat(20), 4, 5)?;
+        red.adjust_hsv(120., 0.5);
+        assert!(red.content().chunks_exact(C).all(|p| p == [120, 200, 120]));
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    #[test]
+    fn simd() -> crate::Result<()> {
+        // vectors of 16 pixels, and some left over
+        let img = photo_image(33, 17, 6);
+        let hsv = HsvImage::from_rgb(&img);
+        for (i, p) in img.content().chunks_exact(C).enumerate() {
+            let expected = rgb_to_hsv([p[0], p[1], p[2]]);
+            assert_eq!([0, 1, 2].map(|c| hsv.plane(c)[i]), expected, "{}", i);
+        }
+        let expected: Vec<u8> = (0..img.height * img.width)
+            .flat_map(|i| hsv_to_rgb([0, 1, 2].map(|c| hsv.plane(c)[i])))
+            .collect();
+        assert_eq!(hsv.to_rgb().content(), &expected[..]);
+
+        for (hue, saturation) in [(37., 1.3), (-100., 0.4)] {
+            let mut adjusted = RgbImage::from_raw(img.content().to_vec(), 33, 17)?;
+            adjusted.adjust_hsv(hue, saturation);
+            let shift = if hue < 0. { hue + 360. } else { hue };
+            for (i, p) in img.content().chunks_exact(C).enumerate() {
+                let [h, s, v] = rgb_to_hsv([p[0], p[1], p[2]]);
+                let h = h + shift;
+                let h = if h >= 360. { h - 360. } else { h };
+                let s = (s * saturation).clamp(0., 1.);
+                assert_eq!(
+                    &adjusted.content()[i * C..(i + 1) * C],
+                    hsv_to_rgb([h, s, v]),
+                    "{}",
+                    i
+                );
+            }
+        }
+        Ok(())
+    }
+}
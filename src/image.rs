@@ -1,28 +1,128 @@
-use std::{
-    fs::OpenOptions,
-    io::{self, BufWriter},
-    path::Path,
-};
+//! RGB image type and PNG IO (with the `std` feature).
+
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::{fs::OpenOptions, io::BufWriter, path::Path};
 
+#[cfg(feature = "std")]
 use png::{BitDepth, ColorType, Decoder, Encoder};
 
+#[cfg(feature = "serde")]
+pub use crate::serialize::compressed;
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{
+    allocator::{self, ImageAllocator, ImageBuffer},
+    error::{Error, Result},
+    kernel::Rounding,
+    processor::{pad, BorderMode},
+    C,
+};
+
+/// Order of the channels in the bytes of a pixel.
+///
+/// [`RgbImage`] is always RGB, and filters with different weights per channel (e.g.
+/// [`DepthwiseSeparableProcessor`](crate::depthwise::DepthwiseSeparableProcessor)) take them in that order.
+/// Buffers from APIs delivering BGR, such as OpenCV and many capture APIs, are converted with
+/// [`RgbImage::from_raw_ordered`] and back with [`RgbImage::into_raw_ordered`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelOrder {
+    /// `[r, g, b]`
+    Rgb,
+    /// `[b, g, r]`
+    Bgr,
+}
+
+impl Default for PixelOrder {
+    fn default() -> Self {
+        PixelOrder::Rgb
+    }
+}
+
+/// 8-bit RGB image stored as interleaved `[r, g, b, r, g, b, ...]` rows.
+///
+/// Pixels are allocated by the global allocator unless built with
+/// [`zeroed_in`](Self::zeroed_in) or [`from_raw_in`](Self::from_raw_in), see
+/// [`allocator`](crate::allocator).
 #[derive(Debug)]
 pub struct RgbImage {
-    pub(crate) inner: Vec<u8>,
+    pub(crate) inner: ImageBuffer,
     pub(crate) height: usize,
     pub(crate) width: usize,
 }
 
 impl RgbImage {
+    /// Image with no pixels.
     pub const fn empty() -> Self {
         Self {
-            inner: vec![],
+            inner: Vec::new_in(ImageAllocator::global()),
             height: 0,
             width: 0,
         }
     }
 
-    pub const fn from_raw(content: Vec<u8>, height: usize, width: usize) -> Self {
+    /// Black image of `height` x `width` pixels allocated by `allocator`, e.g. for frames which
+    /// must stay in a pinned region.
+    pub fn zeroed_in(height: usize, width: usize, allocator: ImageAllocator) -> Self {
+        Self::from_buffer_unchecked(
+            allocator::zeroed_in(height * width * C, allocator),
+            height,
+            width,
+        )
+    }
+
+    /// Same as [`from_raw`](Self::from_raw) for `content` allocated by an [`ImageAllocator`].
+    pub fn from_raw_in(content: ImageBuffer, height: usize, width: usize) -> Result<Self> {
+        if content.len() != height * width * C {
+            return Err(Error::InvalidLength {
+                expected: height * width * C,
+                actual: content.len(),
+            });
+        }
+        Ok(Self::from_buffer_unchecked(content, height, width))
+    }
+
+    /// Allocator of the pixels.
+    pub fn allocator(&self) -> &ImageAllocator {
+        self.inner.allocator()
+    }
+
+    /// Wraps interleaved RGB `content` of `height * width * 3` bytes.
+    ///
+    /// Fails with [`Error::InvalidLength`] if the length of `content` is inconsistent.
+    pub fn from_raw(content: Vec<u8>, height: usize, width: usize) -> Result<Self> {
+        if content.len() != height * width * C {
+            return Err(Error::InvalidLength {
+                expected: height * width * C,
+                actual: content.len(),
+            });
+        }
+        Ok(Self::from_raw_unchecked(content, height, width))
+    }
+
+    /// Same as [`from_raw`](Self::from_raw) for `content` with pixels in `order`, which is
+    /// converted to RGB in place.
+    pub fn from_raw_ordered(
+        mut content: Vec<u8>,
+        height: usize,
+        width: usize,
+        order: PixelOrder,
+    ) -> Result<Self> {
+        if order == PixelOrder::Bgr {
+            swap_red_blue(&mut content);
+        }
+        Self::from_raw(content, height, width)
+    }
+
+    pub(crate) fn from_raw_unchecked(content: Vec<u8>, height: usize, width: usize) -> Self {
+        Self::from_buffer_unchecked(allocator::from_global(content), height, width)
+    }
+
+    pub(crate) const fn from_buffer_unchecked(
+        content: ImageBuffer,
+        height: usize,
+        width: usize,
+    ) -> Self {
         Self {
             inner: content,
             height,
@@ -30,7 +130,23 @@ impl RgbImage {
         }
     }
 
-    pub fn load<P>(path: P) -> io::Result<Self>
+    // fails if the image is smaller than min x min
+    pub(crate) fn check_min_size(&self, min: usize) -> Result<()> {
+        if self.height < min || self.width < min {
+            return Err(Error::ImageTooSmall {
+                height: self.height,
+                width: self.width,
+                min,
+            });
+        }
+        Ok(())
+    }
+
+    /// Loads a PNG file. Alpha channel of RGBA images is dropped.
+    ///
+    /// Fails with [`Error::UnsupportedFormat`] if the image is neither RGB nor RGBA.
+    #[cfg(feature = "std")]
+    pub fn load<P>(path: P) -> Result<Self>
     where
         P: AsRef<Path>,
     {
@@ -50,17 +166,19 @@ impl RgbImage {
                 }
                 buf.truncate(3 * len / 4);
             }
-            _ => panic!("unsupported format."),
+            ty => return Err(Error::UnsupportedFormat(format!("{:?}", ty))),
         }
 
-        Ok(Self {
-            inner: buf,
-            height: info.height as usize,
-            width: info.width as usize,
-        })
+        Ok(Self::from_raw_unchecked(
+            buf,
+            info.height as usize,
+            info.width as usize,
+        ))
     }
 
-    pub fn save<P>(&self, path: P) -> io::Result<()>
+    /// Saves the image as an 8-bit RGB PNG file.
+    #[cfg(feature = "std")]
+    pub fn save<P>(&self, path: P) -> Result<()>
     where
         P: AsRef<Path>,
     {
@@ -74,13 +192,311 @@ impl RgbImage {
         Ok(())
     }
 
+    /// Height in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Width in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Interleaved RGB bytes.
     pub fn content(&self) -> &[u8] {
         &self.inner
     }
 
+    /// Mutable interleaved RGB bytes.
     pub fn content_mut(&mut self) -> &mut [u8] {
         &mut self.inner
     }
+
+    /// Interleaved bytes with pixels in `order`, converted in place. They are copied to the
+    /// global allocator if another one allocated them.
+    pub fn into_raw_ordered(mut self, order: PixelOrder) -> Vec<u8> {
+        if order == PixelOrder::Bgr {
+            swap_red_blue(&mut self.inner);
+        }
+        allocator::into_global(self.inner)
+    }
+
+    /// Copies the pixels into `dst` in `order`, e.g. the buffer of the next frame to display.
+    ///
+    /// Fails with [`Error::InvalidLength`] if `dst` is not `height * width * 3` bytes.
+    pub fn write_ordered(&self, dst: &mut [u8], order: PixelOrder) -> Result<()> {
+        if dst.len() != self.inner.len() {
+            return Err(Error::InvalidLength {
+                expected: self.inner.len(),
+                actual: dst.len(),
+            });
+        }
+        dst.copy_from_slice(&self.inner);
+        if order == PixelOrder::Bgr {
+            swap_red_blue(dst);
+        }
+        Ok(())
+    }
+
+    /// Sets every pixel to `color`, `[r, g, b]`, 16 pixels per store with NEON.
+    pub fn fill(&mut self, color: [u8; C]) {
+        fill_pixels(&mut self.inner, color);
+    }
+
+    /// Sets every pixel to black.
+    pub fn clear(&mut self) {
+        self.fill([0; C]);
+    }
+
+    /// Adjusts brightness and contrast in place: every value `v` becomes
+    /// `(v - 128) * contrast + 128 + brightness`, rounded to the nearest integer and saturated
+    /// to `[0, 255]`, 16 values at a time with NEON.
+    ///
+    /// A `contrast` of 1 and a `brightness` of 0 leave the image as it is, and a `contrast`
+    /// below 1 flattens it towards mid-gray.
+    pub fn adjust(&mut self, brightness: f32, contrast: f32) {
+        let (scale, offset) = adjust_coeffs(brightness, contrast);
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let bytes = {
+            let bytes = &mut self.inner[..];
+            let (scale, offset) = (simd::splat(scale), simd::splat(offset));
+            let simd_end = bytes.len() - bytes.len() % 16;
+            for i in (0..simd_end).step_by(16) {
+                // separate multiplication and addition to round like the serial loop
+                let v = simd::load_u8x16(bytes, i).map(|v| simd::add(simd::mul(v, scale), offset));
+                simd::store_u8x16(bytes, i, &v, Rounding::Nearest);
+            }
+            &mut bytes[simd_end..]
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let bytes = &mut self.inner[..];
+        for v in bytes {
+            *v = adjust_value(*v, scale, offset);
+        }
+    }
+
+    /// Region of `height` x `width` pixels whose top-left pixel is `(y, x)`, e.g. a tile.
+    ///
+    /// Fails with [`Error::SizeMismatch`] if the region does not fit in the image, `expected`
+    /// being the size the image would need.
+    pub fn view(&self, y: usize, x: usize, height: usize, width: usize) -> Result<ImageView<'_>> {
+        if y + height > self.height || x + width > self.width {
+            return Err(Error::SizeMismatch {
+                expected: (y + height, x + width),
+                actual: (self.height, self.width),
+            });
+        }
+        Ok(ImageView {
+            img: self,
+            y,
+            x,
+            height,
+            width,
+        })
+    }
+
+    /// Copies the pixels of `src`, row by row with 16 bytes per store with NEON.
+    ///
+    /// Fails with [`Error::SizeMismatch`] if `src` is not the size of the image.
+    pub fn copy_from(&mut self, src: &ImageView<'_>) -> Result<()> {
+        if (src.height, src.width) != (self.height, self.width) {
+            return Err(Error::SizeMismatch {
+                expected: (self.height, self.width),
+                actual: (src.height, src.width),
+            });
+        }
+        let row = self.width * C;
+        if row == 0 {
+            return Ok(());
+        }
+        for (y, dst) in self.inner.chunks_exact_mut(row).enumerate() {
+            copy_bytes(dst, src.row(y));
+        }
+        Ok(())
+    }
+
+    /// Copy with `half` pixels of padding on each side computed according to `border`, e.g. to
+    /// convolve the border with kernels which only handle the inside of images.
+    pub fn padded(&self, half: usize, border: BorderMode) -> RgbImage {
+        let mut dst = RgbImage::empty();
+        pad(self, half, border, &mut dst);
+        dst
+    }
+}
+
+/// Rectangular region of an [`RgbImage`], borrowing its pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageView<'a> {
+    img: &'a RgbImage,
+    y: usize,
+    x: usize,
+    height: usize,
+    width: usize,
+}
+
+impl<'a> ImageView<'a> {
+    /// Height in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Width in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Interleaved RGB bytes of row `y` of the region.
+    ///
+    /// # Panics
+    ///
+    /// If `y` is not less than the height.
+    pub fn row(&self, y: usize) -> &'a [u8] {
+        assert!(y < self.height, "row {} of {}", y, self.height);
+        let start = ((self.y + y) * self.img.width + self.x) * C;
+        &self.img.inner[start..start + self.width * C]
+    }
+}
+
+impl<'a> From<&'a RgbImage> for ImageView<'a> {
+    fn from(img: &'a RgbImage) -> Self {
+        Self {
+            img,
+            y: 0,
+            x: 0,
+            height: img.height,
+            width: img.width,
+        }
+    }
+}
+
+/// Read-only image over borrowed interleaved RGB bytes, e.g. a memory-mapped file (see
+/// [`mmap`](crate::mmap) with the `mmap` feature).
+#[derive(Debug, Clone, Copy)]
+pub struct ImageRef<'a> {
+    content: &'a [u8],
+    height: usize,
+    width: usize,
+}
+
+impl<'a> ImageRef<'a> {
+    /// Wraps interleaved RGB `content` of `height * width * 3` bytes.
+    ///
+    /// Fails with [`Error::InvalidLength`] if the length of `content` is inconsistent.
+    pub fn from_raw(content: &'a [u8], height: usize, width: usize) -> Result<Self> {
+        if content.len() != height * width * C {
+            return Err(Error::InvalidLength {
+                expected: height * width * C,
+                actual: content.len(),
+            });
+        }
+        Ok(Self {
+            content,
+            height,
+            width,
+        })
+    }
+
+    /// Height in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Width in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Interleaved RGB bytes.
+    pub fn content(&self) -> &'a [u8] {
+        self.content
+    }
+
+    /// Copies the rows from `y` into `dst`, a whole number of rows, e.g. as the `read` callback
+    /// of [`ConvProcessor::convolve_bands`](crate::ConvProcessor::convolve_bands).
+    ///
+    /// Fails with [`Error::InvalidLength`] if `dst` is not a whole number of rows or goes past
+    /// the last row.
+    pub fn read_rows(&self, y: usize, dst: &mut [u8]) -> Result<()> {
+        let (row, start) = (self.width * C, y * self.width * C);
+        if dst.len() % row.max(1) != 0 || start + dst.len() > self.content.len() {
+            return Err(Error::InvalidLength {
+                expected: self.content.len().saturating_sub(start) / row.max(1) * row,
+                actual: dst.len(),
+            });
+        }
+        copy_bytes(dst, &self.content[start..start + dst.len()]);
+        Ok(())
+    }
+
+    /// Copies the pixels into an [`RgbImage`].
+    pub fn to_image(&self) -> RgbImage {
+        RgbImage::from_raw_unchecked(self.content.to_vec(), self.height, self.width)
+    }
+}
+
+impl<'a> From<&'a RgbImage> for ImageRef<'a> {
+    fn from(img: &'a RgbImage) -> Self {
+        Self {
+            content: &img.inner,
+            height: img.height,
+            width: img.width,
+        }
+    }
+}
+
+// `scale` and `offset` of `RgbImage::adjust`, whose values become `v * scale + offset`
+pub(crate) fn adjust_coeffs(brightness: f32, contrast: f32) -> (f32, f32) {
+    (contrast, 128. * (1. - contrast) + brightness)
+}
+
+// a value adjusted with the coefficients of `adjust_coeffs`
+#[inline]
+pub(crate) fn adjust_value(v: u8, scale: f32, offset: f32) -> u8 {
+    Rounding::Nearest.to_u8(v as f32 * scale + offset)
+}
+
+// sets every pixel of `bytes` to `color`
+pub(crate) fn fill_pixels(bytes: &mut [u8], color: [u8; C]) {
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let bytes = {
+        let v = color.map(simd::splat_u8);
+        let simd_end = bytes.len() - bytes.len() % (16 * C);
+        for i in (0..simd_end).step_by(16 * C) {
+            simd::interleave16(bytes, i, v);
+        }
+        &mut bytes[simd_end..]
+    };
+    for pixel in bytes.chunks_exact_mut(C) {
+        pixel.copy_from_slice(&color);
+    }
+}
+
+// copies `src` into `dst` of the same length
+pub(crate) fn copy_bytes(dst: &mut [u8], src: &[u8]) {
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let (dst, src) = {
+        let simd_end = src.len() - src.len() % 16;
+        for i in (0..simd_end).step_by(16) {
+            simd::store_bytes16(dst, i, simd::load_bytes16(src, i));
+        }
+        (&mut dst[simd_end..], &src[simd_end..])
+    };
+    dst.copy_from_slice(src);
+}
+
+// swaps the first and last channels of every pixel, i.e. converts between RGB and BGR
+fn swap_red_blue(bytes: &mut [u8]) {
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let bytes = {
+        let simd_end = bytes.len() - bytes.len() % (16 * C);
+        for i in (0..simd_end).step_by(16 * C) {
+            simd::swap_red_blue16(bytes, i);
+        }
+        &mut bytes[simd_end..]
+    };
+    for pixel in bytes.chunks_exact_mut(C) {
+        pixel.swap(0, 2);
+    }
 }
 
 impl PartialEq for RgbImage {
@@ -96,17 +512,181 @@ impl PartialEq for RgbImage {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::consts::*;
+    use crate::{consts::*, processor::border_index};
 
     #[test]
-    fn eq() -> io::Result<()> {
+    fn eq() -> Result<()> {
         let img = RgbImage::load(ORIGINAL)?;
-        let dummy = RgbImage {
-            inner: vec![0u8; img.height * img.width],
-            height: img.height,
-            width: img.width,
-        };
+        let dummy =
+            RgbImage::from_raw_unchecked(vec![0u8; img.height * img.width], img.height, img.width);
         assert_ne!(img, dummy);
         Ok(())
     }
+
+    #[test]
+    fn image_ref() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let src = ImageRef::from(&img);
+        let row = img.width * C;
+        let mut rows = vec![0; 3 * row];
+        src.read_rows(5, &mut rows)?;
+        assert_eq!(rows, img.content()[5 * row..8 * row]);
+        src.read_rows(img.height - 3, &mut rows)?;
+        assert!(matches!(
+            src.read_rows(img.height - 2, &mut rows),
+            Err(Error::InvalidLength { .. })
+        ));
+        assert!(matches!(
+            src.read_rows(0, &mut rows[1..]),
+            Err(Error::InvalidLength { .. })
+        ));
+        assert!(matches!(
+            ImageRef::from_raw(&rows, 2, img.width),
+            Err(Error::InvalidLength { .. })
+        ));
+        assert_eq!(src.to_image(), img);
+        Ok(())
+    }
+
+    #[test]
+    fn from_raw() {
+        assert!(RgbImage::from_raw(vec![0u8; 2 * 3 * C], 2, 3).is_ok());
+        assert!(matches!(
+            RgbImage::from_raw(vec![0u8; 2 * 3], 2, 3),
+            Err(Error::InvalidLength {
+                expected: 18,
+                actual: 6
+            })
+        ));
+    }
+
+    #[test]
+    fn pixel_order() -> Result<()> {
+        // longer than a vector of 16 pixels, with some left over
+        let rgb: Vec<u8> = (0..21 * C).map(|i| i as u8).collect();
+        let bgr: Vec<u8> = rgb
+            .chunks_exact(C)
+            .flat_map(|p| [p[2], p[1], p[0]])
+            .collect();
+        let img = RgbImage::from_raw_ordered(bgr.clone(), 3, 7, PixelOrder::Bgr)?;
+        assert_eq!(img.content(), &rgb[..]);
+        let mut dst = vec![0; rgb.len()];
+        img.write_ordered(&mut dst, PixelOrder::Bgr)?;
+        assert_eq!(dst, bgr);
+        img.write_ordered(&mut dst, PixelOrder::Rgb)?;
+        assert_eq!(dst, rgb);
+        assert!(matches!(
+            img.write_ordered(&mut dst[1..], PixelOrder::Rgb),
+            Err(Error::InvalidLength { .. })
+        ));
+        assert_eq!(img.into_raw_ordered(PixelOrder::Bgr), bgr);
+        assert_eq!(
+            RgbImage::from_raw_ordered(rgb.clone(), 3, 7, PixelOrder::Rgb)?
+                .into_raw_ordered(PixelOrder::Rgb),
+            rgb
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn fill_copy() -> Result<()> {
+        // longer than a vector of 16 pixels, with some left over
+        let mut img = RgbImage::from_raw(vec![0; 3 * 19 * C], 3, 19)?;
+        img.fill([1, 2, 3]);
+        assert!(img.content().chunks_exact(C).all(|p| p == [1, 2, 3]));
+        img.clear();
+        assert!(img.content().iter().all(|&v| v == 0));
+
+        let src = RgbImage::from_raw((0..5 * 23 * C).map(|i| i as u8).collect(), 5, 23)?;
+        let view = src.view(1, 2, 3, 19)?;
+        img.copy_from(&view)?;
+        for y in 0..3 {
+            let start = ((y + 1) * 23 + 2) * C;
+            assert_eq!(
+                &img.content()[y * 19 * C..(y + 1) * 19 * C],
+                &src.content()[start..start + 19 * C]
+            );
+        }
+        let mut copy = RgbImage::from_raw(vec![0; src.content().len()], 5, 23)?;
+        copy.copy_from(&ImageView::from(&src))?;
+        assert_eq!(copy, src);
+
+        assert!(matches!(
+            src.view(3, 2, 3, 19),
+            Err(Error::SizeMismatch {
+                expected: (6, 21),
+                actual: (5, 23)
+            })
+        ));
+        assert!(matches!(
+            img.copy_from(&src.view(0, 0, 3, 18)?),
+            Err(Error::SizeMismatch {
+                expected: (3, 19),
+                actual: (3, 18)
+            })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn adjust() -> Result<()> {
+        // longer than a vector of 16 values, with some left over
+        let content: Vec<u8> = (0..=255).collect();
+        let mut img = RgbImage::from_raw(content[..5 * 17 * C].to_vec(), 5, 17)?;
+        let original = RgbImage::from_raw(img.content().to_vec(), 5, 17)?;
+        img.adjust(0., 1.);
+        assert_eq!(img, original);
+
+        img.adjust(10., 1.5);
+        for (&v, &o) in img.content().iter().zip(original.content()) {
+            let expected = ((o as f32 - 128.) * 1.5 + 138.).round().clamp(0., 255.);
+            assert_eq!(v, expected as u8, "{}", o);
+        }
+        // saturated both ways
+        assert_eq!(img.content()[0], 0);
+        assert_eq!(img.content()[254], 255);
+
+        img.adjust(-20., 0.);
+        assert!(img.content().iter().all(|&v| v == 108));
+        Ok(())
+    }
+
+    #[test]
+    fn padded() -> Result<()> {
+        let (h, w, half) = (4, 18, 3);
+        let img = RgbImage::from_raw((0..h * w * C).map(|i| i as u8).collect(), h, w)?;
+        let pixel = |y: usize, x: usize| &img.content()[(y * w + x) * C..][..C];
+        for border in [BorderMode::Zero, BorderMode::Replicate, BorderMode::Reflect] {
+            let padded = img.padded(half, border);
+            assert_eq!((padded.height, padded.width), (h + 2 * half, w + 2 * half));
+            for (i, p) in padded.content().chunks_exact(C).enumerate() {
+                let (py, px) = (i / padded.width, i % padded.width);
+                let (y, x) = (py as isize - half as isize, px as isize - half as isize);
+                let expected = match border {
+                    BorderMode::Zero
+                        if !(0..h as isize).contains(&y) || !(0..w as isize).contains(&x) =>
+                    {
+                        &[0; C]
+                    }
+                    _ => pixel(border_index(y, h, border), border_index(x, w, border)),
+                };
+                assert_eq!(p, expected, "{:?} {:?}", border, (py, px));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn check_min_size() {
+        let img = RgbImage::from_raw_unchecked(vec![0u8; 2 * 5 * C], 2, 5);
+        assert!(img.check_min_size(2).is_ok());
+        assert!(matches!(
+            img.check_min_size(3),
+            Err(Error::ImageTooSmall {
+                height: 2,
+                width: 5,
+                min: 3
+            })
+        ));
+    }
 }
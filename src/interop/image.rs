@@ -0,0 +1,112 @@
+//! Conversions from and to the image types of the `image` crate, with the `image-interop`
+//! feature.
+//!
+//! Both crates store 8-bit RGB pixels as interleaved rows without padding, so converting an
+//! `image::RgbImage` either way moves its buffer without copying the pixels, unless a custom
+//! [`ImageAllocator`](crate::allocator::ImageAllocator) allocated them. Other kinds of
+//! `image::DynamicImage` are converted to 8-bit RGB first. Sizes of the `image` crate are `u32`,
+//! so the conversions to it fail for (empty) images wider or taller than that.
+
+use core::convert::TryFrom;
+
+use ::image::{DynamicImage, ImageBuffer};
+
+use crate::{
+    allocator,
+    error::{Error, Result},
+    image::RgbImage,
+    C,
+};
+
+impl From<::image::RgbImage> for RgbImage {
+    fn from(img: ::image::RgbImage) -> Self {
+        let (height, width) = (img.height() as usize, img.width() as usize);
+        let mut content = img.into_raw();
+        // the buffer of an `ImageBuffer` may be longer than its pixels
+        content.truncate(height * width * C);
+        RgbImage::from_raw_unchecked(content, height, width)
+    }
+}
+
+impl From<DynamicImage> for RgbImage {
+    fn from(img: DynamicImage) -> Self {
+        match img {
+            DynamicImage::ImageRgb8(img) => img.into(),
+            img => img.to_rgb8().into(),
+        }
+    }
+}
+
+/// Fails with [`Error::UnsupportedFormat`] if the width or height does not fit in `u32`, which
+/// is only possible for empty images.
+impl TryFrom<RgbImage> for ::image::RgbImage {
+    type Error = Error;
+
+    fn try_from(img: RgbImage) -> Result<Self> {
+        let (height, width) = match (u32::try_from(img.height), u32::try_from(img.width)) {
+            (Ok(height), Ok(width)) => (height, width),
+            _ => {
+                return Err(Error::UnsupportedFormat(format!(
+                    "{}x{} image exceeds the u32 sizes of the image crate",
+                    img.height, img.width
+                )))
+            }
+        };
+        let content = allocator::into_global(img.inner);
+        Ok(ImageBuffer::from_raw(width, height, content).expect("buffer holds every pixel"))
+    }
+}
+
+/// Fails like the conversion to `image::RgbImage`.
+impl TryFrom<RgbImage> for DynamicImage {
+    type Error = Error;
+
+    fn try_from(img: RgbImage) -> Result<Self> {
+        ::image::RgbImage::try_from(img).map(DynamicImage::ImageRgb8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::image::Rgb;
+
+    use super::*;
+    use crate::{consts::ORIGINAL, error::Result, ConvProcessor};
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let ptr = img.content().as_ptr();
+        let theirs = ::image::RgbImage::try_from(img)?;
+        assert_eq!(theirs.as_raw().as_ptr(), ptr);
+        let ours = RgbImage::from(theirs);
+        assert_eq!(ours.content().as_ptr(), ptr);
+
+        let blur = ConvProcessor::<3>::new(&[1.; 9], true)?;
+        let expected = blur.naive2(&ours)?;
+        let dynamic = DynamicImage::try_from(ours)?;
+        assert_eq!(blur.convolve(&dynamic.into())?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn layout() -> Result<()> {
+        let mut theirs = ::image::RgbImage::new(3, 2);
+        theirs.put_pixel(2, 1, Rgb([1, 2, 3]));
+        let ours = RgbImage::from(theirs);
+        assert_eq!((ours.height(), ours.width()), (2, 3));
+        assert_eq!(&ours.content()[(3 + 2) * C..], &[1, 2, 3]);
+
+        // converted from other pixel types
+        let gray = DynamicImage::ImageLuma8(::image::GrayImage::from_pixel(2, 2, [7].into()));
+        assert_eq!(RgbImage::from(gray).content(), &[7; 2 * 2 * C]);
+        let empty = ::image::RgbImage::try_from(RgbImage::from(::image::RgbImage::new(0, 0)))?;
+        assert_eq!(empty.dimensions(), (0, 0));
+        let wide = RgbImage::from_raw(vec![], 0, u32::MAX as usize + 1)?;
+        assert!(matches!(
+            DynamicImage::try_from(wide),
+            Err(Error::UnsupportedFormat(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,8 @@
+//! Conversions from and to the types of other crates, each behind a feature of its own.
+
+#[cfg(feature = "image-interop")]
+mod image;
+#[cfg(feature = "nalgebra")]
+mod nalgebra;
+#[cfg(feature = "ndarray")]
+mod ndarray;
@@ -0,0 +1,50 @@
+//! Kernels from `nalgebra` matrices, with the `nalgebra` feature.
+
+use alloc::vec::Vec;
+
+use ::nalgebra::SMatrix;
+
+use crate::{error::Result, kernel::ConvKernel};
+
+impl<const K: usize> ConvKernel<K> {
+    /// Creates a kernel from a `K` x `K` matrix, where `matrix[(i, j)]` is the weight at the
+    /// `i`-th row and `j`-th column (although `nalgebra` stores matrices in column-major order),
+    /// like [`new`](Self::new) otherwise.
+    ///
+    /// Fails like [`new`](Self::new), i.e. if `K` is even or less than 3, or `avg` is set for
+    /// weights summing up to 0.
+    pub fn from_matrix(matrix: &SMatrix<f32, K, K>, avg: bool) -> Result<Self> {
+        let weights: Vec<f32> = matrix.transpose().iter().copied().collect();
+        Self::new(&weights, avg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::nalgebra::{Matrix3, SMatrix, Vector5};
+
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn from_matrix() -> Result<()> {
+        let matrix = Matrix3::new(1., 2., 3., 4., 5., 6., 7., 8., 9.);
+        let kernel = ConvKernel::<3>::from_matrix(&matrix, true)?;
+        assert_eq!(kernel.weights(), &[1., 2., 3., 4., 5., 6., 7., 8., 9.]);
+        assert_eq!(kernel.at(0, 2), matrix[(0, 2)]);
+        assert_eq!(kernel.div(), Some(45.));
+
+        // outer product of a derivative and a smoothing vector, i.e. a 5x5 Sobel-like filter
+        let derivative = Vector5::new(-1., -2., 0., 2., 1.);
+        let smoothing = Vector5::new(1., 4., 6., 4., 1.);
+        let kernel = ConvKernel::<5>::from_matrix(&(derivative * smoothing.transpose()), false)?;
+        assert_eq!(kernel.at(0, 2), -6.);
+        assert_eq!(kernel.at(4, 1), 4.);
+
+        assert!(matches!(
+            ConvKernel::<2>::from_matrix(&SMatrix::<f32, 2, 2>::identity(), false),
+            Err(Error::BadKernel(_))
+        ));
+        Ok(())
+    }
+}
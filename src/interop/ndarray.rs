@@ -0,0 +1,187 @@
+//! Conversions of images from and to `ndarray` arrays of shape `(height, width, 3)`, and kernels
+//! from 2D arrays, with the `ndarray` feature.
+//!
+//! Owned arrays in standard (row-major) layout share their buffer with [`RgbImage`], and
+//! [`ArrayView3`]s of an image borrow its pixels, so neither copies them (except images of a
+//! custom [`ImageAllocator`](crate::allocator::ImageAllocator), copied into owned arrays).
+
+use alloc::{format, vec::Vec};
+use core::convert::TryFrom;
+
+use ::ndarray::{Array3, ArrayView2, ArrayView3, ArrayViewMut3};
+
+use crate::{
+    allocator,
+    error::{Error, Result},
+    image::RgbImage,
+    kernel::ConvKernel,
+    C,
+};
+
+// `(height, width)` of an array of shape `(height, width, 3)`
+fn check_dim((height, width, channels): (usize, usize, usize)) -> Result<(usize, usize)> {
+    if channels != C {
+        return Err(Error::UnsupportedFormat(format!(
+            "{} channels instead of {}",
+            channels, C
+        )));
+    }
+    Ok((height, width))
+}
+
+/// Fails with [`Error::UnsupportedFormat`] if the last axis does not have 3 elements.
+impl TryFrom<Array3<u8>> for RgbImage {
+    type Error = Error;
+
+    fn try_from(array: Array3<u8>) -> Result<Self> {
+        let (height, width) = check_dim(array.dim())?;
+        let len = height * width * C;
+        if !array.is_standard_layout() {
+            let content = array.iter().copied().collect();
+            return Ok(RgbImage::from_raw_unchecked(content, height, width));
+        }
+        let start = array.as_ptr();
+        let raw = array.into_raw_vec();
+        if raw.as_ptr() == start && raw.len() == len {
+            return Ok(RgbImage::from_raw_unchecked(raw, height, width));
+        }
+        // sliced out of a larger array, whose whole buffer is returned
+        let offset = start as usize - raw.as_ptr() as usize;
+        let content = raw[offset..offset + len].to_vec();
+        Ok(RgbImage::from_raw_unchecked(content, height, width))
+    }
+}
+
+/// Copies the pixels. Fails with [`Error::UnsupportedFormat`] if the last axis does not have
+/// 3 elements.
+impl TryFrom<ArrayView3<'_, u8>> for RgbImage {
+    type Error = Error;
+
+    fn try_from(view: ArrayView3<'_, u8>) -> Result<Self> {
+        let (height, width) = check_dim(view.dim())?;
+        let content = match view.as_slice() {
+            Some(slice) => slice.to_vec(),
+            None => view.iter().copied().collect::<Vec<_>>(),
+        };
+        Ok(RgbImage::from_raw_unchecked(content, height, width))
+    }
+}
+
+impl From<RgbImage> for Array3<u8> {
+    fn from(img: RgbImage) -> Self {
+        Array3::from_shape_vec(
+            (img.height, img.width, C),
+            allocator::into_global(img.inner),
+        )
+        .expect("buffer holds every pixel")
+    }
+}
+
+impl<'a> From<&'a RgbImage> for ArrayView3<'a, u8> {
+    fn from(img: &'a RgbImage) -> Self {
+        ArrayView3::from_shape((img.height, img.width, C), &img.inner)
+            .expect("buffer holds every pixel")
+    }
+}
+
+impl<'a> From<&'a mut RgbImage> for ArrayViewMut3<'a, u8> {
+    fn from(img: &'a mut RgbImage) -> Self {
+        ArrayViewMut3::from_shape((img.height, img.width, C), &mut img.inner)
+            .expect("buffer holds every pixel")
+    }
+}
+
+impl<const K: usize> ConvKernel<K> {
+    /// Creates a kernel from a `K` x `K` array, where `weights[[i, j]]` is the weight at the
+    /// `i`-th row and `j`-th column whatever the memory layout, like [`new`](Self::new) otherwise.
+    ///
+    /// Fails with [`Error::BadKernel`] if `weights` is not `K` x `K`, or like [`new`](Self::new).
+    pub fn from_ndarray(weights: ArrayView2<'_, f32>, avg: bool) -> Result<Self> {
+        if weights.dim() != (K, K) {
+            return Err(Error::BadKernel(format!(
+                "inconsistent filter shape {:?} for K={}",
+                weights.dim(),
+                K
+            )));
+        }
+        Self::new(&weights.iter().copied().collect::<Vec<_>>(), avg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::ndarray::{s, Array2};
+
+    use super::*;
+    use crate::ConvProcessor;
+
+    fn image(height: usize, width: usize) -> RgbImage {
+        let content = (0..height * width * C).map(|i| (i % 251) as u8).collect();
+        RgbImage::from_raw_unchecked(content, height, width)
+    }
+
+    #[test]
+    fn images() -> Result<()> {
+        let img = image(4, 5);
+        let ptr = img.content().as_ptr();
+        let view = ArrayView3::from(&img);
+        assert_eq!(view[[2, 3, 1]], img.content()[(2 * 5 + 3) * C + 1]);
+        assert_eq!(RgbImage::try_from(view)?, img);
+
+        // the buffer moves both ways
+        let array = Array3::from(img);
+        assert_eq!(array.as_ptr(), ptr);
+        let img = RgbImage::try_from(array)?;
+        assert_eq!(img.content().as_ptr(), ptr);
+
+        // other layouts are copied in logical order
+        let array = Array3::from(image(4, 5));
+        let expected: Vec<u8> = array.slice(s![1..3, .., ..]).iter().copied().collect();
+        let rows = RgbImage::try_from(array.clone().slice_move(s![1..3, .., ..]))?;
+        assert_eq!(
+            (rows.height(), rows.width(), rows.content()),
+            (2, 5, &expected[..])
+        );
+        let expected: Vec<u8> = array.slice(s![.., 1..4, ..]).iter().copied().collect();
+        let cols = RgbImage::try_from(array.slice(s![.., 1..4, ..]))?;
+        assert_eq!(cols.content(), &expected[..]);
+        let transposed = array.permuted_axes([1, 0, 2]);
+        let expected: Vec<u8> = transposed.iter().copied().collect();
+        let transposed = RgbImage::try_from(transposed)?;
+        assert_eq!((transposed.height(), transposed.width()), (5, 4));
+        assert_eq!(transposed.content(), &expected[..]);
+
+        let mut img = image(2, 2);
+        ArrayViewMut3::from(&mut img)[[1, 0, 2]] = 7;
+        assert_eq!(img.content()[2 * C + 2], 7);
+
+        assert!(matches!(
+            RgbImage::try_from(Array3::<u8>::zeros((2, 2, 4))),
+            Err(Error::UnsupportedFormat(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn kernels() -> Result<()> {
+        let weights = Array2::from_shape_fn((3, 3), |(i, j)| (i * 3 + j) as f32);
+        let kernel = ConvKernel::<3>::from_ndarray(weights.view(), false)?;
+        assert_eq!(kernel.at(1, 2), 5.);
+        // transposed views are read by index, not by memory order
+        let kernel = ConvKernel::<3>::from_ndarray(weights.t(), false)?;
+        assert_eq!(kernel.at(1, 2), 7.);
+        assert!(matches!(
+            ConvKernel::<5>::from_ndarray(weights.view(), false),
+            Err(Error::BadKernel(_))
+        ));
+
+        let blur = ConvProcessor::from_kernel(ConvKernel::<3>::from_ndarray(
+            Array2::ones((3, 3)).view(),
+            true,
+        )?);
+        let img = image(8, 9);
+        let blurred = Array3::from(blur.convolve(&img)?);
+        assert_eq!(blurred, Array3::from(blur.naive2(&img)?));
+        Ok(())
+    }
+}
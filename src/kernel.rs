@@ -0,0 +1,380 @@
+//! Convolution kernels.
+
+use alloc::{format, string::ToString, vec::Vec};
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    any(
+        feature = "impl-simd1",
+        feature = "impl-simd2",
+        feature = "impl-simd3",
+        feature = "impl-simd4",
+        feature = "impl-wide",
+        feature = "impl-ring"
+    )
+))]
+use core::arch::aarch64::float32x4_t;
+
+use crate::error::{Error, Result};
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    any(
+        feature = "impl-simd1",
+        feature = "impl-simd2",
+        feature = "impl-simd3",
+        feature = "impl-simd4",
+        feature = "impl-wide",
+        feature = "impl-ring"
+    )
+))]
+use crate::simd;
+
+/// Square KxK convolution kernel stored in row-major order.
+///
+/// `K` must be an odd number >= 3 so that the kernel has a center pixel.
+#[derive(Debug)]
+pub struct ConvKernel<const K: usize> {
+    inner: Vec<f32>,
+    pub(crate) div: Option<f32>,
+    pub(crate) rounding: Rounding,
+    // rows of the weights broadcast to every lane, so that NEON implementations broadcast them
+    // once rather than for every vector of pixels (not divided by `div`, which would round
+    // the products differently)
+    #[cfg(all(
+        any(target_arch = "aarch64"),
+        target_feature = "neon",
+        any(
+            feature = "impl-simd1",
+            feature = "impl-simd2",
+            feature = "impl-simd3",
+            feature = "impl-simd4",
+            feature = "impl-wide",
+            feature = "impl-ring"
+        )
+    ))]
+    splats: Vec<[float32x4_t; K]>,
+}
+
+/// How weighted sums are converted to `u8`, after dividing by [`ConvKernel::div`] if any.
+/// Either way, results are saturated to `[0, 255]`, to the range of `i16` by
+/// [`ConvProcessor::convolve_i16`](crate::ConvProcessor::convolve_i16), or to that of the samples
+/// by [`ConvProcessor::convolve_u16`](crate::ConvProcessor::convolve_u16).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// Towards 0, like `as u8`.
+    Truncate,
+    /// To the nearest integer, ties away from 0 (i.e. up, for sums in the range of `u8`).
+    Nearest,
+}
+
+impl Default for Rounding {
+    fn default() -> Self {
+        Rounding::Truncate
+    }
+}
+
+impl Rounding {
+    // scalar conversion shared by every implementation
+    #[inline]
+    pub(crate) fn to_u8(self, t: f32) -> u8 {
+        let t = match self {
+            Rounding::Truncate => t,
+            Rounding::Nearest => round(t),
+        };
+        t.clamp(u8::MIN as f32, u8::MAX as f32) as u8
+    }
+
+    // same as `to_u8`, for signed results
+    #[inline]
+    pub(crate) fn to_i16(self, t: f32) -> i16 {
+        let t = match self {
+            Rounding::Truncate => t,
+            Rounding::Nearest => round(t),
+        };
+        t.clamp(i16::MIN as f32, i16::MAX as f32) as i16
+    }
+
+    // same as `to_u8`, for samples of up to 16 bits saturated to `[0, max]`
+    #[inline]
+    pub(crate) fn to_u16(self, t: f32, max: u16) -> u16 {
+        let t = match self {
+            Rounding::Truncate => t,
+            Rounding::Nearest => round(t),
+        };
+        t.clamp(0., max as f32) as u16
+    }
+}
+
+impl<const K: usize> ConvKernel<K> {
+    /// Creates a kernel from `K * K` weights in row-major order.
+    /// If `avg` is set, results are divided by the sum of the weights.
+    ///
+    /// Fails with [`Error::BadKernel`] if `filter.len() != K * K`, `K` is even or less than 3,
+    /// or `avg` is set for weights summing up to 0.
+    pub fn new(filter: &[f32], avg: bool) -> Result<Self> {
+        if filter.len() != K * K {
+            return Err(Error::BadKernel(format!(
+                "inconsistent filter size {} for K={}",
+                filter.len(),
+                K
+            )));
+        }
+        if K % 2 == 0 || K < 3 {
+            return Err(Error::BadKernel(
+                "only odd number >= 3 is available for kernel size".to_string(),
+            ));
+        }
+        let div = if avg {
+            let sum = filter.iter().sum();
+            if sum == 0. {
+                return Err(Error::BadKernel(
+                    "cannot calculate average on filter with weights of total 0.".to_string(),
+                ));
+            }
+            Some(sum)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            inner: filter.to_vec(),
+            div,
+            rounding: Rounding::default(),
+            #[cfg(all(
+                any(target_arch = "aarch64"),
+                target_feature = "neon",
+                any(
+                    feature = "impl-simd1",
+                    feature = "impl-simd2",
+                    feature = "impl-simd3",
+                    feature = "impl-simd4",
+                    feature = "impl-wide",
+                    feature = "impl-ring"
+                )
+            ))]
+            splats: filter
+                .chunks_exact(K)
+                .map(|row| {
+                    let mut splats = [simd::splat(0.); K];
+                    for (v, &weight) in splats.iter_mut().zip(row) {
+                        *v = simd::splat(weight);
+                    }
+                    splats
+                })
+                .collect(),
+        })
+    }
+
+    /// Gaussian kernel with standard deviation `sigma`, averaging like `new(filter, true)`.
+    ///
+    /// Fails with [`Error::BadKernel`] if `sigma` is not positive, or `K` is invalid as in [`new`](Self::new).
+    pub fn gaussian(sigma: f32) -> Result<Self> {
+        if sigma.is_nan() || sigma <= 0. {
+            return Err(Error::BadKernel(format!(
+                "sigma must be positive, got {}",
+                sigma
+            )));
+        }
+        let half = (K / 2) as f32;
+        let mut filter = Vec::with_capacity(K * K);
+        for i in 0..K {
+            for j in 0..K {
+                let (y, x) = (i as f32 - half, j as f32 - half);
+                filter.push(exp(-(x * x + y * y) / (2. * sigma * sigma)));
+            }
+        }
+        Self::new(&filter, true)
+    }
+
+    /// Weight at the `i`-th row and `j`-th column.
+    pub fn at(&self, i: usize, j: usize) -> f32 {
+        self.inner[i * K + j]
+    }
+
+    /// All weights in row-major order.
+    pub fn weights(&self) -> &[f32] {
+        &self.inner
+    }
+
+    // the weights broadcast to vectors, `splats()[i][j]` for `at(i, j)`
+    #[cfg(all(
+        any(target_arch = "aarch64"),
+        target_feature = "neon",
+        any(
+            feature = "impl-simd1",
+            feature = "impl-simd2",
+            feature = "impl-simd3",
+            feature = "impl-simd4",
+            feature = "impl-wide",
+            feature = "impl-ring"
+        )
+    ))]
+    pub(crate) fn splats(&self) -> &[[float32x4_t; K]] {
+        &self.splats
+    }
+
+    /// Divisor applied to the weighted sums, i.e. the sum of weights if the kernel averages.
+    pub fn div(&self) -> Option<f32> {
+        self.div
+    }
+
+    /// Reciprocal of [`div`](Self::div).
+    pub fn scale(&self) -> Option<f32> {
+        self.div.map(|div| 1. / div)
+    }
+
+    /// Same kernel converting results to `u8` with `rounding` ([`Rounding::Truncate`] by default).
+    pub fn with_rounding(mut self, rounding: Rounding) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    /// How results are converted to `u8`.
+    pub fn rounding(&self) -> Rounding {
+        self.rounding
+    }
+}
+
+// f32::exp is not available in core
+#[cfg(feature = "std")]
+pub(crate) fn exp(x: f32) -> f32 {
+    x.exp()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn exp(x: f32) -> f32 {
+    libm::expf(x)
+}
+
+// f32::round is not available in core either
+#[cfg(feature = "std")]
+pub(crate) fn round(x: f32) -> f32 {
+    x.round()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn round(x: f32) -> f32 {
+    libm::roundf(x)
+}
+
+// nor f32::sqrt
+#[cfg(feature = "std")]
+pub(crate) fn sqrt(x: f32) -> f32 {
+    x.sqrt()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn sqrt(x: f32) -> f32 {
+    libm::sqrtf(x)
+}
+
+// nor f32::mul_add, `a * b + c` rounded once like the NEON `vfmaq_f32`
+#[cfg(feature = "std")]
+pub(crate) fn fma(a: f32, b: f32, c: f32) -> f32 {
+    a.mul_add(b, c)
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn fma(a: f32, b: f32, c: f32) -> f32 {
+    libm::fmaf(a, b, c)
+}
+
+// nor f32::powf
+#[cfg(feature = "std")]
+pub(crate) fn powf(x: f32, y: f32) -> f32 {
+    x.powf(y)
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn powf(x: f32, y: f32) -> f32 {
+    libm::powf(x, y)
+}
+
+// nor f32::cos
+#[cfg(feature = "std")]
+pub(crate) fn cos(x: f32) -> f32 {
+    x.cos()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn cos(x: f32) -> f32 {
+    libm::cosf(x)
+}
+
+// nor f32::sin
+#[cfg(feature = "std")]
+pub(crate) fn sin(x: f32) -> f32 {
+    x.sin()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn sin(x: f32) -> f32 {
+    libm::sinf(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_kernel() {
+        assert!(ConvKernel::<3>::new(&[1.; 9], true).is_ok());
+        assert!(matches!(
+            ConvKernel::<3>::new(&[1.; 8], true),
+            Err(Error::BadKernel(_))
+        ));
+        assert!(matches!(
+            ConvKernel::<2>::new(&[1.; 4], true),
+            Err(Error::BadKernel(_))
+        ));
+        let mut zero_sum = [1.; 9];
+        zero_sum[4] = -8.;
+        assert!(ConvKernel::<3>::new(&zero_sum, false).is_ok());
+        assert!(matches!(
+            ConvKernel::<3>::new(&zero_sum, true),
+            Err(Error::BadKernel(_))
+        ));
+    }
+
+    #[test]
+    fn gaussian() -> Result<()> {
+        let kernel = ConvKernel::<5>::gaussian(1.)?;
+        assert_eq!(
+            kernel.at(2, 2),
+            kernel.weights().iter().cloned().fold(0., f32::max)
+        );
+        assert_eq!(kernel.at(0, 1), kernel.at(1, 0));
+        assert_eq!(kernel.at(0, 1), kernel.at(4, 3));
+        assert!((kernel.div().unwrap() - kernel.weights().iter().sum::<f32>()).abs() < 1e-6);
+        assert_eq!(kernel.scale(), Some(1. / kernel.div().unwrap()));
+        assert_eq!(ConvKernel::<3>::new(&[1.; 9], false)?.scale(), None);
+        assert!(matches!(
+            ConvKernel::<5>::gaussian(0.),
+            Err(Error::BadKernel(_))
+        ));
+        assert!(matches!(
+            ConvKernel::<5>::gaussian(f32::NAN),
+            Err(Error::BadKernel(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn rounding() {
+        let cases = [
+            (-0.5, 0, 0),
+            (0.49, 0, 0),
+            (0.5, 0, 1),
+            (1.5, 1, 2),
+            (2.5, 2, 3),
+            (254.5, 254, 255),
+            (255.5, 255, 255),
+            (300., 255, 255),
+        ];
+        for &(t, truncated, nearest) in cases.iter() {
+            assert_eq!(Rounding::Truncate.to_u8(t), truncated, "{}", t);
+            assert_eq!(Rounding::Nearest.to_u8(t), nearest, "{}", t);
+        }
+    }
+}
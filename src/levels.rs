@@ -0,0 +1,239 @@
+//! Lookup tables per channel, and contrast stretching ("auto levels") built on them.
+//!
+//! [`RgbImage::auto_levels`] finds the values at two percentiles of the histogram of each channel
+//! or of the luma, and remaps the range between them linearly to `[0, 255]` with a lookup table.
+//! Tables of 256 entries are applied with NEON 16 pixels at a time, as 4 lookups in 64-byte
+//! tables of 4 registers each.
+
+use alloc::format;
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{
+    error::{Error, Result},
+    fast::luma,
+    image::RgbImage,
+    kernel::Rounding,
+    reduce::{histogram, histograms},
+    C,
+};
+
+/// Values whose range [`RgbImage::auto_levels`] stretches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LevelsMode {
+    /// The range of each channel, stretched independently, which also removes color casts.
+    PerChannel,
+    /// The range of the luma, stretched the same way in every channel to keep hues.
+    Luma,
+}
+
+impl RgbImage {
+    /// Replaces every value of the channel `c` by its entry in `tables[c]`.
+    pub fn apply_luts(&mut self, tables: &[[u8; 256]; C]) {
+        let bytes = &mut self.inner[..];
+        // pixels from `start` are left to the serial loop
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let start = {
+            let end = bytes.len() - bytes.len() % (16 * C);
+            let registers = tables.map(|table| {
+                [0, 1, 2, 3].map(|k| unsafe { vld1q_u8_x4(table[64 * k..].as_ptr()) })
+            });
+            for i in (0..end).step_by(16 * C) {
+                let mut v = simd::deinterleave16(bytes, i);
+                for (v, registers) in v.iter_mut().zip(registers.iter()) {
+                    *v = lookup(registers, *v);
+                }
+                simd::interleave16(bytes, i, v);
+            }
+            end
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let start = 0;
+        for pixel in bytes[start..].chunks_exact_mut(C) {
+            for (v, table) in pixel.iter_mut().zip(tables.iter()) {
+                *v = table[*v as usize];
+            }
+        }
+    }
+
+    /// Remaps linearly the values between the `low` and `high` percentiles (e.g. 1 and 99) of
+    /// each channel or of the luma to `[0, 255]`, saturating those outside. Ranges of a single
+    /// value are left as they are.
+    ///
+    /// Fails with [`Error::BadKernel`] unless `0 <= low < high <= 100`.
+    pub fn auto_levels(&mut self, low: f32, high: f32, mode: LevelsMode) -> Result<()> {
+        if !(0. ..=100.).contains(&low) || !(0. ..=100.).contains(&high) || low >= high {
+            return Err(Error::BadKernel(format!(
+                "percentiles must be 0 <= low < high <= 100, got {} and {}",
+                low, high
+            )));
+        }
+        if self.inner.is_empty() {
+            return Ok(());
+        }
+        let tables = match mode {
+            LevelsMode::PerChannel => {
+                histograms(&self.inner).map(|h| stretch(percentiles(&h, low, high)))
+            }
+            LevelsMode::Luma => [stretch(percentiles(&histogram(&luma(self)), low, high)); C],
+        };
+        self.apply_luts(&tables);
+        Ok(())
+    }
+}
+
+// values at the `low` and `high` percentiles of `histogram`: the lowest value above the first
+// `low`% of the values, and the highest below the last `100 - high`%
+fn percentiles(histogram: &[u64; 256], low: f32, high: f32) -> (u8, u8) {
+    let n: u64 = histogram.iter().sum();
+    let find = |bins: &mut dyn Iterator<Item = (usize, &u64)>, skip: f32| {
+        let skip = skip as f64 / 100. * n as f64;
+        let mut count = 0;
+        for (v, &c) in bins {
+            count += c;
+            if count as f64 > skip {
+                return v as u8;
+            }
+        }
+        0
+    };
+    let lo = find(&mut histogram.iter().enumerate(), low);
+    let hi = find(&mut histogram.iter().enumerate().rev(), 100. - high);
+    (lo, hi)
+}
+
+// table remapping `[lo, hi]` to `[0, 255]`, or leaving values as they are if `hi <= lo`
+fn stretch((lo, hi): (u8, u8)) -> [u8; 256] {
+    let mut table = [0; 256];
+    for (v, t) in table.iter_mut().enumerate() {
+        *t = if hi <= lo {
+            v as u8
+        } else {
+            let scale = u8::MAX as f32 / (hi - lo) as f32;
+            Rounding::Nearest.to_u8((v as f32 - lo as f32) * scale)
+        };
+    }
+    table
+}
+
+// entries of the table of 256 bytes in `registers` at the indices `v`, looked up in each 64-byte
+// quarter in turn: indices out of the range of a quarter keep the entry found so far
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+fn lookup(registers: &[uint8x16x4_t; 4], v: uint8x16_t) -> uint8x16_t {
+    unsafe {
+        let step = vdupq_n_u8(64);
+        let mut result = vqtbl4q_u8(registers[0], v);
+        let mut index = v;
+        for &quarter in &registers[1..] {
+            index = vsubq_u8(index, step);
+            result = vqtbx4q_u8(result, quarter, index);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::test_util::photo_image;
+
+    #[test]
+    fn apply_luts() -> Result<()> {
+        // a different table per channel, over vectors of 16 pixels with some left over
+        let mut img = photo_image(3, 37, 46);
+        let original = photo_image(3, 37, 46);
+        let mut tables = [[0; 256]; C];
+        for (c, table) in tables.iter_mut().enumerate() {
+            for (v, t) in table.iter_mut().enumerate() {
+                *t = (v * (2 * c + 3) + c) as u8;
+            }
+        }
+        img.apply_luts(&tables);
+        for (p, o) in img
+            .content()
+            .chunks_exact(C)
+            .zip(original.content().chunks_exact(C))
+        {
+            for c in 0..C {
+                assert_eq!(p[c], tables[c][o[c] as usize]);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn auto_levels() -> Result<()> {
+        // channels in [50, 150], [0, 100] and [100, 200], with outliers in 1% of the pixels
+        let content = (0..1000)
+            .flat_map(|i| match i {
+                0..=4 => [0; C],
+                5..=9 => [255; C],
+                _ => {
+                    let v = (i % 101) as u8;
+                    [v + 50, v, v + 100]
+                }
+            })
+            .collect();
+        let img = RgbImage::from_raw(content, 10, 100)?;
+        let mut stretched = RgbImage::from_raw(img.content().to_vec(), 10, 100)?;
+        stretched.auto_levels(1., 99., LevelsMode::PerChannel)?;
+        for (p, o) in stretched
+            .content()
+            .chunks_exact(C)
+            .zip(img.content().chunks_exact(C))
+            .skip(10)
+        {
+            let expected = Rounding::Nearest.to_u8(o[1] as f32 * 2.55);
+            assert_eq!(p, [expected; C]);
+        }
+        assert_eq!(&stretched.content()[..C], &[0; C]);
+
+        // the range of the luma, keeping the differences between channels
+        let mut luma = RgbImage::from_raw(img.content().to_vec(), 10, 100)?;
+        luma.auto_levels(1., 99., LevelsMode::Luma)?;
+        let p = &luma.content()[15 * C..16 * C];
+        assert!(p[0] > p[1] && p[2] > p[0], "{:?}", p);
+        // whole range, and a flat image
+        let mut same = photo_image(6, 7, 47);
+        same.content_mut()[..C].fill(0);
+        same.content_mut()[C..2 * C].fill(255);
+        let expected = RgbImage::from_raw(same.content().to_vec(), 6, 7)?;
+        same.auto_levels(0., 100., LevelsMode::PerChannel)?;
+        assert_eq!(same, expected);
+        let mut flat = RgbImage::from_raw(vec![77; 4 * 4 * C], 4, 4)?;
+        flat.auto_levels(2., 98., LevelsMode::Luma)?;
+        assert_eq!(flat.content(), &[77; 4 * 4 * C][..]);
+
+        for (low, high) in [(50., 50.), (-1., 99.), (1., 101.)] {
+            assert!(matches!(
+                flat.auto_levels(low, high, LevelsMode::PerChannel),
+                Err(Error::BadKernel(_))
+            ));
+        }
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    #[test]
+    fn simd() {
+        // every index of a table, in each quarter of the registers
+        let table: [u8; 256] = core::array::from_fn(|v| (255 - v) as u8 ^ 0x5a);
+        let registers = [0, 1, 2, 3].map(|k| unsafe { vld1q_u8_x4(table[64 * k..].as_ptr()) });
+        let indices: alloc::vec::Vec<u8> = (0..=255).collect();
+        for i in (0..256).step_by(16) {
+            let mut out = [0; 16];
+            simd::store_bytes16(
+                &mut out,
+                0,
+                lookup(&registers, simd::load_bytes16(&indices, i)),
+            );
+            for (k, &o) in out.iter().enumerate() {
+                assert_eq!(o, table[i + k]);
+            }
+        }
+    }
+}
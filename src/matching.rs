@@ -0,0 +1,332 @@
+//! Template matching, to find where a small patch appears in an image, e.g. to align frames.
+//!
+//! [`match_template`] scores the template at every position where it fits in the image, like a
+//! convolution without padding whose kernel is the template, and [`ScoreMap::best`] gives the
+//! position of the best score. Rows of the template and of the image are compared as flat values,
+//! 16 at a time with NEON: absolute differences for [`Metric::Sad`], and widening products for
+//! [`Metric::Ncc`], added pairwise into 32-bit lanes.
+
+use alloc::{string::ToString, vec::Vec};
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{
+    error::{Error, Result},
+    image::RgbImage,
+    kernel::sqrt,
+    C,
+};
+
+/// Score of the template at a position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// Sum of absolute differences, divided by the number of values of the template so that
+    /// scores are in `[0, 255]`. The lower the better, 0 being an exact match.
+    Sad,
+    /// Normalized cross-correlation of the values minus their means, in `[-1, 1]`. The higher the
+    /// better, 1 being a match up to brightness and contrast. Flat windows or templates score 0.
+    Ncc,
+}
+
+/// Position and score of a match.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Match {
+    /// Row of the top-left pixel of the template in the image.
+    pub y: usize,
+    /// Column of the top-left pixel of the template in the image.
+    pub x: usize,
+    /// Score at this position.
+    pub score: f32,
+}
+
+/// Scores of the template at every position, `h - th + 1` rows of `w - tw + 1` for an image of
+/// `h` x `w` pixels and a template of `th` x `tw` pixels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoreMap {
+    scores: Vec<f32>,
+    height: usize,
+    width: usize,
+    metric: Metric,
+}
+
+impl ScoreMap {
+    /// Number of rows of positions.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Number of columns of positions.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Metric of the scores.
+    pub fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    /// Scores of all the positions, row by row.
+    pub fn scores(&self) -> &[f32] {
+        &self.scores
+    }
+
+    /// Score of the template with its top-left pixel at `(y, x)`.
+    pub fn at(&self, y: usize, x: usize) -> f32 {
+        self.scores[y * self.width + x]
+    }
+
+    /// Best position, the lowest score with [`Metric::Sad`] or the highest with [`Metric::Ncc`],
+    /// the first in row order on ties.
+    pub fn best(&self) -> Match {
+        let better = |a: f32, b: f32| match self.metric {
+            Metric::Sad => a < b,
+            Metric::Ncc => a > b,
+        };
+        let mut best = 0;
+        for (i, &score) in self.scores.iter().enumerate() {
+            if better(score, self.scores[best]) {
+                best = i;
+            }
+        }
+        Match {
+            y: best / self.width,
+            x: best % self.width,
+            score: self.scores[best],
+        }
+    }
+}
+
+/// Scores `template` at every position of `img` where it fits entirely.
+///
+/// Fails with [`Error::BadKernel`] if `template` is empty, or [`Error::ImageTooSmall`] if it is
+/// taller or wider than `img`.
+pub fn match_template(img: &RgbImage, template: &RgbImage, metric: Metric) -> Result<ScoreMap> {
+    let (th, tw) = (template.height, template.width);
+    if th == 0 || tw == 0 {
+        return Err(Error::BadKernel("template must not be empty".to_string()));
+    }
+    if img.height < th || img.width < tw {
+        return Err(Error::ImageTooSmall {
+            height: img.height,
+            width: img.width,
+            min: th.max(tw),
+        });
+    }
+    let (oh, ow) = (img.height - th + 1, img.width - tw + 1);
+    let (row, trow) = (img.width * C, tw * C);
+    // rows of the template, and of the window of the image at (y, x)
+    let template_rows = || template.inner.chunks_exact(trow);
+    let window =
+        |y: usize, x: usize| (y..y + th).map(move |y| &img.inner[y * row + x * C..][..trow]);
+    let n = (th * trow) as f64;
+    let mut scores = Vec::with_capacity(oh * ow);
+    match metric {
+        Metric::Sad => {
+            for y in 0..oh {
+                for x in 0..ow {
+                    let sum: u64 = window(y, x)
+                        .zip(template_rows())
+                        .map(|(a, t)| sad(a, t))
+                        .sum();
+                    scores.push((sum as f64 / n) as f32);
+                }
+            }
+        }
+        Metric::Ncc => {
+            let (mut st, mut stt) = (0, 0);
+            for t in template_rows() {
+                let [s, ss, _] = correlate(t, t);
+                st += s;
+                stt += ss;
+            }
+            let var_t = stt as f64 - st as f64 * st as f64 / n;
+            for y in 0..oh {
+                for x in 0..ow {
+                    let [mut s, mut ss, mut sst] = [0; 3];
+                    for (a, t) in window(y, x).zip(template_rows()) {
+                        let sums = correlate(a, t);
+                        s += sums[0];
+                        ss += sums[1];
+                        sst += sums[2];
+                    }
+                    let var = ss as f64 - s as f64 * s as f64 / n;
+                    let cov = sst as f64 - s as f64 * st as f64 / n;
+                    let denominator = sqrt((var * var_t) as f32);
+                    scores.push(if denominator > 0. {
+                        (cov as f32 / denominator).clamp(-1., 1.)
+                    } else {
+                        0.
+                    });
+                }
+            }
+        }
+    }
+    Ok(ScoreMap {
+        scores,
+        height: oh,
+        width: ow,
+        metric,
+    })
+}
+
+// sum of the absolute differences of `a` and `b`, of the same length
+fn sad(a: &[u8], b: &[u8]) -> u64 {
+    // values from `start` are left to the serial loop
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let (start, mut sum) = unsafe {
+        let end = a.len() - a.len() % 16;
+        let mut acc = vdupq_n_u32(0);
+        for i in (0..end).step_by(16) {
+            let d = vabdq_u8(simd::load_bytes16(a, i), simd::load_bytes16(b, i));
+            acc = vpadalq_u16(acc, vpaddlq_u8(d));
+        }
+        (end, vaddlvq_u32(acc))
+    };
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    let (start, mut sum) = (0, 0);
+    for (&a, &b) in a[start..].iter().zip(&b[start..]) {
+        sum += (a as i16 - b as i16).unsigned_abs() as u64;
+    }
+    sum
+}
+
+// sums of `a`, `a * a` and `a * b`, of the same length. A 32-bit lane gains at most 4 * 255 * 255
+// per vector, so rows of templates would need more than 16000 vectors to overflow it.
+fn correlate(a: &[u8], b: &[u8]) -> [u64; 3] {
+    // values from `start` are left to the serial loop
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let (start, mut sums) = unsafe {
+        let end = a.len() - a.len() % 16;
+        let mut acc = [vdupq_n_u32(0); 3];
+        for i in (0..end).step_by(16) {
+            let (va, vb) = (simd::load_bytes16(a, i), simd::load_bytes16(b, i));
+            let (la, lb) = (vget_low_u8(va), vget_low_u8(vb));
+            acc[0] = vpadalq_u16(acc[0], vpaddlq_u8(va));
+            acc[1] = vpadalq_u16(acc[1], vmull_u8(la, la));
+            acc[1] = vpadalq_u16(acc[1], vmull_high_u8(va, va));
+            acc[2] = vpadalq_u16(acc[2], vmull_u8(la, lb));
+            acc[2] = vpadalq_u16(acc[2], vmull_high_u8(va, vb));
+        }
+        (end, acc.map(|acc| vaddlvq_u32(acc)))
+    };
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    let (start, mut sums) = (0, [0; 3]);
+    for (&a, &b) in a[start..].iter().zip(&b[start..]) {
+        let (a, b) = (a as u64, b as u64);
+        sums[0] += a;
+        sums[1] += a * a;
+        sums[2] += a * b;
+    }
+    sums
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::test_util::photo_image;
+
+    // copy of the region of `img` of `h` x `w` pixels at `(y, x)`
+    fn crop(img: &RgbImage, y: usize, x: usize, h: usize, w: usize) -> Result<RgbImage> {
+        let mut dst = RgbImage::from_raw(vec![0; h * w * C], h, w)?;
+        dst.copy_from(&img.view(y, x, h, w)?)?;
+        Ok(dst)
+    }
+
+    #[test]
+    fn match_template() -> Result<()> {
+        // a patch of the image itself, of rows longer than a vector with some left over
+        let img = photo_image(24, 40, 28);
+        let patch = crop(&img, 11, 17, 5, 7)?;
+        let sad = super::match_template(&img, &patch, Metric::Sad)?;
+        assert_eq!((sad.height(), sad.width()), (20, 34));
+        assert_eq!(
+            sad.best(),
+            Match {
+                y: 11,
+                x: 17,
+                score: 0.
+            }
+        );
+        let ncc = super::match_template(&img, &patch, Metric::Ncc)?;
+        let best = ncc.best();
+        assert_eq!((best.y, best.x), (11, 17));
+        assert!((best.score - 1.).abs() < 1e-4);
+
+        // every score, against sums over the values
+        let n = (5 * 7 * C) as f64;
+        for y in 0..sad.height() {
+            for x in 0..sad.width() {
+                let values = |y: usize, x: usize| -> Vec<f64> {
+                    (0..5)
+                        .flat_map(|i| &img.content()[((y + i) * 40 + x) * C..][..7 * C])
+                        .map(|&v| v as f64)
+                        .collect()
+                };
+                let (a, b) = (values(y, x), values(11, 17));
+                let pairs = || a.iter().copied().zip(b.iter().copied());
+                let mean_abs = pairs().map(|(a, b)| (a - b).abs()).sum::<f64>() / n;
+                assert_eq!(sad.at(y, x), mean_abs as f32);
+                let (ma, mb) = (
+                    pairs().map(|p| p.0).sum::<f64>() / n,
+                    pairs().map(|p| p.1).sum::<f64>() / n,
+                );
+                let cov: f64 = pairs().map(|(a, b)| (a - ma) * (b - mb)).sum();
+                let var_a: f64 = pairs().map(|(a, _)| (a - ma) * (a - ma)).sum();
+                let var_b: f64 = pairs().map(|(_, b)| (b - mb) * (b - mb)).sum();
+                let expected = cov / (var_a * var_b).sqrt();
+                assert!((ncc.at(y, x) as f64 - expected).abs() < 1e-4, "{} {}", y, x);
+            }
+        }
+
+        // brighter and with more contrast, still a perfect correlation but no longer an exact match
+        let mut brighter = crop(&img, 11, 17, 5, 7)?;
+        brighter.adjust(10., 1.2);
+        let best = super::match_template(&img, &brighter, Metric::Ncc)?.best();
+        assert_eq!((best.y, best.x), (11, 17));
+        assert!(
+            super::match_template(&img, &brighter, Metric::Sad)?
+                .best()
+                .score
+                > 0.
+        );
+        let flat = RgbImage::from_raw(vec![9; 4 * C], 2, 2)?;
+        assert!(super::match_template(&img, &flat, Metric::Ncc)?
+            .scores()
+            .iter()
+            .all(|&s| s == 0.));
+
+        assert!(matches!(
+            super::match_template(&patch, &img, Metric::Sad),
+            Err(Error::ImageTooSmall { min: 40, .. })
+        ));
+        assert!(matches!(
+            super::match_template(&img, &RgbImage::from_raw(vec![], 0, 3)?, Metric::Ncc),
+            Err(Error::BadKernel(_))
+        ));
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    #[test]
+    fn simd() {
+        // every length up to 3 vectors, against the values one by one
+        let a: Vec<u8> = (0..48).map(|i| (i * 97 % 256) as u8).collect();
+        let b: Vec<u8> = (0..48).map(|i| (255 - i * 31 % 256) as u8).collect();
+        for len in 0..=48 {
+            let (a, b) = (&a[..len], &b[..len]);
+            let pairs = || a.iter().zip(b).map(|(&a, &b)| (a as u64, b as u64));
+            let expected_sad: u64 = pairs().map(|(a, b)| a.abs_diff(b)).sum();
+            assert_eq!(sad(a, b), expected_sad);
+            let expected: [u64; 3] = [
+                pairs().map(|(a, _)| a).sum(),
+                pairs().map(|(a, _)| a * a).sum(),
+                pairs().map(|(a, b)| a * b).sum(),
+            ];
+            assert_eq!(correlate(a, b), expected);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Read-only memory-mapped raw RGB files, with the `mmap` feature.
+//!
+//! The OS reads the pages of a [`MappedImage`] as they are accessed and can evict them again, so
+//! that a file larger than memory can be convolved band by band with
+//! [`ConvProcessor::convolve_bands`](crate::ConvProcessor::convolve_bands) and
+//! [`ImageRef::read_rows`] without loading it.
+
+use std::{fs::File, path::Path};
+
+#[cfg(unix)]
+use memmap2::Advice;
+use memmap2::Mmap;
+
+use crate::{
+    error::{Error, Result},
+    image::ImageRef,
+    C,
+};
+
+/// Raw file of interleaved RGB rows mapped in memory.
+#[derive(Debug)]
+pub struct MappedImage {
+    map: Mmap,
+    offset: usize,
+    height: usize,
+    width: usize,
+}
+
+impl MappedImage {
+    /// Maps the file at `path`, whose `height * width * 3` bytes from `offset` (e.g. after a
+    /// header) are the pixels. Pages are read ahead for accesses in order.
+    ///
+    /// Fails with [`Error::InvalidLength`] if the file is shorter than that, and with
+    /// [`Error::Io`] if it cannot be opened or mapped.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated while it is mapped, which would change or
+    /// remove memory the image refers to.
+    pub unsafe fn open<P: AsRef<Path>>(
+        path: P,
+        offset: usize,
+        height: usize,
+        width: usize,
+    ) -> Result<Self> {
+        let file = File::open(path)?;
+        let map = Mmap::map(&file)?;
+        let len = height * width * C;
+        if map.len() < offset + len {
+            return Err(Error::InvalidLength {
+                expected: offset + len,
+                actual: map.len(),
+            });
+        }
+        #[cfg(unix)]
+        map.advise(Advice::Sequential)?;
+        Ok(Self {
+            map,
+            offset,
+            height,
+            width,
+        })
+    }
+
+    /// The pixels of the file.
+    pub fn image(&self) -> ImageRef<'_> {
+        let len = self.height * self.width * C;
+        ImageRef::from_raw(
+            &self.map[self.offset..self.offset + len],
+            self.height,
+            self.width,
+        )
+        .expect("length checked when mapped")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::{consts::*, image::RgbImage, processor::BorderMode, ConvProcessor};
+
+    #[test]
+    fn convolve_bands() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let (h, w) = (img.height(), img.width());
+        let path = env::temp_dir().join(format!("simd_playground_mmap_{}.rgb", process::id()));
+        // raw pixels after a header of 7 bytes
+        let mut content = b"header\n".to_vec();
+        content.extend_from_slice(img.content());
+        fs::write(&path, &content)?;
+
+        let mapped = unsafe { MappedImage::open(&path, 7, h, w) }?;
+        assert!(matches!(
+            unsafe { MappedImage::open(&path, 8, h, w) },
+            Err(Error::InvalidLength { .. })
+        ));
+        let src = mapped.image();
+        assert_eq!(src.to_image(), img);
+
+        let layer = ConvProcessor::<5>::builder()
+            .kernel(&[1.; 25])
+            .normalize(true)
+            .border(BorderMode::Reflect)
+            .build()?;
+        let mut dst = vec![];
+        layer.convolve_bands(
+            h,
+            w,
+            32,
+            |y, buf| src.read_rows(y, buf),
+            |_, rows| {
+                dst.extend_from_slice(rows);
+                Ok(())
+            },
+        )?;
+        assert_eq!(RgbImage::from_raw(dst, h, w)?, layer.convolve(&img)?);
+
+        drop(mapped);
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+//! Seeded noise added to images, to test and benchmark denoising filters on controlled
+//! degradations.
+//!
+//! Random bits come from the [`Rng`] of the generators, so that a seed gives the same noise on
+//! every run and machine. Gaussian values are the sum of the four 16-bit words of a random `u64`
+//! (an Irwin-Hall distribution, within 3.46 standard deviations of the mean) rather than of
+//! logarithms and cosines, whose results may differ by platform. NEON sums the words, scales them
+//! and adds them to 16 values at a time, and salt and pepper replaces 16 pixels at a time with
+//! bitwise selects.
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{generate::Rng, image::RgbImage, kernel::Rounding, C};
+
+// mean and standard deviation of the sum of 4 uniform `u16`
+const SUM_MEAN: f32 = 2. * u16::MAX as f32;
+const SUM_STD: f32 = 37_837.227;
+
+impl RgbImage {
+    /// Adds Gaussian noise of standard deviation `sigma` to every value independently, rounded
+    /// to the nearest integer and saturated to `[0, 255]`.
+    pub fn add_gaussian_noise(&mut self, sigma: f32, seed: u64) {
+        let mut rng = Rng::new(seed);
+        let scale = sigma / SUM_STD;
+        let bytes = &mut self.inner[..];
+        // values from `start` are left to the serial loop
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let start = unsafe {
+            let end = bytes.len() - bytes.len() % 16;
+            let (mean, scale) = (simd::splat(SUM_MEAN), simd::splat(scale));
+            let mut bits = [0u64; 16];
+            for i in (0..end).step_by(16) {
+                bits.fill_with(|| rng.next_u64());
+                let mut v = simd::load_u8x16(bytes, i);
+                for (k, v) in v.iter_mut().enumerate() {
+                    // the words of 2 `u64` added pairwise twice, to the sums of 4 `u64`
+                    let lo = vreinterpretq_u16_u64(vld1q_u64(bits[4 * k..].as_ptr()));
+                    let hi = vreinterpretq_u16_u64(vld1q_u64(bits[4 * k + 2..].as_ptr()));
+                    let sums = vpaddq_u32(vpaddlq_u16(lo), vpaddlq_u16(hi));
+                    let n = vsubq_f32(vcvtq_f32_u32(sums), mean);
+                    *v = simd::add(*v, simd::mul(n, scale));
+                }
+                simd::store_u8x16(bytes, i, &v, Rounding::Nearest);
+            }
+            end
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let start = 0;
+        for v in bytes[start..].iter_mut() {
+            let n = word_sum(rng.next_u64()) as f32 - SUM_MEAN;
+            *v = Rounding::Nearest.to_u8(*v as f32 + n * scale);
+        }
+    }
+
+    /// Sets each pixel to black or white with probability `amount / 2` each, and keeps it
+    /// otherwise.
+    pub fn add_salt_and_pepper(&mut self, amount: f32, seed: u64) {
+        let mut rng = Rng::new(seed);
+        // a pixel is hit if the high 32 bits of its `u64` are below `limit`, and white if its
+        // lowest bit is set
+        let limit = (amount.clamp(0., 1.) as f64 * (1u64 << 32) as f64) as u64;
+        let mut draw = || {
+            let bits = rng.next_u64();
+            ((bits >> 32) < limit, bits & 1 == 1)
+        };
+        let bytes = &mut self.inner[..];
+        // pixels from `start` are left to the serial loop
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let start = {
+            let pixels = bytes.len() / C;
+            let end = pixels - pixels % 16;
+            let (mut hit, mut salt) = ([0; 16], [0; 16]);
+            for i in (0..end * C).step_by(16 * C) {
+                for (h, s) in hit.iter_mut().zip(salt.iter_mut()) {
+                    let (is_hit, is_salt) = draw();
+                    *h = if is_hit { 0xff } else { 0 };
+                    *s = if is_salt { 0xff } else { 0 };
+                }
+                let (hit, salt) = (simd::load_bytes16(&hit, 0), simd::load_bytes16(&salt, 0));
+                let v = simd::deinterleave16(bytes, i).map(|v| unsafe { vbslq_u8(hit, salt, v) });
+                simd::interleave16(bytes, i, v);
+            }
+            end
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let start = 0;
+        for pixel in bytes[start * C..].chunks_exact_mut(C) {
+            if let (true, salt) = draw() {
+                pixel.fill(if salt { u8::MAX } else { 0 });
+            }
+        }
+    }
+}
+
+// sum of the four 16-bit words of `bits`
+fn word_sum(bits: u64) -> u32 {
+    (0..4).map(|i| (bits >> (16 * i)) as u16 as u32).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{vec, vec::Vec};
+
+    use super::*;
+    use crate::{error::Result, test_util::photo_image};
+
+    // gray image of `n` pixels of `v`
+    fn gray(n: usize, v: u8) -> Result<RgbImage> {
+        RgbImage::from_raw(vec![v; n * C], 1, n)
+    }
+
+    #[test]
+    fn gaussian() -> Result<()> {
+        // vectors of 16 values with some left over
+        let mut img = gray(10_001, 128)?;
+        img.add_gaussian_noise(10., 34);
+        let values: Vec<f64> = img.content().iter().map(|&v| v as f64).collect();
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let var = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / values.len() as f64;
+        assert!((mean - 128.).abs() < 0.2, "{}", mean);
+        assert!((var.sqrt() - 10.).abs() < 0.2, "{}", var.sqrt());
+        assert!(img.content().iter().all(|&v| (93..=163).contains(&v)));
+
+        // the same noise for the same seed, and none without deviation
+        let mut again = gray(10_001, 128)?;
+        again.add_gaussian_noise(10., 34);
+        assert_eq!(again, img);
+        again.add_gaussian_noise(10., 35);
+        assert_ne!(again, img);
+        let mut photo = photo_image(7, 9, 35);
+        photo.add_gaussian_noise(0., 36);
+        assert_eq!(photo, photo_image(7, 9, 35));
+        // saturated
+        let mut white = gray(100, 250)?;
+        white.add_gaussian_noise(50., 37);
+        assert!(white.content().contains(&255));
+        Ok(())
+    }
+
+    #[test]
+    fn salt_and_pepper() -> Result<()> {
+        let photo = photo_image(50, 41, 38);
+        let mut img = photo_image(50, 41, 38);
+        img.add_salt_and_pepper(0.1, 39);
+        let (mut salt, mut pepper) = (0, 0);
+        for (p, o) in img
+            .content()
+            .chunks_exact(C)
+            .zip(photo.content().chunks_exact(C))
+        {
+            if p == [255; C] && p != o {
+                salt += 1;
+            } else if p == [0; C] && p != o {
+                pepper += 1;
+            } else {
+                assert_eq!(p, o);
+            }
+        }
+        // about 5% each of 2050 pixels
+        assert!((70..140).contains(&salt), "{}", salt);
+        assert!((70..140).contains(&pepper), "{}", pepper);
+
+        let mut none = photo_image(50, 41, 38);
+        none.add_salt_and_pepper(0., 39);
+        assert_eq!(none, photo);
+        let mut all = photo_image(50, 41, 38);
+        all.add_salt_and_pepper(1., 39);
+        assert!(all
+            .content()
+            .chunks_exact(C)
+            .all(|p| p == [0; C] || p == [255; C]));
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    #[test]
+    fn simd() -> Result<()> {
+        // the same values as the serial loop, drawing the same bits in the same order
+        let mut img = photo_image(3, 21, 40);
+        img.add_gaussian_noise(12., 41);
+        let mut rng = Rng::new(41);
+        for (&v, &o) in img.content().iter().zip(photo_image(3, 21, 40).content()) {
+            let n = word_sum(rng.next_u64()) as f32 - SUM_MEAN;
+            assert_eq!(v, Rounding::Nearest.to_u8(o as f32 + n * (12. / SUM_STD)));
+        }
+
+        let mut img = photo_image(3, 21, 40);
+        img.add_salt_and_pepper(0.3, 42);
+        let mut rng = Rng::new(42);
+        let limit = (0.3f64 * (1u64 << 32) as f64) as u64;
+        for (p, o) in img
+            .content()
+            .chunks_exact(C)
+            .zip(photo_image(3, 21, 40).content().chunks_exact(C))
+        {
+            let bits = rng.next_u64();
+            let expected = match ((bits >> 32) < limit, bits & 1 == 1) {
+                (false, _) => [o[0], o[1], o[2]],
+                (true, salt) => [if salt { 255 } else { 0 }; C],
+            };
+            assert_eq!(p, expected);
+        }
+        Ok(())
+    }
+}
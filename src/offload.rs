@@ -0,0 +1,200 @@
+//! Convolution offloaded to background threads, for async code which must not block.
+//!
+//! [`ConvProcessor::convolve_async`] returns a [`ConvFuture`] right away and runs the convolution
+//! on a pool of worker threads shared by the whole process, so that e.g. the reactor threads of an
+//! async runtime keep serving other tasks. The future does not depend on any runtime.
+
+use std::{
+    future::Future,
+    lazy::SyncOnceCell,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{mpsc, Arc, Condvar, Mutex},
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+use crate::{error::Result, image::RgbImage, processor::ConvProcessor};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// sender of jobs to the workers, which are started on the first use
+fn pool() -> &'static Mutex<mpsc::Sender<Job>> {
+    static POOL: SyncOnceCell<Mutex<mpsc::Sender<Job>>> = SyncOnceCell::new();
+    POOL.get_or_init(|| {
+        let (tx, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+        let workers = thread::available_parallelism().map_or(1, |n| n.get());
+        for i in 0..workers {
+            let rx = Arc::clone(&rx);
+            thread::Builder::new()
+                .name(format!("simd-playground-{}", i))
+                .spawn(move || loop {
+                    // the lock is released before running the job
+                    let job = rx.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                })
+                .expect("cannot spawn a worker thread");
+        }
+        Mutex::new(tx)
+    })
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+    done: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    result: Option<thread::Result<Result<RgbImage>>>,
+    waker: Option<Waker>,
+}
+
+/// Result of [`ConvProcessor::convolve_async`], which can be awaited in any async runtime or
+/// waited for with [`wait`](Self::wait).
+///
+/// A panic in the convolution is resumed when the result is taken.
+#[derive(Debug)]
+pub struct ConvFuture {
+    shared: Arc<Shared>,
+}
+
+impl ConvFuture {
+    fn spawn<F>(f: F) -> Self
+    where
+        F: FnOnce() -> Result<RgbImage> + Send + 'static,
+    {
+        let shared = Arc::new(Shared::default());
+        let sender = Arc::clone(&shared);
+        let job = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+            let mut state = sender.state.lock().unwrap();
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+            sender.done.notify_all();
+        });
+        pool()
+            .lock()
+            .unwrap()
+            .send(job)
+            .expect("worker threads exited");
+        Self { shared }
+    }
+
+    /// Whether the result is available, i.e. polling or [`wait`](Self::wait) does not block.
+    pub fn is_ready(&self) -> bool {
+        self.shared.state.lock().unwrap().result.is_some()
+    }
+
+    /// Blocks the current thread until the convolution finishes.
+    pub fn wait(self) -> Result<RgbImage> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(result) = state.result.take() {
+                return result.unwrap_or_else(|e| panic::resume_unwind(e));
+            }
+            state = self.shared.done.wait(state).unwrap();
+        }
+    }
+}
+
+impl Future for ConvFuture {
+    type Output = Result<RgbImage>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result.unwrap_or_else(|e| panic::resume_unwind(e))),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<const K: usize> ConvProcessor<K>
+where
+    [(); (K / 2 + 1) / 2 + 1]: Sized,
+{
+    /// Same as [`convolve`](Self::convolve), but runs on a background worker thread and returns
+    /// a future of the result right away. `src` is moved to the worker thread.
+    ///
+    /// ```no_run
+    /// use std::sync::Arc;
+    ///
+    /// use simd_playground::{ConvProcessor, RgbImage};
+    ///
+    /// # async fn filter(frame: RgbImage) -> simd_playground::Result<RgbImage> {
+    /// let blur = Arc::new(ConvProcessor::<5>::new(&[1.; 25], true)?);
+    /// blur.convolve_async(frame).await
+    /// # }
+    /// ```
+    pub fn convolve_async(self: &Arc<Self>, src: RgbImage) -> ConvFuture {
+        let processor = Arc::clone(self);
+        ConvFuture::spawn(move || processor.convolve(&src))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::task::Wake;
+
+    use super::*;
+    use crate::{consts::*, error::Error};
+
+    // wakes the thread blocked in `block_on`
+    struct Unpark(thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(f: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut f = Box::pin(f);
+        loop {
+            match f.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn convolve_async() -> crate::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let blur = Arc::new(ConvProcessor::<5>::new(&[1.; 25], true)?);
+        let expected = blur.convolve(&img)?;
+
+        let futures: Vec<_> = (0..4)
+            .map(|_| {
+                blur.convolve_async(
+                    RgbImage::from_raw(img.content().to_vec(), img.height, img.width).unwrap(),
+                )
+            })
+            .collect();
+        for f in futures {
+            assert_eq!(block_on(f)?, expected);
+        }
+        let f = blur.convolve_async(img);
+        assert_eq!(f.wait()?, expected);
+
+        let tiny = RgbImage::from_raw(vec![0; 2 * 2 * 3], 2, 2)?;
+        assert!(matches!(
+            block_on(blur.convolve_async(tiny)),
+            Err(Error::ImageTooSmall { .. })
+        ));
+        Ok(())
+    }
+}
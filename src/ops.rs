@@ -0,0 +1,116 @@
+//! Simple per-pixel effects: inverting, posterizing and sepia toning.
+//!
+//! [`RgbImage::invert`] complements 16 values at a time with NEON, [`RgbImage::posterize`] is a
+//! lookup table applied by [`RgbImage::apply_luts`], and [`RgbImage::sepia`] is the
+//! [`SEPIA`] color matrix.
+
+use alloc::string::ToString;
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{
+    color_matrix::SEPIA,
+    error::{Error, Result},
+    image::RgbImage,
+    kernel::Rounding,
+    C,
+};
+
+impl RgbImage {
+    /// Replaces every value `v` by `255 - v`, i.e. makes a negative.
+    pub fn invert(&mut self) {
+        let bytes = &mut self.inner[..];
+        // values from `start` are left to the serial loop
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let start = {
+            let end = bytes.len() - bytes.len() % 16;
+            for i in (0..end).step_by(16) {
+                let v = unsafe { vmvnq_u8(simd::load_bytes16(bytes, i)) };
+                simd::store_bytes16(bytes, i, v);
+            }
+            end
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let start = 0;
+        for v in bytes[start..].iter_mut() {
+            *v = !*v;
+        }
+    }
+
+    /// Rounds every value to the nearest of `levels` evenly spaced values from 0 to 255, e.g. 2
+    /// for black and white channels.
+    ///
+    /// Fails with [`Error::BadKernel`] if `levels` is less than 2.
+    pub fn posterize(&mut self, levels: u8) -> Result<()> {
+        if levels < 2 {
+            return Err(Error::BadKernel(
+                "posterizing requires at least 2 levels".to_string(),
+            ));
+        }
+        let step = u8::MAX as f32 / (levels - 1) as f32;
+        let mut table = [0; 256];
+        for (v, t) in table.iter_mut().enumerate() {
+            let level = Rounding::Nearest.to_u8(v as f32 / step);
+            *t = Rounding::Nearest.to_u8(level as f32 * step);
+        }
+        self.apply_luts(&[table; C]);
+        Ok(())
+    }
+
+    /// Image toned in sepia, by [`apply_color_matrix`](RgbImage::apply_color_matrix) with
+    /// [`SEPIA`].
+    pub fn sepia(&self) -> RgbImage {
+        self.apply_color_matrix(SEPIA)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::photo_image;
+
+    #[test]
+    fn invert() {
+        // vectors of 16 values with some left over, and back
+        let mut img = photo_image(3, 23, 48);
+        let original = photo_image(3, 23, 48);
+        img.invert();
+        for (&v, &o) in img.content().iter().zip(original.content()) {
+            assert_eq!(v, 255 - o);
+        }
+        img.invert();
+        assert_eq!(img, original);
+    }
+
+    #[test]
+    fn posterize() -> Result<()> {
+        let mut img = photo_image(5, 19, 49);
+        let original = photo_image(5, 19, 49);
+        img.posterize(4)?;
+        for (&v, &o) in img.content().iter().zip(original.content()) {
+            assert!([0, 85, 170, 255].contains(&v), "{}", v);
+            assert!((v as i16 - o as i16).abs() <= 43, "{} {}", v, o);
+        }
+        let mut two = RgbImage::from_raw((0..=250).step_by(5).collect(), 1, 17)?;
+        two.content_mut()[..C].copy_from_slice(&[127, 128, 255]);
+        two.posterize(2)?;
+        assert_eq!(&two.content()[..C], &[0, 255, 255]);
+        // already on the levels
+        let posterized = RgbImage::from_raw(img.content().to_vec(), 5, 19)?;
+        img.posterize(4)?;
+        assert_eq!(img, posterized);
+        assert!(matches!(img.posterize(1), Err(Error::BadKernel(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn sepia() -> Result<()> {
+        let img = photo_image(4, 18, 50);
+        assert_eq!(img.sepia(), img.apply_color_matrix(SEPIA));
+        let white = RgbImage::from_raw(alloc::vec![255; C], 1, 1)?;
+        assert_eq!(white.sepia().content(), [255, 255, 239]);
+        Ok(())
+    }
+}
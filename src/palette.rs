@@ -0,0 +1,357 @@
+//! Palette quantization to indexed images, e.g. to save filtered results as small PNG8 files.
+//!
+//! [`median_cut`] builds a palette by splitting the colors of an image along their widest
+//! channel until there are enough boxes, and [`RgbImage::quantize`] maps every pixel to its
+//! nearest entry, 16 pixels at a time with NEON. Squared distances are integers below `2^24`, so
+//! `f32` lanes compute them exactly and NEON picks the same entries as the serial loop.
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::{fs::File, io::BufWriter, path::Path};
+
+use alloc::{format, vec, vec::Vec};
+#[cfg(feature = "std")]
+use png::{BitDepth, ColorType, Encoder};
+
+use crate::{
+    error::{Error, Result},
+    image::RgbImage,
+    C,
+};
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::{kernel::Rounding, simd};
+
+/// Largest number of colors in a palette, which `u8` indices can address.
+pub const MAX_COLORS: usize = 256;
+
+/// 8-bit image whose pixels are indices into a palette of up to [`MAX_COLORS`] colors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedImage {
+    indices: Vec<u8>,
+    palette: Vec<[u8; C]>,
+    height: usize,
+    width: usize,
+}
+
+impl IndexedImage {
+    /// Height in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Width in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Index of every pixel, row by row.
+    pub fn indices(&self) -> &[u8] {
+        &self.indices
+    }
+
+    /// Colors the indices refer to.
+    pub fn palette(&self) -> &[[u8; C]] {
+        &self.palette
+    }
+
+    /// Image with the palette color of every pixel.
+    pub fn to_rgb(&self) -> RgbImage {
+        let content = self
+            .indices
+            .iter()
+            .flat_map(|&i| self.palette[i as usize])
+            .collect();
+        RgbImage::from_raw_unchecked(content, self.height, self.width)
+    }
+
+    /// Saves the image as an 8-bit indexed PNG file.
+    #[cfg(feature = "std")]
+    pub fn save<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let f = File::create(path)?;
+        let w = BufWriter::new(f);
+        let mut encoder = Encoder::new(w, self.width as u32, self.height as u32);
+        encoder.set_color(ColorType::Indexed);
+        encoder.set_depth(BitDepth::Eight);
+        encoder.set_palette(self.palette.concat());
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.indices)?;
+        Ok(())
+    }
+}
+
+impl RgbImage {
+    /// Indexed image mapping every pixel to the nearest color of `palette` in squared Euclidean
+    /// distance, the first one on ties.
+    ///
+    /// Fails with [`Error::BadKernel`] if `palette` has more than [`MAX_COLORS`] colors, or none
+    /// while the image has pixels.
+    pub fn quantize(&self, palette: &[[u8; C]]) -> Result<IndexedImage> {
+        if palette.len() > MAX_COLORS || palette.is_empty() && !self.inner.is_empty() {
+            return Err(Error::BadKernel(format!(
+                "palette must have 1 to {} colors, got {}",
+                MAX_COLORS,
+                palette.len()
+            )));
+        }
+        let len = self.height * self.width;
+        let mut indices = vec![0; len];
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let start = {
+            let entries: Vec<[float32x4_t; C]> = palette
+                .iter()
+                .map(|e| e.map(|v| simd::splat(v as f32)))
+                .collect();
+            let simd_end = len - len % 16;
+            for i in (0..simd_end).step_by(16) {
+                let [r, g, b] = simd::load_rgb16(self.content(), i * C);
+                let mut best = [simd::splat(0.); 4];
+                for (z, best) in best.iter_mut().enumerate() {
+                    *best = unsafe { nearest4([r[z], g[z], b[z]], &entries) };
+                }
+                simd::store_u8x16(&mut indices, i, &best, Rounding::Nearest);
+            }
+            simd_end
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let start = 0;
+        for (i, p) in self.content().chunks_exact(C).enumerate().skip(start) {
+            indices[i] = nearest([p[0], p[1], p[2]], palette);
+        }
+        Ok(IndexedImage {
+            indices,
+            palette: palette.to_vec(),
+            height: self.height,
+            width: self.width,
+        })
+    }
+
+    /// Indexed image with a palette of up to `max_colors` colors from [`median_cut`].
+    ///
+    /// Fails with [`Error::BadKernel`] if `max_colors` is not in `1..=MAX_COLORS`.
+    pub fn to_indexed(&self, max_colors: usize) -> Result<IndexedImage> {
+        let palette = median_cut(self, max_colors)?;
+        self.quantize(&palette)
+    }
+}
+
+/// Palette of up to `max_colors` colors by median cut: starting from a box of all the pixels,
+/// the box whose colors spread the most along a channel is split around the median of that
+/// channel, until there are `max_colors` boxes or each has a single color. Entries are the mean colors of
+/// the boxes, so an image with `max_colors` colors or fewer gets exactly them (and an empty image
+/// no entry).
+///
+/// Fails with [`Error::BadKernel`] if `max_colors` is not in `1..=MAX_COLORS`.
+pub fn median_cut(img: &RgbImage, max_colors: usize) -> Result<Vec<[u8; C]>> {
+    if !(1..=MAX_COLORS).contains(&max_colors) {
+        return Err(Error::BadKernel(format!(
+            "max_colors must be in 1..={}, got {}",
+            MAX_COLORS, max_colors
+        )));
+    }
+    let mut pixels: Vec<[u8; C]> = img
+        .content()
+        .chunks_exact(C)
+        .map(|p| [p[0], p[1], p[2]])
+        .collect();
+    if pixels.is_empty() {
+        return Ok(Vec::new());
+    }
+    // ranges of `pixels`, with their widest channel and its spread
+    let mut boxes = vec![widest(&pixels, 0..pixels.len())];
+    while boxes.len() < max_colors {
+        let (i, &(ref range, c, spread)) = boxes
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, &(_, _, spread))| spread)
+            .unwrap();
+        if spread == 0 {
+            break;
+        }
+        let range = range.clone();
+        pixels[range.clone()].sort_unstable_by_key(|p| p[c]);
+        // split next to the median between different values, so that no color is in both
+        // halves and both are non-empty
+        let sorted = &pixels[range.clone()];
+        let median = sorted[sorted.len() / 2][c];
+        let mid = match sorted.partition_point(|p| p[c] < median) {
+            0 => sorted.partition_point(|p| p[c] <= median),
+            mid => mid,
+        } + range.start;
+        boxes[i] = widest(&pixels, range.start..mid);
+        boxes.push(widest(&pixels, mid..range.end));
+    }
+    Ok(boxes
+        .into_iter()
+        .map(|(range, _, _)| mean(&pixels[range]))
+        .collect())
+}
+
+// `range` with the channel whose values spread the most in it and that spread
+fn widest(pixels: &[[u8; C]], range: Range<usize>) -> (Range<usize>, usize, u8) {
+    let mut min = [u8::MAX; C];
+    let mut max = [0; C];
+    for p in &pixels[range.clone()] {
+        for c in 0..C {
+            min[c] = min[c].min(p[c]);
+            max[c] = max[c].max(p[c]);
+        }
+    }
+    let (c, spread) = (0..C)
+        .map(|c| (c, max[c] - min[c]))
+        .max_by_key(|&(_, s)| s)
+        .unwrap();
+    (range, c, spread)
+}
+
+// mean color rounded to the nearest integer
+fn mean(pixels: &[[u8; C]]) -> [u8; C] {
+    let mut sums = [0u64; C];
+    for p in pixels {
+        for (sum, &v) in sums.iter_mut().zip(p) {
+            *sum += v as u64;
+        }
+    }
+    let n = pixels.len() as u64;
+    sums.map(|sum| ((sum + n / 2) / n) as u8)
+}
+
+// index of the nearest entry of `palette`, the first one on ties
+fn nearest(p: [u8; C], palette: &[[u8; C]]) -> u8 {
+    let mut best = (u32::MAX, 0);
+    for (i, e) in palette.iter().enumerate() {
+        let d: u32 = p
+            .iter()
+            .zip(e)
+            .map(|(&v, &e)| (v as i32 - e as i32).pow(2) as u32)
+            .sum();
+        if d < best.0 {
+            best = (d, i);
+        }
+    }
+    best.1 as u8
+}
+
+// `nearest` of 4 pixels, with the indices in `f32`
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[inline]
+unsafe fn nearest4(p: [float32x4_t; C], entries: &[[float32x4_t; C]]) -> float32x4_t {
+    let mut best_d = vdupq_n_f32(f32::INFINITY);
+    let mut best_i = vdupq_n_f32(0.);
+    for (i, e) in entries.iter().enumerate() {
+        let dr = vsubq_f32(p[0], e[0]);
+        let dg = vsubq_f32(p[1], e[1]);
+        let db = vsubq_f32(p[2], e[2]);
+        let d = vfmaq_f32(vfmaq_f32(vmulq_f32(dr, dr), dg, dg), db, db);
+        let closer = vcltq_f32(d, best_d);
+        best_d = vbslq_f32(closer, d, best_d);
+        best_i = vbslq_f32(closer, vdupq_n_f32(i as f32), best_i);
+    }
+    best_i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::photo_image;
+
+    #[test]
+    fn few_colors() -> Result<()> {
+        // longer than a vector of 16 pixels, with some left over
+        let colors = [[200, 10, 10], [10, 200, 10], [10, 10, 200], [90, 90, 90]];
+        let content: Vec<u8> = (0..3 * 19).flat_map(|i| colors[i * 7 % 4]).collect();
+        let img = RgbImage::from_raw(content, 3, 19)?;
+        let mut palette = median_cut(&img, 16)?;
+        palette.sort_unstable();
+        let mut expected = colors.to_vec();
+        expected.sort_unstable();
+        assert_eq!(palette, expected);
+        let indexed = img.to_indexed(16)?;
+        assert_eq!(indexed.palette().len(), 4);
+        assert_eq!(indexed.to_rgb(), img);
+
+        // a single color is the mean
+        let two = RgbImage::from_raw(vec![0, 0, 0, 255, 101, 3], 1, 2)?;
+        assert_eq!(median_cut(&two, 1)?, [[128, 51, 2]]);
+        assert_eq!(
+            median_cut(&RgbImage::from_raw(Vec::new(), 0, 0)?, 8)?,
+            Vec::<[u8; C]>::new()
+        );
+        assert!(matches!(median_cut(&img, 0), Err(Error::BadKernel(_))));
+        assert!(matches!(median_cut(&img, 257), Err(Error::BadKernel(_))));
+        assert!(matches!(img.quantize(&[]), Err(Error::BadKernel(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn nearest_entries() -> Result<()> {
+        let img = RgbImage::from_raw(vec![0, 0, 0, 100, 100, 100, 200, 0, 0, 50, 50, 50], 2, 2)?;
+        let palette = [[255, 0, 0], [0, 0, 0], [255, 255, 255], [100, 100, 100]];
+        let indexed = img.quantize(&palette)?;
+        // 50 is as far from black as from the gray, which comes later
+        assert_eq!(indexed.indices(), [1, 3, 0, 1]);
+
+        // errors of a photo shrink with more colors
+        let photo = photo_image(24, 40, 13);
+        let error = |colors| -> Result<u64> {
+            let rgb = photo.to_indexed(colors)?.to_rgb();
+            Ok(rgb
+                .content()
+                .iter()
+                .zip(photo.content())
+                .map(|(&a, &b)| (a as i64 - b as i64).pow(2) as u64)
+                .sum())
+        };
+        let errors = [error(2)?, error(16)?, error(256)?];
+        assert!(
+            errors[0] > errors[1] && errors[1] > errors[2],
+            "{:?}",
+            errors
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn save() -> Result<()> {
+        let img = photo_image(9, 14, 14);
+        let indexed = img.to_indexed(8)?;
+        let path = std::env::temp_dir().join("simd_playground_palette.png");
+        // over a larger file, which must not leave its tail behind
+        std::fs::write(&path, vec![0xff; 1 << 16])?;
+        indexed.save(&path)?;
+        assert!(std::fs::metadata(&path)?.len() < 1 << 16);
+        let decoder = png::Decoder::new(std::fs::File::open(&path)?);
+        let mut reader = decoder.read_info()?;
+        let info = reader.info();
+        assert_eq!(info.color_type, ColorType::Indexed);
+        assert_eq!(
+            info.palette.as_deref(),
+            Some(&indexed.palette().concat()[..])
+        );
+        let mut buf = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buf)?;
+        assert_eq!(buf, indexed.indices());
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    #[test]
+    fn simd() -> Result<()> {
+        // vectors of 16 pixels, and some left over
+        let img = photo_image(17, 29, 15);
+        for colors in [1, 7, 64, 256] {
+            let palette = median_cut(&img, colors)?;
+            let indexed = img.quantize(&palette)?;
+            for (p, &i) in img.content().chunks_exact(C).zip(indexed.indices()) {
+                assert_eq!(i, nearest([p[0], p[1], p[2]], &palette), "{:?}", p);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,374 @@
+//! Chains of filters executed band by band.
+//!
+//! Applying filters one by one reads and writes the whole image for every stage.
+//! [`Pipeline`] instead computes a band of output rows through all stages at once, so that
+//! intermediate results only take a few rows and stay in cache.
+
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::{fmt::Debug, ops::Range};
+
+use crate::{
+    backend::{select, ConvBackend},
+    error::Result,
+    image::{adjust_coeffs, adjust_value, RgbImage},
+    kernel::ConvKernel,
+    processor::resolve_threads,
+    C,
+};
+
+/// Sequence of convolutions and per-value operations (lookup tables, thresholds).
+///
+/// The result is the same as applying each stage to the whole image in order, where
+/// convolutions fill pixels closer than `K / 2` to the image border with 0 like [`ConvProcessor`](crate::ConvProcessor).
+/// Consecutive per-value operations are fused into a single lookup table.
+///
+/// ```no_run
+/// use simd_playground::{pipeline::Pipeline, ConvKernel, RgbImage};
+///
+/// # fn main() -> simd_playground::Result<()> {
+/// let img = RgbImage::load("img/Lenna.png")?;
+/// let edges = Pipeline::new()
+///     .convolve(ConvKernel::<5>::new(&[1.; 25], true)?)
+///     .convolve(ConvKernel::<3>::new(&[0., -1., 0., -1., 5., -1., 0., -1., 0.], false)?)
+///     .map(|v| v / 2)
+///     .threshold(64)
+///     .run(&img)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Pipeline {
+    stages: Vec<Stage>,
+    band_rows: usize,
+    threads: usize,
+}
+
+#[derive(Debug)]
+enum Stage {
+    Conv(Box<dyn ConvStage>),
+    Lut(Box<[u8; 256]>),
+}
+
+// convolution with the kernel size erased
+trait ConvStage: Debug + Send + Sync {
+    fn size(&self) -> usize;
+    fn convolve_rows(&self, src: &RgbImage, rows: Range<usize>, dst: &mut [u8]);
+}
+
+#[derive(Debug)]
+struct ConvStep<const K: usize> {
+    kernel: ConvKernel<K>,
+    select: fn(usize) -> &'static dyn ConvBackend<K>,
+}
+
+impl<const K: usize> ConvStage for ConvStep<K> {
+    fn size(&self) -> usize {
+        K
+    }
+
+    fn convolve_rows(&self, src: &RgbImage, rows: Range<usize>, dst: &mut [u8]) {
+        (self.select)(src.width).convolve_rows(&self.kernel, src, rows, dst);
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self {
+            stages: vec![],
+            band_rows: 64,
+            threads: 1,
+        }
+    }
+}
+
+impl Pipeline {
+    /// Pipeline without stages, which just copies images.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a convolution with `kernel`, computed by the fastest backend for the target.
+    pub fn convolve<const K: usize>(mut self, kernel: ConvKernel<K>) -> Self
+    where
+        [(); (K / 2 + 1) / 2 + 1]: Sized,
+    {
+        self.stages.push(Stage::Conv(Box::new(ConvStep {
+            kernel,
+            select: select::<K>,
+        })));
+        self
+    }
+
+    /// Appends a lookup table applied to each value of every channel.
+    pub fn lut(mut self, table: [u8; 256]) -> Self {
+        match self.stages.last_mut() {
+            Some(Stage::Lut(prev)) => {
+                for v in prev.iter_mut() {
+                    *v = table[*v as usize];
+                }
+            }
+            _ => self.stages.push(Stage::Lut(Box::new(table))),
+        }
+        self
+    }
+
+    /// Appends `f` applied to each value of every channel.
+    pub fn map<F: Fn(u8) -> u8>(self, f: F) -> Self {
+        let mut table = [0; 256];
+        for (v, t) in table.iter_mut().enumerate() {
+            *t = f(v as u8);
+        }
+        self.lut(table)
+    }
+
+    /// Appends a threshold turning values `>= t` into 255 and others into 0.
+    pub fn threshold(self, t: u8) -> Self {
+        self.map(|v| if v >= t { u8::MAX } else { 0 })
+    }
+
+    /// Appends a brightness and contrast adjustment like [`RgbImage::adjust`].
+    pub fn adjust(self, brightness: f32, contrast: f32) -> Self {
+        let (scale, offset) = adjust_coeffs(brightness, contrast);
+        self.map(|v| adjust_value(v, scale, offset))
+    }
+
+    /// Number of output rows computed through all stages at once (64 by default).
+    /// Rows around each band are computed again for every band, so small bands trade extra
+    /// computation for less memory traffic.
+    pub fn band_rows(mut self, rows: usize) -> Self {
+        self.band_rows = rows.max(1);
+        self
+    }
+
+    /// Number of threads among which bands are split (1 by default), using scoped threads of
+    /// the standard library. 0 means the number of available CPUs.
+    ///
+    /// Ignored without the `std` feature, where bands are computed on the calling thread.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = resolve_threads(threads);
+        self
+    }
+
+    /// Applies all stages to `src`.
+    ///
+    /// Fails with [`Error::ImageTooSmall`](crate::Error::ImageTooSmall) if `src` is smaller
+    /// than any kernel.
+    pub fn run(&self, src: &RgbImage) -> Result<RgbImage> {
+        for stage in self.stages.iter() {
+            if let Stage::Conv(conv) = stage {
+                src.check_min_size(conv.size())?;
+            }
+        }
+
+        let (h, w) = (src.height, src.width);
+        let row = w * C;
+        let mut dst = vec![0u8; h * row];
+        let bands = (h + self.band_rows - 1) / self.band_rows;
+        let threads = self.threads.min(bands).max(1);
+        if threads == 1 {
+            self.run_bands(src, 0, &mut dst);
+        } else {
+            #[cfg(feature = "std")]
+            {
+                // contiguous bands for each thread
+                let chunk = (bands + threads - 1) / threads * self.band_rows;
+                std::thread::scope(|s| {
+                    for (i, dst) in dst.chunks_mut(chunk * row).enumerate() {
+                        s.spawn(move |_| self.run_bands(src, i * chunk, dst));
+                    }
+                });
+            }
+        }
+        Ok(RgbImage::from_raw_unchecked(dst, h, w))
+    }
+
+    // compute the rows of `dst`, from `y` to the end of it or of the image
+    fn run_bands(&self, src: &RgbImage, y: usize, dst: &mut [u8]) {
+        let (h, w) = (src.height, src.width);
+        let row = w * C;
+        let end = y + dst.len() / row;
+        // strips of intermediate results, used alternately
+        let mut bufs = [RgbImage::empty(), RgbImage::empty()];
+        let mut ranges = vec![0..0; self.stages.len() + 1];
+
+        for y0 in (y..end).step_by(self.band_rows) {
+            // rows required from each stage, from the last one backwards.
+            // ranges[i] is the input of the i-th stage, and ranges[i + 1] is its output.
+            ranges[self.stages.len()] = y0..(y0 + self.band_rows).min(end);
+            for (i, stage) in self.stages.iter().enumerate().rev() {
+                let out = &ranges[i + 1];
+                ranges[i] = match stage {
+                    Stage::Conv(conv) => {
+                        let half = conv.size() / 2;
+                        out.start.saturating_sub(half)..(out.end + half).min(h)
+                    }
+                    Stage::Lut(_) => out.clone(),
+                };
+            }
+
+            // index of the strip holding the result so far, or None for src itself
+            let mut cur = None;
+            for (i, stage) in self.stages.iter().enumerate() {
+                let input = &ranges[i];
+                // first row of `prev`, src holds all rows
+                let offset = cur.map_or(0, |_| input.start);
+                let out = &ranges[i + 1];
+                let next = cur.map_or(0, |k| 1 - k);
+                let (prev, strip) = match cur {
+                    None => (src, &mut bufs[0]),
+                    Some(k) => {
+                        let (a, b) = bufs.split_at_mut(1);
+                        if k == 0 {
+                            (&a[0], &mut b[0])
+                        } else {
+                            (&b[0], &mut a[0])
+                        }
+                    }
+                };
+
+                match stage {
+                    Stage::Conv(conv) => {
+                        let half = conv.size() / 2;
+                        resize(strip, out.len(), w);
+                        strip.inner.fill(0);
+                        // rows on the image border stay 0
+                        let inner = out.start.max(half)..out.end.min(h - half);
+                        if !inner.is_empty() {
+                            conv.convolve_rows(
+                                prev,
+                                inner.start - offset..inner.end - offset,
+                                &mut strip.inner[(inner.start - out.start) * row
+                                    ..(inner.end - out.start) * row],
+                            );
+                        }
+                        cur = Some(next);
+                    }
+                    Stage::Lut(table) => {
+                        let k = match cur {
+                            Some(k) => k,
+                            None => {
+                                // src cannot be modified
+                                resize(strip, input.len(), w);
+                                strip.inner.copy_from_slice(
+                                    &src.inner[input.start * row..input.end * row],
+                                );
+                                cur = Some(next);
+                                next
+                            }
+                        };
+                        for v in bufs[k].inner.iter_mut() {
+                            *v = table[*v as usize];
+                        }
+                    }
+                }
+            }
+
+            let out = &ranges[self.stages.len()];
+            let (result, offset) = match cur {
+                None => (src, 0),
+                Some(k) => (&bufs[k], out.start),
+            };
+            dst[(out.start - y) * row..(out.end - y) * row].copy_from_slice(
+                &result.inner[(out.start - offset) * row..(out.end - offset) * row],
+            );
+        }
+    }
+}
+
+// reuse the buffer of `strip` for `height` rows
+fn resize(strip: &mut RgbImage, height: usize, width: usize) {
+    strip.inner.resize(height * width * C, 0);
+    strip.height = height;
+    strip.width = width;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{consts::*, error::Error, ConvProcessor};
+
+    const SHARPEN: [f32; 9] = [0., -1., 0., -1., 5., -1., 0., -1., 0.];
+
+    fn apply_lut(img: &RgbImage, f: impl Fn(u8) -> u8) -> RgbImage {
+        let content = img.content().iter().map(|&v| f(v)).collect();
+        RgbImage::from_raw_unchecked(content, img.height, img.width)
+    }
+
+    #[test]
+    fn same_as_sequential() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let blur = ConvProcessor::<5>::new(&[1.; 25], true)?;
+        let sharpen = ConvProcessor::<3>::new(&SHARPEN, false)?;
+        let expected = sharpen.naive2(&blur.naive2(&img)?)?;
+        let expected = apply_lut(&apply_lut(&expected, |v| v / 2 + 10), |v| {
+            if v >= 64 {
+                255
+            } else {
+                0
+            }
+        });
+
+        for &band_rows in [1, 7, 16, img.height].iter() {
+            let processed = Pipeline::new()
+                .convolve(ConvKernel::<5>::new(&[1.; 25], true)?)
+                .convolve(ConvKernel::<3>::new(&SHARPEN, false)?)
+                .map(|v| v / 2 + 10)
+                .threshold(64)
+                .band_rows(band_rows)
+                .run(&img)?;
+            assert_eq!(processed, expected, "band_rows {}", band_rows);
+        }
+
+        // more threads than bands, and bands split unevenly
+        for &(band_rows, threads) in [(7, 3), (200, 16)].iter() {
+            let processed = Pipeline::new()
+                .convolve(ConvKernel::<5>::new(&[1.; 25], true)?)
+                .convolve(ConvKernel::<3>::new(&SHARPEN, false)?)
+                .map(|v| v / 2 + 10)
+                .threshold(64)
+                .band_rows(band_rows)
+                .threads(threads)
+                .run(&img)?;
+            assert_eq!(processed, expected, "{} threads", threads);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn lut_first() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let sobel = ConvProcessor::<3>::new(&SOBEL_FILTER, false)?;
+        // lut applied to the border of the convolution
+        let expected = apply_lut(&sobel.naive2(&apply_lut(&img, |v| 255 - v))?, |v| v | 1);
+
+        let processed = Pipeline::new()
+            .map(|v| 255 - v)
+            .convolve(ConvKernel::<3>::new(&SOBEL_FILTER, false)?)
+            .map(|v| v | 1)
+            .band_rows(5)
+            .run(&img)?;
+        assert_eq!(processed, expected);
+
+        assert_eq!(Pipeline::new().run(&img)?, img);
+        assert_eq!(
+            Pipeline::new().threshold(128).run(&img)?,
+            apply_lut(&img, |v| if v >= 128 { 255 } else { 0 })
+        );
+        let mut adjusted = RgbImage::from_raw(img.content().to_vec(), img.height, img.width)?;
+        adjusted.adjust(-15., 1.2);
+        assert_eq!(Pipeline::new().adjust(-15., 1.2).run(&img)?, adjusted);
+        Ok(())
+    }
+
+    #[test]
+    fn too_small() {
+        let img = RgbImage::from_raw_unchecked(vec![0; 4 * 8 * C], 4, 8);
+        let pipeline = Pipeline::new()
+            .convolve(ConvKernel::<3>::new(&[1.; 9], true).unwrap())
+            .convolve(ConvKernel::<5>::new(&[1.; 25], true).unwrap());
+        assert!(matches!(
+            pipeline.run(&img),
+            Err(Error::ImageTooSmall { min: 5, .. })
+        ));
+    }
+}
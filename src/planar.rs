@@ -0,0 +1,358 @@
+//! Planar RGB images, with one plane of bytes per channel.
+//!
+//! [`RgbImage`] interleaves the channels of every pixel, which NEON deinterleaves on the fly with
+//! `vld3q_u8`. Filters working on a channel at a time instead read contiguous bytes from a
+//! [`PlanarImage`]. Conversions both ways use `vld3q_u8`/`vst3q_u8` on 16 pixels at a time.
+
+use alloc::{vec, vec::Vec};
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{
+    error::{Error, Result},
+    image::RgbImage,
+    C,
+};
+
+/// 8-bit RGB image stored as 3 planes of `height * width` bytes, red, green and blue in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanarImage {
+    planes: Vec<u8>,
+    height: usize,
+    width: usize,
+}
+
+impl PlanarImage {
+    /// Black image of `height` x `width` pixels, e.g. a buffer for
+    /// [`RgbImage::to_planar_into`].
+    pub fn new(height: usize, width: usize) -> Self {
+        Self {
+            planes: vec![0; height * width * C],
+            height,
+            width,
+        }
+    }
+
+    /// Wraps the red, green and blue planes of `height * width` bytes each, one after another.
+    ///
+    /// Fails with [`Error::InvalidLength`] if the length of `planes` is inconsistent.
+    pub fn from_raw(planes: Vec<u8>, height: usize, width: usize) -> Result<Self> {
+        if planes.len() != height * width * C {
+            return Err(Error::InvalidLength {
+                expected: height * width * C,
+                actual: planes.len(),
+            });
+        }
+        Ok(Self {
+            planes,
+            height,
+            width,
+        })
+    }
+
+    /// Height in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Width in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Plane of channel `c` (0 for red, 1 for green, 2 for blue), in row-major order.
+    ///
+    /// # Panics
+    ///
+    /// If `c` is 3 or more.
+    pub fn plane(&self, c: usize) -> &[u8] {
+        let len = self.height * self.width;
+        &self.planes[c * len..][..len]
+    }
+
+    /// Mutable plane of channel `c`, see [`plane`](Self::plane).
+    pub fn plane_mut(&mut self, c: usize) -> &mut [u8] {
+        let len = self.height * self.width;
+        &mut self.planes[c * len..][..len]
+    }
+
+    /// All the planes one after another.
+    pub fn content(&self) -> &[u8] {
+        &self.planes
+    }
+
+    /// Interleaves the planes into a new [`RgbImage`].
+    pub fn to_interleaved(&self) -> RgbImage {
+        let mut dst =
+            RgbImage::from_raw_unchecked(vec![0; self.planes.len()], self.height, self.width);
+        interleave(planes_ref(&self.planes), &mut dst.inner);
+        dst
+    }
+
+    /// Same as [`to_interleaved`](Self::to_interleaved), but writes into the existing buffer of
+    /// `dst`.
+    ///
+    /// Fails with [`Error::SizeMismatch`] if `dst` does not have the same size.
+    pub fn to_interleaved_into(&self, dst: &mut RgbImage) -> Result<()> {
+        check_size((self.height, self.width), (dst.height, dst.width))?;
+        interleave(planes_ref(&self.planes), &mut dst.inner);
+        Ok(())
+    }
+}
+
+impl RgbImage {
+    /// Deinterleaves the channels into a new [`PlanarImage`].
+    pub fn to_planar(&self) -> PlanarImage {
+        let mut dst = PlanarImage::new(self.height, self.width);
+        deinterleave(&self.inner, planes_mut(&mut dst.planes));
+        dst
+    }
+
+    /// Same as [`to_planar`](Self::to_planar), but writes into the existing buffer of `dst`.
+    ///
+    /// Fails with [`Error::SizeMismatch`] if `dst` does not have the same size.
+    pub fn to_planar_into(&self, dst: &mut PlanarImage) -> Result<()> {
+        check_size((self.height, self.width), (dst.height, dst.width))?;
+        deinterleave(&self.inner, planes_mut(&mut dst.planes));
+        Ok(())
+    }
+
+    /// Red, green and blue planes of `height * width` bytes each, in row-major order.
+    pub fn split(&self) -> [Vec<u8>; C] {
+        let len = self.height * self.width;
+        let [mut r, mut g, mut b] = [vec![0; len], vec![0; len], vec![0; len]];
+        deinterleave(&self.inner, [&mut r, &mut g, &mut b]);
+        [r, g, b]
+    }
+
+    /// Interleaves red, green and blue planes of `height * width` bytes each, the reverse of
+    /// [`split`](Self::split).
+    ///
+    /// Fails with [`Error::InvalidLength`] if a plane does not have `height * width` bytes.
+    pub fn merge(r: &[u8], g: &[u8], b: &[u8], height: usize, width: usize) -> Result<Self> {
+        for plane in [r, g, b] {
+            if plane.len() != height * width {
+                return Err(Error::InvalidLength {
+                    expected: height * width,
+                    actual: plane.len(),
+                });
+            }
+        }
+        let mut dst = RgbImage::from_raw_unchecked(vec![0; height * width * C], height, width);
+        interleave([r, g, b], &mut dst.inner);
+        Ok(dst)
+    }
+
+    /// Plane of channel `c` (0 for red, 1 for green, 2 for blue), like the `c`th plane of
+    /// [`split`](Self::split).
+    ///
+    /// # Panics
+    ///
+    /// If `c` is 3 or more.
+    pub fn extract_channel(&self, c: usize) -> Vec<u8> {
+        assert!(c < C, "channel {} out of {}", c, C);
+        let len = self.height * self.width;
+        let mut dst = vec![0; len];
+        // pixels from `start` are left to the serial loop
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let start = {
+            let simd_end = len - len % 16;
+            for x in (0..simd_end).step_by(16) {
+                simd::store_bytes16(&mut dst, x, simd::deinterleave16(&self.inner, x * C)[c]);
+            }
+            simd_end
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let start = 0;
+        for (d, s) in dst[start..]
+            .iter_mut()
+            .zip(self.inner[start * C..].chunks_exact(C))
+        {
+            *d = s[c];
+        }
+        dst
+    }
+
+    /// Image whose channel `c` is the channel `order[c]` of this one, e.g. `[2, 1, 0]` swaps
+    /// red and blue and `[1, 1, 1]` is the green channel in gray.
+    ///
+    /// # Panics
+    ///
+    /// If an index of `order` is 3 or more.
+    pub fn swizzle(&self, order: [usize; C]) -> RgbImage {
+        assert!(
+            order.iter().all(|&c| c < C),
+            "channels {:?} out of {}",
+            order,
+            C
+        );
+        let len = self.height * self.width;
+        let mut dst = vec![0; len * C];
+        // pixels from `start` are left to the serial loop
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let start = {
+            let simd_end = len - len % 16;
+            for x in (0..simd_end).step_by(16) {
+                let v = simd::deinterleave16(&self.inner, x * C);
+                simd::interleave16(&mut dst, x * C, order.map(|c| v[c]));
+            }
+            simd_end
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let start = 0;
+        for (s, d) in self.inner[start * C..]
+            .chunks_exact(C)
+            .zip(dst[start * C..].chunks_exact_mut(C))
+        {
+            for (d, &c) in d.iter_mut().zip(order.iter()) {
+                *d = s[c];
+            }
+        }
+        RgbImage::from_raw_unchecked(dst, self.height, self.width)
+    }
+}
+
+impl From<&RgbImage> for PlanarImage {
+    fn from(img: &RgbImage) -> Self {
+        img.to_planar()
+    }
+}
+
+impl From<&PlanarImage> for RgbImage {
+    fn from(img: &PlanarImage) -> Self {
+        img.to_interleaved()
+    }
+}
+
+fn check_size(expected: (usize, usize), actual: (usize, usize)) -> Result<()> {
+    if expected != actual {
+        return Err(Error::SizeMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+// the 3 planes of `planes`, one after another
+fn planes_mut(planes: &mut [u8]) -> [&mut [u8]; C] {
+    let len = planes.len() / C;
+    let (r, gb) = planes.split_at_mut(len);
+    let (g, b) = gb.split_at_mut(len);
+    [r, g, b]
+}
+
+fn planes_ref(planes: &[u8]) -> [&[u8]; C] {
+    let len = planes.len() / C;
+    [&planes[..len], &planes[len..2 * len], &planes[2 * len..]]
+}
+
+// `planes` of `src.len() / 3` bytes each from interleaved `src`
+fn deinterleave(src: &[u8], planes: [&mut [u8]; C]) {
+    let len = src.len() / C;
+    // pixels from `start` are left to the serial loop
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let start = {
+        let simd_end = len - len % 16;
+        for x in (0..simd_end).step_by(16) {
+            for (c, &v) in simd::deinterleave16(src, x * C).iter().enumerate() {
+                simd::store_bytes16(planes[c], x, v);
+            }
+        }
+        simd_end
+    };
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    let start = 0;
+    for x in start..len {
+        for c in 0..C {
+            planes[c][x] = src[x * C + c];
+        }
+    }
+}
+
+// interleaved `dst` from `planes` of `dst.len() / 3` bytes each
+fn interleave(planes: [&[u8]; C], dst: &mut [u8]) {
+    let len = dst.len() / C;
+    // pixels from `start` are left to the serial loop
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let start = {
+        let simd_end = len - len % 16;
+        for x in (0..simd_end).step_by(16) {
+            let v = planes.map(|plane| simd::load_bytes16(plane, x));
+            simd::interleave16(dst, x * C, v);
+        }
+        simd_end
+    };
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    let start = 0;
+    for x in start..len {
+        for c in 0..C {
+            dst[x * C + c] = planes[c][x];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        // 2 vectors of 16 pixels and some left over
+        let (h, w) = (5, 7);
+        let content: Vec<u8> = (0..h * w * C).map(|i| (i * 7 % 256) as u8).collect();
+        let img = RgbImage::from_raw(content.clone(), h, w)?;
+        let planar = img.to_planar();
+        assert_eq!((planar.height(), planar.width()), (h, w));
+        for c in 0..C {
+            let expected: Vec<u8> = content.iter().skip(c).step_by(C).copied().collect();
+            assert_eq!(planar.plane(c), &expected[..], "channel {}", c);
+        }
+        assert_eq!(planar.to_interleaved(), img);
+
+        let mut dst = RgbImage::from_raw(vec![0; content.len()], h, w)?;
+        planar.to_interleaved_into(&mut dst)?;
+        assert_eq!(dst, img);
+        let mut reused = PlanarImage::new(h, w);
+        img.to_planar_into(&mut reused)?;
+        assert_eq!(reused, planar);
+        assert_eq!(
+            PlanarImage::from_raw(planar.content().to_vec(), h, w)?,
+            planar
+        );
+
+        assert!(matches!(
+            img.to_planar_into(&mut PlanarImage::new(w, h)),
+            Err(Error::SizeMismatch { .. })
+        ));
+        assert!(matches!(
+            PlanarImage::from_raw(vec![0; 4], 1, 1),
+            Err(Error::InvalidLength { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn channels() -> Result<()> {
+        // 2 vectors of 16 pixels and some left over
+        let (h, w) = (5, 7);
+        let content: Vec<u8> = (0..h * w * C).map(|i| (i * 7 % 256) as u8).collect();
+        let img = RgbImage::from_raw(content.clone(), h, w)?;
+        let [r, g, b] = img.split();
+        for (c, plane) in [&r, &g, &b].iter().enumerate() {
+            let expected: Vec<u8> = content.iter().skip(c).step_by(C).copied().collect();
+            assert_eq!(plane[..], expected[..], "channel {}", c);
+            assert_eq!(img.extract_channel(c), expected, "channel {}", c);
+        }
+        assert_eq!(RgbImage::merge(&r, &g, &b, h, w)?, img);
+        assert!(matches!(
+            RgbImage::merge(&r, &g[1..], &b, h, w),
+            Err(Error::InvalidLength {
+                expected: 35,
+                actual: 34
+            })
+        ));
+
+        assert_eq!(img.swizzle([0, 1, 2]), img);
+        assert_eq!(img.swizzle([2, 1, 0]), RgbImage::merge(&b, &g, &r, h, w)?);
+        assert_eq!(img.swizzle([1, 1, 0]), RgbImage::merge(&g, &g, &r, h, w)?);
+        Ok(())
+    }
+}
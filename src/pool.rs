@@ -0,0 +1,322 @@
+//! Max and average pooling.
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+
+use alloc::{string::ToString, vec};
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::util::{vec_mut, vec_ref};
+use crate::{
+    error::{Error, Result},
+    image::RgbImage,
+    C,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Reduction applied to each window.
+pub enum PoolType {
+    /// Maximum value in the window.
+    Max,
+    /// Average of the window, rounded half up.
+    Avg,
+}
+
+/// KxK pooling for each channel. Windows are taken without padding,
+/// so the output has `(h - K) / stride + 1` rows and `(w - K) / stride + 1` columns.
+#[derive(Debug)]
+pub struct PoolProcessor<const K: usize> {
+    ty: PoolType,
+    stride: usize,
+}
+
+impl<const K: usize> PoolProcessor<K> {
+    /// Creates a processor pooling KxK windows every `stride` pixels.
+    ///
+    /// Fails with [`Error::BadKernel`] if `K` or `stride` is 0.
+    pub fn new(ty: PoolType, stride: usize) -> Result<Self> {
+        if K == 0 {
+            return Err(Error::BadKernel(
+                "pooling window must not be empty".to_string(),
+            ));
+        }
+        if stride == 0 {
+            return Err(Error::BadKernel("stride must be positive".to_string()));
+        }
+        Ok(Self { ty, stride })
+    }
+
+    /// Height and width of the output for an input of the given size.
+    ///
+    /// Fails with [`Error::ImageTooSmall`] if the input is smaller than KxK.
+    pub fn output_size(&self, height: usize, width: usize) -> Result<(usize, usize)> {
+        if height < K || width < K {
+            return Err(Error::ImageTooSmall {
+                height,
+                width,
+                min: K,
+            });
+        }
+        Ok((
+            (height - K) / self.stride + 1,
+            (width - K) / self.stride + 1,
+        ))
+    }
+
+    /// Scalar implementation.
+    pub fn naive(&self, src: &RgbImage) -> Result<RgbImage> {
+        src.check_min_size(K)?;
+        let (oh, ow) = self.output_size(src.height, src.width)?;
+        let mut dst = vec![0u8; oh * ow * C];
+
+        for oy in 0..oh {
+            for ox in 0..ow {
+                for c in 0..C {
+                    dst[(oy * ow + ox) * C + c] = self.pool_at(src, oy, ox, c);
+                }
+            }
+        }
+        Ok(RgbImage::from_raw_unchecked(dst, oh, ow))
+    }
+
+    /// NEON implementation. 2x2 windows with stride 2 are fully vectorized, otherwise
+    /// only the vertical reduction is.
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    pub fn simd(&self, src: &RgbImage) -> Result<RgbImage> {
+        src.check_min_size(K)?;
+        if K == 2 && self.stride == 2 {
+            return self.simd_2x2(src);
+        }
+
+        let (oh, ow) = self.output_size(src.height, src.width)?;
+        let w = src.width;
+        let mut dst = vec![0u8; oh * ow * C];
+
+        // reduce K rows vertically with simd, then reduce each window horizontally in serial
+        let mut rows = [vec![0u16; w], vec![0u16; w], vec![0u16; w]];
+        let simd_end = w - w % 16;
+        for oy in 0..oh {
+            let y0 = oy * self.stride;
+            for x in (0..simd_end).step_by(16) {
+                unsafe {
+                    let mut acc = [[vdupq_n_u16(0); 2]; C];
+                    for i in 0..K {
+                        let sc = vld3q_u8(vec_ref(src.content(), ((y0 + i) * w + x) * C, 16 * C));
+                        for (vt, &s) in acc.iter_mut().zip([sc.0, sc.1, sc.2].iter()) {
+                            match self.ty {
+                                PoolType::Max => {
+                                    vt[0] = vmaxq_u16(vt[0], vmovl_u8(vget_low_u8(s)));
+                                    vt[1] = vmaxq_u16(vt[1], vmovl_high_u8(s));
+                                }
+                                PoolType::Avg => {
+                                    vt[0] = vaddw_u8(vt[0], vget_low_u8(s));
+                                    vt[1] = vaddw_high_u8(vt[1], s);
+                                }
+                            }
+                        }
+                    }
+                    for (row, vt) in rows.iter_mut().zip(acc.iter()) {
+                        vst1q_u16(vec_mut(row, x, 8), vt[0]);
+                        vst1q_u16(vec_mut(row, x + 8, 8), vt[1]);
+                    }
+                }
+            }
+            for x in simd_end..w {
+                for (c, row) in rows.iter_mut().enumerate() {
+                    let column = (0..K).map(|i| src.content()[((y0 + i) * w + x) * C + c] as u16);
+                    row[x] = match self.ty {
+                        PoolType::Max => column.max().unwrap(),
+                        PoolType::Avg => column.sum(),
+                    };
+                }
+            }
+
+            for ox in 0..ow {
+                let x0 = ox * self.stride;
+                for (c, row) in rows.iter().enumerate() {
+                    let window = row[x0..x0 + K].iter().map(|&v| v as u32);
+                    let acc = match self.ty {
+                        PoolType::Max => window.max().unwrap(),
+                        PoolType::Avg => window.sum(),
+                    };
+                    dst[(oy * ow + ox) * C + c] = self.finish(acc);
+                }
+            }
+        }
+        Ok(RgbImage::from_raw_unchecked(dst, oh, ow))
+    }
+
+    // 2x2 windows with stride 2 reduce neighboring lanes, so pairwise instructions do all the work
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    fn simd_2x2(&self, src: &RgbImage) -> Result<RgbImage> {
+        let (oh, ow) = self.output_size(src.height, src.width)?;
+        let w = src.width;
+        let mut dst = vec![0u8; oh * ow * C];
+
+        // write 16 elements (= read 32 elements in 2 rows) in parallel
+        let simd_end = ow - ow % 16;
+        for oy in 0..oh {
+            let y0 = oy * 2;
+            for ox in (0..simd_end).step_by(16) {
+                let x0 = ox * 2;
+                unsafe {
+                    let load = |y: usize, x: usize| {
+                        vld3q_u8(vec_ref(src.content(), (y * w + x) * C, 16 * C))
+                    };
+                    let (a0, b0) = (load(y0, x0), load(y0, x0 + 16));
+                    let (a1, b1) = (load(y0 + 1, x0), load(y0 + 1, x0 + 16));
+                    let pool = |a0, b0, a1, b1| match self.ty {
+                        PoolType::Max => vpmaxq_u8(vmaxq_u8(a0, a1), vmaxq_u8(b0, b1)),
+                        PoolType::Avg => vcombine_u8(
+                            vrshrn_n_u16::<2>(vpadalq_u8(vpaddlq_u8(a0), a1)),
+                            vrshrn_n_u16::<2>(vpadalq_u8(vpaddlq_u8(b0), b1)),
+                        ),
+                    };
+                    vst3q_u8(
+                        vec_mut(&mut dst, (oy * ow + ox) * C, 16 * C),
+                        uint8x16x3_t(
+                            pool(a0.0, b0.0, a1.0, b1.0),
+                            pool(a0.1, b0.1, a1.1, b1.1),
+                            pool(a0.2, b0.2, a1.2, b1.2),
+                        ),
+                    );
+                }
+            }
+
+            for ox in simd_end..ow {
+                for c in 0..C {
+                    dst[(oy * ow + ox) * C + c] = self.pool_at(src, oy, ox, c);
+                }
+            }
+        }
+        Ok(RgbImage::from_raw_unchecked(dst, oh, ow))
+    }
+
+    fn pool_at(&self, src: &RgbImage, oy: usize, ox: usize, c: usize) -> u8 {
+        let w = src.width;
+        let (y0, x0) = (oy * self.stride, ox * self.stride);
+        let mut acc = 0u32;
+        for i in 0..K {
+            for j in 0..K {
+                let v = src.content()[((y0 + i) * w + x0 + j) * C + c] as u32;
+                acc = match self.ty {
+                    PoolType::Max => acc.max(v),
+                    PoolType::Avg => acc + v,
+                };
+            }
+        }
+        self.finish(acc)
+    }
+
+    // average is rounded half up
+    fn finish(&self, acc: u32) -> u8 {
+        match self.ty {
+            PoolType::Max => acc as u8,
+            PoolType::Avg => {
+                let n = (K * K) as u32;
+                ((acc + n / 2) / n) as u8
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::*;
+
+    // 4x4 image whose channels are (v, 255 - v, 10 * (v % 3))
+    fn small() -> Result<RgbImage> {
+        let values = [1u8, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+        let content = values
+            .iter()
+            .flat_map(|&v| [v, 255 - v, 10 * (v % 3)])
+            .collect();
+        RgbImage::from_raw(content, 4, 4)
+    }
+
+    #[test]
+    fn max_2x2() -> Result<()> {
+        let processed = PoolProcessor::<2>::new(PoolType::Max, 2)?.naive(&small()?)?;
+        assert_eq!((processed.height, processed.width), (2, 2));
+        #[rustfmt::skip]
+        assert_eq!(
+            processed.content(),
+            [
+                6, 254, 20,  8, 252, 20,
+                14, 246, 20, 16, 244, 20,
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn avg_3x3() -> Result<()> {
+        // sums of the windows are 54, 63, 90, 99 for the first channel
+        let processed = PoolProcessor::<3>::new(PoolType::Avg, 1)?.naive(&small()?)?;
+        assert_eq!((processed.height, processed.width), (2, 2));
+        let first: Vec<u8> = processed.content().iter().step_by(C).cloned().collect();
+        assert_eq!(first, [6, 7, 10, 11]);
+        // 2x2 average rounds half up: (1 + 2 + 5 + 6) / 4 = 3.5
+        let processed = PoolProcessor::<2>::new(PoolType::Avg, 2)?.naive(&small()?)?;
+        assert_eq!(processed.content()[0], 4);
+        Ok(())
+    }
+
+    #[test]
+    fn output_size() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let processed = PoolProcessor::<3>::new(PoolType::Max, 2)?.naive(&img)?;
+        assert_eq!(
+            (processed.height, processed.width),
+            ((img.height - 3) / 2 + 1, (img.width - 3) / 2 + 1)
+        );
+        let layer = PoolProcessor::<3>::new(PoolType::Max, 1)?;
+        assert_eq!(layer.output_size(3, 4)?, (1, 2));
+        assert!(matches!(
+            layer.output_size(2, 4),
+            Err(Error::ImageTooSmall {
+                height: 2,
+                width: 4,
+                min: 3
+            })
+        ));
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), all(target_feature = "neon")))]
+    #[test]
+    fn simd() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        // odd sized top-left part of the image to run remnants in serial
+        let (h, w) = (45, 101);
+        let content = (0..h)
+            .flat_map(|y| img.content()[y * img.width * C..(y * img.width + w) * C].to_vec())
+            .collect();
+        let cropped = RgbImage::from_raw(content, h, w)?;
+
+        for src in [&img, &cropped].iter() {
+            for &ty in [PoolType::Max, PoolType::Avg].iter() {
+                for stride in 1..4 {
+                    let layer = PoolProcessor::<2>::new(ty, stride)?;
+                    assert_eq!(
+                        layer.simd(src)?,
+                        layer.naive(src)?,
+                        "2x2 {:?} stride {}",
+                        ty,
+                        stride
+                    );
+                    let layer = PoolProcessor::<3>::new(ty, stride)?;
+                    assert_eq!(
+                        layer.simd(src)?,
+                        layer.naive(src)?,
+                        "3x3 {:?} stride {}",
+                        ty,
+                        stride
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
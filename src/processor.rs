@@ -0,0 +1,2019 @@
+//! Convolution on RGB images.
+
+use alloc::{string::ToString, vec, vec::Vec};
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+#[cfg(feature = "std")]
+use crate::autotune::{self, Tuner};
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd1"
+))]
+use crate::backend::Simd1;
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd2"
+))]
+use crate::backend::Simd2;
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd3"
+))]
+use crate::backend::Simd3;
+#[cfg(feature = "stats")]
+use crate::stats::{self, Stats};
+use crate::{
+    allocator::{ImageAllocator, ImageBuffer},
+    backend::{clear_border, select, ConvBackend, Naive1, Naive2},
+    buffers::BufferPool,
+    cancel::CancelToken,
+    error::{Error, Result},
+    image::RgbImage,
+    kernel::{ConvKernel, Rounding},
+    stream::{self, RowStream},
+    tile::convolve_tiles,
+    C,
+};
+
+/// How pixels closer than `K / 2` to the image border are computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BorderMode {
+    /// Border pixels are filled with 0.
+    Zero,
+    /// Pixels outside the image take the value of the nearest edge pixel (`aaa|abcd|ddd`).
+    Replicate,
+    /// Pixels outside the image are mirrored at the edge pixel (`dcb|abcd|cba`).
+    Reflect,
+}
+
+impl Default for BorderMode {
+    fn default() -> Self {
+        BorderMode::Zero
+    }
+}
+
+/// Order in which a [`ConvProcessor`] walks the tiles of images, see
+/// [`ConvProcessorBuilder::traversal`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Traversal {
+    /// Bands of rows from top to bottom, each from left to right.
+    Rows,
+    /// Columns of tiles from left to right, each from top to bottom, so that the `K - 1` source
+    /// rows shared by a tile and the next one are still in cache, e.g. for tall kernels. Without
+    /// a tile size, columns are strips of all the rows and 256 pixels.
+    Columns,
+}
+
+impl Default for Traversal {
+    fn default() -> Self {
+        Traversal::Rows
+    }
+}
+
+// width of the strips of `Traversal::Columns` without a tile size
+const STRIP_PIXELS: usize = 256;
+
+// stage of a convolution, timed with the `stats` feature
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Pad,
+    Convolve,
+    Copy,
+}
+
+// how rows of a convolution are distributed among threads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Split {
+    // contiguous chunks for the given number of scoped threads
+    Threads(usize),
+    // rows on the current Rayon pool
+    #[cfg(feature = "rayon")]
+    Rayon,
+}
+
+/// Backend, number of threads, tile size and traversal with which a [`ConvProcessor`] convolves images.
+///
+/// Chosen for each image size by measurement with
+/// [`ConvProcessorBuilder::autotune`], see [`ConvProcessor::tuning`].
+#[derive(Debug, Clone, Copy)]
+pub struct Tuning<const K: usize> {
+    /// Backend computing the rows.
+    pub backend: &'static dyn ConvBackend<K>,
+    /// Number of threads among which rows are split.
+    pub threads: usize,
+    /// `(height, width)` of tiles, or `None` for whole rows.
+    pub tile: Option<(usize, usize)>,
+    /// Order in which tiles are computed.
+    pub traversal: Traversal,
+}
+
+// buffers for the border modes other than `BorderMode::Zero`, reused among images and calls
+#[derive(Debug)]
+pub(crate) struct Scratch {
+    // source image with padding
+    padded: RgbImage,
+    // output rows of `padded`
+    rows: ImageBuffer,
+}
+
+impl Scratch {
+    pub(crate) fn new_in(allocator: ImageAllocator) -> Self {
+        Self {
+            padded: RgbImage::from_buffer_unchecked(Vec::new_in(allocator.clone()), 0, 0),
+            rows: Vec::new_in(allocator),
+        }
+    }
+}
+
+impl Default for Scratch {
+    fn default() -> Self {
+        Self::new_in(ImageAllocator::global())
+    }
+}
+
+/// Applies a KxK convolution to [`RgbImage`]s.
+///
+/// The actual computation is done by a [`ConvBackend`], and every backend gives the same result,
+/// except [`SimdWinograd`](crate::backend::SimdWinograd) which may differ by 1 on some pixels.
+/// Pixels closer than `K / 2` to the image border are computed according to [`BorderMode`].
+#[derive(Debug)]
+pub struct ConvProcessor<const K: usize> {
+    kernel: ConvKernel<K>,
+    border: BorderMode,
+    backend: Option<&'static dyn ConvBackend<K>>,
+    threads: usize,
+    cancel: Option<CancelToken>,
+    tile: Option<(usize, usize)>,
+    traversal: Traversal,
+    linear: bool,
+    luma: bool,
+    // scratch and output buffers reused among calls
+    buffers: BufferPool,
+    // best tuning for each image size if autotuning
+    #[cfg(feature = "std")]
+    tuned: Option<Mutex<HashMap<(usize, usize), Tuning<K>>>>,
+    #[cfg(feature = "std")]
+    tuner: Option<Arc<Tuner>>,
+    #[cfg(feature = "stats")]
+    stats: Mutex<Stats>,
+}
+
+impl<const K: usize> ConvProcessor<K> {
+    /// Creates a processor with a kernel made from `filter`, see [`ConvKernel::new`].
+    /// Other options take their default values, see [`ConvProcessorBuilder`].
+    pub fn new(filter: &[f32], avg: bool) -> Result<Self> {
+        Ok(Self::from_kernel(ConvKernel::<K>::new(filter, avg)?))
+    }
+
+    /// Creates a processor with an existing kernel.
+    pub fn from_kernel(kernel: ConvKernel<K>) -> Self {
+        Self {
+            kernel,
+            border: BorderMode::default(),
+            backend: None,
+            threads: 1,
+            cancel: None,
+            tile: None,
+            traversal: Traversal::default(),
+            linear: false,
+            luma: false,
+            buffers: BufferPool::default(),
+            #[cfg(feature = "std")]
+            tuned: None,
+            #[cfg(feature = "std")]
+            tuner: None,
+            #[cfg(feature = "stats")]
+            stats: Default::default(),
+        }
+    }
+
+    /// Starts configuring a processor.
+    pub fn builder() -> ConvProcessorBuilder<K> {
+        ConvProcessorBuilder::default()
+    }
+
+    /// Kernel applied by this processor.
+    pub fn kernel(&self) -> &ConvKernel<K> {
+        &self.kernel
+    }
+
+    /// How the border of images is computed.
+    pub fn border(&self) -> BorderMode {
+        self.border
+    }
+
+    /// Smallest height and width of the images this processor convolves: `K` with
+    /// [`BorderMode::Zero`], which leaves no pixel to convolve in smaller images, and 1 with
+    /// the other border modes, which pad images with as many pixels as needed.
+    pub fn min_size(&self) -> usize {
+        match self.border {
+            BorderMode::Zero => K,
+            _ => 1,
+        }
+    }
+
+    /// Number of threads used for a convolution.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// `(height, width)` of the tiles in which images are convolved, or `None` for whole rows.
+    pub fn tile_size(&self) -> Option<(usize, usize)> {
+        self.tile
+    }
+
+    /// Order in which tiles are computed, see [`ConvProcessorBuilder::traversal`].
+    pub fn traversal(&self) -> Traversal {
+        self.traversal
+    }
+
+    /// Allocator of the results and scratch buffers, see [`ConvProcessorBuilder::allocator`].
+    pub fn allocator(&self) -> &ImageAllocator {
+        self.buffers.allocator()
+    }
+
+    /// Whether images are convolved in linear light, see [`ConvProcessorBuilder::linear_light`].
+    pub fn linear_light(&self) -> bool {
+        self.linear
+    }
+
+    /// Whether only the luma of images is convolved, see [`ConvProcessorBuilder::luma_only`].
+    pub fn luma_only(&self) -> bool {
+        self.luma
+    }
+
+    /// Convolves `src` into a new image with `backend`, ignoring the configured backend.
+    ///
+    /// Fails with [`Error::ImageTooSmall`](crate::Error::ImageTooSmall) if `src` is smaller
+    /// than [`min_size`](Self::min_size).
+    pub fn apply<B: ConvBackend<K> + ?Sized>(
+        &self,
+        backend: &B,
+        src: &RgbImage,
+    ) -> Result<RgbImage> {
+        let split = Split::Threads(self.threads);
+        self.apply_split(backend, src, split, self.tile, self.traversal)
+    }
+
+    /// Same as [`apply`](Self::apply), but rows are computed on the current Rayon thread pool
+    /// regardless of [`threads`](Self::threads).
+    #[cfg(feature = "rayon")]
+    pub fn par_apply<B: ConvBackend<K> + ?Sized>(
+        &self,
+        backend: &B,
+        src: &RgbImage,
+    ) -> Result<RgbImage> {
+        self.apply_split(backend, src, Split::Rayon, self.tile, self.traversal)
+    }
+
+    fn apply_split<B: ConvBackend<K> + ?Sized>(
+        &self,
+        backend: &B,
+        src: &RgbImage,
+        split: Split,
+        tile: Option<(usize, usize)>,
+        traversal: Traversal,
+    ) -> Result<RgbImage> {
+        src.check_min_size(self.min_size())?;
+        let len = src.height * src.width * C;
+        let mut dst =
+            RgbImage::from_buffer_unchecked(self.buffers.output(len), src.height, src.width);
+        let result = self.with_scratch(|scratch| {
+            self.run(backend, src, &mut dst, split, tile, traversal, scratch)
+        });
+        match result {
+            Ok(()) => Ok(dst),
+            Err(e) => {
+                self.buffers.put_output(dst.inner);
+                Err(e)
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn run<B: ConvBackend<K> + ?Sized>(
+        &self,
+        backend: &B,
+        src: &RgbImage,
+        dst: &mut RgbImage,
+        split: Split,
+        tile: Option<(usize, usize)>,
+        traversal: Traversal,
+        scratch: &mut Scratch,
+    ) -> Result<()> {
+        src.check_min_size(self.min_size())?;
+        let (h, w) = (src.height, src.width);
+        if (dst.height, dst.width) != (h, w) {
+            return Err(Error::SizeMismatch {
+                expected: (h, w),
+                actual: (dst.height, dst.width),
+            });
+        }
+
+        #[cfg(feature = "stats")]
+        {
+            self.stats.lock().unwrap().images += 1;
+        }
+        if self.linear {
+            return self.timed(Stage::Convolve, || self.convolve_linear(src, dst));
+        }
+        if self.luma {
+            return self.timed(Stage::Convolve, || self.convolve_luma(src, dst));
+        }
+
+        let half = K / 2;
+        if self.border == BorderMode::Zero {
+            // dst may hold a previous result, and backends only write the inner part
+            self.timed(Stage::Pad, || clear_border(&mut dst.inner, h, w, half));
+            let row = w * C;
+            return self.timed(Stage::Convolve, || {
+                self.run_rows(
+                    backend,
+                    src,
+                    half..h - half,
+                    &mut dst.inner[half * row..(h - half) * row],
+                    split,
+                    tile,
+                    traversal,
+                )
+            });
+        }
+
+        // convolve the padded image, then drop the padding on both sides of each row
+        let Scratch { padded, rows } = scratch;
+        self.timed(Stage::Pad, || pad(src, half, self.border, padded));
+        let (row, padded_row) = (w * C, padded.width * C);
+        // every byte which is copied to dst is overwritten
+        rows.resize(h * padded_row, 0);
+        self.timed(Stage::Convolve, || {
+            self.run_rows(
+                backend,
+                padded,
+                half..h + half,
+                rows,
+                split,
+                tile,
+                traversal,
+            )
+        })?;
+        self.timed(Stage::Copy, || {
+            for (d, s) in dst
+                .inner
+                .chunks_exact_mut(row)
+                .zip(rows.chunks_exact(padded_row))
+            {
+                d.copy_from_slice(&s[half * C..half * C + row]);
+            }
+        });
+        Ok(())
+    }
+
+    // runs `f` with scratch buffers of the pool, and gives them back after
+    fn with_scratch<T>(&self, f: impl FnOnce(&mut Scratch) -> T) -> T {
+        let mut scratch = self.buffers.scratch();
+        let out = f(&mut scratch);
+        self.buffers.put_scratch(scratch);
+        out
+    }
+
+    // runs `f`, adding the time it takes to `stage` of the stats
+    #[cfg(feature = "stats")]
+    fn timed<T>(&self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        let start = std::time::Instant::now();
+        let out = f();
+        let elapsed = start.elapsed();
+        let mut stats = self.stats.lock().unwrap();
+        *match stage {
+            Stage::Pad => &mut stats.pad,
+            Stage::Convolve => &mut stats.convolve,
+            Stage::Copy => &mut stats.copy,
+        } += elapsed;
+        out
+    }
+
+    #[cfg(not(feature = "stats"))]
+    fn timed<T>(&self, _stage: Stage, f: impl FnOnce() -> T) -> T {
+        f()
+    }
+
+    // convolve `rows` of `src` into `dst`, checking the cancel token if any
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn run_rows<B: ConvBackend<K> + ?Sized>(
+        &self,
+        backend: &B,
+        src: &RgbImage,
+        rows: Range<usize>,
+        dst: &mut [u8],
+        split: Split,
+        tile: Option<(usize, usize)>,
+        traversal: Traversal,
+    ) -> Result<()> {
+        let row = src.width * C;
+        // returns the number of computed rows
+        let work = |rows: Range<usize>, dst: &mut [u8]| {
+            let tile = match (tile, traversal) {
+                (None, Traversal::Columns) => Some((rows.len(), STRIP_PIXELS)),
+                (tile, _) => tile,
+            };
+            match (&self.cancel, tile) {
+                (cancel, Some(tile)) => {
+                    let kernel = &self.kernel;
+                    convolve_tiles(
+                        backend,
+                        kernel,
+                        src,
+                        rows,
+                        dst,
+                        tile,
+                        traversal,
+                        cancel.as_ref(),
+                        self.allocator(),
+                    )
+                }
+                (None, None) => {
+                    backend.convolve_rows(&self.kernel, src, rows.clone(), dst);
+                    rows.len()
+                }
+                (Some(token), None) => {
+                    for (y, dst) in rows.clone().zip(dst.chunks_exact_mut(row)) {
+                        if token.is_cancelled() {
+                            return y - rows.start;
+                        }
+                        backend.convolve_rows(&self.kernel, src, y..y + 1, dst);
+                    }
+                    rows.len()
+                }
+            }
+        };
+
+        // adds what `work` counted on the current thread to the stats
+        #[cfg(feature = "stats")]
+        let work = |rows: Range<usize>, dst: &mut [u8]| {
+            stats::take();
+            let completed_rows = work(rows, dst);
+            *self.stats.lock().unwrap() += stats::take();
+            completed_rows
+        };
+
+        let total = rows.len();
+        // rows computed together, which are not split among threads
+        let unit = tile.map_or(1, |(h, _)| h);
+        let completed_rows = match split {
+            Split::Threads(threads) => Self::split_threads(&work, rows, dst, row, unit, threads),
+            #[cfg(feature = "rayon")]
+            Split::Rayon => {
+                use rayon::prelude::*;
+
+                // one unit per item, which Rayon groups adaptively
+                dst.par_chunks_mut(unit * row)
+                    .enumerate()
+                    .map(|(i, dst)| {
+                        let start = rows.start + i * unit;
+                        work(start..start + dst.len() / row, dst)
+                    })
+                    .sum()
+            }
+        };
+
+        if completed_rows < total {
+            return Err(Error::Cancelled { completed_rows });
+        }
+        Ok(())
+    }
+
+    // split rows into contiguous chunks per thread, returning the number of computed rows
+    #[cfg(feature = "std")]
+    fn split_threads<F>(
+        work: &F,
+        rows: Range<usize>,
+        dst: &mut [u8],
+        row: usize,
+        unit: usize,
+        threads: usize,
+    ) -> usize
+    where
+        F: Fn(Range<usize>, &mut [u8]) -> usize + Sync,
+    {
+        let threads = threads.min(rows.len()).max(1);
+        if threads == 1 {
+            return work(rows, dst);
+        }
+        // multiple of `unit` rows
+        let chunk = (rows.len() + threads * unit - 1) / (threads * unit) * unit;
+        thread::scope(|s| {
+            // spawn all threads before joining any
+            #[allow(clippy::needless_collect)]
+            let handles: Vec<_> = dst
+                .chunks_mut(chunk * row)
+                .enumerate()
+                .map(|(i, dst)| {
+                    let start = rows.start + i * chunk;
+                    let rows = start..start + dst.len() / row;
+                    s.spawn(move |_| work(rows, dst))
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).sum()
+        })
+    }
+
+    // rows are computed on the calling thread without std
+    #[cfg(not(feature = "std"))]
+    fn split_threads<F>(
+        work: &F,
+        rows: Range<usize>,
+        dst: &mut [u8],
+        _row: usize,
+        _unit: usize,
+        _threads: usize,
+    ) -> usize
+    where
+        F: Fn(Range<usize>, &mut [u8]) -> usize + Sync,
+    {
+        work(rows, dst)
+    }
+
+    /// Same as `apply(&Naive1, src)`, see [`Naive1`].
+    pub fn naive1(&self, src: &RgbImage) -> Result<RgbImage> {
+        self.apply(&Naive1, src)
+    }
+
+    /// Same as `apply(&Naive2, src)`, see [`Naive2`].
+    pub fn naive2(&self, src: &RgbImage) -> Result<RgbImage> {
+        self.apply(&Naive2, src)
+    }
+
+    /// Same as `apply(&Simd1, src)`, see [`Simd1`].
+    #[cfg(all(
+        any(target_arch = "aarch64"),
+        target_feature = "neon",
+        feature = "impl-simd1"
+    ))]
+    pub fn simd1(&self, src: &RgbImage) -> Result<RgbImage> {
+        self.apply(&Simd1, src)
+    }
+
+    /// Same as `par_apply(&Naive1, src)`, see [`Naive1`].
+    #[cfg(feature = "rayon")]
+    pub fn par_naive1(&self, src: &RgbImage) -> Result<RgbImage> {
+        self.par_apply(&Naive1, src)
+    }
+
+    /// Same as `par_apply(&Naive2, src)`, see [`Naive2`].
+    #[cfg(feature = "rayon")]
+    pub fn par_naive2(&self, src: &RgbImage) -> Result<RgbImage> {
+        self.par_apply(&Naive2, src)
+    }
+
+    /// Same as `par_apply(&Simd1, src)`, see [`Simd1`].
+    #[cfg(all(
+        feature = "rayon",
+        any(target_arch = "aarch64"),
+        target_feature = "neon",
+        feature = "impl-simd1"
+    ))]
+    pub fn par_simd1(&self, src: &RgbImage) -> Result<RgbImage> {
+        self.par_apply(&Simd1, src)
+    }
+}
+
+impl<const K: usize> ConvProcessor<K>
+where
+    [(); (K / 2 + 1) / 2 + 1]: Sized,
+{
+    /// Convolves `src` into a new image with the configured backend, or with the fastest
+    /// backend for the target and the width of `src` (see [`select`]) if not configured.
+    ///
+    /// Fails with [`Error::ImageTooSmall`](crate::Error::ImageTooSmall) if `src` is smaller
+    /// than [`min_size`](Self::min_size).
+    ///
+    /// With [`ConvProcessorBuilder::autotune`], the first image of each size is also used to
+    /// measure the candidate backends, thread counts, tile sizes and traversals.
+    pub fn convolve(&self, src: &RgbImage) -> Result<RgbImage> {
+        let t = self.tuning_for(src)?;
+        let split = Split::Threads(t.threads);
+        self.apply_split(t.backend, src, split, t.tile, t.traversal)
+    }
+
+    /// Same as [`convolve`](Self::convolve), but writes into the existing buffer of `dst`.
+    /// It does not allocate unless the border mode is other than [`BorderMode::Zero`].
+    ///
+    /// Fails with [`Error::SizeMismatch`](crate::Error::SizeMismatch) if `dst` does not have
+    /// the same size as `src`.
+    pub fn convolve_into(&self, src: &RgbImage, dst: &mut RgbImage) -> Result<()> {
+        let t = self.tuning_for(src)?;
+        let split = Split::Threads(t.threads);
+        self.with_scratch(|scratch| {
+            self.run(t.backend, src, dst, split, t.tile, t.traversal, scratch)
+        })
+    }
+
+    /// Gives the buffer of `img`, e.g. a result of [`convolve`](Self::convolve) which is no
+    /// longer needed, back to the processor: the next [`convolve`](Self::convolve) or
+    /// [`convolve_batch`](Self::convolve_batch) of an image as large or smaller takes it instead
+    /// of allocating. The processor keeps the few largest buffers of its
+    /// [`allocator`](Self::allocator) (none without `std`), and reuses its scratch buffers for
+    /// borders other than [`BorderMode::Zero`] by itself.
+    pub fn recycle(&self, img: RgbImage) {
+        self.buffers.put_output(img.inner);
+    }
+
+    /// Convolves every image of `srcs` like [`convolve`](Self::convolve), e.g. many small
+    /// thumbnails for which the cost of a call matters.
+    ///
+    /// Images are split among [`threads`](Self::threads) threads, and rows of each image among
+    /// the remaining threads if there are fewer images than threads. Buffers for the border
+    /// are reused among images. Fails if any image is smaller than [`min_size`](Self::min_size),
+    /// in which case nothing is computed.
+    pub fn convolve_batch(&self, srcs: &[&RgbImage]) -> Result<Vec<RgbImage>> {
+        let mut dsts: Vec<_> = srcs
+            .iter()
+            .map(|src| {
+                let len = src.height * src.width * C;
+                RgbImage::from_buffer_unchecked(self.buffers.output(len), src.height, src.width)
+            })
+            .collect();
+        if let Err(e) = self.convolve_batch_into(srcs, &mut dsts) {
+            for dst in dsts {
+                self.buffers.put_output(dst.inner);
+            }
+            return Err(e);
+        }
+        Ok(dsts)
+    }
+
+    /// Same as [`convolve_batch`](Self::convolve_batch), but writes into the existing buffers
+    /// of `dsts`, e.g. those of the previous batch.
+    ///
+    /// Fails with [`Error::SizeMismatch`] if `dsts` does not have as many images as `srcs`
+    /// (the sizes are given as `(len, 1)` then) or any image of `dsts` does not have the same
+    /// size as the corresponding one of `srcs`.
+    pub fn convolve_batch_into(&self, srcs: &[&RgbImage], dsts: &mut [RgbImage]) -> Result<()> {
+        if srcs.len() != dsts.len() {
+            return Err(Error::SizeMismatch {
+                expected: (srcs.len(), 1),
+                actual: (dsts.len(), 1),
+            });
+        }
+        // look up tunings beforehand, so that autotuning measures without other work running
+        let tunings = srcs
+            .iter()
+            .zip(dsts.iter())
+            .map(|(src, dst)| {
+                src.check_min_size(self.min_size())?;
+                if (dst.height, dst.width) != (src.height, src.width) {
+                    return Err(Error::SizeMismatch {
+                        expected: (src.height, src.width),
+                        actual: (dst.height, dst.width),
+                    });
+                }
+                self.tuning_for(src)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let groups = self.threads.min(srcs.len()).max(1);
+        let row_threads = (self.threads / groups).max(1);
+        let work = |srcs: &[&RgbImage], tunings: &[Tuning<K>], dsts: &mut [RgbImage]| {
+            self.with_scratch(|scratch| {
+                for ((src, t), dst) in srcs.iter().zip(tunings).zip(dsts) {
+                    let split = Split::Threads(t.threads.min(row_threads));
+                    self.run(t.backend, src, dst, split, t.tile, t.traversal, scratch)?;
+                }
+                Ok(())
+            })
+        };
+
+        #[cfg(feature = "std")]
+        if groups > 1 {
+            let chunk = (srcs.len() + groups - 1) / groups;
+            let work = &work;
+            return thread::scope(|s| {
+                // spawn all threads before joining any
+                #[allow(clippy::needless_collect)]
+                let handles: Vec<_> = srcs
+                    .chunks(chunk)
+                    .zip(tunings.chunks(chunk))
+                    .zip(dsts.chunks_mut(chunk))
+                    .map(|((srcs, tunings), dsts)| s.spawn(move |_| work(srcs, tunings, dsts)))
+                    .collect();
+                handles.into_iter().try_for_each(|h| h.join().unwrap())
+            });
+        }
+        work(srcs, &tunings, dsts)
+    }
+
+    /// Same as [`convolve`](Self::convolve), but rows are computed on the current Rayon
+    /// thread pool regardless of [`threads`](Self::threads).
+    #[cfg(feature = "rayon")]
+    pub fn par_convolve(&self, src: &RgbImage) -> Result<RgbImage> {
+        self.par_apply(self.backend_for(src.width), src)
+    }
+
+    /// Same as [`convolve_into`](Self::convolve_into), but rows are computed on the current
+    /// Rayon thread pool regardless of [`threads`](Self::threads).
+    #[cfg(feature = "rayon")]
+    pub fn par_convolve_into(&self, src: &RgbImage, dst: &mut RgbImage) -> Result<()> {
+        let backend = self.backend_for(src.width);
+        let (tile, traversal) = (self.tile, self.traversal);
+        self.with_scratch(|scratch| {
+            self.run(backend, src, dst, Split::Rayon, tile, traversal, scratch)
+        })
+    }
+
+    /// Convolves an image given as an iterator of rows with `width` pixels, yielding output rows
+    /// as soon as they can be computed. See [`RowStream`] for details.
+    pub fn stream_rows<I, R>(&self, rows: I, width: usize) -> RowStream<'_, K, I::IntoIter>
+    where
+        I: IntoIterator<Item = R>,
+        R: AsRef<[u8]>,
+    {
+        RowStream::new(self, self.backend_for(width), rows.into_iter(), width)
+    }
+
+    /// Convolves an image of `height` x `width` pixels which does not fit in memory, e.g. a
+    /// gigapixel scan, band by band: `read(y, buf)` fills `buf` with the source rows from `y`
+    /// (a whole number of rows of `width * 3` bytes), and `write(y, rows)` receives the output
+    /// rows from `y`, `band` rows at a time (fewer for the last band, and `band` is clamped to
+    /// `1..=height`).
+    ///
+    /// Only `band + K - 1` source rows and `band` output rows are held, and every source row is
+    /// read once, in order. The result is the same as that of [`convolve`](Self::convolve), with
+    /// the threads, tiles and cancel token of the processor applied to each band.
+    ///
+    /// Fails with [`Error::ImageTooSmall`] if the image is smaller than
+    /// [`min_size`](Self::min_size), with [`Error::UnsupportedFormat`] for processors in
+    /// [linear light](ConvProcessorBuilder::linear_light) or
+    /// [on the luma only](ConvProcessorBuilder::luma_only), and with the errors of `read` and
+    /// `write`, in which case the bands written so far are left as they are.
+    pub fn convolve_bands<R, W>(
+        &self,
+        height: usize,
+        width: usize,
+        band: usize,
+        read: R,
+        write: W,
+    ) -> Result<()>
+    where
+        R: FnMut(usize, &mut [u8]) -> Result<()>,
+        W: FnMut(usize, &[u8]) -> Result<()>,
+    {
+        let backend = self.backend_for(width);
+        stream::convolve_bands(self, backend, (height, width), band, read, write)
+    }
+
+    pub(crate) fn backend_for(&self, width: usize) -> &'static dyn ConvBackend<K> {
+        self.backend.unwrap_or_else(|| select::<K>(width))
+    }
+
+    /// Counters and timings of the convolutions by this processor since it was created or
+    /// [`reset_stats`](Self::reset_stats) was called, with the `stats` feature.
+    ///
+    /// Rows convolved by [`stream_rows`](Self::stream_rows) are not counted, while the
+    /// measurements of [`ConvProcessorBuilder::autotune`] are.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Sets every counter and timing of [`stats`](Self::stats) to 0.
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&self) {
+        *self.stats.lock().unwrap() = Stats::default();
+    }
+
+    /// Tuning found for images of `height` x `width` pixels, or `None` if no such image has
+    /// been convolved or autotuning is not enabled.
+    #[cfg(feature = "std")]
+    pub fn tuning(&self, height: usize, width: usize) -> Option<Tuning<K>> {
+        let tuned = self.tuned.as_ref()?.lock().unwrap();
+        tuned.get(&(height, width)).copied()
+    }
+
+    // configured tuning, or the measured one for the size of `src` if autotuning
+    pub(crate) fn tuning_for(&self, src: &RgbImage) -> Result<Tuning<K>> {
+        #[cfg(feature = "std")]
+        if let Some(tuned) = &self.tuned {
+            // held while measuring, so that other threads neither measure again nor disturb it
+            let mut tuned = tuned.lock().unwrap();
+            let size = (src.height, src.width);
+            if let Some(t) = tuned.get(&size) {
+                return Ok(*t);
+            }
+            let t = self.tune(src)?;
+            tuned.insert(size, t);
+            return Ok(t);
+        }
+        // the tuner measures on `src` itself, which padded images may be too small for
+        #[cfg(feature = "std")]
+        if let (None, Some(tuner), true) =
+            (self.backend, &self.tuner, src.check_min_size(K).is_ok())
+        {
+            return Ok(Tuning {
+                backend: tuner.backend(&self.kernel, src)?,
+                threads: self.threads,
+                tile: self.tile,
+                traversal: self.traversal,
+            });
+        }
+        Ok(Tuning {
+            backend: self.backend_for(src.width),
+            threads: self.threads,
+            tile: self.tile,
+            traversal: self.traversal,
+        })
+    }
+
+    // fastest candidate on the first rows of `src`
+    #[cfg(feature = "std")]
+    pub(crate) fn tune(&self, src: &RgbImage) -> Result<Tuning<K>> {
+        src.check_min_size(self.min_size())?;
+        let rows = src.height.min(autotune::SAMPLE_ROWS);
+        let len = rows * src.width * C;
+        let sample = RgbImage::from_raw_unchecked(src.inner[..len].to_vec(), rows, src.width);
+        let mut dst = RgbImage::from_raw_unchecked(vec![0; len], rows, src.width);
+        let mut scratch = Scratch::new_in(self.allocator().clone());
+
+        let backends = match self.backend {
+            Some(backend) => vec![backend],
+            None => autotune::backends::<K>(src.width),
+        };
+        autotune::fastest(autotune::candidates(&backends, src.width), |t| {
+            let split = Split::Threads(t.threads);
+            self.run(
+                t.backend,
+                &sample,
+                &mut dst,
+                split,
+                t.tile,
+                t.traversal,
+                &mut scratch,
+            )
+        })
+    }
+}
+
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd2"
+))]
+impl<const K: usize> ConvProcessor<K>
+where
+    [(); (K / 2 + 1) / 2 + 1]: Sized,
+{
+    /// Same as `apply(&Simd2, src)`, see [`Simd2`].
+    pub fn simd2(&self, src: &RgbImage) -> Result<RgbImage> {
+        self.apply(&Simd2, src)
+    }
+
+    /// Same as `par_apply(&Simd2, src)`, see [`Simd2`].
+    #[cfg(feature = "rayon")]
+    pub fn par_simd2(&self, src: &RgbImage) -> Result<RgbImage> {
+        self.par_apply(&Simd2, src)
+    }
+}
+
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    feature = "impl-simd3"
+))]
+impl<const K: usize> ConvProcessor<K> {
+    /// Same as `apply(&Simd3, src)`, see [`Simd3`].
+    pub fn simd3(&self, src: &RgbImage) -> Result<RgbImage> {
+        self.apply(&Simd3, src)
+    }
+
+    /// Same as `par_apply(&Simd3, src)`, see [`Simd3`].
+    #[cfg(feature = "rayon")]
+    pub fn par_simd3(&self, src: &RgbImage) -> Result<RgbImage> {
+        self.par_apply(&Simd3, src)
+    }
+}
+
+/// Builder of [`ConvProcessor`], created by [`ConvProcessor::builder`].
+#[derive(Debug)]
+pub struct ConvProcessorBuilder<const K: usize> {
+    filter: Option<Vec<f32>>,
+    normalize: bool,
+    rounding: Rounding,
+    border: BorderMode,
+    backend: Option<&'static dyn ConvBackend<K>>,
+    threads: usize,
+    cancel: Option<CancelToken>,
+    tile: Option<(usize, usize)>,
+    traversal: Traversal,
+    allocator: ImageAllocator,
+    linear: bool,
+    luma: bool,
+    autotune: bool,
+    #[cfg(feature = "std")]
+    tuner: Option<Arc<Tuner>>,
+}
+
+impl<const K: usize> Default for ConvProcessorBuilder<K> {
+    fn default() -> Self {
+        Self {
+            filter: None,
+            normalize: false,
+            rounding: Rounding::default(),
+            border: BorderMode::default(),
+            backend: None,
+            threads: 1,
+            cancel: None,
+            tile: None,
+            traversal: Traversal::default(),
+            allocator: ImageAllocator::global(),
+            linear: false,
+            luma: false,
+            autotune: false,
+            #[cfg(feature = "std")]
+            tuner: None,
+        }
+    }
+}
+
+impl<const K: usize> ConvProcessorBuilder<K> {
+    /// `K * K` weights in row-major order, which must be given.
+    pub fn kernel(mut self, filter: &[f32]) -> Self {
+        self.filter = Some(filter.to_vec());
+        self
+    }
+
+    /// Whether results are divided by the sum of the weights (`false` by default).
+    pub fn normalize(mut self, normalize: bool) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    /// How results are converted to `u8` ([`Rounding::Truncate`] by default), see
+    /// [`ConvKernel::with_rounding`].
+    pub fn rounding(mut self, rounding: Rounding) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    /// How the border of images is computed ([`BorderMode::Zero`] by default).
+    pub fn border(mut self, border: BorderMode) -> Self {
+        self.border = border;
+        self
+    }
+
+    /// Backend used by [`ConvProcessor::convolve`], selected for each image by default.
+    pub fn backend(mut self, backend: &'static dyn ConvBackend<K>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Number of threads among which rows are split (1 by default).
+    /// 0 means the number of available CPUs.
+    ///
+    /// Ignored without the `std` feature, where rows are computed on the calling thread.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Convolves images in tiles of `height` rows and `width` pixels instead of whole rows
+    /// (not set by default), which keeps the source rows of a tile in cache for very wide images.
+    /// Threads take bands of tiles rather than rows. Zero sizes are taken as 1.
+    pub fn tile_size(mut self, height: usize, width: usize) -> Self {
+        self.tile = Some((height, width));
+        self
+    }
+
+    /// Order in which tiles are computed ([`Traversal::Rows`] by default), to tune the memory
+    /// access pattern to the shape of images and kernels. Results are the same either way.
+    ///
+    /// Images are not transposed for [`Traversal::Columns`]: backends would sum the weights in
+    /// another order then, and round some results differently.
+    pub fn traversal(mut self, traversal: Traversal) -> Self {
+        self.traversal = traversal;
+        self
+    }
+
+    /// Allocator of the images returned by [`ConvProcessor::convolve`] and
+    /// [`ConvProcessor::convolve_batch`], of the padded copies of images for borders other
+    /// than [`BorderMode::Zero`], of tiles and of the buffers of a
+    /// [`FrameProcessor`](crate::video::FrameProcessor) (the global one by default), e.g. to keep
+    /// frames in a pinned region.
+    pub fn allocator(mut self, allocator: ImageAllocator) -> Self {
+        self.allocator = allocator;
+        self
+    }
+
+    /// Whether sRGB-encoded images are decoded to linear light before convolving them and
+    /// encoded back after (`false` by default), see [`srgb`](crate::srgb). Blurring encoded
+    /// values darkens the edges between bright and dark areas.
+    ///
+    /// Convolutions then run on the current thread in `f32` like
+    /// [`ConvProcessor::convolve_f32`], whatever the backend, threads and tiles.
+    pub fn linear_light(mut self, linear: bool) -> Self {
+        self.linear = linear;
+        self
+    }
+
+    /// Whether only the luma Y of images in [`YCbCr`](crate::ycbcr) is convolved, keeping their
+    /// chroma (`false` by default), e.g. to sharpen without colored fringes. It is a third of
+    /// the values, plus the conversions.
+    ///
+    /// Convolutions then run on the current thread like [`ConvProcessor::convolve_samples`],
+    /// whatever the backend, threads and tiles. With [`BorderMode::Zero`], only the luma of the
+    /// border is 0. Ignored with [`linear_light`](Self::linear_light).
+    pub fn luma_only(mut self, luma: bool) -> Self {
+        self.luma = luma;
+        self
+    }
+
+    /// Whether [`ConvProcessor::convolve`] and [`ConvProcessor::convolve_into`] measure the
+    /// combinations of backend, number of threads, tile size and traversal on the first image of
+    /// each size, and use the fastest one for images of that size afterwards (`false` by default).
+    ///
+    /// [`threads`](Self::threads), [`tile_size`](Self::tile_size) and
+    /// [`traversal`](Self::traversal) are ignored then, and
+    /// [`backend`](Self::backend) limits the candidates to that backend. The chosen
+    /// [`Tuning`] is returned by [`ConvProcessor::tuning`].
+    ///
+    /// Ignored without the `std` feature.
+    pub fn autotune(mut self, autotune: bool) -> Self {
+        self.autotune = autotune;
+        self
+    }
+
+    /// Tuner measuring which backend is the fastest on the first image of each size (not set by
+    /// default), shared with other processors so that each size is measured once, see [`Tuner`].
+    ///
+    /// Unlike [`autotune`](Self::autotune), only the backend is measured, and
+    /// [`threads`](Self::threads) and [`tile_size`](Self::tile_size) still apply.
+    /// Ignored if [`backend`](Self::backend) or [`autotune`](Self::autotune) is set.
+    #[cfg(feature = "std")]
+    pub fn tuner(mut self, tuner: Arc<Tuner>) -> Self {
+        self.tuner = Some(tuner);
+        self
+    }
+
+    /// Token checked before computing each row (each band with [`tile_size`](Self::tile_size)),
+    /// not set by default.
+    ///
+    /// Convolutions fail with [`Error::Cancelled`] once the token is cancelled. The rows of
+    /// `dst` given to [`ConvProcessor::convolve_into`] are left partially computed then
+    /// (only for [`BorderMode::Zero`], other modes leave `dst` untouched).
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Creates the processor.
+    ///
+    /// Fails with [`Error::BadKernel`] if the kernel is not given or invalid, see [`ConvKernel::new`].
+    pub fn build(self) -> Result<ConvProcessor<K>> {
+        let filter = self
+            .filter
+            .ok_or_else(|| Error::BadKernel("kernel is not given".to_string()))?;
+        let threads = resolve_threads(self.threads);
+        Ok(ConvProcessor {
+            kernel: ConvKernel::<K>::new(&filter, self.normalize)?.with_rounding(self.rounding),
+            border: self.border,
+            backend: self.backend,
+            threads,
+            cancel: self.cancel,
+            tile: self.tile.map(|(h, w)| (h.max(1), w.max(1))),
+            traversal: self.traversal,
+            linear: self.linear,
+            luma: self.luma,
+            buffers: BufferPool::new(self.allocator),
+            #[cfg(feature = "std")]
+            tuned: self.autotune.then(Default::default),
+            #[cfg(feature = "std")]
+            tuner: self.tuner,
+            #[cfg(feature = "stats")]
+            stats: Default::default(),
+        })
+    }
+}
+
+// number of threads to use for the option `threads`, where 0 means the number of CPUs
+#[cfg(feature = "std")]
+pub(crate) fn resolve_threads(threads: usize) -> usize {
+    match threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+}
+
+// everything runs on the calling thread without std
+#[cfg(not(feature = "std"))]
+pub(crate) fn resolve_threads(_threads: usize) -> usize {
+    1
+}
+
+// index in an axis of length `n` for the coordinate `p`, which may be out of the image
+pub(crate) fn border_index(p: isize, n: usize, border: BorderMode) -> usize {
+    let last = n as isize - 1;
+    let p = match border {
+        BorderMode::Replicate => p.clamp(0, last),
+        // mirrored again at the other edge if the axis is shorter than the padding
+        BorderMode::Reflect if last == 0 => 0,
+        BorderMode::Reflect => {
+            let p = p.rem_euclid(2 * last);
+            if p > last {
+                2 * last - p
+            } else {
+                p
+            }
+        }
+        BorderMode::Zero => p,
+    };
+    p as usize
+}
+
+// interleaved values of `h` x `w` pixels of `channels` values with `half` pixels of padding on
+// every side, e.g. `f32` or `u16` images or single planes, which `pad` does not handle
+pub(crate) fn pad_values<T: Copy + Default>(
+    src: &[T],
+    h: usize,
+    w: usize,
+    channels: usize,
+    half: usize,
+    border: BorderMode,
+) -> Vec<T> {
+    let pw = w + 2 * half;
+    let mut dst = vec![T::default(); (h + 2 * half) * pw * channels];
+    for (py, row) in dst.chunks_exact_mut(pw * channels).enumerate() {
+        let sy = border_index(py as isize - half as isize, h, border);
+        for (px, pixel) in row.chunks_exact_mut(channels).enumerate() {
+            let sx = border_index(px as isize - half as isize, w, border);
+            pixel.copy_from_slice(&src[(sy * w + sx) * channels..][..channels]);
+        }
+    }
+    dst
+}
+
+// copy a row of pixels into `dst` with `half` pixels of padding on both sides, 0 with
+// `BorderMode::Zero`
+pub(crate) fn pad_row(row: &[u8], half: usize, border: BorderMode, dst: &mut [u8]) {
+    let w = row.len() / C;
+    dst[half * C..(half + w) * C].copy_from_slice(row);
+    for x in (0..half).chain(half + w..dst.len() / C) {
+        let pix = &mut dst[x * C..(x + 1) * C];
+        if border == BorderMode::Zero {
+            pix.fill(0);
+        } else {
+            let sx = border_index(x as isize - half as isize, w, border);
+            pix.copy_from_slice(&row[sx * C..(sx + 1) * C]);
+        }
+    }
+}
+
+// copy `src` into `dst`, resized to `half` pixels of padding on each side
+pub(crate) fn pad(src: &RgbImage, half: usize, border: BorderMode, dst: &mut RgbImage) {
+    let (h, w) = (src.height, src.width);
+    let (ph, pw) = (h + 2 * half, w + 2 * half);
+    dst.inner.resize(ph * pw * C, 0);
+    dst.height = ph;
+    dst.width = pw;
+    if pw == 0 {
+        return;
+    }
+    for (y, dst) in dst.inner.chunks_exact_mut(pw * C).enumerate() {
+        let y = y as isize - half as isize;
+        if border == BorderMode::Zero && !(0..h as isize).contains(&y) {
+            dst.fill(0);
+            continue;
+        }
+        let sy = border_index(y, h, border);
+        pad_row(&src.inner[sy * w * C..(sy + 1) * w * C], half, border, dst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{
+        backend::available,
+        consts::*,
+        util::test_util::{test, Check, FilterType},
+    };
+
+    // check filters for every available backend
+    // use macro here due to test multiple constant generic parameter
+    macro_rules! check {
+        ($($k:literal)*) => {{
+            for &ty in [ $(FilterType::Box($k),)* FilterType::Sobel,].iter() {
+                match ty.size() {
+                    $(
+                        $k => {
+                            for backend in available::<$k>() {
+                                test(None, Check::InMemory, ty, backend)?;
+                            }
+                        }
+                    )*
+                    _ => unreachable!(),
+                }
+            }
+            Ok(())
+        }};
+    }
+
+    #[test]
+    fn backends() -> Result<()> {
+        // you can specify which size of kernels are tested by adding odd numbers inside check!()
+        check!(3 5 7 9 11 13 15 17 19)
+    }
+
+    // kernel columns 4.. used to be loaded from the pixels of the columns 1.., which shows on
+    // K >= 9 whatever the width
+    #[cfg(all(
+        any(target_arch = "aarch64"),
+        target_feature = "neon",
+        feature = "impl-simd3"
+    ))]
+    #[test]
+    fn simd3_odd_width() -> Result<()> {
+        let (h, w) = (23, 45);
+        let content = (0..h * w * C).map(|i| (i * 7 % 251) as u8).collect();
+        let img = RgbImage::from_raw(content, h, w)?;
+        macro_rules! check {
+            ($($k:literal)*) => {$(
+                let layer = ConvProcessor::<$k>::new(&[1.; $k * $k], true)?;
+                assert_eq!(layer.simd3(&img)?, layer.naive2(&img)?, "K = {}", $k);
+            )*};
+        }
+        check!(9 11 13 15 17 19);
+        Ok(())
+    }
+
+    #[test]
+    fn convolve() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let layer = ConvProcessor::<5>::new(&[1.; 25], true)?;
+        assert_eq!(layer.convolve(&img)?, layer.naive2(&img)?);
+
+        // too narrow for 16 pixels at once
+        let content = (0..img.height)
+            .flat_map(|y| img.content()[y * img.width * C..(y * img.width + 19) * C].to_vec())
+            .collect();
+        let narrow = RgbImage::from_raw(content, img.height, 19)?;
+        assert_eq!(layer.convolve(&narrow)?, layer.naive2(&narrow)?);
+        Ok(())
+    }
+
+    // flat regions sum up to multiples of the kernel sum, e.g. 121 for 1 with K = 11, which
+    // multiplying by the reciprocal of 121 alone gets 1 ulp below, truncated to 0
+    #[test]
+    fn flat_box() -> Result<()> {
+        let layer = ConvProcessor::<11>::builder()
+            .kernel(&[1.; 121])
+            .normalize(true)
+            .border(BorderMode::Replicate)
+            .build()?;
+        for v in 0..=255 {
+            let img = RgbImage::from_raw(vec![v; 13 * 45 * C], 13, 45)?;
+            for backend in available::<11>() {
+                let processed = layer.apply(backend, &img)?;
+                assert!(processed == img, "{} on {}", backend.name(), v);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rounding() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let (h, w) = (img.height, img.width);
+        // average of the pixel and its right neighbor, which is a tie for odd sums
+        let mut pair = [0.; 9];
+        pair[4] = 1.;
+        pair[5] = 1.;
+        for (rounding, round) in [
+            (Rounding::Truncate, (|s| s / 2) as fn(u32) -> u32),
+            (Rounding::Nearest, |s| (s + 1) / 2),
+        ] {
+            let mut expected = vec![0; h * w * C];
+            for y in 1..h - 1 {
+                for x in 1..w - 1 {
+                    for c in 0..C {
+                        let i = (y * w + x) * C + c;
+                        let sum = img.inner[i] as u32 + img.inner[i + C] as u32;
+                        expected[i] = round(sum) as u8;
+                    }
+                }
+            }
+            let expected = RgbImage::from_raw(expected, h, w)?;
+            let layer = ConvProcessor::<3>::builder()
+                .kernel(&pair)
+                .normalize(true)
+                .rounding(rounding)
+                .build()?;
+            assert_eq!(layer.kernel().rounding(), rounding);
+            for backend in available::<3>() {
+                let processed = layer.apply(backend, &img)?;
+                assert!(
+                    processed == expected,
+                    "{} with {:?}",
+                    backend.name(),
+                    rounding
+                );
+            }
+
+            // divided by a sum other than a power of 2
+            let layer = ConvProcessor::<3>::builder()
+                .kernel(&[1.; 9])
+                .normalize(true)
+                .rounding(rounding)
+                .build()?;
+            let expected = layer.naive2(&img)?;
+            for backend in available::<3>() {
+                let processed = layer.apply(backend, &img)?;
+                assert!(
+                    processed == expected,
+                    "{} with {:?}",
+                    backend.name(),
+                    rounding
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn convolve_into() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        // dirty buffer as if reused from the previous frame
+        let mut dst = RgbImage::from_raw(vec![255; img.content().len()], img.height, img.width)?;
+        let layer = ConvProcessor::<5>::new(&[1.; 25], true)?;
+        layer.convolve_into(&img, &mut dst)?;
+        assert_eq!(dst, layer.naive2(&img)?);
+
+        // narrower border than the previous call
+        let layer = ConvProcessor::<3>::new(&SOBEL_FILTER, false)?;
+        layer.convolve_into(&img, &mut dst)?;
+        assert_eq!(dst, layer.naive2(&img)?);
+
+        let mut small = RgbImage::from_raw(vec![0; 4 * 4 * C], 4, 4)?;
+        assert!(matches!(
+            layer.convolve_into(&img, &mut small),
+            Err(Error::SizeMismatch { actual: (4, 4), .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn recycle() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let layer = ConvProcessor::<5>::builder()
+            .kernel(&[1.; 25])
+            .normalize(true)
+            .border(BorderMode::Reflect)
+            .build()?;
+        let expected = layer.convolve(&img)?;
+        // the padded image is kept for the next call
+        assert!(layer.buffers.scratch().padded.inner.capacity() > 0);
+
+        // dirty buffer of a previous result
+        let old = RgbImage::from_raw(vec![255; img.content().len()], img.height, img.width)?;
+        let ptr = old.content().as_ptr();
+        layer.recycle(old);
+        let dst = layer.convolve(&img)?;
+        assert_eq!(dst.content().as_ptr(), ptr);
+        assert_eq!(dst, expected);
+
+        // given back on errors
+        let layer = ConvProcessor::<5>::new(&[1.; 25], true)?;
+        layer.recycle(dst);
+        let tiny = RgbImage::from_raw(vec![0; 2 * 2 * C], 2, 2)?;
+        assert!(layer.convolve_batch(&[&img, &tiny]).is_err());
+        assert_eq!(layer.convolve(&img)?.content().as_ptr(), ptr);
+        Ok(())
+    }
+
+    #[test]
+    fn allocator() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let allocator = ImageAllocator::new(alloc::alloc::Global);
+        let layer = ConvProcessor::<5>::builder()
+            .kernel(&[1.; 25])
+            .normalize(true)
+            .border(BorderMode::Reflect)
+            .allocator(allocator.clone())
+            .build()?;
+        assert_eq!(*layer.allocator(), allocator);
+        let expected = ConvProcessor::<5>::builder()
+            .kernel(&[1.; 25])
+            .normalize(true)
+            .border(BorderMode::Reflect)
+            .build()?
+            .convolve(&img)?;
+
+        let dst = layer.convolve(&img)?;
+        assert_eq!(*dst.allocator(), allocator);
+        assert_eq!(dst, expected);
+        let scratch = layer.buffers.scratch();
+        assert_eq!(*scratch.padded.allocator(), allocator);
+        assert_eq!(*scratch.rows.allocator(), allocator);
+        for dst in layer.convolve_batch(&[&img, &img])? {
+            assert_eq!(*dst.allocator(), allocator);
+        }
+
+        // sources in any allocator
+        let mut src = RgbImage::zeroed_in(img.height, img.width, allocator.clone());
+        src.content_mut().copy_from_slice(img.content());
+        assert_eq!(layer.convolve(&src)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn builder() -> Result<()> {
+        assert!(matches!(
+            ConvProcessor::<3>::builder().build(),
+            Err(Error::BadKernel(_))
+        ));
+        assert!(matches!(
+            ConvProcessor::<3>::builder()
+                .kernel(&[0.; 9])
+                .normalize(true)
+                .build(),
+            Err(Error::BadKernel(_))
+        ));
+
+        let layer = ConvProcessor::<3>::builder()
+            .kernel(&[1.; 9])
+            .normalize(true)
+            .border(BorderMode::Reflect)
+            .backend(&Naive1)
+            .threads(0)
+            .build()?;
+        assert_eq!(layer.kernel().div(), Some(9.));
+        assert_eq!(layer.border(), BorderMode::Reflect);
+        assert!(layer.threads() >= 1);
+        Ok(())
+    }
+
+    // straightforward convolution reading pixels outside the image through `map`
+    fn reference<const K: usize>(
+        kernel: &ConvKernel<K>,
+        src: &RgbImage,
+        map: impl Fn(isize, usize) -> usize,
+    ) -> RgbImage {
+        let (h, w) = (src.height, src.width);
+        let half = (K / 2) as isize;
+        let mut dst = vec![0u8; h * w * C];
+        for y in 0..h {
+            for x in 0..w {
+                let mut rgb = [0f32; C];
+                for i in 0..K {
+                    for j in 0..K {
+                        let sy = map(y as isize - half + i as isize, h);
+                        let sx = map(x as isize - half + j as isize, w);
+                        for (c, pix) in rgb.iter_mut().enumerate() {
+                            let v = src.inner[(sy * w + sx) * C + c] as f32;
+                            *pix = crate::kernel::fma(v, kernel.at(i, j), *pix);
+                        }
+                    }
+                }
+                for (c, &t) in rgb.iter().enumerate() {
+                    let t = kernel.div().map_or(t, |div| t / div);
+                    dst[(y * w + x) * C + c] = kernel.rounding().to_u8(t);
+                }
+            }
+        }
+        RgbImage::from_raw_unchecked(dst, h, w)
+    }
+
+    #[test]
+    fn border() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let build = |border| {
+            ConvProcessor::<5>::builder()
+                .kernel(&[1.; 25])
+                .normalize(true)
+                .border(border)
+                .build()
+        };
+
+        let replicate = |p: isize, n: usize| p.clamp(0, n as isize - 1) as usize;
+        let expected = reference(build(BorderMode::Replicate)?.kernel(), &img, replicate);
+        assert_eq!(build(BorderMode::Replicate)?.convolve(&img)?, expected);
+
+        let reflect = |p: isize, n: usize| {
+            let last = n as isize - 1;
+            (if p < 0 {
+                -p
+            } else if p > last {
+                2 * last - p
+            } else {
+                p
+            }) as usize
+        };
+        let expected = reference(build(BorderMode::Reflect)?.kernel(), &img, reflect);
+        assert_eq!(build(BorderMode::Reflect)?.convolve(&img)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn small_images() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        // mirrored as many times as needed
+        let reflect = |mut p: isize, n: usize| {
+            let last = n as isize - 1;
+            if last == 0 {
+                return 0;
+            }
+            loop {
+                if p < 0 {
+                    p = -p;
+                } else if p > last {
+                    p = 2 * last - p;
+                } else {
+                    return p as usize;
+                }
+            }
+        };
+        let replicate = |p: isize, n: usize| p.clamp(0, n as isize - 1) as usize;
+        let build = |border| {
+            ConvProcessor::<5>::builder()
+                .kernel(&[1.; 25])
+                .normalize(true)
+                .border(border)
+                .build()
+        };
+        for &(h, w) in [(1, 1), (1, 7), (5, 4), (4, 5), (2, 3), (5, 5)].iter() {
+            let content = (0..h)
+                .flat_map(|y| img.content()[y * img.width * C..(y * img.width + w) * C].to_vec())
+                .collect();
+            let small = RgbImage::from_raw(content, h, w)?;
+
+            let zero = build(BorderMode::Zero)?;
+            if h < 5 || w < 5 {
+                assert!(matches!(
+                    zero.convolve(&small),
+                    Err(Error::ImageTooSmall { min: 5, .. })
+                ));
+            } else {
+                assert_eq!(zero.convolve(&small)?, zero.naive2(&small)?);
+            }
+
+            let layer = build(BorderMode::Replicate)?;
+            let expected = reference(layer.kernel(), &small, replicate);
+            for backend in available::<5>() {
+                assert!(
+                    layer.apply(backend, &small)? == expected,
+                    "{} on {}x{}",
+                    backend.name(),
+                    h,
+                    w
+                );
+            }
+            let layer = build(BorderMode::Reflect)?;
+            let expected = reference(layer.kernel(), &small, reflect);
+            for backend in available::<5>() {
+                assert!(
+                    layer.apply(backend, &small)? == expected,
+                    "{} on {}x{}",
+                    backend.name(),
+                    h,
+                    w
+                );
+            }
+            let mut dst = RgbImage::from_raw(vec![0; h * w * C], h, w)?;
+            layer.convolve_into(&small, &mut dst)?;
+            assert_eq!(dst, expected);
+        }
+
+        let empty = RgbImage::from_raw(vec![], 0, 0)?;
+        for &border in [BorderMode::Zero, BorderMode::Replicate, BorderMode::Reflect].iter() {
+            assert!(matches!(
+                build(border)?.convolve(&empty),
+                Err(Error::ImageTooSmall {
+                    height: 0,
+                    width: 0,
+                    ..
+                })
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn threads() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        // more threads than rows
+        let tiny = RgbImage::from_raw(img.content()[..7 * 7 * C].to_vec(), 7, 7)?;
+        for &border in [BorderMode::Zero, BorderMode::Reflect].iter() {
+            let build = |threads| {
+                ConvProcessor::<5>::builder()
+                    .kernel(&[1.; 25])
+                    .normalize(true)
+                    .border(border)
+                    .threads(threads)
+                    .build()
+            };
+            for src in [&img, &tiny].iter() {
+                let expected = build(1)?.convolve(src)?;
+                for threads in [2, 3, 16].iter() {
+                    assert_eq!(build(*threads)?.convolve(src)?, expected);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tiles() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        for &border in [BorderMode::Zero, BorderMode::Reflect].iter() {
+            let build = |tile: Option<(usize, usize)>, traversal, threads| {
+                let builder = ConvProcessor::<5>::builder()
+                    .kernel(&[1.; 25])
+                    .normalize(true)
+                    .border(border)
+                    .traversal(traversal)
+                    .threads(threads);
+                match tile {
+                    Some((h, w)) => builder.tile_size(h, w),
+                    None => builder,
+                }
+                .build()
+            };
+            let expected = build(None, Traversal::Rows, 1)?.convolve(&img)?;
+            let layer = build(None, Traversal::Columns, 3)?;
+            assert_eq!(layer.traversal(), Traversal::Columns);
+            assert_eq!(layer.convolve(&img)?, expected);
+            for &tile in [(0, 0), (16, 24), (64, 512), (1000, 1000)].iter() {
+                for &traversal in [Traversal::Rows, Traversal::Columns].iter() {
+                    let layer = build(Some(tile), traversal, 3)?;
+                    assert_eq!(
+                        layer.convolve(&img)?,
+                        expected,
+                        "{:?} {:?}",
+                        tile,
+                        traversal
+                    );
+                }
+            }
+        }
+
+        let token = CancelToken::new();
+        token.cancel();
+        let layer = ConvProcessor::<3>::builder()
+            .kernel(&SOBEL_FILTER)
+            .tile_size(8, 8)
+            .cancel_token(token)
+            .build()?;
+        assert!(matches!(
+            layer.convolve(&img),
+            Err(Error::Cancelled { completed_rows: 0 })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn batch() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let row = img.width * C;
+        // images of different sizes
+        let mut srcs = vec![];
+        for h in [5, 17, 64, img.height].iter() {
+            srcs.push(RgbImage::from_raw(
+                img.content()[..h * row].to_vec(),
+                *h,
+                img.width,
+            )?);
+        }
+        srcs.push(RgbImage::from_raw(
+            img.content()[..7 * 7 * C].to_vec(),
+            7,
+            7,
+        )?);
+        let srcs: Vec<_> = srcs.iter().collect();
+
+        for &border in [BorderMode::Zero, BorderMode::Reflect].iter() {
+            for &threads in [1, 3, 16].iter() {
+                let layer = ConvProcessor::<5>::builder()
+                    .kernel(&[1.; 25])
+                    .normalize(true)
+                    .border(border)
+                    .threads(threads)
+                    .build()?;
+                let dsts = layer.convolve_batch(&srcs)?;
+                assert_eq!(dsts.len(), srcs.len());
+                for (src, dst) in srcs.iter().zip(dsts.iter()) {
+                    assert_eq!(*dst, layer.naive2(src)?);
+                }
+                // dirty buffers of the previous batch
+                let mut again = dsts;
+                for dst in again.iter_mut() {
+                    dst.content_mut().fill(255);
+                }
+                layer.convolve_batch_into(&srcs, &mut again)?;
+                for (src, dst) in srcs.iter().zip(again.iter()) {
+                    assert_eq!(*dst, layer.naive2(src)?);
+                }
+            }
+        }
+
+        let layer = ConvProcessor::<5>::new(&[1.; 25], true)?;
+        assert!(layer.convolve_batch(&[])?.is_empty());
+        let tiny = RgbImage::from_raw(vec![0; 2 * 2 * C], 2, 2)?;
+        assert!(matches!(
+            layer.convolve_batch(&[&img, &tiny]),
+            Err(Error::ImageTooSmall { .. })
+        ));
+        let mut dsts = vec![RgbImage::from_raw(vec![0; row], 1, img.width)?];
+        assert!(matches!(
+            layer.convolve_batch_into(&[&img, &img], &mut dsts),
+            Err(Error::SizeMismatch {
+                expected: (2, 1),
+                actual: (1, 1)
+            })
+        ));
+        assert!(matches!(
+            layer.convolve_batch_into(&[&img], &mut dsts),
+            Err(Error::SizeMismatch { actual: (1, _), .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn autotune() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let build = |backend: Option<&'static dyn ConvBackend<5>>| {
+            let builder = ConvProcessor::<5>::builder()
+                .kernel(&[1.; 25])
+                .normalize(true)
+                .border(BorderMode::Replicate)
+                .autotune(true);
+            match backend {
+                Some(backend) => builder.backend(backend),
+                None => builder,
+            }
+            .build()
+        };
+
+        let layer = build(None)?;
+        let expected = layer.naive2(&img)?;
+        assert!(layer.tuning(img.height, img.width).is_none());
+        assert_eq!(layer.convolve(&img)?, expected);
+        let tuning = layer.tuning(img.height, img.width).unwrap();
+        assert!(tuning.threads >= 1);
+        // reused for the next image of the same size
+        let mut dst = RgbImage::from_raw(vec![0; img.content().len()], img.height, img.width)?;
+        layer.convolve_into(&img, &mut dst)?;
+        assert_eq!(dst, expected);
+        let again = layer.tuning(img.height, img.width).unwrap();
+        assert_eq!(again.backend.name(), tuning.backend.name());
+        assert_eq!((again.threads, again.tile), (tuning.threads, tuning.tile));
+        assert_eq!(again.traversal, tuning.traversal);
+
+        let layer = build(Some(&Naive1))?;
+        assert_eq!(layer.convolve(&img)?, expected);
+        assert_eq!(
+            layer.tuning(img.height, img.width).unwrap().backend.name(),
+            "naive1"
+        );
+
+        // padded images smaller than the kernel are measured like others
+        let tiny = RgbImage::from_raw(vec![7; 2 * 2 * C], 2, 2)?;
+        assert_eq!(layer.convolve(&tiny)?, layer.naive2(&tiny)?);
+        assert!(layer.tuning(2, 2).is_some());
+        let empty = RgbImage::from_raw(vec![], 0, 0)?;
+        assert!(matches!(
+            layer.convolve(&empty),
+            Err(Error::ImageTooSmall { .. })
+        ));
+        assert!(layer.tuning(0, 0).is_none());
+        assert!(ConvProcessor::<5>::new(&[1.; 25], true)?
+            .tuning(img.height, img.width)
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn tuner() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let tuner = Arc::new(Tuner::new());
+        let box3 = ConvProcessor::<3>::builder()
+            .kernel(&[1.; 9])
+            .normalize(true)
+            .tuner(Arc::clone(&tuner))
+            .build()?;
+        let box5 = ConvProcessor::<5>::builder()
+            .kernel(&[1.; 25])
+            .normalize(true)
+            .threads(2)
+            .tuner(Arc::clone(&tuner))
+            .build()?;
+
+        assert!(tuner.chosen(img.height, img.width, 3).is_none());
+        assert_eq!(box3.convolve(&img)?, box3.naive2(&img)?);
+        let chosen = tuner.chosen(img.height, img.width, 3).unwrap();
+        assert!(autotune::backends::<3>(img.width)
+            .iter()
+            .any(|b| b.name() == chosen));
+        // measured separately for each kernel size, and reused afterwards
+        assert!(tuner.chosen(img.height, img.width, 5).is_none());
+        assert_eq!(box5.convolve(&img)?, box5.naive2(&img)?);
+        assert!(tuner.chosen(img.height, img.width, 5).is_some());
+        assert_eq!(box3.convolve(&img)?, box3.naive2(&img)?);
+        assert_eq!(tuner.chosen(img.height, img.width, 3), Some(chosen));
+
+        let tiny = RgbImage::from_raw(vec![0; 2 * 2 * C], 2, 2)?;
+        assert!(matches!(
+            box5.convolve(&tiny),
+            Err(Error::ImageTooSmall { .. })
+        ));
+        assert!(tuner.chosen(2, 2, 5).is_none());
+        Ok(())
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn stats() -> Result<()> {
+        use std::time::Duration;
+
+        let img = RgbImage::load(ORIGINAL)?;
+        let inner = ((img.height - 4) * (img.width - 4)) as u64;
+        let layer = ConvProcessor::<5>::builder()
+            .kernel(&[1.; 25])
+            .normalize(true)
+            .backend(&Naive2)
+            .threads(3)
+            .build()?;
+        assert_eq!(layer.stats(), Stats::default());
+        layer.convolve(&img)?;
+        let stats = layer.stats();
+        assert_eq!(stats.images, 1);
+        // counted on every thread
+        assert_eq!((stats.peel_pixels, stats.simd_pixels), (inner, 0));
+        assert_eq!((stats.loads, stats.tiles), (0, 0));
+        assert!(stats.convolve > Duration::ZERO);
+
+        layer.reset_stats();
+        assert_eq!(layer.stats(), Stats::default());
+        layer.apply(&Naive1, &img)?;
+        layer.apply(&Naive1, &img)?;
+        assert_eq!(layer.stats().images, 2);
+        assert_eq!(layer.stats().peel_pixels, 2 * inner);
+
+        // padded images have `K - 1` more rows and pixels per row to convolve
+        let layer = ConvProcessor::<5>::builder()
+            .kernel(&[1.; 25])
+            .border(BorderMode::Reflect)
+            .tile_size(64, 100)
+            .build()?;
+        layer.convolve(&img)?;
+        let stats = layer.stats();
+        let tiles = (img.height + 63) / 64 * ((img.width + 99) / 100);
+        assert_eq!(stats.tiles, tiles as u64);
+        // overlapping vectors compute some pixels twice
+        assert!(stats.peel_pixels + stats.simd_pixels >= (img.height * img.width) as u64);
+
+        #[cfg(all(
+            any(target_arch = "aarch64"),
+            target_feature = "neon",
+            feature = "impl-simd3"
+        ))]
+        {
+            let layer = ConvProcessor::<5>::builder()
+                .kernel(&[1.; 25])
+                .backend(&Simd3)
+                .build()?;
+            layer.convolve(&img)?;
+            let stats = layer.stats();
+            assert_eq!(stats.peel_pixels, 0);
+            assert!(stats.simd_pixels >= inner);
+            assert!(stats.loads > 0);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        for &border in [BorderMode::Zero, BorderMode::Replicate].iter() {
+            let layer = ConvProcessor::<7>::builder()
+                .kernel(&[1.; 49])
+                .normalize(true)
+                .border(border)
+                .build()?;
+            let expected = layer.naive2(&img)?;
+            for backend in available::<7>() {
+                assert_eq!(layer.par_apply(backend, &img)?, expected, "{:?}", backend);
+            }
+            assert_eq!(layer.par_convolve(&img)?, expected);
+            let mut dst = RgbImage::from_raw(vec![0; img.content().len()], img.height, img.width)?;
+            layer.par_convolve_into(&img, &mut dst)?;
+            assert_eq!(dst, expected);
+        }
+
+        let token = CancelToken::new();
+        token.cancel();
+        let layer = ConvProcessor::<3>::builder()
+            .kernel(&SOBEL_FILTER)
+            .cancel_token(token)
+            .build()?;
+        assert!(matches!(
+            layer.par_convolve(&img),
+            Err(Error::Cancelled { completed_rows: 0 })
+        ));
+        Ok(())
+    }
+
+    // Naive2 cancelling the token after computing `rows` rows
+    #[derive(Debug)]
+    struct CancelAfter {
+        token: CancelToken,
+        rows: usize,
+        computed: std::sync::atomic::AtomicUsize,
+    }
+
+    impl ConvBackend<3> for CancelAfter {
+        fn name(&self) -> &'static str {
+            "cancel_after"
+        }
+
+        fn convolve_rows(
+            &self,
+            kernel: &ConvKernel<3>,
+            src: &RgbImage,
+            rows: Range<usize>,
+            dst: &mut [u8],
+        ) {
+            use std::sync::atomic::Ordering;
+            let computed = self.computed.fetch_add(rows.len(), Ordering::Relaxed) + rows.len();
+            if computed >= self.rows {
+                self.token.cancel();
+            }
+            Naive2.convolve_rows(kernel, src, rows, dst);
+        }
+    }
+
+    #[test]
+    fn cancel() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let expected = ConvProcessor::<3>::new(&SOBEL_FILTER, false)?.naive2(&img)?;
+        let token = CancelToken::new();
+        let build = |threads| {
+            ConvProcessor::<3>::builder()
+                .kernel(&SOBEL_FILTER)
+                .threads(threads)
+                .cancel_token(token.clone())
+                .build()
+        };
+
+        token.cancel();
+        assert!(matches!(
+            build(1)?.convolve(&img),
+            Err(Error::Cancelled { completed_rows: 0 })
+        ));
+        token.reset();
+        assert_eq!(build(1)?.convolve(&img)?, expected);
+
+        let backend = CancelAfter {
+            token: token.clone(),
+            rows: 10,
+            computed: Default::default(),
+        };
+        let mut dst = RgbImage::from_raw(vec![0; img.content().len()], img.height, img.width)?;
+        assert!(matches!(
+            build(1)?.run(
+                &backend,
+                &img,
+                &mut dst,
+                Split::Threads(1),
+                None,
+                Traversal::Rows,
+                &mut Scratch::default()
+            ),
+            Err(Error::Cancelled { completed_rows: 10 })
+        ));
+        // rows computed so far are left in dst
+        let row = img.width * C;
+        assert_eq!(dst.content()[..11 * row], expected.content()[..11 * row]);
+        assert!(dst.content()[11 * row..].iter().all(|&v| v == 0));
+
+        token.reset();
+        let backend = CancelAfter {
+            token: token.clone(),
+            rows: 10,
+            computed: Default::default(),
+        };
+        match build(4)?.apply(&backend, &img) {
+            Err(Error::Cancelled { completed_rows }) => {
+                assert!(completed_rows < img.height - 2)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        Ok(())
+    }
+}
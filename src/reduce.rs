@@ -0,0 +1,304 @@
+//! Reductions of whole images to one value per channel, e.g. for auto-normalization, exposure
+//! metering, or checking in tests that filters preserve energy.
+//!
+//! Sums, minimums and maximums are computed together in a single pass, 16 pixels at a time with
+//! NEON: channels are deinterleaved, added pairwise into wider lanes and compared lane by lane, so
+//! only the final vectors are reduced horizontally.
+//!
+//! [`RgbImage::stats`] gives all the statistics of each channel with its histogram, e.g. for
+//! auto-contrast or normalization. Its single pass deinterleaves 16 pixels at a time with NEON and
+//! counts the values in 4 histograms per channel, so that runs of the same value do not wait for
+//! the previous increment of their bin, and the moments, minimum and maximum are then computed
+//! exactly from the 256 bins.
+
+use alloc::vec;
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{error::Result, image::RgbImage, kernel::sqrt, C};
+
+/// Statistics of the values of a channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelStats {
+    /// Mean value.
+    pub mean: f32,
+    /// Standard deviation of the values.
+    pub std_dev: f32,
+    /// Smallest value.
+    pub min: u8,
+    /// Largest value.
+    pub max: u8,
+    /// Number of pixels with each value.
+    pub histogram: [u64; 256],
+}
+
+// vectors of 16 pixels added up in 32-bit lanes before moving their sums to `u64`: a lane gains
+// at most 4 * 255 per vector
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+const FLUSH: usize = 1 << 16;
+
+impl RgbImage {
+    /// Sum of the values of each channel.
+    pub fn channel_sums(&self) -> [u64; C] {
+        reduce(self).0
+    }
+
+    /// Mean of the values of each channel.
+    ///
+    /// Fails with [`Error::ImageTooSmall`](crate::Error::ImageTooSmall) if the image is empty.
+    pub fn channel_means(&self) -> Result<[f32; C]> {
+        self.check_min_size(1)?;
+        let pixels = (self.height * self.width) as f64;
+        Ok(reduce(self).0.map(|sum| (sum as f64 / pixels) as f32))
+    }
+
+    /// Smallest value of each channel.
+    ///
+    /// Fails with [`Error::ImageTooSmall`](crate::Error::ImageTooSmall) if the image is empty.
+    pub fn channel_min(&self) -> Result<[u8; C]> {
+        self.check_min_size(1)?;
+        Ok(reduce(self).1)
+    }
+
+    /// Largest value of each channel.
+    ///
+    /// Fails with [`Error::ImageTooSmall`](crate::Error::ImageTooSmall) if the image is empty.
+    pub fn channel_max(&self) -> Result<[u8; C]> {
+        self.check_min_size(1)?;
+        Ok(reduce(self).2)
+    }
+
+    /// Mean, standard deviation, minimum, maximum and histogram of each channel, in one pass.
+    ///
+    /// Fails with [`Error::ImageTooSmall`](crate::Error::ImageTooSmall) if the image is empty.
+    pub fn stats(&self) -> Result<[ChannelStats; C]> {
+        self.check_min_size(1)?;
+        let pixels = (self.height * self.width) as f64;
+        Ok(histograms(&self.inner).map(|histogram| {
+            let (mut sum, mut squares) = (0, 0);
+            for (v, &n) in histogram.iter().enumerate() {
+                sum += v as u64 * n;
+                squares += (v * v) as u64 * n;
+            }
+            let mean = sum as f64 / pixels;
+            let variance = (squares as f64 / pixels - mean * mean).max(0.);
+            ChannelStats {
+                mean: mean as f32,
+                std_dev: sqrt(variance as f32),
+                min: histogram.iter().position(|&n| n > 0).unwrap_or(0) as u8,
+                max: histogram.iter().rposition(|&n| n > 0).unwrap_or(0) as u8,
+                histogram,
+            }
+        }))
+    }
+}
+
+// histogram of each channel of the pixels of `bytes`
+pub(crate) fn histograms(bytes: &[u8]) -> [[u64; 256]; C] {
+    // 4 tables per channel, for the pixels at each index modulo 4
+    let mut tables = vec![[0u64; 256]; 4 * C];
+    // pixels from `start` are left to the serial loop
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let start = {
+        let end = bytes.len() - bytes.len() % (16 * C);
+        let mut planes = [[0; 16]; C];
+        for i in (0..end).step_by(16 * C) {
+            let v = simd::deinterleave16(bytes, i);
+            for (plane, &v) in planes.iter_mut().zip(v.iter()) {
+                simd::store_bytes16(plane, 0, v);
+            }
+            for (c, plane) in planes.iter().enumerate() {
+                for (k, &v) in plane.iter().enumerate() {
+                    tables[4 * c + k % 4][v as usize] += 1;
+                }
+            }
+        }
+        end / C
+    };
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    let start = 0;
+    for (k, pixel) in bytes[start * C..].chunks_exact(C).enumerate() {
+        for (c, &v) in pixel.iter().enumerate() {
+            tables[4 * c + k % 4][v as usize] += 1;
+        }
+    }
+    let mut histograms = [[0; 256]; C];
+    for (histogram, tables) in histograms.iter_mut().zip(tables.chunks_exact(4)) {
+        for table in tables {
+            for (n, &t) in histogram.iter_mut().zip(table.iter()) {
+                *n += t;
+            }
+        }
+    }
+    histograms
+}
+
+// histogram of `values`, counted in 4 tables like those of `histograms`
+pub(crate) fn histogram(values: &[u8]) -> [u64; 256] {
+    let mut tables = [[0u64; 256]; 4];
+    for (k, &v) in values.iter().enumerate() {
+        tables[k % 4][v as usize] += 1;
+    }
+    let mut histogram = [0; 256];
+    for table in tables.iter() {
+        for (n, &t) in histogram.iter_mut().zip(table.iter()) {
+            *n += t;
+        }
+    }
+    histogram
+}
+
+// sums, minimums and maximums of each channel of `img`, minimums of 255 and maximums of 0 if it
+// is empty
+fn reduce(img: &RgbImage) -> ([u64; C], [u8; C], [u8; C]) {
+    let bytes = &img.inner[..];
+    let mut sums = [0; C];
+    let mut mins = [u8::MAX; C];
+    let mut maxs = [0; C];
+    // pixels from `start` are left to the serial loop
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let start = unsafe {
+        let end = bytes.len() - bytes.len() % (16 * C);
+        let mut vmins = [vdupq_n_u8(u8::MAX); C];
+        let mut vmaxs = [vdupq_n_u8(0); C];
+        for chunk in (0..end).step_by(FLUSH * 16 * C) {
+            let mut accs = [vdupq_n_u32(0); C];
+            for i in (chunk..end.min(chunk + FLUSH * 16 * C)).step_by(16 * C) {
+                let v = simd::deinterleave16(bytes, i);
+                for (c, &v) in v.iter().enumerate() {
+                    accs[c] = vpadalq_u16(accs[c], vpaddlq_u8(v));
+                    vmins[c] = vminq_u8(vmins[c], v);
+                    vmaxs[c] = vmaxq_u8(vmaxs[c], v);
+                }
+            }
+            for (sum, &acc) in sums.iter_mut().zip(accs.iter()) {
+                *sum += vaddlvq_u32(acc);
+            }
+        }
+        for c in 0..C {
+            mins[c] = vminvq_u8(vmins[c]);
+            maxs[c] = vmaxvq_u8(vmaxs[c]);
+        }
+        end
+    };
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    let start = 0;
+    for pixel in bytes[start..].chunks_exact(C) {
+        for (c, &v) in pixel.iter().enumerate() {
+            sums[c] += v as u64;
+            mins[c] = mins[c].min(v);
+            maxs[c] = maxs[c].max(v);
+        }
+    }
+    (sums, mins, maxs)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::{
+        error::Error,
+        processor::{BorderMode, ConvProcessor},
+        test_util::photo_image,
+    };
+
+    #[test]
+    fn reductions() -> Result<()> {
+        // vectors of 16 pixels, and some left over
+        let img = photo_image(11, 37, 26);
+        for c in 0..C {
+            let values = || img.content().iter().skip(c).step_by(C).copied();
+            let sum: u64 = values().map(|v| v as u64).sum();
+            assert_eq!(img.channel_sums()[c], sum);
+            assert_eq!(img.channel_means()?[c], (sum as f64 / 407.) as f32);
+            assert_eq!(img.channel_min()?[c], values().min().unwrap());
+            assert_eq!(img.channel_max()?[c], values().max().unwrap());
+        }
+
+        let pixel = RgbImage::from_raw(vec![7, 0, 255], 1, 1)?;
+        assert_eq!(pixel.channel_sums(), [7, 0, 255]);
+        assert_eq!(pixel.channel_min()?, pixel.channel_max()?);
+        let empty = RgbImage::from_raw(vec![], 4, 0)?;
+        assert_eq!(empty.channel_sums(), [0; C]);
+        for result in [empty.channel_min(), empty.channel_max()] {
+            assert!(matches!(result, Err(Error::ImageTooSmall { .. })));
+        }
+        assert!(empty.channel_means().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn stats() -> Result<()> {
+        // vectors of 16 pixels, and some left over
+        let img = photo_image(13, 37, 45);
+        let stats = img.stats()?;
+        for (c, stats) in stats.iter().enumerate() {
+            let values: Vec<f64> = img
+                .content()
+                .iter()
+                .skip(c)
+                .step_by(C)
+                .map(|&v| v as f64)
+                .collect();
+            let n = values.len() as f64;
+            let mean = values.iter().sum::<f64>() / n;
+            let variance = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / n;
+            assert_eq!(stats.mean, img.channel_means()?[c]);
+            assert!((stats.std_dev - variance.sqrt() as f32).abs() < 1e-4);
+            assert_eq!(stats.min, img.channel_min()?[c]);
+            assert_eq!(stats.max, img.channel_max()?[c]);
+            for v in 0..256 {
+                let count = values.iter().filter(|&&x| x == v as f64).count();
+                assert_eq!(stats.histogram[v], count as u64);
+            }
+        }
+
+        // flat channels, and half black half white
+        let img = RgbImage::from_raw([9, 0, 255, 9, 255, 255].repeat(20), 4, 10)?;
+        let [r, g, b] = img.stats()?;
+        assert_eq!((r.mean, r.std_dev, r.min, r.max), (9., 0., 9, 9));
+        assert_eq!((g.mean, g.std_dev, g.min, g.max), (127.5, 127.5, 0, 255));
+        assert_eq!(
+            (g.histogram[0], g.histogram[255], b.histogram[255]),
+            (20, 20, 40)
+        );
+        assert!(matches!(
+            RgbImage::from_raw(vec![], 0, 3)?.stats(),
+            Err(Error::ImageTooSmall { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn energy() -> Result<()> {
+        // a normalized blur moves values between neighbours but keeps their mean, up to rounding
+        // and to the pixels reflected at the border
+        let img = photo_image(32, 48, 27);
+        let blur = ConvProcessor::<5>::builder()
+            .kernel(&[1.; 25])
+            .normalize(true)
+            .border(BorderMode::Reflect)
+            .build()?;
+        let (before, after) = (img.channel_means()?, blur.convolve(&img)?.channel_means()?);
+        for (b, a) in before.iter().zip(&after) {
+            assert!((b - a).abs() < 1., "{:?} {:?}", before, after);
+        }
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    #[test]
+    fn simd() -> Result<()> {
+        // white pixels over several flushes of the 32-bit lanes, with some left over
+        let pixels = 2 * FLUSH * 16 + 5;
+        let img = RgbImage::from_raw(vec![255; pixels * C], 1, pixels)?;
+        assert_eq!(img.channel_sums(), [255 * pixels as u64; C]);
+        assert_eq!(img.channel_min()?, [255; C]);
+        assert_eq!(img.stats()?[2].histogram[255], pixels as u64);
+        Ok(())
+    }
+}
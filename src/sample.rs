@@ -0,0 +1,305 @@
+//! Scalar types of samples, over which [`ConvProcessor::convolve_samples`] is generic.
+//!
+//! [`Sample`] is implemented for `u8` ([`RgbImage`](crate::RgbImage)), `u16`
+//! ([`RgbImage16`](crate::RgbImage16)) and `f32` ([`RgbImageF32`](crate::RgbImageF32)) samples,
+//! which are accumulated in `f32` and converted back with the [`Rounding`] of the kernel, 8 at a
+//! time with NEON. [`ConvProcessor::convolve_u16`] and [`ConvProcessor::convolve_f32`] are built
+//! on it, while [`ConvProcessor::convolve`] keeps the backends specialized for `u8`.
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+use core::{fmt::Debug, ops::Range};
+
+use alloc::{vec, vec::Vec};
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{
+    error::{Error, Result},
+    kernel::{fma, ConvKernel, Rounding},
+    processor::{pad_values, BorderMode, ConvProcessor},
+    C,
+};
+
+/// Scalar of the samples of an image, accumulated in `f32` by convolutions.
+///
+/// It is sealed: the NEON loads and stores of each type are private to the crate.
+pub trait Sample:
+    sealed::Sealed + Copy + Default + Debug + PartialOrd + Send + Sync + 'static
+{
+    /// Largest value, to which results are saturated unless a smaller one is given.
+    const MAX: Self;
+
+    /// Value in the accumulator.
+    fn to_f32(self) -> f32;
+
+    /// Result `t` converted with `rounding` and saturated to `[0, max]`. Floating-point samples
+    /// keep `t` as it is.
+    fn from_f32(t: f32, rounding: Rounding, max: Self) -> Self;
+}
+
+mod sealed {
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    use core::arch::aarch64::float32x4_t;
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    use crate::kernel::Rounding;
+
+    // NEON loads and stores of 8 samples from and to the lanes of 2 vectors, like
+    // `Sample::to_f32` and `Sample::from_f32`
+    pub trait Sealed: Sized {
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        fn load8(src: &[Self], index: usize) -> [float32x4_t; 2];
+
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        fn store8(
+            dst: &mut [Self],
+            index: usize,
+            v: &[float32x4_t; 2],
+            rounding: Rounding,
+            max: Self,
+        );
+    }
+}
+
+impl Sample for u8 {
+    const MAX: Self = u8::MAX;
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn from_f32(t: f32, rounding: Rounding, max: Self) -> Self {
+        rounding.to_u8(t).min(max)
+    }
+}
+
+impl sealed::Sealed for u8 {
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    fn load8(src: &[Self], index: usize) -> [float32x4_t; 2] {
+        simd::load_u8x8(src, index)
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    fn store8(dst: &mut [Self], index: usize, v: &[float32x4_t; 2], rounding: Rounding, max: u8) {
+        simd::store_u8x8(dst, index, v, rounding, max)
+    }
+}
+
+impl Sample for u16 {
+    const MAX: Self = u16::MAX;
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn from_f32(t: f32, rounding: Rounding, max: Self) -> Self {
+        rounding.to_u16(t, max)
+    }
+}
+
+impl sealed::Sealed for u16 {
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    fn load8(src: &[Self], index: usize) -> [float32x4_t; 2] {
+        simd::load_u16x8(src, index)
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    fn store8(dst: &mut [Self], index: usize, v: &[float32x4_t; 2], rounding: Rounding, max: u16) {
+        simd::store_u16x8(dst, index, v, rounding, max)
+    }
+}
+
+impl Sample for f32 {
+    const MAX: Self = f32::MAX;
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn from_f32(t: f32, _rounding: Rounding, _max: Self) -> Self {
+        t
+    }
+}
+
+impl sealed::Sealed for f32 {
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    fn load8(src: &[Self], index: usize) -> [float32x4_t; 2] {
+        [simd::load_f32(src, index), simd::load_f32(src, index + 4)]
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    fn store8(dst: &mut [Self], index: usize, v: &[float32x4_t; 2], _: Rounding, _: f32) {
+        simd::store_f32(dst, index, v[0]);
+        simd::store_f32(dst, index + 4, v[1]);
+    }
+}
+
+impl<const K: usize> ConvProcessor<K> {
+    /// Convolves `src`, interleaved RGB samples of `height` x `width` pixels, like
+    /// [`convolve`](Self::convolve), but converts the results with the
+    /// [`Rounding`](crate::Rounding) of the kernel to samples saturated to `[0, max]` (e.g.
+    /// [`Sample::MAX`]). Pixels of the border are 0 with [`BorderMode::Zero`].
+    ///
+    /// Runs on the current thread with its own NEON implementation if available, whatever the
+    /// backend, threads and tiles of the processor.
+    ///
+    /// Fails with [`Error::InvalidLength`] if the length of `src` is inconsistent, or
+    /// [`Error::ImageTooSmall`] if the image is smaller than [`min_size`](Self::min_size).
+    pub fn convolve_samples<T: Sample>(
+        &self,
+        src: &[T],
+        height: usize,
+        width: usize,
+        max: T,
+    ) -> Result<Vec<T>> {
+        self.convolve_channels(src, height, width, C, max)
+    }
+
+    // `convolve_samples` of pixels of `channels` values, e.g. 1 for a single plane
+    pub(crate) fn convolve_channels<T: Sample>(
+        &self,
+        src: &[T],
+        height: usize,
+        width: usize,
+        channels: usize,
+        max: T,
+    ) -> Result<Vec<T>> {
+        let (h, w) = (height, width);
+        if src.len() != h * w * channels {
+            return Err(Error::InvalidLength {
+                expected: h * w * channels,
+                actual: src.len(),
+            });
+        }
+        let min = self.min_size();
+        if h < min || w < min {
+            return Err(Error::ImageTooSmall {
+                height: h,
+                width: w,
+                min,
+            });
+        }
+        let half = K / 2;
+        let mut dst = vec![T::default(); h * w * channels];
+        let n = channels;
+        if self.border() == BorderMode::Zero {
+            convolve_rows(self.kernel(), src, w, n, half..h - half, 0, &mut dst, max);
+        } else {
+            let padded = pad_values(src, h, w, n, half, self.border());
+            let (rows, pw) = (half..h + half, w + 2 * half);
+            convolve_rows(self.kernel(), &padded, pw, n, rows, half, &mut dst, max);
+        }
+        Ok(dst)
+    }
+}
+
+// convolve `rows` of `src` (`w` pixels of `n` values wide, rows at least `K / 2` from its
+// border) into `dst`, where the pixel at `(y, x)` of `src` goes to `(y - offset, x - offset)`.
+// Every channel is computed alike, so rows are taken as flat values, tap `j` being
+// `(j - K / 2) * n` values away.
+#[allow(clippy::too_many_arguments)]
+fn convolve_rows<const K: usize, T: Sample>(
+    kernel: &ConvKernel<K>,
+    src: &[T],
+    w: usize,
+    n: usize,
+    rows: Range<usize>,
+    offset: usize,
+    dst: &mut [T],
+    max: T,
+) {
+    let row = w * n;
+    let drow = (w - 2 * offset) * n;
+    let half = K / 2;
+    // values of the row whose pixels have all their taps in `src`
+    let (kstart, kend) = (half * n, row - half * n);
+    for y in rows {
+        let dst = &mut dst[(y - offset) * drow..][..drow];
+        // values from `start` are left to the serial loop
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let start = {
+            let div = kernel.div.map(simd::Divisor::new);
+            let mut k = kstart;
+            while k + 8 <= kend {
+                let mut acc = [simd::splat(0.); 2];
+                for i in 0..K {
+                    let src_row = (y + i - half) * row;
+                    for j in 0..K {
+                        let v = T::load8(src, src_row + k + j * n - half * n);
+                        let weight = simd::splat(kernel.at(i, j));
+                        for (acc, &v) in acc.iter_mut().zip(v.iter()) {
+                            *acc = simd::fma(*acc, v, weight);
+                        }
+                    }
+                }
+                if let Some(div) = div {
+                    acc = acc.map(|acc| div.divide(acc));
+                }
+                T::store8(dst, k - offset * n, &acc, kernel.rounding, max);
+                k += 8;
+            }
+            k
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let start = kstart;
+        for k in start..kend {
+            let mut t = 0.;
+            for i in 0..K {
+                let src_row = (y + i - half) * row;
+                for j in 0..K {
+                    let v = src[src_row + k + j * n - half * n].to_f32();
+                    t = fma(v, kernel.at(i, j), t);
+                }
+            }
+            if let Some(div) = kernel.div {
+                t /= div;
+            }
+            dst[k - offset * n] = T::from_f32(t, kernel.rounding, max);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{consts::SOBEL_FILTER, test_util::photo_image};
+
+    #[test]
+    fn bytes() -> Result<()> {
+        // the same as the backends for kernels whose sums are exact, over vectors of 8 values
+        // with some left over
+        let img = photo_image(7, 13, 25);
+        for border in [BorderMode::Zero, BorderMode::Reflect] {
+            for (filter, normalize) in [(&SOBEL_FILTER, false), (&[1.; 9], true)] {
+                let processor = ConvProcessor::<3>::builder()
+                    .kernel(filter)
+                    .normalize(normalize)
+                    .border(border)
+                    .rounding(Rounding::Nearest)
+                    .build()?;
+                let convolved = processor.convolve_samples(img.content(), 7, 13, u8::MAX)?;
+                assert_eq!(convolved, processor.convolve(&img)?.content());
+                // saturated to a smaller range
+                let clamped = processor.convolve_samples(img.content(), 7, 13, 100)?;
+                for (&c, &v) in clamped.iter().zip(&convolved) {
+                    assert_eq!(c, v.min(100));
+                }
+            }
+        }
+
+        let blur = ConvProcessor::<3>::new(&[1.; 9], true)?;
+        assert!(matches!(
+            blur.convolve_samples(&[0u8; 20], 2, 3, u8::MAX),
+            Err(Error::InvalidLength {
+                expected: 18,
+                actual: 20
+            })
+        ));
+        assert!(matches!(
+            blur.convolve_samples(&[0.; 18], 2, 3, f32::MAX),
+            Err(Error::ImageTooSmall { .. })
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+//! Separable convolution, for KxK kernels which are the outer product of a column and a row.
+//!
+//! Each row of the image is converted to `f32` and filtered horizontally once, into a ring
+//! buffer of the last `K` such rows, which the vertical pass combines into an output row.
+//! A pixel takes `2 * K` multiplications instead of `K * K`, and is converted to `f32` only once.
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+
+use alloc::{format, string::ToString, vec, vec::Vec};
+
+use crate::{
+    error::{Error, Result},
+    image::RgbImage,
+    kernel::exp,
+    C,
+};
+
+/// Convolution with the KxK kernel whose weight at `(i, j)` is `col[i] * row[j]`.
+///
+/// Gives the same result as [`ConvProcessor`](crate::ConvProcessor) with that kernel and
+/// [`BorderMode::Zero`](crate::BorderMode::Zero), up to the rounding of the weighted sums.
+#[derive(Debug)]
+pub struct SeparableProcessor<const K: usize> {
+    col: [f32; K],
+    row: [f32; K],
+    div: Option<f32>,
+}
+
+impl<const K: usize> SeparableProcessor<K> {
+    /// Creates a processor from `K` weights of the column and `K` weights of the row.
+    /// If `avg` is set, results are divided by the sum of the weights of the KxK kernel.
+    ///
+    /// Fails with [`Error::BadKernel`] if `col` or `row` does not have `K` weights, `K` is even
+    /// or less than 3, or `avg` is set for weights summing up to 0.
+    pub fn new(col: &[f32], row: &[f32], avg: bool) -> Result<Self> {
+        if col.len() != K || row.len() != K {
+            return Err(Error::BadKernel(format!(
+                "inconsistent filter sizes {} and {} for K={}",
+                col.len(),
+                row.len(),
+                K
+            )));
+        }
+        if K % 2 == 0 || K < 3 {
+            return Err(Error::BadKernel(
+                "only odd number >= 3 is available for kernel size".to_string(),
+            ));
+        }
+        let div = if avg {
+            let sum = col.iter().sum::<f32>() * row.iter().sum::<f32>();
+            if sum == 0. {
+                return Err(Error::BadKernel(
+                    "cannot calculate average on filter with weights of total 0.".to_string(),
+                ));
+            }
+            Some(sum)
+        } else {
+            None
+        };
+
+        let mut processor = Self {
+            col: [0.; K],
+            row: [0.; K],
+            div,
+        };
+        processor.col.copy_from_slice(col);
+        processor.row.copy_from_slice(row);
+        Ok(processor)
+    }
+
+    /// Gaussian blur with standard deviation `sigma`, the separable form of
+    /// [`ConvKernel::gaussian`](crate::ConvKernel::gaussian).
+    ///
+    /// Fails with [`Error::BadKernel`] if `sigma` is not positive, or `K` is invalid as in [`new`](Self::new).
+    pub fn gaussian(sigma: f32) -> Result<Self> {
+        if sigma.is_nan() || sigma <= 0. {
+            return Err(Error::BadKernel(format!(
+                "sigma must be positive, got {}",
+                sigma
+            )));
+        }
+        let half = (K / 2) as f32;
+        let weights: Vec<_> = (0..K)
+            .map(|i| {
+                let x = i as f32 - half;
+                exp(-x * x / (2. * sigma * sigma))
+            })
+            .collect();
+        Self::new(&weights, &weights, true)
+    }
+
+    /// Weights of the column, applied in the vertical pass.
+    pub fn col(&self) -> &[f32; K] {
+        &self.col
+    }
+
+    /// Weights of the row, applied in the horizontal pass.
+    pub fn row(&self) -> &[f32; K] {
+        &self.row
+    }
+
+    /// Scalar implementation.
+    pub fn naive(&self, src: &RgbImage) -> Result<RgbImage> {
+        self.run(src, Self::horizontal, Self::vertical)
+    }
+
+    /// NEON implementation computing 4 values of the horizontal pass and 16 of the vertical
+    /// pass at once.
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    pub fn simd(&self, src: &RgbImage) -> Result<RgbImage> {
+        self.run(src, Self::horizontal_simd, Self::vertical_simd)
+    }
+
+    // Rows of floats are interleaved like the image, so that both passes are the same for every
+    // channel: neighboring pixels are `C` values apart.
+    //
+    // `horizontal(self, line, out)` filters the converted row `line` into `out`, and
+    // `vertical(self, ring, top, dst)` combines the rows of `ring` starting from `top` into `dst`.
+    // Both only compute the values of pixels at least `K / 2` away from the left and right border.
+    fn run(
+        &self,
+        src: &RgbImage,
+        horizontal: impl Fn(&Self, &[f32], &mut [f32]),
+        vertical: impl Fn(&Self, &[Vec<f32>], usize, &mut [u8]),
+    ) -> Result<RgbImage> {
+        src.check_min_size(K)?;
+        let (h, w) = (src.height, src.width);
+        let half = K / 2;
+        let row = w * C;
+        let mut dst = vec![0u8; h * row]; // 0 padding
+        let mut line = vec![0f32; row];
+        // horizontally filtered rows, row y at `y % K`
+        let mut ring = vec![vec![0f32; row]; K];
+
+        for (y, src) in src.inner.chunks_exact(row).enumerate() {
+            for (f, &v) in line.iter_mut().zip(src) {
+                *f = v as f32;
+            }
+            horizontal(self, &line, &mut ring[y % K]);
+            if y + 1 >= K {
+                // rows y + 1 - K..=y are ready, the oldest one at (y + 1) % K
+                let oy = y - half;
+                vertical(self, &ring, (y + 1) % K, &mut dst[oy * row..(oy + 1) * row]);
+            }
+        }
+        Ok(RgbImage::from_raw_unchecked(dst, h, w))
+    }
+
+    // range of values computed in a row
+    fn inner(len: usize) -> (usize, usize) {
+        let half = K / 2;
+        (half * C, len - half * C)
+    }
+
+    fn horizontal(&self, line: &[f32], out: &mut [f32]) {
+        let (start, end) = Self::inner(line.len());
+        for (k, v) in (start..end).zip(&mut out[start..end]) {
+            *v = self.horizontal_at(line, k);
+        }
+    }
+
+    fn horizontal_at(&self, line: &[f32], k: usize) -> f32 {
+        let mut t = 0.;
+        for (j, &weight) in self.row.iter().enumerate() {
+            t += line[k + j * C - K / 2 * C] * weight;
+        }
+        t
+    }
+
+    fn vertical(&self, ring: &[Vec<f32>], top: usize, dst: &mut [u8]) {
+        let (start, end) = Self::inner(dst.len());
+        for (k, v) in (start..end).zip(&mut dst[start..end]) {
+            *v = self.vertical_at(ring, top, k);
+        }
+    }
+
+    fn vertical_at(&self, ring: &[Vec<f32>], top: usize, k: usize) -> u8 {
+        let mut t = 0.;
+        for (i, &weight) in self.col.iter().enumerate() {
+            t += ring[(top + i) % K][k] * weight;
+        }
+        if let Some(div) = self.div {
+            t /= div;
+        }
+        t.clamp(u8::MIN as f32, u8::MAX as f32) as u8
+    }
+
+    // mul and add separately to get the same rounding as `horizontal`
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    fn horizontal_simd(&self, line: &[f32], out: &mut [f32]) {
+        let (start, end) = Self::inner(line.len());
+        let simd_end = end - (end - start) % 4;
+        for k in (start..simd_end).step_by(4) {
+            let mut t = unsafe { vdupq_n_f32(0.) };
+            for (j, &weight) in self.row.iter().enumerate() {
+                let s = &line[k + j * C - K / 2 * C..][..4];
+                unsafe {
+                    t = vaddq_f32(t, vmulq_f32(vld1q_f32(s.as_ptr()), vdupq_n_f32(weight)));
+                }
+            }
+            unsafe { vst1q_f32(out[k..k + 4].as_mut_ptr(), t) };
+        }
+        for (k, v) in (simd_end..end).zip(&mut out[simd_end..end]) {
+            *v = self.horizontal_at(line, k);
+        }
+    }
+
+    // mul and add separately to get the same rounding as `vertical`
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    fn vertical_simd(&self, ring: &[Vec<f32>], top: usize, dst: &mut [u8]) {
+        use crate::{kernel::Rounding, simd, util::f32x4x4_to_u8x16};
+
+        let (start, end) = Self::inner(dst.len());
+        let simd_end = end - (end - start) % 16;
+        for k in (start..simd_end).step_by(16) {
+            let mut t = unsafe { [vdupq_n_f32(0.); 4] };
+            for (i, &weight) in self.col.iter().enumerate() {
+                let s = &ring[(top + i) % K][k..k + 16];
+                for (z, t) in t.iter_mut().enumerate() {
+                    unsafe {
+                        let v = vld1q_f32(s[z * 4..].as_ptr());
+                        *t = vaddq_f32(*t, vmulq_f32(v, vdupq_n_f32(weight)));
+                    }
+                }
+            }
+            if let Some(div) = self.div.map(simd::Divisor::new) {
+                for t in t.iter_mut() {
+                    *t = div.divide(*t);
+                }
+            }
+            unsafe {
+                vst1q_u8(
+                    dst[k..k + 16].as_mut_ptr(),
+                    f32x4x4_to_u8x16(t, Rounding::Truncate),
+                )
+            };
+        }
+        for (k, v) in (simd_end..end).zip(&mut dst[simd_end..end]) {
+            *v = self.vertical_at(ring, top, k);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{consts::*, ConvKernel, ConvProcessor};
+
+    #[test]
+    fn same_as_2d() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        // sums of integers are exact in either order
+        let layer = SeparableProcessor::<7>::new(&[1.; 7], &[1.; 7], true)?;
+        assert_eq!(
+            layer.naive(&img)?,
+            ConvProcessor::<7>::new(&[1.; 49], true)?.naive2(&img)?
+        );
+        let layer = SeparableProcessor::<3>::new(&[-1., 0., 1.], &[1., 2., 1.], false)?;
+        assert_eq!(
+            layer.naive(&img)?,
+            ConvProcessor::<3>::new(&SOBEL_FILTER, false)?.naive2(&img)?
+        );
+
+        // off by one at most due to rounding
+        let layer = SeparableProcessor::<7>::gaussian(1.5)?;
+        let expected = ConvProcessor::from_kernel(ConvKernel::<7>::gaussian(1.5)?).naive2(&img)?;
+        let processed = layer.naive(&img)?;
+        for (&a, &b) in processed.content().iter().zip(expected.content()) {
+            assert!((a as i16 - b as i16).abs() <= 1);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn bad_kernel() {
+        assert!(matches!(
+            SeparableProcessor::<3>::new(&[1.; 3], &[1.; 2], false),
+            Err(Error::BadKernel(_))
+        ));
+        assert!(matches!(
+            SeparableProcessor::<4>::new(&[1.; 4], &[1.; 4], false),
+            Err(Error::BadKernel(_))
+        ));
+        assert!(matches!(
+            SeparableProcessor::<3>::new(&[-1., 0., 1.], &[1.; 3], true),
+            Err(Error::BadKernel(_))
+        ));
+        assert!(matches!(
+            SeparableProcessor::<3>::gaussian(0.),
+            Err(Error::BadKernel(_))
+        ));
+        let tiny = RgbImage::from_raw(vec![0; 2 * 2 * C], 2, 2).unwrap();
+        assert!(matches!(
+            SeparableProcessor::<3>::new(&[1.; 3], &[1.; 3], true)
+                .unwrap()
+                .naive(&tiny),
+            Err(Error::ImageTooSmall { .. })
+        ));
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), all(target_feature = "neon")))]
+    #[test]
+    fn simd() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let layer = SeparableProcessor::<9>::gaussian(2.)?;
+        assert_eq!(layer.simd(&img)?, layer.naive(&img)?);
+        let layer =
+            SeparableProcessor::<5>::new(&[1., -2., 0.5, 3., 1.], &[-1., 0., 2., 1., 0.], false)?;
+        assert_eq!(layer.simd(&img)?, layer.naive(&img)?);
+
+        // narrower than a vector of the vertical pass
+        let narrow = RgbImage::from_raw(img.content()[..9 * 9 * C].to_vec(), 9, 9)?;
+        assert_eq!(layer.simd(&narrow)?, layer.naive(&narrow)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+//! Serde support for [`RgbImage`], with the `serde` feature.
+//!
+//! Images are serialized as a struct of `height`, `width` and `data` (raw interleaved RGB bytes).
+//! [`compressed`] instead stores the bytes deflated in `deflate`.
+
+use alloc::{format, string::String, vec::Vec};
+use core::fmt;
+
+use serde::{
+    de::{self, Deserializer, MapAccess, SeqAccess, Visitor},
+    ser::{SerializeStruct, Serializer},
+    Deserialize, Serialize,
+};
+
+use crate::{image::RgbImage, C};
+
+// written with `serialize_bytes` rather than as a sequence of numbers
+struct Bytes<'a>(&'a [u8]);
+
+impl Serialize for Bytes<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+struct ByteBuf(Vec<u8>);
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BytesVisitor;
+
+        impl<'de> Visitor<'de> for BytesVisitor {
+            type Value = ByteBuf;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("bytes")
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<ByteBuf, E> {
+                Ok(ByteBuf(v.to_vec()))
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<ByteBuf, E> {
+                Ok(ByteBuf(v))
+            }
+
+            // formats without a byte type, e.g. JSON
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ByteBuf, A::Error> {
+                let mut v = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(b) = seq.next_element()? {
+                    v.push(b);
+                }
+                Ok(ByteBuf(v))
+            }
+        }
+
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+const NAME: &str = "RgbImage";
+const FIELDS: &[&str] = &["height", "width", "data"];
+const COMPRESSED_NAME: &str = "CompressedRgbImage";
+const COMPRESSED_FIELDS: &[&str] = &["height", "width", "deflate"];
+
+fn serialize_with<S: Serializer>(
+    img: &RgbImage,
+    name: &'static str,
+    fields: &'static [&'static str],
+    data: &[u8],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut s = serializer.serialize_struct(name, 3)?;
+    s.serialize_field(fields[0], &img.height)?;
+    s.serialize_field(fields[1], &img.width)?;
+    s.serialize_field(fields[2], &Bytes(data))?;
+    s.end()
+}
+
+struct ImageVisitor {
+    compressed: bool,
+}
+
+impl ImageVisitor {
+    fn fields(&self) -> &'static [&'static str] {
+        if self.compressed {
+            COMPRESSED_FIELDS
+        } else {
+            FIELDS
+        }
+    }
+
+    fn build<E: de::Error>(
+        &self,
+        height: usize,
+        width: usize,
+        data: Vec<u8>,
+    ) -> Result<RgbImage, E> {
+        let len = height
+            .checked_mul(width)
+            .and_then(|n| n.checked_mul(C))
+            .ok_or_else(|| E::custom(format!("image too large: {}x{}", height, width)))?;
+        let data = if self.compressed {
+            // the limit rejects data inflating to more than the image
+            miniz_oxide::inflate::decompress_to_vec_with_limit(&data, len)
+                .map_err(|e| E::custom(format!("invalid deflate stream: {:?}", e)))?
+        } else {
+            data
+        };
+        RgbImage::from_raw(data, height, width).map_err(E::custom)
+    }
+}
+
+impl<'de> Visitor<'de> for ImageVisitor {
+    type Value = RgbImage;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "struct with fields {:?}", self.fields())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<RgbImage, A::Error> {
+        let height = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let width = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        let data: ByteBuf = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+        self.build(height, width, data.0)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<RgbImage, A::Error> {
+        let fields = self.fields();
+        let (mut height, mut width, mut data) = (None, None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                k if k == fields[0] => height = Some(map.next_value()?),
+                k if k == fields[1] => width = Some(map.next_value()?),
+                k if k == fields[2] => data = Some(map.next_value::<ByteBuf>()?),
+                k => return Err(de::Error::unknown_field(k, fields)),
+            }
+        }
+        let height = height.ok_or_else(|| de::Error::missing_field(fields[0]))?;
+        let width = width.ok_or_else(|| de::Error::missing_field(fields[1]))?;
+        let data = data.ok_or_else(|| de::Error::missing_field(fields[2]))?;
+        self.build(height, width, data.0)
+    }
+}
+
+impl Serialize for RgbImage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_with(self, NAME, FIELDS, &self.inner, serializer)
+    }
+}
+
+/// Fails if the length of `data` is not `height * width * 3`.
+impl<'de> Deserialize<'de> for RgbImage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct(NAME, FIELDS, ImageVisitor { compressed: false })
+    }
+}
+
+/// Serialization of [`RgbImage`] with deflated pixel data, for `#[serde(with = "...")]`:
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize)]
+/// struct Fixture {
+///     #[serde(with = "simd_playground::image::compressed")]
+///     expected: RgbImage,
+/// }
+/// ```
+pub mod compressed {
+    use super::*;
+
+    /// Serializes `img` with the pixel data deflated.
+    pub fn serialize<S: Serializer>(img: &RgbImage, serializer: S) -> Result<S::Ok, S::Error> {
+        let data = miniz_oxide::deflate::compress_to_vec(&img.inner, 6);
+        serialize_with(img, COMPRESSED_NAME, COMPRESSED_FIELDS, &data, serializer)
+    }
+
+    /// Deserializes an image written by [`serialize`].
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<RgbImage, D::Error> {
+        deserializer.deserialize_struct(
+            COMPRESSED_NAME,
+            COMPRESSED_FIELDS,
+            ImageVisitor { compressed: true },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::*;
+
+    #[test]
+    fn round_trip() -> crate::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let json = serde_json::to_vec(&img).unwrap();
+        assert_eq!(serde_json::from_slice::<RgbImage>(&json).unwrap(), img);
+
+        let mut compact = vec![];
+        compressed::serialize(&img, &mut serde_json::Serializer::new(&mut compact)).unwrap();
+        assert!(compact.len() < json.len());
+        let mut de = serde_json::Deserializer::from_slice(&compact);
+        assert_eq!(compressed::deserialize(&mut de).unwrap(), img);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() {
+        let parse = |s: &str| serde_json::from_str::<RgbImage>(s);
+        assert!(parse(r#"{"height":1,"width":1,"data":[1,2,3]}"#).is_ok());
+        assert!(parse(r#"{"height":1,"width":2,"data":[1,2,3]}"#).is_err());
+        assert!(parse(r#"{"height":1,"width":1}"#).is_err());
+        assert!(parse(r#"{"height":1,"width":1,"data":[1,2,3],"depth":8}"#).is_err());
+        assert!(parse(r#"{"height":18446744073709551615,"width":2,"data":[]}"#).is_err());
+
+        // plain data is not a deflate stream
+        let mut de =
+            serde_json::Deserializer::from_str(r#"{"height":1,"width":1,"deflate":[1,2,3]}"#);
+        assert!(compressed::deserialize(&mut de).is_err());
+    }
+}
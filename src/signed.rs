@@ -0,0 +1,269 @@
+//! Convolution results as signed 16-bit planes, for derivative filters.
+//!
+//! [`ConvProcessor::convolve`] saturates results to `[0, 255]`, so the negative half of the
+//! response of Sobel or Laplacian kernels is lost. [`ConvProcessor::convolve_i16`] keeps it in a
+//! [`SignedImage`], from which gradient magnitudes and orientations can be computed.
+
+use alloc::{vec, vec::Vec};
+use core::ops::Range;
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{
+    error::Result,
+    image::RgbImage,
+    kernel::ConvKernel,
+    processor::{pad, BorderMode, ConvProcessor},
+    C,
+};
+
+/// Image of signed 16-bit values stored as 3 planes of `height * width` values, red, green and
+/// blue in order like [`PlanarImage`](crate::planar::PlanarImage).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedImage {
+    planes: Vec<i16>,
+    height: usize,
+    width: usize,
+}
+
+impl SignedImage {
+    /// Image of `height` x `width` pixels filled with 0.
+    pub fn new(height: usize, width: usize) -> Self {
+        Self {
+            planes: vec![0; height * width * C],
+            height,
+            width,
+        }
+    }
+
+    /// Height in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Width in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Plane of channel `c` (0 for red, 1 for green, 2 for blue), in row-major order.
+    ///
+    /// # Panics
+    ///
+    /// If `c` is 3 or more.
+    pub fn plane(&self, c: usize) -> &[i16] {
+        let len = self.height * self.width;
+        &self.planes[c * len..][..len]
+    }
+
+    /// Mutable plane of channel `c`, see [`plane`](Self::plane).
+    pub fn plane_mut(&mut self, c: usize) -> &mut [i16] {
+        let len = self.height * self.width;
+        &mut self.planes[c * len..][..len]
+    }
+
+    /// All the planes one after another.
+    pub fn content(&self) -> &[i16] {
+        &self.planes
+    }
+}
+
+impl<const K: usize> ConvProcessor<K> {
+    /// Convolves `src` like [`convolve`](Self::convolve), but converts the results to `i16`
+    /// with the [`Rounding`](crate::Rounding) of the kernel, saturating them to `[-32768, 32767]`
+    /// instead of `[0, 255]`. Pixels of the border are 0 with [`BorderMode::Zero`].
+    ///
+    /// Runs on the current thread with its own NEON implementation if available, whatever the
+    /// backend, threads and tiles of the processor.
+    ///
+    /// Fails with [`Error::ImageTooSmall`](crate::Error::ImageTooSmall) if `src` is smaller
+    /// than [`min_size`](Self::min_size).
+    pub fn convolve_i16(&self, src: &RgbImage) -> Result<SignedImage> {
+        src.check_min_size(self.min_size())?;
+        let (h, w) = (src.height, src.width);
+        let half = K / 2;
+        let mut dst = SignedImage::new(h, w);
+        if self.border() == BorderMode::Zero {
+            convolve_rows(self.kernel(), src, half..h - half, 0, &mut dst);
+        } else {
+            let mut padded = RgbImage::empty();
+            pad(src, half, self.border(), &mut padded);
+            convolve_rows(self.kernel(), &padded, half..h + half, half, &mut dst);
+        }
+        Ok(dst)
+    }
+}
+
+// convolve `rows` of `src` (pixels at least `K / 2` from its border) into `dst`, where the
+// pixel at `(y, x)` of `src` goes to `(y - offset, x - offset)`
+fn convolve_rows<const K: usize>(
+    kernel: &ConvKernel<K>,
+    src: &RgbImage,
+    rows: Range<usize>,
+    offset: usize,
+    dst: &mut SignedImage,
+) {
+    let (w, dw) = (src.width, dst.width);
+    let len = dst.height * dw;
+    let half = K / 2;
+    let xend = w - half;
+    for y in rows {
+        let dst_row = (y - offset) * dw;
+        // pixels from `start` are left to the serial loop
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let start = {
+            let div = kernel.div.map(simd::Divisor::new);
+            let mut x = half;
+            while x + 16 <= xend {
+                let mut acc: simd::Rgb16 = [[simd::splat(0.); 4]; C];
+                for i in 0..K {
+                    for j in 0..K {
+                        let v = simd::load_rgb16(
+                            src.content(),
+                            ((y + i - half) * w + x + j - half) * C,
+                        );
+                        let weight = simd::splat(kernel.at(i, j));
+                        for (acc, v) in acc.iter_mut().flatten().zip(v.iter().flatten()) {
+                            *acc = simd::fma(*acc, *v, weight);
+                        }
+                    }
+                }
+                simd::divide(&mut acc, div);
+                for (c, acc) in acc.iter().enumerate() {
+                    simd::store_i16x16(
+                        &mut dst.planes,
+                        c * len + dst_row + x - offset,
+                        acc,
+                        kernel.rounding,
+                    );
+                }
+                x += 16;
+            }
+            x
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let start = half;
+        for x in start..xend {
+            for c in 0..C {
+                let mut t = 0.;
+                for i in 0..K {
+                    for j in 0..K {
+                        let index = ((y + i - half) * w + x + j - half) * C + c;
+                        t += src.content()[index] as f32 * kernel.at(i, j);
+                    }
+                }
+                if let Some(div) = kernel.div {
+                    t /= div;
+                }
+                dst.planes[c * len + dst_row + x - offset] = kernel.rounding.to_i16(t);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{consts::SOBEL_FILTER, processor::border_index, Rounding};
+
+    // every pixel computed independently, with `border_index` for those out of `src`
+    fn reference<const K: usize>(processor: &ConvProcessor<K>, src: &RgbImage) -> SignedImage {
+        let (h, w) = (src.height, src.width);
+        let (kernel, border, half) = (processor.kernel(), processor.border(), (K / 2) as isize);
+        let mut dst = SignedImage::new(h, w);
+        for c in 0..C {
+            for y in 0..h {
+                for x in 0..w {
+                    let (y, x) = (y as isize, x as isize);
+                    if border == BorderMode::Zero
+                        && (y < half
+                            || x < half
+                            || y >= h as isize - half
+                            || x >= w as isize - half)
+                    {
+                        continue;
+                    }
+                    let mut t = 0.;
+                    for i in 0..K {
+                        for j in 0..K {
+                            let sy = border_index(y + i as isize - half, h, border);
+                            let sx = border_index(x + j as isize - half, w, border);
+                            t += src.content()[(sy * w + sx) * C + c] as f32 * kernel.at(i, j);
+                        }
+                    }
+                    if let Some(div) = kernel.div {
+                        t /= div;
+                    }
+                    dst.plane_mut(c)[y as usize * w + x as usize] = kernel.rounding.to_i16(t);
+                }
+            }
+        }
+        dst
+    }
+
+    #[test]
+    fn sobel() -> Result<()> {
+        // darker downwards, so that the response is negative everywhere
+        let (h, w) = (6, 40);
+        let content = (0..h * w * C)
+            .map(|i| (200 - i / (w * C) * 10) as u8)
+            .collect();
+        let img = RgbImage::from_raw(content, h, w)?;
+        let sobel = ConvProcessor::<3>::new(&SOBEL_FILTER, false)?;
+        assert!(sobel.convolve(&img)?.content().iter().all(|&v| v == 0));
+        let signed = sobel.convolve_i16(&img)?;
+        for c in 0..C {
+            for (i, &v) in signed.plane(c).iter().enumerate() {
+                let (y, x) = (i / w, i % w);
+                let inner = (1..h - 1).contains(&y) && (1..w - 1).contains(&x);
+                assert_eq!(v, if inner { -80 } else { 0 }, "{:?}", (c, y, x));
+            }
+        }
+
+        let replicate = ConvProcessor::<3>::builder()
+            .kernel(&SOBEL_FILTER)
+            .border(BorderMode::Replicate)
+            .build()?;
+        let signed = replicate.convolve_i16(&img)?;
+        assert_eq!(signed.plane(1)[0], -40);
+        assert_eq!(signed.plane(2)[w + 5], -80);
+        Ok(())
+    }
+
+    #[test]
+    fn matches_reference() -> Result<()> {
+        // vectors of 16 pixels, and some left over
+        let (h, w) = (9, 45);
+        let content = (0..h * w * C).map(|i| (i * 37 % 251) as u8).collect();
+        let img = RgbImage::from_raw(content, h, w)?;
+        let weights: Vec<f32> = (0..25).map(|i| (i % 7) as f32 * 300. - 900.).collect();
+        for border in [BorderMode::Zero, BorderMode::Replicate, BorderMode::Reflect] {
+            for rounding in [Rounding::Truncate, Rounding::Nearest] {
+                for normalize in [false, true] {
+                    let processor = ConvProcessor::<5>::builder()
+                        .kernel(&weights)
+                        .normalize(normalize)
+                        .rounding(rounding)
+                        .border(border)
+                        .build()?;
+                    let signed = processor.convolve_i16(&img)?;
+                    assert_eq!(
+                        signed,
+                        reference(&processor, &img),
+                        "{:?} {:?} {}",
+                        border,
+                        rounding,
+                        normalize
+                    );
+                }
+            }
+        }
+        // saturated both ways
+        for (weight, limit) in [(1000., i16::MAX), (-1000., i16::MIN)] {
+            let processor = ConvProcessor::<5>::new(&[weight; 25], false)?;
+            let signed = processor.convolve_i16(&img)?;
+            assert!(signed.content().contains(&limit));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,444 @@
+//! Typed wrappers over the NEON intrinsics that the SIMD backends repeat: deinterleaved loads of
+//! RGB pixels widened to `f32`, `vext` shifts among adjacent vectors, and packing back to `u8`.
+//!
+//! The module only exists with the `neon` target feature, so calling the intrinsics is sound
+//! except for the pointers they take. The wrappers take slices and indices instead, and check them
+//! like [`vec_ref`]: the first element always, the whole vector in debug builds and with the
+//! `audit` feature. Callers still have to keep vectors within their slices in release builds.
+
+use core::arch::aarch64::*;
+
+use crate::{
+    kernel::Rounding,
+    util::{
+        f32x4_to_i32x4, f32x4_to_u32x4, f32x4x2_to_u8x8, f32x4x4_to_u8x16, u8x16_to_f32x4x4,
+        u8x8_to_f32x4x2, vec_mut, vec_ref,
+    },
+    C,
+};
+
+/// 16 pixels per channel as `f32`, `[c][z]` holding channel c of the (4z)..(4z+3) th pixels.
+pub type Rgb16 = [[float32x4_t; 4]; C];
+
+/// 8 pixels per channel as `f32`, laid out like [`Rgb16`].
+#[cfg(feature = "impl-simd4")]
+pub type Rgb8 = [[float32x4_t; 2]; C];
+
+/// All 4 lanes set to `v`.
+#[inline(always)]
+pub fn splat(v: f32) -> float32x4_t {
+    unsafe { vdupq_n_f32(v) }
+}
+
+/// All 16 lanes set to `v`.
+#[inline(always)]
+pub fn splat_u8(v: u8) -> uint8x16_t {
+    unsafe { vdupq_n_u8(v) }
+}
+
+/// `acc + a * b` lane by lane, fused (rounded once).
+#[inline(always)]
+pub fn fma(acc: float32x4_t, a: float32x4_t, b: float32x4_t) -> float32x4_t {
+    unsafe { vfmaq_f32(acc, a, b) }
+}
+
+/// `a + b` lane by lane.
+#[inline(always)]
+pub fn add(a: float32x4_t, b: float32x4_t) -> float32x4_t {
+    unsafe { vaddq_f32(a, b) }
+}
+
+/// `a * b` lane by lane.
+#[inline(always)]
+pub fn mul(a: float32x4_t, b: float32x4_t) -> float32x4_t {
+    unsafe { vmulq_f32(a, b) }
+}
+
+/// Divisor of weighted sums, e.g. [`ConvKernel::div`](crate::ConvKernel::div), broadcast to every
+/// lane.
+#[derive(Debug, Clone, Copy)]
+pub struct Divisor {
+    div: float32x4_t,
+}
+
+impl Divisor {
+    /// Divides by `div`.
+    #[inline(always)]
+    pub fn new(div: f32) -> Self {
+        Self { div: splat(div) }
+    }
+
+    /// `v / div` lane by lane, with `vdivq_f32` so that quotients are those of the scalar division.
+    #[inline(always)]
+    pub fn divide(self, v: float32x4_t) -> float32x4_t {
+        unsafe { vdivq_f32(v, self.div) }
+    }
+}
+
+/// Divides every vector by `div` if any, see [`Divisor::divide`].
+#[inline(always)]
+pub fn divide<const N: usize>(v: &mut [[float32x4_t; N]; C], div: Option<Divisor>) {
+    if let Some(div) = div {
+        for v in v.iter_mut().flatten() {
+            *v = div.divide(*v);
+        }
+    }
+}
+
+/// Lanes `n..4` of `a` followed by lanes `0..n` of `b`, i.e. the 4 pixels `n` after those of `a`
+/// when `b` holds the next ones. `n` must be less than 4.
+#[cfg(any(feature = "impl-unrolled", feature = "impl-simd4"))]
+#[inline(always)]
+pub fn ext(a: float32x4_t, b: float32x4_t, n: usize) -> float32x4_t {
+    unsafe {
+        match n {
+            0 => a,
+            1 => vextq_f32::<1>(a, b),
+            2 => vextq_f32::<2>(a, b),
+            3 => vextq_f32::<3>(a, b),
+            _ => unreachable!("vext by {} lanes", n),
+        }
+    }
+}
+
+/// The 4 pixels `d` (-4 < d < 4) after those of `v[z]`, where `v[-1]` is `prev` and `v[4]` is
+/// `next`, so that pixels shifted by kernel columns take no load of their own.
+#[cfg(feature = "impl-unrolled")]
+#[inline(always)]
+pub fn shifted(
+    prev: float32x4_t,
+    v: &[float32x4_t; 4],
+    next: float32x4_t,
+    z: usize,
+    d: isize,
+) -> float32x4_t {
+    match d {
+        0 => v[z],
+        d if d < 0 => ext(if z == 0 { prev } else { v[z - 1] }, v[z], (4 + d) as usize),
+        d => ext(v[z], if z == 3 { next } else { v[z + 1] }, d as usize),
+    }
+}
+
+/// 4 `f32` from `src[index..]`.
+#[inline(always)]
+#[track_caller]
+pub fn load_f32(src: &[f32], index: usize) -> float32x4_t {
+    unsafe { vld1q_f32(vec_ptr(src, index, 4)) }
+}
+
+/// Stores 4 `f32` into `dst[index..]`.
+#[inline(always)]
+#[track_caller]
+pub fn store_f32(dst: &mut [f32], index: usize, v: float32x4_t) {
+    unsafe { vst1q_f32(vec_mut_ptr(dst, index, 4), v) }
+}
+
+/// 16 bytes from `src[index..]` widened to `f32`, regardless of channels.
+#[inline(always)]
+#[track_caller]
+pub fn load_u8x16(src: &[u8], index: usize) -> [float32x4_t; 4] {
+    unsafe { u8x16_to_f32x4x4(vld1q_u8(vec_ptr(src, index, 16))) }
+}
+
+/// Stores 16 bytes into `dst[index..]`, converted to `u8` with `rounding` and saturated.
+#[inline(always)]
+#[track_caller]
+pub fn store_u8x16(dst: &mut [u8], index: usize, v: &[float32x4_t; 4], rounding: Rounding) {
+    unsafe { vst1q_u8(vec_mut_ptr(dst, index, 16), f32x4x4_to_u8x16(*v, rounding)) }
+}
+
+/// Stores 16 `i16` into `dst[index..]`, converted with `rounding` and saturated.
+#[inline(always)]
+#[track_caller]
+pub fn store_i16x16(dst: &mut [i16], index: usize, v: &[float32x4_t; 4], rounding: Rounding) {
+    unsafe {
+        let dst = vec_mut_ptr(dst, index, 16);
+        let [a, b, c, d] = v.map(|v| f32x4_to_i32x4(v, rounding));
+        vst1q_s16(dst, vqmovn_high_s32(vqmovn_s32(a), b));
+        vst1q_s16(dst.add(8), vqmovn_high_s32(vqmovn_s32(c), d));
+    }
+}
+
+/// 8 bytes from `src[index..]` widened to `f32`.
+#[inline(always)]
+#[track_caller]
+pub fn load_u8x8(src: &[u8], index: usize) -> [float32x4_t; 2] {
+    unsafe { u8x8_to_f32x4x2(vld1_u8(vec_ptr(src, index, 8))) }
+}
+
+/// Stores 8 bytes into `dst[index..]`, converted with `rounding` and saturated to `[0, max]`.
+#[inline(always)]
+#[track_caller]
+pub fn store_u8x8(dst: &mut [u8], index: usize, v: &[float32x4_t; 2], rounding: Rounding, max: u8) {
+    unsafe {
+        let v = vmin_u8(f32x4x2_to_u8x8(*v, rounding), vdup_n_u8(max));
+        vst1_u8(vec_mut_ptr(dst, index, 8), v);
+    }
+}
+
+/// 8 `u16` from `src[index..]` widened to `f32`, e.g. 10 or 12-bit samples.
+#[inline(always)]
+#[track_caller]
+pub fn load_u16x8(src: &[u16], index: usize) -> [float32x4_t; 2] {
+    unsafe {
+        let v = vld1q_u16(vec_ptr(src, index, 8));
+        [
+            vcvtq_f32_u32(vmovl_u16(vget_low_u16(v))),
+            vcvtq_f32_u32(vmovl_high_u16(v)),
+        ]
+    }
+}
+
+/// Stores 8 `u16` into `dst[index..]`, converted with `rounding` and saturated to `[0, max]`.
+#[inline(always)]
+#[track_caller]
+pub fn store_u16x8(
+    dst: &mut [u16],
+    index: usize,
+    v: &[float32x4_t; 2],
+    rounding: Rounding,
+    max: u16,
+) {
+    unsafe {
+        let max = vdupq_n_u32(max as u32);
+        let [a, b] = v.map(|v| vminq_u32(f32x4_to_u32x4(v, rounding), max));
+        vst1q_u16(vec_mut_ptr(dst, index, 8), vmovn_high_u32(vmovn_u32(a), b));
+    }
+}
+
+/// 16 RGB pixels from `src[index..]`, deinterleaved by channel and widened to `f32`.
+#[inline(always)]
+#[track_caller]
+pub fn load_rgb16(src: &[u8], index: usize) -> Rgb16 {
+    unsafe {
+        let sc = vld3q_u8(vec_ptr(src, index, 16 * C));
+        [
+            u8x16_to_f32x4x4(sc.0),
+            u8x16_to_f32x4x4(sc.1),
+            u8x16_to_f32x4x4(sc.2),
+        ]
+    }
+}
+
+/// 8 RGB pixels from `src[index..]`, like [`load_rgb16`].
+#[cfg(feature = "impl-simd4")]
+#[inline(always)]
+#[track_caller]
+pub fn load_rgb8(src: &[u8], index: usize) -> Rgb8 {
+    unsafe {
+        let sc = vld3_u8(vec_ptr(src, index, 8 * C));
+        [
+            u8x8_to_f32x4x2(sc.0),
+            u8x8_to_f32x4x2(sc.1),
+            u8x8_to_f32x4x2(sc.2),
+        ]
+    }
+}
+
+/// Stores 16 pixels into `dst[index..]` interleaving the channels, converted to `u8` with
+/// `rounding` and saturated.
+#[inline(always)]
+#[track_caller]
+pub fn store_rgb16(dst: &mut [u8], index: usize, v: &Rgb16, rounding: Rounding) {
+    unsafe {
+        vst3q_u8(
+            vec_mut_ptr(dst, index, 16 * C),
+            uint8x16x3_t(
+                f32x4x4_to_u8x16(v[0], rounding),
+                f32x4x4_to_u8x16(v[1], rounding),
+                f32x4x4_to_u8x16(v[2], rounding),
+            ),
+        );
+    }
+}
+
+/// Stores 8 pixels into `dst[index..]`, like [`store_rgb16`].
+#[cfg(feature = "impl-simd4")]
+#[inline(always)]
+#[track_caller]
+pub fn store_rgb8(dst: &mut [u8], index: usize, v: &Rgb8, rounding: Rounding) {
+    unsafe {
+        vst3_u8(
+            vec_mut_ptr(dst, index, 8 * C),
+            uint8x8x3_t(
+                f32x4x2_to_u8x8(v[0], rounding),
+                f32x4x2_to_u8x8(v[1], rounding),
+                f32x4x2_to_u8x8(v[2], rounding),
+            ),
+        );
+    }
+}
+
+/// 16 bytes from `src[index..]`.
+#[inline(always)]
+#[track_caller]
+pub fn load_bytes16(src: &[u8], index: usize) -> uint8x16_t {
+    unsafe { vld1q_u8(vec_ptr(src, index, 16)) }
+}
+
+/// Stores 16 bytes into `dst[index..]`.
+#[inline(always)]
+#[track_caller]
+pub fn store_bytes16(dst: &mut [u8], index: usize, v: uint8x16_t) {
+    unsafe { vst1q_u8(vec_mut_ptr(dst, index, 16), v) }
+}
+
+/// 16 RGB pixels from `src[index..]`, deinterleaved by channel.
+#[inline(always)]
+#[track_caller]
+pub fn deinterleave16(src: &[u8], index: usize) -> [uint8x16_t; C] {
+    let sc = unsafe { vld3q_u8(vec_ptr(src, index, 16 * C)) };
+    [sc.0, sc.1, sc.2]
+}
+
+/// Stores 16 pixels of each channel into `dst[index..]`, interleaving the channels.
+#[inline(always)]
+#[track_caller]
+pub fn interleave16(dst: &mut [u8], index: usize, v: [uint8x16_t; C]) {
+    unsafe {
+        vst3q_u8(
+            vec_mut_ptr(dst, index, 16 * C),
+            uint8x16x3_t(v[0], v[1], v[2]),
+        )
+    }
+}
+
+/// 16 `u16` from `src[index..]` as 2 vectors, e.g. RGB565 pixels.
+#[inline(always)]
+#[track_caller]
+pub fn load_u16x8x2(src: &[u16], index: usize) -> [uint16x8_t; 2] {
+    let v = unsafe { vld1q_u16_x2(vec_ptr(src, index, 16)) };
+    [v.0, v.1]
+}
+
+/// Stores 16 `u16` from 2 vectors into `dst[index..]`.
+#[inline(always)]
+#[track_caller]
+pub fn store_u16x8x2(dst: &mut [u16], index: usize, v: [uint16x8_t; 2]) {
+    unsafe { vst1q_u16_x2(vec_mut_ptr(dst, index, 16), uint16x8x2_t(v[0], v[1])) }
+}
+
+/// 16 RGBA pixels from `src[index..]`, deinterleaved by channel.
+#[inline(always)]
+#[track_caller]
+pub fn deinterleave_rgba16(src: &[u8], index: usize) -> [uint8x16_t; 4] {
+    let sc = unsafe { vld4q_u8(vec_ptr(src, index, 16 * 4)) };
+    [sc.0, sc.1, sc.2, sc.3]
+}
+
+/// Stores 16 RGBA pixels into `dst[index..]`, interleaving the channels.
+#[inline(always)]
+#[track_caller]
+pub fn interleave_rgba16(dst: &mut [u8], index: usize, v: [uint8x16_t; 4]) {
+    unsafe {
+        vst4q_u8(
+            vec_mut_ptr(dst, index, 16 * 4),
+            uint8x16x4_t(v[0], v[1], v[2], v[3]),
+        )
+    }
+}
+
+/// Swaps the first and last channels of 16 RGB pixels at `data[index..]`, i.e. converts them
+/// between RGB and BGR.
+#[inline(always)]
+#[track_caller]
+pub fn swap_red_blue16(data: &mut [u8], index: usize) {
+    let [r, g, b] = deinterleave16(data, index);
+    interleave16(data, index, [b, g, r]);
+}
+
+// pointer to `slice[index]` for a vector load of `len` elements, checked like `vec_ref`, so that
+// the wrappers cost no more than the intrinsics on slices in release builds
+//
+// Safety: the `len` elements from `index` must be within `slice`.
+#[inline(always)]
+#[track_caller]
+unsafe fn vec_ptr<T>(slice: &[T], index: usize, len: usize) -> *const T {
+    vec_ref(slice, index, len)
+}
+
+// pointer to `slice[index]` for a vector store, checked like `vec_ptr`
+//
+// Safety: the `len` elements from `index` must be within `slice`.
+#[inline(always)]
+#[track_caller]
+unsafe fn vec_mut_ptr<T>(slice: &mut [T], index: usize, len: usize) -> *mut T {
+    vec_mut(slice, index, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{vec, vec::Vec};
+
+    use super::*;
+
+    fn lanes(v: float32x4_t) -> [f32; 4] {
+        let mut out = [0.; 4];
+        store_f32(&mut out, 0, v);
+        out
+    }
+
+    #[test]
+    fn roundtrip() {
+        let src: Vec<u8> = (0..16 * C as u8).collect();
+        let v = load_rgb16(&src, 0);
+        assert_eq!(lanes(v[1][2]), [25., 28., 31., 34.]);
+        let mut dst = vec![0; 16 * C];
+        store_rgb16(&mut dst, 0, &v, Rounding::Truncate);
+        assert_eq!(dst, src);
+
+        let v = load_u8x16(&src, 5);
+        assert_eq!(lanes(v[3]), [17., 18., 19., 20.]);
+        store_u8x16(&mut dst, 1, &v, Rounding::Truncate);
+        assert_eq!(dst[1..17], src[5..21]);
+
+        let mut signed = [0; 18];
+        let v = [-40000., -2.5, 2.5, 40000.].map(splat);
+        store_i16x16(&mut signed, 1, &v, Rounding::Nearest);
+        assert_eq!(signed[1..5], [i16::MIN; 4]);
+        assert_eq!(signed[5..9], [-3; 4]);
+        assert_eq!(signed[9..13], [3; 4]);
+        assert_eq!(signed[13..17], [i16::MAX; 4]);
+        assert_eq!([signed[0], signed[17]], [0, 0]);
+    }
+
+    #[cfg(feature = "impl-simd4")]
+    #[test]
+    fn roundtrip8() {
+        let src: Vec<u8> = (0..16 * C as u8).collect();
+        let v = load_rgb8(&src, 3);
+        assert_eq!(lanes(v[0][1]), [15., 18., 21., 24.]);
+        let mut dst = vec![0; 8 * C];
+        store_rgb8(&mut dst, 0, &v, Rounding::Nearest);
+        assert_eq!(dst, src[3..][..8 * C]);
+    }
+
+    #[cfg(any(debug_assertions, feature = "audit"))]
+    #[test]
+    #[should_panic]
+    fn out_of_bounds() {
+        let src = [0; 16 * C];
+        load_rgb16(&src, 1);
+    }
+
+    #[cfg(feature = "impl-unrolled")]
+    #[test]
+    fn shifts() {
+        let f: Vec<f32> = (0..24).map(|i| i as f32).collect();
+        let v = [4, 8, 12, 16].map(|i| load_f32(&f, i));
+        let (prev, next) = (load_f32(&f, 0), load_f32(&f, 20));
+        for z in 0..4 {
+            for d in -3..4 {
+                let start = (4 + 4 * z as isize + d) as f32;
+                let expected = [start, start + 1., start + 2., start + 3.];
+                assert_eq!(
+                    lanes(shifted(prev, &v, next, z, d)),
+                    expected,
+                    "{} {}",
+                    z,
+                    d
+                );
+            }
+        }
+    }
+}
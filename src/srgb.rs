@@ -0,0 +1,218 @@
+//! sRGB transfer function, to filter in linear light.
+//!
+//! Bytes of images are usually sRGB-encoded, i.e. roughly the square root of the light they
+//! stand for, so averaging them (e.g. blurring) darkens the edges between bright and dark areas.
+//! [`RgbImageF32::from_srgb`] decodes bytes to linear values with a table of the 256 exact ones,
+//! and [`RgbImageF32::to_srgb`] encodes them back with an approximation by square roots, within
+//! 0.4 levels of the exact curve so that every byte comes back unchanged, 16 values at a time
+//! with NEON. [`ConvProcessorBuilder::linear_light`](crate::processor::ConvProcessorBuilder::linear_light)
+//! convolves images this way.
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+
+use alloc::vec;
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{
+    error::Result,
+    hdr::RgbImageF32,
+    image::RgbImage,
+    kernel::{fma, powf, sqrt, Rounding},
+    processor::ConvProcessor,
+};
+
+// linear values below which the sRGB curve is a straight line, and sRGB values below which the
+// inverse is
+const LINEAR_KNEE: f32 = 0.003_130_8;
+const SRGB_KNEE: f32 = 0.040_45;
+
+// weights of the square, 4th and 8th roots in `encode`
+const ROOTS: [f32; 3] = [0.585_122_4, 0.783_140_36, -0.368_262_74];
+
+/// Linear value of the sRGB-encoded `v`, both in `[0, 1]`.
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= SRGB_KNEE {
+        v / 12.92
+    } else {
+        powf((v + 0.055) / 1.055, 2.4)
+    }
+}
+
+/// sRGB encoding of the linear `v`, both in `[0, 1]`, the inverse of [`srgb_to_linear`].
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= LINEAR_KNEE {
+        v * 12.92
+    } else {
+        1.055 * powf(v, 1. / 2.4) - 0.055
+    }
+}
+
+impl RgbImageF32 {
+    /// Decodes the sRGB bytes of `src` to linear values in `[0, 1]` with [`srgb_to_linear`].
+    pub fn from_srgb(src: &RgbImage) -> Self {
+        let mut table = [0.; 256];
+        for (v, t) in table.iter_mut().enumerate() {
+            *t = srgb_to_linear(v as f32 / 255.);
+        }
+        let content = src.content().iter().map(|&v| table[v as usize]).collect();
+        RgbImageF32::from_raw_unchecked(content, src.height, src.width)
+    }
+
+    /// Encodes the linear values to sRGB bytes, rounded to the nearest integer and saturated to
+    /// `[0, 255]`.
+    pub fn to_srgb(&self) -> RgbImage {
+        let mut dst = vec![0; self.content().len()];
+        encode_into(self.content(), &mut dst, Rounding::Nearest);
+        RgbImage::from_raw_unchecked(dst, self.height(), self.width())
+    }
+}
+
+impl<const K: usize> ConvProcessor<K> {
+    // `run` in linear light, converting the results with the rounding of the kernel
+    pub(crate) fn convolve_linear(&self, src: &RgbImage, dst: &mut RgbImage) -> Result<()> {
+        let linear = self.convolve_f32(&RgbImageF32::from_srgb(src))?;
+        encode_into(linear.content(), &mut dst.inner, self.kernel().rounding);
+        Ok(())
+    }
+}
+
+// `encode` of every value of `src`, scaled to `[0, 255]`
+fn encode_into(src: &[f32], dst: &mut [u8], rounding: Rounding) {
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    let start = {
+        let simd_end = src.len() - src.len() % 16;
+        for i in (0..simd_end).step_by(16) {
+            let v = [0, 4, 8, 12].map(|z| unsafe {
+                let x = simd::load_f32(src, i + z);
+                simd::mul(encode4(x), simd::splat(255.))
+            });
+            simd::store_u8x16(dst, i, &v, rounding);
+        }
+        simd_end
+    };
+    #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+    let start = 0;
+    for (d, &v) in dst[start..].iter_mut().zip(&src[start..]) {
+        *d = rounding.to_u8(encode(v) * 255.);
+    }
+}
+
+// `linear_to_srgb` approximated by a weighted sum of roots, without `powf`
+fn encode(x: f32) -> f32 {
+    if x <= LINEAR_KNEE {
+        x * 12.92
+    } else {
+        let [a, b, c] = ROOTS;
+        let s1 = sqrt(x);
+        let s2 = sqrt(s1);
+        let s3 = sqrt(s2);
+        fma(c, s3, fma(b, s2, a * s1))
+    }
+}
+
+// `encode` of 4 values, both branches being computed
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[inline]
+unsafe fn encode4(x: float32x4_t) -> float32x4_t {
+    let [a, b, c] = ROOTS.map(|k| vdupq_n_f32(k));
+    let s1 = vsqrtq_f32(x);
+    let s2 = vsqrtq_f32(s1);
+    let s3 = vsqrtq_f32(s2);
+    let roots = vfmaq_f32(vfmaq_f32(vmulq_f32(a, s1), b, s2), c, s3);
+    let line = vmulq_f32(x, vdupq_n_f32(12.92));
+    vbslq_f32(vcleq_f32(x, vdupq_n_f32(LINEAR_KNEE)), line, roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::{processor::BorderMode, test_util::photo_image, C};
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        // every byte, over vectors of 16 values with some left over
+        let content: Vec<u8> = (0..=255).chain(0..=255).chain(0..=21).collect();
+        let img = RgbImage::from_raw(content, 1, 178)?;
+        let linear = RgbImageF32::from_srgb(&img);
+        assert_eq!(linear.to_srgb(), img);
+        assert_eq!(linear.content()[0], 0.);
+        assert!((linear.content()[128] - 0.21586).abs() < 1e-5);
+        assert_eq!(linear.content()[255], 1.);
+
+        // within 0.4 levels of the exact curve, and saturated
+        for i in 0..=10000 {
+            let x = i as f32 / 10000.;
+            assert!((encode(x) - linear_to_srgb(x)).abs() * 255. < 0.4, "{}", x);
+            assert!(
+                (srgb_to_linear(linear_to_srgb(x)) - x).abs() < 1e-5,
+                "{}",
+                x
+            );
+        }
+        let out = RgbImageF32::from_raw(vec![-0.5, 0., 2.], 1, 1)?;
+        assert_eq!(out.to_srgb().content(), [0, 0, 255]);
+        Ok(())
+    }
+
+    #[test]
+    fn linear_light() -> Result<()> {
+        // black and white stripes blur to half the light, brighter than half the bytes
+        let content = (0..4 * 10 * C)
+            .map(|i| if i / C % 2 == 0 { 0 } else { 255 })
+            .collect();
+        let stripes = RgbImage::from_raw(content, 4, 10)?;
+        let blur = |linear| {
+            ConvProcessor::<3>::builder()
+                .kernel(&[0., 0., 0., 1., 2., 1., 0., 0., 0.])
+                .normalize(true)
+                .border(BorderMode::Reflect)
+                .rounding(Rounding::Nearest)
+                .linear_light(linear)
+                .build()
+        };
+        let gamma = blur(false)?.convolve(&stripes)?;
+        assert!(gamma.content().iter().all(|&v| v == 128));
+        let processor = blur(true)?;
+        assert!(processor.linear_light());
+        let linear = processor.convolve(&stripes)?;
+        // 255 * linear_to_srgb(0.5) = 187.52, which the approximation rounds down
+        assert!(linear.content().iter().all(|&v| v == 187), "{:?}", linear);
+
+        // the same as converting by hand, whatever the border
+        let img = photo_image(9, 23, 16);
+        for border in [BorderMode::Zero, BorderMode::Replicate] {
+            let processor = ConvProcessor::<5>::builder()
+                .kernel(&[1.; 25])
+                .normalize(true)
+                .border(border)
+                .rounding(Rounding::Nearest)
+                .linear_light(true)
+                .build()?;
+            let expected = processor
+                .convolve_f32(&RgbImageF32::from_srgb(&img))?
+                .to_srgb();
+            assert_eq!(processor.convolve(&img)?, expected);
+            let mut dst = RgbImage::from_raw(vec![7; 9 * 23 * C], 9, 23)?;
+            processor.convolve_into(&img, &mut dst)?;
+            assert_eq!(dst, expected);
+        }
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    #[test]
+    fn simd() -> Result<()> {
+        // values around the knee and out of range, over vectors of 16 values
+        let content: Vec<f32> = (0..40 * C).map(|i| i as f32 * 0.00017 - 0.002).collect();
+        let content: Vec<f32> = content.iter().chain(&[0.5, 0.9, 1.5]).copied().collect();
+        let img = RgbImageF32::from_raw(content, 1, 41)?;
+        for (&v, &x) in img.to_srgb().content().iter().zip(img.content()) {
+            assert_eq!(v, Rounding::Nearest.to_u8(encode(x) * 255.), "{}", x);
+        }
+        Ok(())
+    }
+}
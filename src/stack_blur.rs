@@ -0,0 +1,251 @@
+//! Stack blur, a large-radius blur with integer arithmetic only.
+//!
+//! Each pass weights the `2 * radius + 1` pixels around a pixel with a tent, `radius + 1` at the
+//! center down to 1 at both ends, as the stack of the original algorithm (Mario Klingemann) does.
+//! The weighted sum is updated from the previous pixel with two running sums, of the pixels
+//! entering and leaving the tent, so that a pixel takes a few additions whatever the radius.
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+
+use alloc::{format, vec};
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::util::{div_u32x4x4_to_u8x16, u8x16_to_u32x4x4, vec_mut, vec_ref};
+use crate::{
+    error::{Error, Result},
+    image::RgbImage,
+    C,
+};
+
+/// Largest radius, for which the sums of a tent fit in 24 bits.
+pub const MAX_RADIUS: usize = 254;
+
+/// Stack blur of the given radius, horizontally then vertically.
+///
+/// Pixels outside the image take the value of the nearest edge pixel. The result of each pass
+/// is the weighted sum divided by `(radius + 1)²` and truncated to `u8`. The tent has a standard
+/// deviation of `sqrt(radius * (radius + 2) / 6)`, e.g. about 8 for a radius of 19.
+#[derive(Debug, Clone, Copy)]
+pub struct StackBlur {
+    radius: usize,
+}
+
+impl StackBlur {
+    /// Creates a blur of `radius` pixels on each side.
+    ///
+    /// Fails with [`Error::BadKernel`] if `radius` is 0 or more than [`MAX_RADIUS`].
+    pub fn new(radius: usize) -> Result<Self> {
+        if radius == 0 || radius > MAX_RADIUS {
+            return Err(Error::BadKernel(format!(
+                "radius must be in 1..={}, got {}",
+                MAX_RADIUS, radius
+            )));
+        }
+        Ok(Self { radius })
+    }
+
+    /// Number of pixels on each side of the center which are weighted.
+    pub fn radius(&self) -> usize {
+        self.radius
+    }
+
+    /// Scalar implementation.
+    pub fn naive(&self, src: &RgbImage) -> Result<RgbImage> {
+        let (h, w) = (src.height, src.width);
+        let mut tmp = vec![0; src.content().len()];
+        self.horizontal(src, &mut tmp);
+        let mut dst = vec![0; tmp.len()];
+        for k in 0..w * C {
+            self.blur_line(&tmp[k..], &mut dst[k..], h, w * C);
+        }
+        Ok(RgbImage::from_raw_unchecked(dst, h, w))
+    }
+
+    /// NEON implementation of the vertical pass, on 16 values of a row at once. The horizontal
+    /// pass is the same as [`naive`](Self::naive).
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    pub fn simd(&self, src: &RgbImage) -> Result<RgbImage> {
+        let (h, w) = (src.height, src.width);
+        let row = w * C;
+        let mut tmp = vec![0; src.content().len()];
+        self.horizontal(src, &mut tmp);
+        let mut dst = vec![0; tmp.len()];
+        let simd_end = row - row % 16;
+        for k in (0..simd_end).step_by(16) {
+            self.vertical_simd(&tmp, &mut dst, k, h, row);
+        }
+        for k in simd_end..row {
+            self.blur_line(&tmp[k..], &mut dst[k..], h, row);
+        }
+        Ok(RgbImage::from_raw_unchecked(dst, h, w))
+    }
+
+    // every channel of every row of `src` into `dst`
+    fn horizontal(&self, src: &RgbImage, dst: &mut [u8]) {
+        let row = src.width * C;
+        if row == 0 {
+            return;
+        }
+        for (s, d) in src
+            .content()
+            .chunks_exact(row)
+            .zip(dst.chunks_exact_mut(row))
+        {
+            for c in 0..C {
+                self.blur_line(&s[c..], &mut d[c..], src.width, C);
+            }
+        }
+    }
+
+    // blurs the `len` values `src[0], src[step], ..` into the same indices of `dst`
+    fn blur_line(&self, src: &[u8], dst: &mut [u8], len: usize, step: usize) {
+        if len == 0 {
+            return;
+        }
+        let r = self.radius as isize;
+        let at = |i: isize| src[i.clamp(0, len as isize - 1) as usize * step] as u32;
+        // weighted sum at `i`, and sums of the values entering and leaving the tent at `i + 1`
+        let mut sum: u32 = (-r..=r).map(|k| (r + 1 - k.abs()) as u32 * at(k)).sum();
+        let mut sum_in: u32 = (1..=r + 1).map(at).sum();
+        let mut sum_out: u32 = (-r..=0).map(at).sum();
+        let div = div(self.radius);
+        for i in 0..len as isize {
+            dst[i as usize * step] = (sum / div) as u8;
+            sum = sum + sum_in - sum_out;
+            let next = at(i + 1);
+            sum_in = sum_in + at(i + r + 2) - next;
+            sum_out = sum_out + next - at(i - r);
+        }
+    }
+
+    // `blur_line` on the 16 columns of bytes from `k`, i.e. `src[k..k + 16]` of every row
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    fn vertical_simd(&self, src: &[u8], dst: &mut [u8], k: usize, h: usize, row: usize) {
+        unsafe {
+            let r = self.radius as isize;
+            let at = |y: isize| {
+                let y = y.clamp(0, h as isize - 1) as usize;
+                u8x16_to_u32x4x4(vld1q_u8(vec_ref(src, y * row + k, 16)))
+            };
+            let zero = [vdupq_n_u32(0); 4];
+            let (mut sum, mut sum_in, mut sum_out) = (zero, zero, zero);
+            for d in -r..=r {
+                let weight = vdupq_n_u32((r + 1 - d.abs()) as u32);
+                let v = at(d);
+                for z in 0..4 {
+                    sum[z] = vmlaq_u32(sum[z], v[z], weight);
+                    if d <= 0 {
+                        sum_out[z] = vaddq_u32(sum_out[z], v[z]);
+                    }
+                }
+            }
+            for d in 1..=r + 1 {
+                let v = at(d);
+                for z in 0..4 {
+                    sum_in[z] = vaddq_u32(sum_in[z], v[z]);
+                }
+            }
+            // sums are below 2^24 and divisors below 2^16
+            let div = vdupq_n_f32(div(self.radius) as f32);
+            for y in 0..h as isize {
+                vst1q_u8(
+                    vec_mut(dst, y as usize * row + k, 16),
+                    div_u32x4x4_to_u8x16(sum, div),
+                );
+                let (next, enter, leave) = (at(y + 1), at(y + r + 2), at(y - r));
+                for z in 0..4 {
+                    sum[z] = vsubq_u32(vaddq_u32(sum[z], sum_in[z]), sum_out[z]);
+                    sum_in[z] = vsubq_u32(vaddq_u32(sum_in[z], enter[z]), next[z]);
+                    sum_out[z] = vsubq_u32(vaddq_u32(sum_out[z], next[z]), leave[z]);
+                }
+            }
+        }
+    }
+}
+
+// sum of the weights of a tent of `radius`
+fn div(radius: usize) -> u32 {
+    ((radius + 1) * (radius + 1)) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::test_util::noise_image;
+
+    // every output computed directly from the tent, with an intermediate `u8` image
+    fn reference(radius: usize, src: &RgbImage) -> RgbImage {
+        let (h, w) = (src.height as isize, src.width as isize);
+        let r = radius as isize;
+        let pass = |src: &[u8], horizontal: bool| -> Vec<u8> {
+            let mut dst = vec![0; src.len()];
+            for y in 0..h {
+                for x in 0..w {
+                    for c in 0..C {
+                        let sum: u32 = (-r..=r)
+                            .map(|d| {
+                                let (sy, sx) = if horizontal {
+                                    (y, (x + d).clamp(0, w - 1))
+                                } else {
+                                    ((y + d).clamp(0, h - 1), x)
+                                };
+                                (r + 1 - d.abs()) as u32
+                                    * src[((sy * w + sx) as usize) * C + c] as u32
+                            })
+                            .sum();
+                        dst[((y * w + x) as usize) * C + c] = (sum / div(radius)) as u8;
+                    }
+                }
+            }
+            dst
+        };
+        let tmp = pass(src.content(), true);
+        RgbImage::from_raw_unchecked(pass(&tmp, false), src.height, src.width)
+    }
+
+    #[test]
+    fn same_as_tent() -> Result<()> {
+        for (h, w) in [(1, 1), (5, 9), (17, 4)] {
+            let img = noise_image(h, w, 1);
+            for radius in [1, 2, 7] {
+                let blur = StackBlur::new(radius)?;
+                assert_eq!(
+                    blur.naive(&img)?,
+                    reference(radius, &img),
+                    "{:?} {}",
+                    (h, w),
+                    radius
+                );
+            }
+        }
+        // a constant image stays constant, even with the largest radius
+        let img = RgbImage::from_raw(vec![255; 3 * 4 * C], 3, 4)?;
+        assert_eq!(StackBlur::new(MAX_RADIUS)?.naive(&img)?, img);
+        assert!(StackBlur::new(0).is_err());
+        assert!(StackBlur::new(MAX_RADIUS + 1).is_err());
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    #[test]
+    fn simd() -> Result<()> {
+        // columns of bytes left over by vectors of 16
+        for (h, w) in [(1, 1), (9, 6), (40, 23)] {
+            let img = noise_image(h, w, 2);
+            for radius in [1, 4, 30, MAX_RADIUS] {
+                let blur = StackBlur::new(radius)?;
+                assert_eq!(
+                    blur.simd(&img)?,
+                    blur.naive(&img)?,
+                    "{:?} {}",
+                    (h, w),
+                    radius
+                );
+            }
+        }
+        Ok(())
+    }
+}
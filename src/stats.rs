@@ -0,0 +1,90 @@
+//! Counters and timings of convolutions, gathered with the `stats` feature.
+//!
+//! Backends count what they compute on the current thread, and
+//! [`ConvProcessor`](crate::ConvProcessor) adds up the counts of the threads it runs on into its
+//! [`Stats`], see [`ConvProcessor::stats`](crate::ConvProcessor::stats).
+
+use core::{cell::Cell, ops::AddAssign, time::Duration};
+
+/// What convolutions of a [`ConvProcessor`](crate::ConvProcessor) computed, and how long each
+/// stage took.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// Images convolved.
+    pub images: u64,
+    /// Output pixels computed by SIMD vectors, counting those computed again by overlapping
+    /// vectors at the end of rows.
+    pub simd_pixels: u64,
+    /// Output pixels computed one by one, by peel loops of SIMD backends and by naive backends.
+    pub peel_pixels: u64,
+    /// Vector loads of source pixels issued by SIMD backends (`vld1q_f32` of 4 values or
+    /// `vld3q_u8`/`vld3_u8` of 16 or 8 pixels each count as one).
+    pub loads: u64,
+    /// Tiles convolved with
+    /// [`ConvProcessorBuilder::tile_size`](crate::processor::ConvProcessorBuilder::tile_size).
+    pub tiles: u64,
+    /// Time spent padding source images, or clearing the border of outputs with
+    /// [`BorderMode::Zero`](crate::BorderMode::Zero).
+    pub pad: Duration,
+    /// Time spent computing output rows, across all threads of an image.
+    pub convolve: Duration,
+    /// Time spent copying the rows convolved from padded images to outputs.
+    pub copy: Duration,
+}
+
+// counts of the current thread
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Counts {
+    simd_pixels: u64,
+    peel_pixels: u64,
+    loads: u64,
+    tiles: u64,
+}
+
+impl AddAssign<Counts> for Stats {
+    fn add_assign(&mut self, counts: Counts) {
+        self.simd_pixels += counts.simd_pixels;
+        self.peel_pixels += counts.peel_pixels;
+        self.loads += counts.loads;
+        self.tiles += counts.tiles;
+    }
+}
+
+std::thread_local! {
+    static COUNTS: Cell<Counts> = Cell::new(Counts::default());
+}
+
+fn count(f: impl FnOnce(&mut Counts)) {
+    COUNTS.with(|counts| {
+        let mut c = counts.get();
+        f(&mut c);
+        counts.set(c);
+    });
+}
+
+// counts `n` output pixels computed by SIMD vectors
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+pub(crate) fn simd_pixels(n: usize) {
+    count(|c| c.simd_pixels += n as u64);
+}
+
+// counts `n` output pixels computed one by one
+pub(crate) fn peel_pixels(n: usize) {
+    count(|c| c.peel_pixels += n as u64);
+}
+
+// counts `n` vector loads of source pixels
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+pub(crate) fn loads(n: usize) {
+    count(|c| c.loads += n as u64);
+}
+
+// counts a tile
+pub(crate) fn tile() {
+    count(|c| c.tiles += 1);
+}
+
+// counts of the current thread since the last call
+pub(crate) fn take() -> Counts {
+    COUNTS.with(|counts| counts.take())
+}
@@ -0,0 +1,514 @@
+//! Row-by-row and band-by-band convolution of streamed images.
+
+use alloc::{collections::VecDeque, string::ToString, vec, vec::Vec};
+
+use crate::{
+    backend::ConvBackend,
+    error::{Error, Result},
+    image::RgbImage,
+    processor::{border_index, pad_row, BorderMode, ConvProcessor, Split},
+    C,
+};
+
+/// Iterator yielding output rows of a convolution as soon as they can be computed,
+/// created by [`ConvProcessor::stream_rows`].
+///
+/// Only the last `K` input rows are held, so neither the input nor the output image has to be
+/// materialized. Each item is a row of `width * 3` bytes, and the rows are the same as the ones of
+/// [`ConvProcessor::convolve`]. Rows are computed on the calling thread regardless of
+/// [`ConvProcessor::threads`].
+///
+/// Yields [`Error::InvalidLength`] for an input row of another length,
+/// [`Error::ImageTooSmall`] if `width` is less than `K` or the input ends before `K` rows, and
+/// [`Error::UnsupportedFormat`] for processors in
+/// [linear light](crate::processor::ConvProcessorBuilder::linear_light) or
+/// [on the luma only](crate::processor::ConvProcessorBuilder::luma_only), then stops.
+#[derive(Debug)]
+pub struct RowStream<'a, const K: usize, I> {
+    processor: &'a ConvProcessor<K>,
+    backend: &'static dyn ConvBackend<K>,
+    input: I,
+    width: usize,
+    // last K input rows, padded on both sides unless the border mode is zero
+    history: VecDeque<Vec<u8>>,
+    received: usize,
+    next_row: usize,
+    input_done: bool,
+    failed: bool,
+    // K rows around the current output row, and the convolved row
+    window: RgbImage,
+    out: Vec<u8>,
+}
+
+impl<'a, const K: usize, I, R> RowStream<'a, K, I>
+where
+    I: Iterator<Item = R>,
+    R: AsRef<[u8]>,
+{
+    pub(crate) fn new(
+        processor: &'a ConvProcessor<K>,
+        backend: &'static dyn ConvBackend<K>,
+        input: I,
+        width: usize,
+    ) -> Self {
+        let padded = match processor.border() {
+            BorderMode::Zero => width,
+            _ => width + K / 2 * 2,
+        };
+        Self {
+            processor,
+            backend,
+            input,
+            width,
+            history: VecDeque::with_capacity(K),
+            received: 0,
+            next_row: 0,
+            input_done: false,
+            failed: false,
+            window: RgbImage::from_raw_unchecked(vec![0; K * padded * C], K, padded),
+            out: vec![0; padded * C],
+        }
+    }
+
+    fn push(&mut self, row: &[u8]) -> Result<()> {
+        if row.len() != self.width * C {
+            return Err(Error::InvalidLength {
+                expected: self.width * C,
+                actual: row.len(),
+            });
+        }
+        // reuse the buffer of the row dropped from the history
+        let mut buf = match self.history.len() {
+            len if len == K => self.history.pop_front().unwrap(),
+            _ => vec![0; self.window.width * C],
+        };
+        match self.processor.border() {
+            BorderMode::Zero => buf.copy_from_slice(row),
+            border => pad_row(row, K / 2, border, &mut buf),
+        }
+        self.history.push_back(buf);
+        self.received += 1;
+        Ok(())
+    }
+
+    // the history covers rows `y - K / 2..=y + K / 2` (or the last K rows at the bottom)
+    fn compute(&mut self, y: usize, height: Option<usize>) -> Vec<u8> {
+        let half = K / 2;
+        let border = self.processor.border();
+        // height is unknown until the input ends, but rows beyond the received ones
+        // are only referred at the bottom
+        let h = height.unwrap_or(self.received);
+        if border == BorderMode::Zero && (y < half || y + half >= h) {
+            return vec![0; self.width * C];
+        }
+
+        let first = self.received - self.history.len();
+        let row = self.window.width * C;
+        for (i, dst) in self.window.inner.chunks_exact_mut(row).enumerate() {
+            let sy = border_index((y + i) as isize - half as isize, h, border);
+            dst.copy_from_slice(&self.history[sy - first]);
+        }
+        self.backend.convolve_rows(
+            self.processor.kernel(),
+            &self.window,
+            half..half + 1,
+            &mut self.out,
+        );
+        match border {
+            BorderMode::Zero => self.out.clone(),
+            _ => self.out[half * C..(half + self.width) * C].to_vec(),
+        }
+    }
+}
+
+impl<'a, const K: usize, I, R> Iterator for RowStream<'a, K, I>
+where
+    I: Iterator<Item = R>,
+    R: AsRef<[u8]>,
+{
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        if self.processor.linear_light() || self.processor.luma_only() {
+            self.failed = true;
+            return Some(Err(Error::UnsupportedFormat(
+                "rows in linear light or luma only".to_string(),
+            )));
+        }
+        if self.width < K {
+            self.failed = true;
+            return Some(Err(Error::ImageTooSmall {
+                height: self.received,
+                width: self.width,
+                min: K,
+            }));
+        }
+        let half = K / 2;
+        loop {
+            // wait for K rows so that a too small image fails before yielding anything
+            if self.received >= K && self.next_row + half < self.received {
+                break;
+            }
+            if self.input_done {
+                if self.received < K {
+                    self.failed = true;
+                    return Some(Err(Error::ImageTooSmall {
+                        height: self.received,
+                        width: self.width,
+                        min: K,
+                    }));
+                }
+                if self.next_row == self.received {
+                    return None;
+                }
+                let row = self.compute(self.next_row, Some(self.received));
+                self.next_row += 1;
+                return Some(Ok(row));
+            }
+            match self.input.next() {
+                Some(row) => {
+                    if let Err(e) = self.push(row.as_ref()) {
+                        self.failed = true;
+                        return Some(Err(e));
+                    }
+                }
+                None => self.input_done = true,
+            }
+        }
+
+        let row = self.compute(self.next_row, None);
+        self.next_row += 1;
+        Some(Ok(row))
+    }
+}
+
+// Convolves an image of `height` x `width` pixels read by `read` into output bands of `band`
+// rows given to `write`, see `ConvProcessor::convolve_bands`.
+//
+// The window holds the extended rows `y0 - K / 2..y1 + K / 2` of the band `y0..y1`, padded on
+// both sides unless the border mode is zero: its last `K - 1` rows are the first ones of the next
+// band, so that every source row is read once. Rows above and below the image are copied from
+// the ones they refer to once those are read (they are always in the window), or zeros.
+pub(crate) fn convolve_bands<const K: usize, R, W>(
+    processor: &ConvProcessor<K>,
+    backend: &'static dyn ConvBackend<K>,
+    (height, width): (usize, usize),
+    band: usize,
+    mut read: R,
+    mut write: W,
+) -> Result<()>
+where
+    R: FnMut(usize, &mut [u8]) -> Result<()>,
+    W: FnMut(usize, &[u8]) -> Result<()>,
+{
+    let min = processor.min_size();
+    if height < min || width < min {
+        return Err(Error::ImageTooSmall { height, width, min });
+    }
+    if processor.linear_light() || processor.luma_only() {
+        return Err(Error::UnsupportedFormat(
+            "bands in linear light or luma only".to_string(),
+        ));
+    }
+    let (half, border) = (K / 2, processor.border());
+    let band = band.clamp(1, height);
+    let padded = match border {
+        BorderMode::Zero => width,
+        _ => width + 2 * half,
+    };
+    let (row, padded_row) = (width * C, padded * C);
+    let mut window = RgbImage::from_raw_unchecked(
+        vec![0; (band + 2 * half) * padded_row],
+        band + 2 * half,
+        padded,
+    );
+    let mut out = vec![0; band * padded_row];
+    let mut line = vec![0; row];
+
+    for y0 in (0..height).step_by(band) {
+        let y1 = (y0 + band).min(height);
+        let (n, rows) = (y1 - y0, y1 - y0 + 2 * half);
+        // extended row `top + i` is row `i` of the window
+        let top = y0 as isize - half as isize;
+        let kept = if y0 == 0 {
+            0
+        } else {
+            let full = band + 2 * half;
+            window
+                .inner
+                .copy_within((full - 2 * half) * padded_row.., 0);
+            2 * half
+        };
+        if rows < window.height {
+            // last band
+            window.inner.truncate(rows * padded_row);
+            window.height = rows;
+        }
+
+        // source rows, then the ones above and below the image
+        let first = (top + kept as isize).max(0) as usize;
+        let last = (y1 + half).min(height);
+        if first < last {
+            let start = (first as isize - top) as usize;
+            let dst = &mut window.inner[start * padded_row..(start + last - first) * padded_row];
+            match border {
+                BorderMode::Zero => read(first, dst)?,
+                _ => {
+                    for (y, dst) in (first..last).zip(dst.chunks_exact_mut(padded_row)) {
+                        read(y, &mut line)?;
+                        pad_row(&line, half, border, dst);
+                    }
+                }
+            }
+        }
+        for i in kept..rows {
+            let y = top + i as isize;
+            if y >= 0 && (y as usize) < height {
+                continue;
+            }
+            let dst = i * padded_row;
+            match border {
+                BorderMode::Zero => window.inner[dst..dst + padded_row].fill(0),
+                _ => {
+                    let src = (border_index(y, height, border) as isize - top) as usize;
+                    let src = src * padded_row;
+                    window.inner.copy_within(src..src + padded_row, dst);
+                }
+            }
+        }
+
+        let split = Split::Threads(processor.threads());
+        let (tile, traversal) = (processor.tile_size(), processor.traversal());
+        let out = &mut out[..n * padded_row];
+        processor
+            .run_rows(
+                backend,
+                &window,
+                half..half + n,
+                out,
+                split,
+                tile,
+                traversal,
+            )
+            .map_err(|e| match e {
+                Error::Cancelled { completed_rows } => Error::Cancelled {
+                    completed_rows: y0 + completed_rows,
+                },
+                e => e,
+            })?;
+        match border {
+            BorderMode::Zero => {
+                // pixels closer than K / 2 to the border are 0
+                for (y, r) in (y0..y1).zip(out.chunks_exact_mut(row)) {
+                    if y < half || y + half >= height {
+                        r.fill(0);
+                    } else {
+                        r[..half * C].fill(0);
+                        r[row - half * C..].fill(0);
+                    }
+                }
+            }
+            _ => {
+                for r in 0..n {
+                    let start = r * padded_row + half * C;
+                    out.copy_within(start..start + row, r * row);
+                }
+            }
+        }
+        write(y0, &out[..n * row])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::*;
+
+    fn collect<const K: usize>(layer: &ConvProcessor<K>, img: &RgbImage) -> Result<RgbImage>
+    where
+        [(); (K / 2 + 1) / 2 + 1]: Sized,
+    {
+        let rows = img.content().chunks_exact(img.width * C);
+        let mut content = vec![];
+        for row in layer.stream_rows(rows, img.width) {
+            content.extend(row?);
+        }
+        RgbImage::from_raw(content, img.height, img.width)
+    }
+
+    #[test]
+    fn same_as_convolve() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        for &border in [BorderMode::Zero, BorderMode::Replicate, BorderMode::Reflect].iter() {
+            let layer = ConvProcessor::<5>::builder()
+                .kernel(&[1.; 25])
+                .normalize(true)
+                .border(border)
+                .build()?;
+            assert_eq!(
+                collect(&layer, &img)?,
+                layer.convolve(&img)?,
+                "{:?}",
+                border
+            );
+        }
+
+        // smallest image
+        let tiny = RgbImage::from_raw(img.content()[..3 * 3 * C].to_vec(), 3, 3)?;
+        let layer = ConvProcessor::<3>::new(&SOBEL_FILTER, false)?;
+        assert_eq!(collect(&layer, &tiny)?, layer.convolve(&tiny)?);
+        Ok(())
+    }
+
+    // convolves `img` band by band, checking that source rows are read once and in order
+    fn bands<const K: usize>(
+        layer: &ConvProcessor<K>,
+        img: &RgbImage,
+        band: usize,
+    ) -> Result<RgbImage>
+    where
+        [(); (K / 2 + 1) / 2 + 1]: Sized,
+    {
+        let row = img.width * C;
+        let mut next = 0;
+        let mut content = vec![];
+        layer.convolve_bands(
+            img.height,
+            img.width,
+            band,
+            |y, buf| {
+                assert_eq!(y, next);
+                next += buf.len() / row;
+                buf.copy_from_slice(&img.content()[y * row..y * row + buf.len()]);
+                Ok(())
+            },
+            |y, rows| {
+                assert_eq!(y * row, content.len());
+                assert!(rows.len() <= band.clamp(1, img.height) * row);
+                content.extend_from_slice(rows);
+                Ok(())
+            },
+        )?;
+        assert_eq!(next, img.height);
+        RgbImage::from_raw(content, img.height, img.width)
+    }
+
+    #[test]
+    fn same_as_convolve_bands() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        for &border in [BorderMode::Zero, BorderMode::Replicate, BorderMode::Reflect].iter() {
+            let layer = ConvProcessor::<5>::builder()
+                .kernel(&[1.; 25])
+                .normalize(true)
+                .border(border)
+                .threads(2)
+                .build()?;
+            let expected = layer.convolve(&img)?;
+            for &band in [1, 2, 7, 64, img.height, usize::MAX].iter() {
+                assert_eq!(
+                    bands(&layer, &img, band)?,
+                    expected,
+                    "{:?} {}",
+                    border,
+                    band
+                );
+            }
+        }
+
+        // images shorter than the padding
+        let tiny = RgbImage::from_raw(img.content()[..2 * 9 * C].to_vec(), 2, 9)?;
+        let layer = ConvProcessor::<7>::builder()
+            .kernel(&[1.; 49])
+            .normalize(true)
+            .border(BorderMode::Reflect)
+            .build()?;
+        for &band in [0, 1, 2, 3].iter() {
+            assert_eq!(bands(&layer, &tiny, band)?, layer.convolve(&tiny)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn band_errors() -> Result<()> {
+        let layer = ConvProcessor::<3>::new(&[1.; 9], true)?;
+        let ignore = |_: usize, _: &[u8]| Ok(());
+        assert!(matches!(
+            layer.convolve_bands(2, 4, 8, |_, _| Ok(()), ignore),
+            Err(Error::ImageTooSmall { height: 2, .. })
+        ));
+        // errors of the callbacks are returned
+        let mut written = 0;
+        let result = layer.convolve_bands(
+            10,
+            4,
+            3,
+            |_, _| Ok(()),
+            |_, _| {
+                written += 1;
+                Err(Error::InvalidLength {
+                    expected: 0,
+                    actual: 1,
+                })
+            },
+        );
+        assert!(matches!(result, Err(Error::InvalidLength { .. })));
+        assert_eq!(written, 1);
+
+        let layer = ConvProcessor::<3>::builder()
+            .kernel(&[1.; 9])
+            .luma_only(true)
+            .build()?;
+        assert!(matches!(
+            layer.convolve_bands(4, 4, 1, |_, _| Ok(()), ignore),
+            Err(Error::UnsupportedFormat(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn errors() -> Result<()> {
+        let layer = ConvProcessor::<3>::new(&[1.; 9], true)?;
+        let rows = vec![vec![0u8; 4 * C]; 2];
+        let mut stream = layer.stream_rows(rows.iter(), 4);
+        assert!(matches!(
+            stream.next(),
+            Some(Err(Error::ImageTooSmall { height: 2, .. }))
+        ));
+        assert!(stream.next().is_none());
+
+        let rows = vec![vec![0u8; 4 * C], vec![0u8; 4 * C], vec![0u8; 5 * C]];
+        let mut stream = layer.stream_rows(rows.iter(), 4);
+        assert!(matches!(
+            stream.next(),
+            Some(Err(Error::InvalidLength { actual: 15, .. }))
+        ));
+        assert!(stream.next().is_none());
+
+        let rows = vec![vec![0u8; 2 * C]; 3];
+        let mut stream = layer.stream_rows(rows.iter(), 2);
+        assert!(matches!(
+            stream.next(),
+            Some(Err(Error::ImageTooSmall { width: 2, .. }))
+        ));
+
+        let rows = vec![vec![0u8; 4 * C]; 4];
+        for (linear, luma) in [(true, false), (false, true)] {
+            let layer = ConvProcessor::<3>::builder()
+                .kernel(&[1.; 9])
+                .linear_light(linear)
+                .luma_only(luma)
+                .build()?;
+            let mut stream = layer.stream_rows(rows.iter(), 4);
+            assert!(matches!(
+                stream.next(),
+                Some(Err(Error::UnsupportedFormat(_)))
+            ));
+            assert!(stream.next().is_none());
+        }
+        Ok(())
+    }
+}
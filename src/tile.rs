@@ -0,0 +1,137 @@
+//! Convolution of rows in 2D tiles.
+//!
+//! Backends compute whole rows, so that the `K` source rows of a very wide image may not stay in
+//! cache until the next output row. Tiles copy a region with its `K - 1` halo into a small buffer
+//! and convolve it there instead, band by band or column by column (see
+//! [`Traversal`](crate::Traversal)).
+
+use alloc::vec::Vec;
+use core::ops::Range;
+
+#[cfg(feature = "stats")]
+use crate::stats;
+use crate::{
+    allocator::ImageAllocator, backend::ConvBackend, cancel::CancelToken, image::RgbImage,
+    kernel::ConvKernel, processor::Traversal, C,
+};
+
+// Convolves `rows` of `src` into `dst` like `ConvBackend::convolve_rows`, tile by tile.
+// Tiles have `tile.0` rows and `tile.1` pixels (without the halo); the last ones in each
+// direction may be smaller. `Traversal::Columns` computes the tiles column by column, so that the
+// halo rows shared by vertically adjacent tiles are still in cache.
+//
+// The token is checked before each band (or column) of tiles. Returns the number of computed
+// rows, which is 0 if columns were cancelled. Tile buffers are allocated by `allocator`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn convolve_tiles<const K: usize, B: ConvBackend<K> + ?Sized>(
+    backend: &B,
+    kernel: &ConvKernel<K>,
+    src: &RgbImage,
+    rows: Range<usize>,
+    dst: &mut [u8],
+    tile: (usize, usize),
+    traversal: Traversal,
+    cancel: Option<&CancelToken>,
+    allocator: &ImageAllocator,
+) -> usize {
+    let half = K / 2;
+    let w = src.width;
+    let row = w * C;
+    let (tile_h, tile_w) = (tile.0.max(1), tile.1.max(1));
+    let cancelled = || cancel.map_or(false, |t| t.is_cancelled());
+    // source region of a tile with its halo, and the convolved rows of it
+    let mut buf = RgbImage::from_buffer_unchecked(Vec::new_in(allocator.clone()), 0, 0);
+    let mut out = Vec::new_in(allocator.clone());
+
+    let mut convolve_tile = |y0: usize, x0: usize| {
+        let y1 = (y0 + tile_h).min(rows.end);
+        let x1 = (x0 + tile_w).min(w - half);
+        let band = &mut dst[(y0 - rows.start) * row..(y1 - rows.start) * row];
+        // tile with the halo, whose inner pixels are x0..x1 of src
+        let (th, tw) = (y1 - y0 + 2 * half, x1 - x0 + 2 * half);
+        buf.inner.resize(th * tw * C, 0);
+        buf.height = th;
+        buf.width = tw;
+        for (i, r) in buf.inner.chunks_exact_mut(tw * C).enumerate() {
+            let sy = y0 - half + i;
+            r.copy_from_slice(&src.inner[sy * row + (x0 - half) * C..sy * row + (x1 + half) * C]);
+        }
+
+        out.resize((y1 - y0) * tw * C, 0);
+        #[cfg(feature = "stats")]
+        stats::tile();
+        backend.convolve_rows(kernel, &buf, half..th - half, &mut out);
+        for (d, s) in band.chunks_exact_mut(row).zip(out.chunks_exact(tw * C)) {
+            d[x0 * C..x1 * C].copy_from_slice(&s[half * C..(half + x1 - x0) * C]);
+        }
+    };
+
+    match traversal {
+        Traversal::Rows => {
+            for y0 in rows.clone().step_by(tile_h) {
+                if cancelled() {
+                    return y0 - rows.start;
+                }
+                for x0 in (half..w - half).step_by(tile_w) {
+                    convolve_tile(y0, x0);
+                }
+            }
+        }
+        Traversal::Columns => {
+            for x0 in (half..w - half).step_by(tile_w) {
+                if cancelled() {
+                    return 0;
+                }
+                for y0 in rows.clone().step_by(tile_h) {
+                    convolve_tile(y0, x0);
+                }
+            }
+        }
+    }
+    rows.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{backend::available, consts::*};
+
+    #[test]
+    fn same_as_rows() -> crate::Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let kernel = ConvKernel::<5>::new(&[1.; 25], true)?;
+        let rows = 2..img.height - 2;
+        let row = img.width * C;
+        for backend in available::<5>() {
+            let mut expected = vec![0; rows.len() * row];
+            backend.convolve_rows(&kernel, &img, rows.clone(), &mut expected);
+            for &tile in [(1, 1), (7, 13), (64, 64), (img.height, img.width)].iter() {
+                for &traversal in [Traversal::Rows, Traversal::Columns].iter() {
+                    let mut dst = vec![0; rows.len() * row];
+                    let done = convolve_tiles(
+                        backend,
+                        &kernel,
+                        &img,
+                        rows.clone(),
+                        &mut dst,
+                        tile,
+                        traversal,
+                        None,
+                        &ImageAllocator::global(),
+                    );
+                    assert_eq!(done, rows.len());
+                    assert!(
+                        dst == expected,
+                        "{:?} with tile {:?} {:?}",
+                        backend,
+                        tile,
+                        traversal
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
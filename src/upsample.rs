@@ -0,0 +1,325 @@
+//! Transposed convolution, enlarging images by an integer factor.
+
+use alloc::{format, string::ToString, vec, vec::Vec};
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{
+    error::{Error, Result},
+    image::RgbImage,
+    kernel::ConvKernel,
+    C,
+};
+
+/// KxK transposed convolution with a stride, e.g. the learned 2x upsampling of decoders.
+///
+/// Each source pixel `(y, x)` adds itself weighted by the kernel to the output pixels around
+/// `(y * stride, x * stride)`, i.e. the weight at `(i, j)` goes to
+/// `(y * stride + i - K / 2, x * stride + j - K / 2)`, and the output has `stride` times as many
+/// rows and columns as the source. This is the same as inserting `stride - 1` zeros between
+/// source pixels and convolving with the kernel rotated by 180 degrees.
+///
+/// Each output pixel only receives the weights at its phase, about `1 / stride²` of them, so a
+/// kernel preserving the brightness sums up to `stride²`, e.g. [`bilinear`](Self::bilinear).
+#[derive(Debug)]
+pub struct TransposedConvProcessor<const K: usize> {
+    kernel: ConvKernel<K>,
+    stride: usize,
+}
+
+impl<const K: usize> TransposedConvProcessor<K> {
+    /// Creates a processor enlarging images `stride` times with `kernel`.
+    ///
+    /// Fails with [`Error::BadKernel`] if `stride` is 0.
+    pub fn new(kernel: ConvKernel<K>, stride: usize) -> Result<Self> {
+        if stride == 0 {
+            return Err(Error::BadKernel("stride must be positive".to_string()));
+        }
+        Ok(Self { kernel, stride })
+    }
+
+    /// Bilinear interpolation between the source pixels, with `K = 2 * stride - 1`.
+    ///
+    /// Fails with [`Error::BadKernel`] if `stride` is 0 or `K` is not `2 * stride - 1`.
+    pub fn bilinear(stride: usize) -> Result<Self> {
+        if stride == 0 || K != 2 * stride - 1 {
+            return Err(Error::BadKernel(format!(
+                "bilinear kernel for stride {} is not {}x{}",
+                stride, K, K
+            )));
+        }
+        // tent falling linearly from 1 at the center to 0 at `stride` pixels
+        let half = (K / 2) as f32;
+        let tent: Vec<f32> = (0..K)
+            .map(|t| 1. - (t as f32 - half).abs() / stride as f32)
+            .collect();
+        let weights: Vec<f32> = tent
+            .iter()
+            .flat_map(|&a| tent.iter().map(move |&b| a * b))
+            .collect();
+        Self::new(ConvKernel::new(&weights, false)?, stride)
+    }
+
+    /// Kernel of the convolution.
+    pub fn kernel(&self) -> &ConvKernel<K> {
+        &self.kernel
+    }
+
+    /// Factor by which images are enlarged.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// Height and width of the output for an input of the given size.
+    pub fn output_size(&self, height: usize, width: usize) -> (usize, usize) {
+        (height * self.stride, width * self.stride)
+    }
+
+    /// Scalar implementation.
+    pub fn naive(&self, src: &RgbImage) -> Result<RgbImage> {
+        let (oh, ow) = self.output_size(src.height, src.width);
+        let mut dst = vec![0u8; oh * ow * C];
+        for oy in 0..oh {
+            let rows = self.taps(oy, src.height);
+            for ox in 0..ow {
+                let cols = self.taps(ox, src.width);
+                for c in 0..C {
+                    dst[(oy * ow + ox) * C + c] = self.pixel(src, &rows, &cols, c);
+                }
+            }
+        }
+        Ok(RgbImage::from_raw_unchecked(dst, oh, ow))
+    }
+
+    /// NEON implementation. Output pixels of the same phase (`ox % stride`) in a row take the
+    /// same weights from consecutive source pixels, so they are accumulated 16 at a time.
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    pub fn simd(&self, src: &RgbImage) -> Result<RgbImage> {
+        let (h, w) = (src.height, src.width);
+        let s = self.stride;
+        let (oh, ow) = self.output_size(h, w);
+        let mut dst = vec![0u8; oh * ow * C];
+        let div = self.kernel.div.map(simd::Divisor::new);
+        // output columns of the row computed 16 at a time, the others are left to the serial loop
+        let mut done = vec![false; ow];
+        for oy in 0..oh {
+            let rows = self.taps(oy, h);
+            done.fill(false);
+            for px in 0..s {
+                // taps of the phase as offsets from the source column `ox / stride`, which
+                // every output column of the phase has
+                let cols: Vec<(usize, isize)> = (0..K)
+                    .filter_map(|j| {
+                        let p = (px + K / 2) as isize - j as isize;
+                        if p.rem_euclid(s as isize) == 0 {
+                            Some((j, p.div_euclid(s as isize)))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect();
+                let (lo, hi) = match (cols.first(), cols.last()) {
+                    // offsets decrease with `j`
+                    (Some(&(_, hi)), Some(&(_, lo))) => (lo, hi),
+                    _ => continue,
+                };
+                // 16 source columns from `n` whose taps are all in the image
+                let mut n = (-lo).max(0) as usize;
+                while n as isize + 16 + hi <= w as isize {
+                    let mut acc: simd::Rgb16 = [[simd::splat(0.); 4]; C];
+                    for &(i, y) in &rows {
+                        for &(j, dx) in &cols {
+                            let x = (n as isize + dx) as usize;
+                            let v = simd::load_rgb16(src.content(), (y * w + x) * C);
+                            let weight = simd::splat(self.kernel.at(i, j));
+                            for (acc, v) in acc.iter_mut().flatten().zip(v.iter().flatten()) {
+                                *acc = simd::fma(*acc, *v, weight);
+                            }
+                        }
+                    }
+                    simd::divide(&mut acc, div);
+                    let mut out = [[0u8; 16]; C];
+                    for (out, acc) in out.iter_mut().zip(acc.iter()) {
+                        simd::store_u8x16(out, 0, acc, self.kernel.rounding);
+                    }
+                    for l in 0..16 {
+                        let ox = (n + l) * s + px;
+                        for (c, out) in out.iter().enumerate() {
+                            dst[(oy * ow + ox) * C + c] = out[l];
+                        }
+                        done[ox] = true;
+                    }
+                    n += 16;
+                }
+            }
+            for ox in (0..ow).filter(|&ox| !done[ox]) {
+                let cols = self.taps(ox, w);
+                for c in 0..C {
+                    dst[(oy * ow + ox) * C + c] = self.pixel(src, &rows, &cols, c);
+                }
+            }
+        }
+        Ok(RgbImage::from_raw_unchecked(dst, oh, ow))
+    }
+
+    // `(kernel index, source index)` of the taps reaching the output index `o` along an axis
+    // of `len` source pixels
+    fn taps(&self, o: usize, len: usize) -> Vec<(usize, usize)> {
+        let s = self.stride as isize;
+        (0..K)
+            .filter_map(|i| {
+                let p = (o + K / 2) as isize - i as isize;
+                let y = p.div_euclid(s);
+                if p.rem_euclid(s) == 0 && (0..len as isize).contains(&y) {
+                    Some((i, y as usize))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    // output channel `c` from the taps of its row and column
+    fn pixel(
+        &self,
+        src: &RgbImage,
+        rows: &[(usize, usize)],
+        cols: &[(usize, usize)],
+        c: usize,
+    ) -> u8 {
+        let mut t = 0.;
+        for &(i, y) in rows {
+            for &(j, x) in cols {
+                t += src.content()[(y * src.width + x) * C + c] as f32 * self.kernel.at(i, j);
+            }
+        }
+        if let Some(div) = self.kernel.div {
+            t /= div;
+        }
+        self.kernel.rounding.to_u8(t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every source pixel added to the output, as the transposed convolution is defined
+    fn scatter<const K: usize>(layer: &TransposedConvProcessor<K>, src: &RgbImage) -> RgbImage {
+        let (h, w, s) = (src.height, src.width, layer.stride());
+        let (oh, ow) = layer.output_size(h, w);
+        let mut acc = vec![0f32; oh * ow * C];
+        for y in 0..h {
+            for x in 0..w {
+                for i in 0..K {
+                    for j in 0..K {
+                        let oy = (y * s + i) as isize - (K / 2) as isize;
+                        let ox = (x * s + j) as isize - (K / 2) as isize;
+                        if !(0..oh as isize).contains(&oy) || !(0..ow as isize).contains(&ox) {
+                            continue;
+                        }
+                        for c in 0..C {
+                            acc[(oy as usize * ow + ox as usize) * C + c] +=
+                                src.content()[(y * w + x) * C + c] as f32 * layer.kernel().at(i, j);
+                        }
+                    }
+                }
+            }
+        }
+        let content = acc.iter().map(|&t| t as u8).collect();
+        RgbImage::from_raw_unchecked(content, oh, ow)
+    }
+
+    fn image(h: usize, w: usize) -> RgbImage {
+        let content = (0..h * w * C).map(|i| (i * 37 % 251) as u8 / 4).collect();
+        RgbImage::from_raw_unchecked(content, h, w)
+    }
+
+    #[test]
+    fn bilinear() -> Result<()> {
+        let src = RgbImage::from_raw(
+            [10u8, 20, 30, 50].iter().flat_map(|&v| [v; C]).collect(),
+            2,
+            2,
+        )?;
+        let layer = TransposedConvProcessor::<3>::bilinear(2)?;
+        let dst = layer.naive(&src)?;
+        assert_eq!((dst.height, dst.width), (4, 4));
+        let first: Vec<u8> = dst.content().iter().step_by(C).copied().collect();
+        // the last row and column fade to the zeros outside the source
+        #[rustfmt::skip]
+        assert_eq!(
+            first,
+            [
+                10, 15, 20, 10,
+                20, 27, 35, 17,
+                30, 40, 50, 25,
+                15, 20, 25, 12,
+            ]
+        );
+        assert!(matches!(
+            TransposedConvProcessor::<5>::bilinear(2),
+            Err(Error::BadKernel(_))
+        ));
+        assert!(matches!(
+            TransposedConvProcessor::new(ConvKernel::<3>::new(&[1.; 9], false)?, 0),
+            Err(Error::BadKernel(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn same_as_scatter() -> Result<()> {
+        let src = image(7, 11);
+        let weights: Vec<f32> = (0..25).map(|i| (i % 4) as f32 * 0.25).collect();
+        for stride in 1..4 {
+            let layer =
+                TransposedConvProcessor::new(ConvKernel::<5>::new(&weights, false)?, stride)?;
+            assert_eq!(
+                layer.naive(&src)?,
+                scatter(&layer, &src),
+                "stride {}",
+                stride
+            );
+            let layer =
+                TransposedConvProcessor::<3>::new(ConvKernel::new(&[1.; 9], false)?, stride)?;
+            assert_eq!(
+                layer.naive(&src)?,
+                scatter(&layer, &src),
+                "stride {}",
+                stride
+            );
+        }
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    #[test]
+    fn simd() -> Result<()> {
+        // several vectors of 16 columns, and some left over
+        for (h, w) in [(5, 3), (6, 40), (9, 53)] {
+            let src = image(h, w);
+            for stride in 1..5 {
+                let layer = TransposedConvProcessor::<3>::new(
+                    ConvKernel::new(&[1., 2., 1., 2., 4., 2., 1., 2., 1.], true)?,
+                    stride,
+                )?;
+                assert_eq!(
+                    layer.simd(&src)?,
+                    layer.naive(&src)?,
+                    "3x3 stride {}",
+                    stride
+                );
+                let layer =
+                    TransposedConvProcessor::<7>::new(ConvKernel::new(&[0.5; 49], false)?, stride)?;
+                assert_eq!(
+                    layer.simd(&src)?,
+                    layer.naive(&src)?,
+                    "7x7 stride {}",
+                    stride
+                );
+            }
+        }
+        Ok(())
+    }
+}
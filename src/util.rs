@@ -1,9 +1,31 @@
-use std::mem;
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    any(
+        feature = "impl-simd1",
+        feature = "impl-simd2",
+        feature = "impl-simd3",
+        feature = "impl-wide"
+    )
+))]
+use core::mem;
 
 #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
-use std::arch::aarch64::*;
+use core::arch::aarch64::*;
 
 #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::kernel::Rounding;
+
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    any(
+        feature = "impl-simd1",
+        feature = "impl-simd2",
+        feature = "impl-simd3",
+        feature = "impl-wide"
+    )
+))]
 #[inline]
 pub unsafe fn init_multiple_float32x4x3<const N: usize>(value: f32) -> [float32x4x3_t; N] {
     let mut init = [mem::zeroed::<float32x4x3_t>(); N];
@@ -13,26 +35,196 @@ pub unsafe fn init_multiple_float32x4x3<const N: usize>(value: f32) -> [float32x
     init
 }
 
-#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[cfg(all(
+    any(target_arch = "aarch64"),
+    target_feature = "neon",
+    any(
+        feature = "impl-simd1",
+        feature = "impl-simd2",
+        feature = "impl-simd3",
+        feature = "impl-wide"
+    )
+))]
 #[inline]
 pub unsafe fn init_float32x4x3(value: f32) -> float32x4x3_t {
     float32x4x3_t(vdupq_n_f32(value), vdupq_n_f32(value), vdupq_n_f32(value))
 }
 
+// `&slice[index]` for a vector load of `len` elements from there. Slice indexing only checks
+// the first element, so the whole vector is checked in debug builds and with the `audit` feature.
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[inline(always)]
+#[track_caller]
+pub fn vec_ref<T>(slice: &[T], index: usize, len: usize) -> &T {
+    if cfg!(any(debug_assertions, feature = "audit")) {
+        audit(slice.len(), index, len);
+    }
+    &slice[index]
+}
+
+// `&mut slice[index]` for a vector store of `len` elements, checked like `vec_ref`
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[inline(always)]
+#[track_caller]
+pub fn vec_mut<T>(slice: &mut [T], index: usize, len: usize) -> &mut T {
+    if cfg!(any(debug_assertions, feature = "audit")) {
+        audit(slice.len(), index, len);
+    }
+    &mut slice[index]
+}
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[inline(never)]
+#[track_caller]
+fn audit(slice_len: usize, index: usize, len: usize) {
+    assert!(
+        index.checked_add(len).map_or(false, |end| end <= slice_len),
+        "vector of {} elements at {} out of bounds of a slice of length {}",
+        len,
+        index,
+        slice_len
+    );
+}
+
+// widen 16 uint8 lanes into 4 float32x4_t (0..4, 4..8, 8..12, 12..16 th lanes)
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[inline]
+pub unsafe fn u8x16_to_f32x4x4(s: uint8x16_t) -> [float32x4_t; 4] {
+    let lo = vmovl_u8(vget_low_u8(s));
+    let hi = vmovl_high_u8(s);
+    [
+        vcvtq_f32_u32(vmovl_u16(vget_low_u16(lo))),
+        vcvtq_f32_u32(vmovl_high_u16(lo)),
+        vcvtq_f32_u32(vmovl_u16(vget_low_u16(hi))),
+        vcvtq_f32_u32(vmovl_high_u16(hi)),
+    ]
+}
+
+// widen 16 uint8 lanes into 4 uint32x4_t, like `u8x16_to_f32x4x4`
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[inline]
+pub unsafe fn u8x16_to_u32x4x4(s: uint8x16_t) -> [uint32x4_t; 4] {
+    let lo = vmovl_u8(vget_low_u8(s));
+    let hi = vmovl_high_u8(s);
+    [
+        vmovl_u16(vget_low_u16(lo)),
+        vmovl_high_u16(lo),
+        vmovl_u16(vget_low_u16(hi)),
+        vmovl_high_u16(hi),
+    ]
+}
+
+// truncated quotients of 4 uint32x4_t by `div`, packed into 16 uint8 lanes and saturated. They
+// are the same as of integers for values below 2^24 and `div` below 2^16, as the quotient of
+// `f32` is then never rounded up to the next integer
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[inline]
+pub unsafe fn div_u32x4x4_to_u8x16(v: [uint32x4_t; 4], div: float32x4_t) -> uint8x16_t {
+    let q = v.map(|v| vcvtq_u32_f32(vdivq_f32(vcvtq_f32_u32(v), div)));
+    let lo = vqmovn_high_u32(vqmovn_u32(q[0]), q[1]);
+    let hi = vqmovn_high_u32(vqmovn_u32(q[2]), q[3]);
+    vqmovn_high_u16(vqmovn_u16(lo), hi)
+}
+
+// widen 8 uint8 lanes into 2 float32x4_t (0..4, 4..8 th lanes)
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[inline]
+pub unsafe fn u8x8_to_f32x4x2(s: uint8x8_t) -> [float32x4_t; 2] {
+    let s = vmovl_u8(s);
+    [
+        vcvtq_f32_u32(vmovl_u16(vget_low_u16(s))),
+        vcvtq_f32_u32(vmovl_high_u16(s)),
+    ]
+}
+
+// convert to u32 lanes with `rounding`, saturating negative values to 0
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[inline]
+pub unsafe fn f32x4_to_u32x4(v: float32x4_t, rounding: Rounding) -> uint32x4_t {
+    match rounding {
+        Rounding::Truncate => vcvtq_u32_f32(v),
+        // ties away from zero like f32::round
+        Rounding::Nearest => vcvtaq_u32_f32(v),
+    }
+}
+
+// convert to i32 lanes with `rounding`, like `f32x4_to_u32x4` for signed results
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[inline]
+pub unsafe fn f32x4_to_i32x4(v: float32x4_t, rounding: Rounding) -> int32x4_t {
+    match rounding {
+        Rounding::Truncate => vcvtq_s32_f32(v),
+        Rounding::Nearest => vcvtaq_s32_f32(v),
+    }
+}
+
+// pack 2 float32x4_t into 8 uint8 lanes, rounding and saturating to [0, 255]
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[inline]
+pub unsafe fn f32x4x2_to_u8x8(v: [float32x4_t; 2], rounding: Rounding) -> uint8x8_t {
+    vqmovn_u16(vqmovn_high_u32(
+        vqmovn_u32(f32x4_to_u32x4(v[0], rounding)),
+        f32x4_to_u32x4(v[1], rounding),
+    ))
+}
+
+// pack 4 float32x4_t into 16 uint8 lanes, rounding and saturating to [0, 255]
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[inline]
+pub unsafe fn f32x4x4_to_u8x16(v: [float32x4_t; 4], rounding: Rounding) -> uint8x16_t {
+    let [a, b, c, d] = v.map(|v| f32x4_to_u32x4(v, rounding));
+    let lo = vqmovn_high_u32(vqmovn_u32(a), b);
+    let hi = vqmovn_high_u32(vqmovn_u32(c), d);
+    vqmovn_high_u16(vqmovn_u16(lo), hi)
+}
+
+// multiply 16 uint8 lanes by `gain` with 8 fractional bits, widening to 32 bits, then round
+// and narrow back with saturation
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+#[inline]
+pub unsafe fn mul_q8_u8x16(v: uint8x16_t, gain: u16) -> uint8x16_t {
+    let lo = vmovl_u8(vget_low_u8(v));
+    let hi = vmovl_high_u8(v);
+    let (g4, g8) = (vdup_n_u16(gain), vdupq_n_u16(gain));
+    let lo = vqrshrn_high_n_u32::<8>(
+        vqrshrn_n_u32::<8>(vmull_u16(vget_low_u16(lo), g4)),
+        vmull_high_u16(lo, g8),
+    );
+    let hi = vqrshrn_high_n_u32::<8>(
+        vqrshrn_n_u32::<8>(vmull_u16(vget_low_u16(hi), g4)),
+        vmull_high_u16(hi, g8),
+    );
+    vqmovn_high_u16(vqmovn_u16(lo), hi)
+}
+
+#[cfg(feature = "std")]
 pub mod test_util {
-    use std::io;
+    use std::{
+        alloc::{AllocError, Allocator, Global, Layout},
+        fmt::Write as _,
+        fs,
+        path::Path,
+        ptr::{self, NonNull},
+    };
 
     use test::Bencher;
 
-    use crate::{consts::*, image::RgbImage, ConvProcessor};
+    use crate::{
+        allocator::ImageAllocator, backend::ConvBackend, consts::*, diff::diff, error::Result,
+        image::RgbImage, ConvProcessor,
+    };
 
+    /// Filters checked by tests and benchmarks.
     #[derive(Debug, Clone, Copy)]
     pub enum FilterType {
+        /// KxK box filter (averaging).
         Box(usize),
+        /// 3x3 Sobel filter, see [`SOBEL_FILTER`].
         Sobel,
     }
 
     impl FilterType {
+        /// Where the expected result is saved.
         pub fn answer_path(&self) -> String {
             match self {
                 FilterType::Box(k) => format!("img/box_ans_{}x{}.png", k, k),
@@ -40,6 +232,7 @@ pub mod test_util {
             }
         }
 
+        /// Weights of the filter.
         pub fn filter(&self) -> Vec<f32> {
             match self {
                 &FilterType::Box(k) => vec![1.; k * k],
@@ -47,6 +240,7 @@ pub mod test_util {
             }
         }
 
+        /// Whether the filter averages.
         pub const fn avg(&self) -> bool {
             match self {
                 FilterType::Box(_) => true,
@@ -54,6 +248,7 @@ pub mod test_util {
             }
         }
 
+        /// Kernel size.
         pub const fn size(&self) -> usize {
             match self {
                 &FilterType::Box(k) => k,
@@ -62,35 +257,255 @@ pub mod test_util {
         }
     }
 
-    // confirm answer image is valid before test
-    fn make<const K: usize>(ty: FilterType) -> io::Result<(RgbImage, ConvProcessor<K>)> {
+    /// How [`test`] checks the result of a backend.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Check {
+        /// No check, e.g. for benchmarks.
+        None,
+        /// Compares with the result of [`Naive1`](crate::backend::Naive1) computed in memory,
+        /// panicking with a [`DiffSummary`](crate::diff::DiffSummary) on mismatch. Nothing is
+        /// written to disk, so that tests can run in parallel on a read-only tree.
+        InMemory,
+        /// Like [`InMemory`](Check::InMemory), but tolerating differences as
+        /// [`RgbImage::approx_eq`] does, for lossy backends.
+        Approx {
+            /// Largest difference of a channel of a pixel counted as the same.
+            max_per_channel_diff: u8,
+            /// Largest number of differing pixels.
+            max_diff_count: usize,
+        },
+        /// Saves the result of [`Naive1`](crate::backend::Naive1) to
+        /// [`answer_path`](FilterType::answer_path) and compares with the reloaded PNG, saving a
+        /// wrong result to [`DEBUG`] and its [heatmap](crate::diff::Diff::heatmap) to
+        /// [`DEBUG_DIFF`].
+        Golden,
+    }
+
+    fn make<const K: usize>(ty: FilterType) -> Result<(RgbImage, ConvProcessor<K>)> {
         let img = RgbImage::load(ORIGINAL)?;
-        let layer = ConvProcessor::<K>::new(&ty.filter(), ty.avg());
-        layer.naive1(&img).save(ty.answer_path())?;
+        let layer = ConvProcessor::<K>::new(&ty.filter(), ty.avg())?;
         Ok((img, layer))
     }
 
-    pub fn test<const K: usize, F>(
+    /// Checks `backend` against [`Naive1`](crate::backend::Naive1) as `check` says,
+    /// then benchmarks it if `b` is given.
+    pub fn test<const K: usize, B>(
         b: Option<&mut Bencher>,
-        enable_assertion: bool,
+        check: Check,
         ty: FilterType,
-        f: F,
-    ) -> io::Result<()>
+        backend: &B,
+    ) -> Result<()>
     where
-        F: Fn(&ConvProcessor<K>, &RgbImage) -> RgbImage,
+        B: ConvBackend<K> + ?Sized,
     {
         let (img, layer) = make::<K>(ty)?;
         let processed = &mut RgbImage::empty(); // initialize with dummy
-        *processed = f(&layer, &img);
+        *processed = layer.apply(backend, &img)?;
 
-        if enable_assertion && *processed != RgbImage::load(ty.answer_path())? {
-            processed.save(DEBUG)?;
-            panic!("invalid calculation in {:?}", ty);
+        match check {
+            Check::None => {}
+            Check::InMemory | Check::Approx { .. } => {
+                let (max_per_channel_diff, max_diff_count) = match check {
+                    Check::Approx {
+                        max_per_channel_diff,
+                        max_diff_count,
+                    } => (max_per_channel_diff, max_diff_count),
+                    _ => (0, 0),
+                };
+                let expected = layer.naive1(&img)?;
+                if !processed.approx_eq(&expected, max_per_channel_diff, max_diff_count) {
+                    panic!(
+                        "invalid calculation by {} in {:?}: {}",
+                        backend.name(),
+                        ty,
+                        diff(processed, &expected)?.summary
+                    );
+                }
+            }
+            Check::Golden => {
+                // confirm answer image is valid before test
+                layer.naive1(&img)?.save(ty.answer_path())?;
+                let expected = RgbImage::load(ty.answer_path())?;
+                if *processed != expected {
+                    processed.save(DEBUG)?;
+                    let diff = diff(processed, &expected)?;
+                    diff.heatmap.save(DEBUG_DIFF)?;
+                    panic!(
+                        "invalid calculation by {} in {:?}: {}",
+                        backend.name(),
+                        ty,
+                        diff.summary
+                    );
+                }
+            }
         }
 
         if let Some(b) = b {
-            b.iter(|| *processed = f(&layer, &img));
+            b.iter(|| *processed = layer.apply(backend, &img).expect("cannot process"));
         }
         Ok(())
     }
+
+    /// Time taken by a backend to convolve an image, as recorded in a [`BenchReport`].
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct BenchRecord {
+        /// [`ConvBackend::name`] of the backend.
+        pub backend: String,
+        /// Kernel size.
+        pub k: usize,
+        /// Width of the image in pixels.
+        pub width: usize,
+        /// Height of the image in pixels.
+        pub height: usize,
+        /// Mean time per convolution in nanoseconds.
+        pub ns: f64,
+    }
+
+    impl BenchRecord {
+        /// Millions of output pixels per second.
+        pub fn mpix_per_s(&self) -> f64 {
+            (self.width * self.height) as f64 / self.ns * 1e3
+        }
+    }
+
+    /// Results of a benchmark run, written as JSON or CSV so that they can be compared across
+    /// commits and machines with external tools.
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct BenchReport {
+        records: Vec<BenchRecord>,
+    }
+
+    impl BenchReport {
+        /// Adds a result.
+        pub fn push(&mut self, record: BenchRecord) {
+            self.records.push(record);
+        }
+
+        /// Results in the order they were added.
+        pub fn records(&self) -> &[BenchRecord] {
+            &self.records
+        }
+
+        /// JSON array of objects with `backend`, `k`, `width`, `height`, `ns` and `mpix_per_s`.
+        pub fn to_json(&self) -> String {
+            let mut json = String::from("[");
+            for (i, r) in self.records.iter().enumerate() {
+                let sep = if i == 0 { "" } else { "," };
+                // `{:?}` escapes quotes and backslashes of names like JSON does
+                let _ = write!(
+                    json,
+                    "{}\n  {{\"backend\": {:?}, \"k\": {}, \"width\": {}, \"height\": {}, \"ns\": {:.1}, \"mpix_per_s\": {:.3}}}",
+                    sep,
+                    r.backend,
+                    r.k,
+                    r.width,
+                    r.height,
+                    r.ns,
+                    r.mpix_per_s()
+                );
+            }
+            json.push_str("\n]\n");
+            json
+        }
+
+        /// CSV with a header line `backend,k,width,height,ns,mpix_per_s`.
+        pub fn to_csv(&self) -> String {
+            let mut csv = String::from("backend,k,width,height,ns,mpix_per_s\n");
+            for r in &self.records {
+                let _ = writeln!(
+                    csv,
+                    "{},{},{},{},{:.1},{:.3}",
+                    r.backend,
+                    r.k,
+                    r.width,
+                    r.height,
+                    r.ns,
+                    r.mpix_per_s()
+                );
+            }
+            csv
+        }
+
+        /// Writes the report to `path`, as CSV if it ends with `.csv` and as JSON otherwise.
+        pub fn save<P>(&self, path: P) -> Result<()>
+        where
+            P: AsRef<Path>,
+        {
+            let path = path.as_ref();
+            let content = match path.extension() {
+                Some(ext) if ext == "csv" => self.to_csv(),
+                _ => self.to_json(),
+            };
+            fs::write(path, content)?;
+            Ok(())
+        }
+    }
+
+    // the global allocator, with buffers of bytes starting `.0` bytes past a 16-byte boundary
+    struct Misaligned(usize);
+
+    unsafe impl Allocator for Misaligned {
+        fn allocate(&self, layout: Layout) -> core::result::Result<NonNull<[u8]>, AllocError> {
+            debug_assert_eq!(layout.align(), 1);
+            let padded = Layout::from_size_align(layout.size() + 16, 16).map_err(|_| AllocError)?;
+            let p = Global.allocate(padded)?.cast::<u8>().as_ptr();
+            // SAFETY: `padded` has 16 bytes more than `layout`
+            let p = unsafe { p.add(self.0) };
+            NonNull::new(ptr::slice_from_raw_parts_mut(p, layout.size())).ok_or(AllocError)
+        }
+
+        unsafe fn deallocate(&self, p: NonNull<u8>, layout: Layout) {
+            let padded = Layout::from_size_align_unchecked(layout.size() + 16, 16);
+            Global.deallocate(NonNull::new_unchecked(p.as_ptr().sub(self.0)), padded)
+        }
+    }
+
+    /// Copy of `img` whose pixels start `offset` (less than 16) bytes past a 16-byte boundary,
+    /// e.g. to check or measure loads from buffers which the global allocator would align.
+    pub fn misaligned(img: &RgbImage, offset: usize) -> RgbImage {
+        assert!(offset < 16, "offset {} from a 16-byte boundary", offset);
+        let allocator = ImageAllocator::new(Misaligned(offset));
+        let mut content = Vec::with_capacity_in(img.content().len(), allocator);
+        content.extend_from_slice(img.content());
+        RgbImage::from_raw_in(content, img.height(), img.width()).unwrap()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn misaligned() {
+            let img = RgbImage::from_raw((0..4 * 5 * 3).collect(), 4, 5).unwrap();
+            for offset in 0..16 {
+                let copy = super::misaligned(&img, offset);
+                assert_eq!(copy.content().as_ptr() as usize % 16, offset);
+                assert!(copy == img);
+            }
+        }
+
+        #[test]
+        fn report() {
+            let mut report = BenchReport::default();
+            assert_eq!(report.to_json(), "[\n]\n");
+            for (backend, ns) in [("simd3", 2e5), ("naive1", 1e7)] {
+                report.push(BenchRecord {
+                    backend: backend.to_string(),
+                    k: 3,
+                    width: 512,
+                    height: 256,
+                    ns,
+                });
+            }
+            assert_eq!(report.records()[0].mpix_per_s(), 655.36);
+            assert_eq!(
+                report.to_json(),
+                "[\n  {\"backend\": \"simd3\", \"k\": 3, \"width\": 512, \"height\": 256, \"ns\": 200000.0, \"mpix_per_s\": 655.360},\n  {\"backend\": \"naive1\", \"k\": 3, \"width\": 512, \"height\": 256, \"ns\": 10000000.0, \"mpix_per_s\": 13.107}\n]\n"
+            );
+            assert_eq!(
+                report.to_csv(),
+                "backend,k,width,height,ns,mpix_per_s\nsimd3,3,512,256,200000.0,655.360\nnaive1,3,512,256,10000000.0,13.107\n"
+            );
+        }
+    }
 }
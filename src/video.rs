@@ -0,0 +1,571 @@
+//! Convolution of video frames.
+//!
+//! [`FrameProcessor`] convolves a stream of frames into a buffer which it owns, so that frames of
+//! the same size take no allocation, and remembers the tuning found for the stream.
+//! [`TemporalFilter`] averages each frame with the previous ones instead of neighbouring pixels.
+
+use alloc::{string::ToString, vec, vec::Vec};
+use core::ops::Range;
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::simd;
+use crate::{
+    allocator::ImageAllocator,
+    error::{Error, Result},
+    image::RgbImage,
+    kernel::Rounding,
+    processor::{ConvProcessor, Scratch, Split, Tuning},
+    C,
+};
+
+/// Convolves frames one after another with a [`ConvProcessor`], reusing the output and border
+/// buffers among frames.
+///
+/// The backend, thread count and tile size are chosen on the first frame, and again whenever the
+/// size of the frames changes, instead of for every frame. With [`autotune`](Self::autotune),
+/// they are measured on that frame for this stream only.
+///
+/// ```no_run
+/// use simd_playground::{video::FrameProcessor, ConvProcessor, RgbImage};
+///
+/// # fn camera() -> impl Iterator<Item = RgbImage> { std::iter::empty() }
+/// # fn main() -> simd_playground::Result<()> {
+/// let blur = ConvProcessor::<5>::new(&[1.; 25], true)?;
+/// let mut frames = FrameProcessor::new(blur).autotune(true);
+/// for frame in camera() {
+///     let blurred = frames.process(&frame)?;
+///     // display or encode `blurred` before the next frame
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct FrameProcessor<const K: usize> {
+    processor: ConvProcessor<K>,
+    #[cfg(feature = "std")]
+    autotune: bool,
+    // tuning for frames of the size of `out`
+    tuning: Option<Tuning<K>>,
+    out: RgbImage,
+    scratch: Scratch,
+    frames: usize,
+}
+
+impl<const K: usize> FrameProcessor<K>
+where
+    [(); (K / 2 + 1) / 2 + 1]: Sized,
+{
+    /// Creates a frame processor convolving with `processor`, which also decides the tuning
+    /// unless [`autotune`](Self::autotune) is set (see [`ConvProcessor::convolve`]). The output
+    /// and border buffers come from [`ConvProcessor::allocator`].
+    pub fn new(processor: ConvProcessor<K>) -> Self {
+        let allocator = processor.allocator().clone();
+        Self {
+            processor,
+            #[cfg(feature = "std")]
+            autotune: false,
+            tuning: None,
+            out: RgbImage::from_buffer_unchecked(Vec::new_in(allocator.clone()), 0, 0),
+            scratch: Scratch::new_in(allocator),
+            frames: 0,
+        }
+    }
+
+    /// If set, candidate backends, thread counts and tile sizes are measured on the first frame
+    /// of each size like [`ConvProcessorBuilder::autotune`](crate::processor::ConvProcessorBuilder::autotune),
+    /// but kept for this stream only. Off by default.
+    #[cfg(feature = "std")]
+    pub fn autotune(mut self, autotune: bool) -> Self {
+        self.autotune = autotune;
+        self
+    }
+
+    /// Convolves `frame`, returning the result which is overwritten by the next frame.
+    ///
+    /// Fails like [`ConvProcessor::convolve`], in which case the previous result may be partly
+    /// overwritten.
+    pub fn process(&mut self, frame: &RgbImage) -> Result<&RgbImage> {
+        frame.check_min_size(self.processor.min_size())?;
+        let size = (frame.height, frame.width);
+        let t = match self.tuning {
+            Some(t) if (self.out.height, self.out.width) == size => t,
+            _ => {
+                let t = self.tune(frame)?;
+                // keeps the allocation of the previous size if large enough
+                self.out.inner.resize(frame.height * frame.width * C, 0);
+                self.out.height = frame.height;
+                self.out.width = frame.width;
+                self.tuning = Some(t);
+                t
+            }
+        };
+        let split = Split::Threads(t.threads);
+        self.processor.run(
+            t.backend,
+            frame,
+            &mut self.out,
+            split,
+            t.tile,
+            t.traversal,
+            &mut self.scratch,
+        )?;
+        self.frames += 1;
+        Ok(&self.out)
+    }
+
+    fn tune(&self, frame: &RgbImage) -> Result<Tuning<K>> {
+        #[cfg(feature = "std")]
+        if self.autotune {
+            return self.processor.tune(frame);
+        }
+        self.processor.tuning_for(frame)
+    }
+
+    /// Tuning used for the frames of the current size, or `None` before the first frame.
+    pub fn tuning(&self) -> Option<Tuning<K>> {
+        self.tuning
+    }
+
+    /// Number of frames convolved so far.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Processor convolving the frames.
+    pub fn processor(&self) -> &ConvProcessor<K> {
+        &self.processor
+    }
+}
+
+/// Weighted average of each frame with the previous ones, which reduces the noise of static
+/// scenes (e.g. low-light video) without blurring their details like spatial filters.
+///
+/// The last `N - 1` frames for `N` weights are kept in a ring buffer, and the result is
+/// `Σ weights[k] * frame[k] / Σ weights[k]` for each byte, where `frame[k]` is the frame `k`
+/// frames before the current one. Fewer frames are averaged until enough have been given, and
+/// after their size changes.
+///
+/// Moving objects leave trails across frames. With [`motion_gate`](Self::motion_gate),
+/// previous frames are left out of the blocks where they differ from the current frame.
+///
+/// ```no_run
+/// use simd_playground::{video::TemporalFilter, RgbImage};
+///
+/// # fn camera() -> impl Iterator<Item = RgbImage> { std::iter::empty() }
+/// # fn main() -> simd_playground::Result<()> {
+/// let mut denoise = TemporalFilter::new(&[4., 3., 2., 1.])?.motion_gate(16, 12.);
+/// for frame in camera() {
+///     let denoised = denoise.process(&frame)?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct TemporalFilter {
+    // weights[k] for the frame k frames before the current one
+    weights: Vec<f32>,
+    rounding: Rounding,
+    // block size and threshold of the mean absolute difference
+    gate: Option<(usize, f32)>,
+    // previous frames, overwritten from `oldest` once `weights.len() - 1` of them are held
+    history: Vec<RgbImage>,
+    oldest: usize,
+    out: RgbImage,
+    allocator: ImageAllocator,
+}
+
+impl TemporalFilter {
+    /// Creates a filter averaging the current frame with `weights[0]`, the previous one with
+    /// `weights[1]` and so on.
+    ///
+    /// Fails with [`Error::BadKernel`] if `weights` is empty, any weight is negative, or the
+    /// weight of the current frame is not positive.
+    pub fn new(weights: &[f32]) -> Result<Self> {
+        if weights.is_empty() {
+            return Err(Error::BadKernel(
+                "temporal filter needs at least one weight".to_string(),
+            ));
+        }
+        if weights.iter().any(|w| w.is_nan() || *w < 0.) {
+            return Err(Error::BadKernel(
+                "weights of frames must not be negative".to_string(),
+            ));
+        }
+        if weights[0] == 0. {
+            return Err(Error::BadKernel(
+                "weight of the current frame must be positive".to_string(),
+            ));
+        }
+        Ok(Self {
+            weights: weights.to_vec(),
+            rounding: Rounding::default(),
+            gate: None,
+            history: Vec::with_capacity(weights.len() - 1),
+            oldest: 0,
+            out: RgbImage::empty(),
+            allocator: ImageAllocator::global(),
+        })
+    }
+
+    /// Plain average of the current frame and `frames - 1` previous ones.
+    ///
+    /// Fails with [`Error::BadKernel`] if `frames` is 0.
+    pub fn average(frames: usize) -> Result<Self> {
+        Self::new(&vec![1.; frames])
+    }
+
+    /// Leaves a previous frame out of a block of `block` x `block` pixels if the mean absolute
+    /// difference of its bytes from the current frame is above `threshold` (out of 255), so
+    /// that moving objects do not leave trails. Not set by default. Zero sizes are taken as 1.
+    pub fn motion_gate(mut self, block: usize, threshold: f32) -> Self {
+        self.gate = Some((block.max(1), threshold));
+        self
+    }
+
+    /// Allocator of the result and of the previous frames (the global one by default).
+    pub fn allocator(mut self, allocator: ImageAllocator) -> Self {
+        self.out = RgbImage::from_buffer_unchecked(Vec::new_in(allocator.clone()), 0, 0);
+        self.history.clear();
+        self.oldest = 0;
+        self.allocator = allocator;
+        self
+    }
+
+    /// How averages are converted to `u8` ([`Rounding::Truncate`] by default).
+    pub fn rounding(mut self, rounding: Rounding) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    /// Averages `frame` with the previous frames, returning the result which is overwritten by
+    /// the next frame.
+    ///
+    /// Fails with [`Error::ImageTooSmall`] if `frame` is empty.
+    pub fn process(&mut self, frame: &RgbImage) -> Result<&RgbImage> {
+        frame.check_min_size(1)?;
+        let (h, w) = (frame.height, frame.width);
+        if self
+            .history
+            .first()
+            .map_or(false, |f| (f.height, f.width) != (h, w))
+        {
+            self.history.clear();
+            self.oldest = 0;
+        }
+        self.out.inner.resize(h * w * C, 0);
+        self.out.height = h;
+        self.out.width = w;
+
+        // from the current frame to the oldest one
+        let (history, oldest) = (&self.history, self.oldest);
+        let n = history.len();
+        let frames: Vec<&[u8]> = core::iter::once(frame.content())
+            .chain((1..=n).map(|k| history[(oldest + n - k) % n].content()))
+            .collect();
+        let mut weights = self.weights[..frames.len()].to_vec();
+        let block = self.gate.map_or(h.max(w), |(block, _)| block);
+        for by in (0..h).step_by(block) {
+            for bx in (0..w).step_by(block) {
+                let (rows, cols) = (by..h.min(by + block), bx..w.min(bx + block));
+                if let Some((_, threshold)) = self.gate {
+                    for (k, weight) in weights.iter_mut().enumerate().skip(1) {
+                        let moved = mean_abs_diff(frames[0], frames[k], w, &rows, &cols);
+                        *weight = if moved > threshold {
+                            0.
+                        } else {
+                            self.weights[k]
+                        };
+                    }
+                }
+                let scale = 1. / weights.iter().sum::<f32>();
+                for y in rows {
+                    let bytes = (y * w + cols.start) * C..(y * w + cols.end) * C;
+                    blend(
+                        &frames,
+                        &weights,
+                        scale,
+                        self.rounding,
+                        bytes,
+                        &mut self.out.inner,
+                    );
+                }
+            }
+        }
+
+        // the current frame replaces the oldest one once the ring buffer is full
+        if n < self.weights.len() - 1 {
+            let mut content = Vec::with_capacity_in(h * w * C, self.allocator.clone());
+            content.extend_from_slice(frame.content());
+            self.history
+                .push(RgbImage::from_buffer_unchecked(content, h, w));
+        } else if n > 0 {
+            self.history[self.oldest]
+                .inner
+                .copy_from_slice(frame.content());
+            self.oldest = (self.oldest + 1) % n;
+        }
+        Ok(&self.out)
+    }
+
+    /// Number of previous frames currently averaged with the next one.
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Forgets the previous frames, e.g. at a scene cut.
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.oldest = 0;
+    }
+}
+
+// mean absolute difference of the bytes of `a` and `b` in the block of `rows` and `cols`
+fn mean_abs_diff(a: &[u8], b: &[u8], w: usize, rows: &Range<usize>, cols: &Range<usize>) -> f32 {
+    let mut sum = 0u64;
+    for y in rows.clone() {
+        let bytes = (y * w + cols.start) * C..(y * w + cols.end) * C;
+        sum += a[bytes.clone()]
+            .iter()
+            .zip(&b[bytes])
+            .map(|(&a, &b)| (a as i16 - b as i16).unsigned_abs() as u64)
+            .sum::<u64>();
+    }
+    sum as f32 / (rows.len() * cols.len() * C) as f32
+}
+
+// `dst[i] = Σ frames[k][i] * weights[k] * scale` for the bytes `i` in `bytes`, skipping frames of
+// weight 0. Multiplications and additions are separate so that every implementation rounds alike.
+#[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+fn blend(
+    frames: &[&[u8]],
+    weights: &[f32],
+    scale: f32,
+    rounding: Rounding,
+    bytes: Range<usize>,
+    dst: &mut [u8],
+) {
+    blend_serial(frames, weights, scale, rounding, bytes, dst)
+}
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+fn blend(
+    frames: &[&[u8]],
+    weights: &[f32],
+    scale: f32,
+    rounding: Rounding,
+    bytes: Range<usize>,
+    dst: &mut [u8],
+) {
+    let vscale = simd::splat(scale);
+    let simd_end = bytes.end - bytes.len() % 16;
+    for i in (bytes.start..simd_end).step_by(16) {
+        let mut acc = [simd::splat(0.); 4];
+        for (frame, &weight) in frames.iter().zip(weights) {
+            if weight == 0. {
+                continue;
+            }
+            let vweight = simd::splat(weight);
+            for (acc, v) in acc.iter_mut().zip(simd::load_u8x16(frame, i)) {
+                *acc = simd::add(*acc, simd::mul(v, vweight));
+            }
+        }
+        for acc in acc.iter_mut() {
+            *acc = simd::mul(*acc, vscale);
+        }
+        simd::store_u8x16(dst, i, &acc, rounding);
+    }
+    blend_serial(frames, weights, scale, rounding, simd_end..bytes.end, dst);
+}
+
+fn blend_serial(
+    frames: &[&[u8]],
+    weights: &[f32],
+    scale: f32,
+    rounding: Rounding,
+    bytes: Range<usize>,
+    dst: &mut [u8],
+) {
+    for i in bytes {
+        let mut t = 0.;
+        for (frame, &weight) in frames.iter().zip(weights) {
+            if weight != 0. {
+                t += frame[i] as f32 * weight;
+            }
+        }
+        dst[i] = rounding.to_u8(t * scale);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{consts::ORIGINAL, processor::BorderMode};
+
+    fn frame(height: usize, width: usize, seed: usize) -> Result<RgbImage> {
+        let content = (0..height * width * C)
+            .map(|i| ((i * 7 + seed * 31) % 256) as u8)
+            .collect();
+        RgbImage::from_raw(content, height, width)
+    }
+
+    #[test]
+    fn process() -> Result<()> {
+        let img = RgbImage::load(ORIGINAL)?;
+        let processor = ConvProcessor::<5>::builder()
+            .kernel(&[1.; 25])
+            .normalize(true)
+            .border(BorderMode::Replicate)
+            .build()?;
+        let expected = processor.naive2(&img)?;
+        let mut frames = FrameProcessor::new(processor);
+        assert!(frames.tuning().is_none());
+
+        let buffer = frames.process(&img)?.content().as_ptr();
+        let tuning = frames.tuning().unwrap();
+        let next = frame(img.height, img.width, 1)?;
+        let next_expected = frames.processor().naive2(&next)?;
+        assert_eq!(frames.process(&next)?, &next_expected);
+        // the same buffer and tuning for frames of the same size
+        assert_eq!(frames.process(&img)?.content().as_ptr(), buffer);
+        assert_eq!(frames.process(&img)?, &expected);
+        assert_eq!(
+            frames.tuning().unwrap().backend.name(),
+            tuning.backend.name()
+        );
+        assert_eq!(frames.frames(), 4);
+
+        // smaller frames, then larger ones again
+        for (h, w) in [(30, 40), (2, 3), (img.height, img.width)] {
+            let small = frame(h, w, h)?;
+            let expected = frames.processor().naive2(&small)?;
+            assert_eq!(frames.process(&small)?, &expected);
+        }
+        let empty = RgbImage::from_raw(vec![], 0, 0)?;
+        assert!(matches!(
+            frames.process(&empty),
+            Err(Error::ImageTooSmall { .. })
+        ));
+        assert_eq!(frames.frames(), 7);
+        Ok(())
+    }
+
+    #[test]
+    fn temporal() -> Result<()> {
+        assert!(matches!(TemporalFilter::new(&[]), Err(Error::BadKernel(_))));
+        assert!(matches!(
+            TemporalFilter::average(0),
+            Err(Error::BadKernel(_))
+        ));
+        assert!(matches!(
+            TemporalFilter::new(&[0., 1.]),
+            Err(Error::BadKernel(_))
+        ));
+        assert!(matches!(
+            TemporalFilter::new(&[1., -1.]),
+            Err(Error::BadKernel(_))
+        ));
+
+        // odd width, so that rows end with bytes left over by vectors
+        let (h, w) = (9, 13);
+        let frames = (0..5)
+            .map(|seed| frame(h, w, seed))
+            .collect::<Result<Vec<_>>>()?;
+        let weights = [4., 3., 0., 1.];
+        let mut filter = TemporalFilter::new(&weights)?.rounding(Rounding::Nearest);
+        for (i, f) in frames.iter().enumerate() {
+            let out = filter.process(f)?.content().to_vec();
+            let used = weights.len().min(i + 1);
+            let sum: f32 = weights[..used].iter().sum();
+            for (b, &o) in out.iter().enumerate() {
+                let mut t = 0.;
+                for (k, &weight) in weights[..used].iter().enumerate() {
+                    if weight != 0. {
+                        t += frames[i - k].content()[b] as f32 * weight;
+                    }
+                }
+                assert_eq!(
+                    o,
+                    Rounding::Nearest.to_u8(t * (1. / sum)),
+                    "frame {} byte {}",
+                    i,
+                    b
+                );
+            }
+            assert_eq!(filter.history_len(), (i + 1).min(weights.len() - 1));
+        }
+
+        // a frame of another size starts over
+        let other = frame(4, 4, 0)?;
+        assert_eq!(filter.process(&other)?, &other);
+        filter.reset();
+        assert_eq!(filter.history_len(), 0);
+        assert!(matches!(
+            filter.process(&RgbImage::from_raw(vec![], 0, 0)?),
+            Err(Error::ImageTooSmall { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn motion_gate() -> Result<()> {
+        let (h, w) = (16, 16);
+        let still = RgbImage::from_raw(vec![100; h * w * C], h, w)?;
+        // the same frame with a bright left half, as if an object moved in
+        let mut moved = vec![100; h * w * C];
+        for row in moved.chunks_exact_mut(w * C) {
+            row[..w / 2 * C].fill(200);
+        }
+        let moved = RgbImage::from_raw(moved, h, w)?;
+
+        let mut filter = TemporalFilter::average(2)?.motion_gate(8, 10.);
+        filter.process(&still)?;
+        let out = filter.process(&moved)?;
+        // the moving half is left as is, and the static one averaged (with the same value)
+        assert_eq!(out, &moved);
+
+        let mut filter = TemporalFilter::average(2)?;
+        filter.process(&still)?;
+        let out = filter.process(&moved)?;
+        assert_eq!(out.content()[0], 150);
+        assert_eq!(out.content()[w * C - 1], 100);
+        Ok(())
+    }
+
+    #[test]
+    fn allocator() -> Result<()> {
+        let allocator = ImageAllocator::new(alloc::alloc::Global);
+        let img = frame(20, 30, 0)?;
+        let processor = ConvProcessor::<3>::builder()
+            .kernel(&[1.; 9])
+            .border(BorderMode::Reflect)
+            .tile_size(8, 8)
+            .allocator(allocator.clone())
+            .build()?;
+        let expected = processor.naive2(&img)?;
+        let mut frames = FrameProcessor::new(processor);
+        let out = frames.process(&img)?;
+        assert_eq!(out, &expected);
+        assert!(out.allocator() == &allocator);
+
+        let mut filter = TemporalFilter::average(2)?.allocator(allocator.clone());
+        filter.process(&img)?;
+        assert!(filter.process(&img)?.allocator() == &allocator);
+        Ok(())
+    }
+
+    #[test]
+    fn autotune() -> Result<()> {
+        let processor = ConvProcessor::<3>::new(&[1.; 9], true)?;
+        let mut frames = FrameProcessor::new(processor).autotune(true);
+        for seed in 0..3 {
+            let frame = frame(64, 80, seed)?;
+            let expected = frames.processor().naive2(&frame)?;
+            // the border is cleared in the reused buffer too
+            assert_eq!(frames.process(&frame)?, &expected);
+        }
+        assert!(frames.tuning().unwrap().threads >= 1);
+        // measured for the stream, not the processor
+        assert!(frames.processor().tuning(64, 80).is_none());
+        Ok(())
+    }
+}
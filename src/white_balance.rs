@@ -0,0 +1,139 @@
+//! Per-channel gains, e.g. to correct the white balance of camera frames before filtering.
+//!
+//! Gains are applied in fixed point with 8 fractional bits: values are widened, multiplied,
+//! then rounded and narrowed back with saturation, 16 pixels at a time with NEON.
+
+use crate::{image::RgbImage, C};
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::{simd, util::mul_q8_u8x16};
+
+/// Largest gain, which fixed point with 8 fractional bits in a `u16` can hold.
+pub const MAX_GAIN: f32 = u16::MAX as f32 / 256.;
+
+impl RgbImage {
+    /// Multiplies every value of channel `c` by `gains[c]` in place, saturating to 255.
+    ///
+    /// Gains are rounded to multiples of `1 / 256` and clamped to `[0, MAX_GAIN]`, and the
+    /// products are rounded to the nearest integer.
+    pub fn apply_gains(&mut self, gains: [f32; C]) {
+        let gains = gains.map(to_q8);
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let bytes = {
+            let bytes = &mut self.inner[..];
+            let simd_end = bytes.len() - bytes.len() % (16 * C);
+            for i in (0..simd_end).step_by(16 * C) {
+                let mut v = simd::deinterleave16(bytes, i);
+                for (v, &gain) in v.iter_mut().zip(gains.iter()) {
+                    *v = unsafe { mul_q8_u8x16(*v, gain) };
+                }
+                simd::interleave16(bytes, i, v);
+            }
+            &mut bytes[simd_end..]
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let bytes = &mut self.inner[..];
+        for pixel in bytes.chunks_exact_mut(C) {
+            for (v, &gain) in pixel.iter_mut().zip(gains.iter()) {
+                *v = mul_q8(*v, gain);
+            }
+        }
+    }
+
+    /// Corrects the white balance with the gains of [`gray_world`].
+    pub fn auto_white_balance(&mut self) {
+        let gains = gray_world(self);
+        self.apply_gains(gains);
+    }
+}
+
+/// Gains making the mean of every channel the mean of the 3, assuming that the scene is gray on
+/// average (the gray-world hypothesis). Channels which are 0 everywhere keep a gain of 1.
+pub fn gray_world(img: &RgbImage) -> [f32; C] {
+    let sums = img.channel_sums();
+    let gray = sums.iter().sum::<u64>() as f32 / C as f32;
+    sums.map(|sum| if sum == 0 { 1. } else { gray / sum as f32 })
+}
+
+// `gain` with 8 fractional bits, rounded and saturated
+fn to_q8(gain: f32) -> u16 {
+    (gain * 256. + 0.5).clamp(0., u16::MAX as f32) as u16
+}
+
+// `v * gain / 256` rounded half up and saturated, like `mul_q8_u8x16`
+fn mul_q8(v: u8, gain: u16) -> u8 {
+    ((v as u32 * gain as u32 + 128) >> 8).min(u8::MAX as u32) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{vec, vec::Vec};
+
+    use super::*;
+    use crate::{error::Result, test_util::photo_image};
+
+    #[test]
+    fn gains() -> Result<()> {
+        // longer than a vector of 16 pixels, with some left over
+        let content: Vec<u8> = (0..3 * 19 * C).map(|i| (i * 7 % 256) as u8).collect();
+        let mut img = RgbImage::from_raw(content.clone(), 3, 19)?;
+        img.apply_gains([1.; C]);
+        assert_eq!(img.content(), &content[..]);
+
+        let gains = [0.5, 1.25, 3.];
+        img.apply_gains(gains);
+        for (i, (&v, &o)) in img.content().iter().zip(&content).enumerate() {
+            let expected = (o as f32 * gains[i % C]).round().min(255.);
+            assert_eq!(v, expected as u8, "{}", i);
+        }
+
+        img.apply_gains([-1., 0., MAX_GAIN * 2.]);
+        for pixel in img.content().chunks_exact(C) {
+            assert_eq!(pixel[..2], [0, 0]);
+            assert!(pixel[2] == 0 || pixel[2] == 255);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn auto_white_balance() -> Result<()> {
+        // a gray scene under a yellowish light
+        let gray = photo_image(12, 20, 8);
+        let content = gray
+            .content()
+            .chunks_exact(C)
+            .flat_map(|p| {
+                let v = p[1] / 2;
+                [v + v / 2, v, v / 2]
+            })
+            .collect();
+        let mut img = RgbImage::from_raw(content, 12, 20)?;
+        img.auto_white_balance();
+        for pixel in img.content().chunks_exact(C) {
+            let (min, max) = (pixel.iter().min().unwrap(), pixel.iter().max().unwrap());
+            assert!(max - min <= 2, "{:?}", pixel);
+        }
+
+        let red = RgbImage::from_raw([90, 0, 0].repeat(4), 2, 2)?;
+        assert_eq!(gray_world(&red), [1. / 3., 1., 1.]);
+        assert_eq!(
+            gray_world(&RgbImage::from_raw(vec![0; 4 * C], 2, 2)?),
+            [1.; C]
+        );
+        Ok(())
+    }
+
+    #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+    #[test]
+    fn simd() -> Result<()> {
+        // every value in each channel
+        let content: Vec<u8> = (0..=255).flat_map(|v| [v, 255 - v, v / 2]).collect();
+        for gains in [[1., 0.7, 2.4], [0.004, 130., 1.5]] {
+            let mut img = RgbImage::from_raw(content.clone(), 16, 16)?;
+            img.apply_gains(gains);
+            for (i, (&v, &o)) in img.content().iter().zip(&content).enumerate() {
+                assert_eq!(v, mul_q8(o, to_q8(gains[i % C])), "{}", i);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+//! YCbCr color space (full range BT.601, as in JPEG), and filtering the luma only.
+//!
+//! [`YCbCrImage`] stores the luma Y and the chroma Cb and Cr in planes of bytes. Conversions
+//! both ways are 3x3 matrices computed by NEON on 16 pixels at a time with the same fused
+//! multiply-adds as the scalar loops. [`ConvProcessorBuilder::luma_only`] uses them to filter only
+//! the Y plane, e.g. to sharpen without colored fringes, or to blur a third of the values.
+//!
+//! [`ConvProcessorBuilder::luma_only`]: crate::processor::ConvProcessorBuilder::luma_only
+
+use alloc::{vec, vec::Vec};
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use core::arch::aarch64::*;
+
+#[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+use crate::{color_matrix::weighted16, simd};
+use crate::{
+    error::Result,
+    image::RgbImage,
+    kernel::{fma, Rounding},
+    processor::ConvProcessor,
+    C,
+};
+
+// rows give Y, Cb and Cr from R, G and B, to which `OFFSETS` are added
+#[rustfmt::skip]
+const RGB_TO_YCBCR: [[f32; C]; C] = [
+    [0.299, 0.587, 0.114],
+    [-0.168_736, -0.331_264, 0.5],
+    [0.5, -0.418_688, -0.081_312],
+];
+const OFFSETS: [f32; C] = [0., 128., 128.];
+
+// rows give R, G and B from Y, Cb and Cr minus `OFFSETS`
+#[rustfmt::skip]
+const YCBCR_TO_RGB: [[f32; C]; C] = [
+    [1., 0., 1.402],
+    [1., -0.344_136, -0.714_136],
+    [1., 1.772, 0.],
+];
+
+/// Image in YCbCr stored as 3 planes of `height * width` bytes: Y, then Cb and Cr centered on
+/// 128.
+#[derive(Debug, Clone, PartialEq)]
+pub struct YCbCrImage {
+    pub(crate) planes: Vec<u8>,
+    pub(crate) height: usize,
+    pub(crate) width: usize,
+}
+
+impl YCbCrImage {
+    /// Converts every pixel of `src`, rounded to the nearest integer and saturated.
+    pub fn from_rgb(src: &RgbImage) -> Self {
+        let len = src.height * src.width;
+        let bytes = src.content();
+        let mut planes = vec![0; len * C];
+        // pixels from `start` are left to the serial loop
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let start = {
+            let m = RGB_TO_YCBCR.map(|row| row.map(simd::splat));
+            let offsets = OFFSETS.map(simd::splat);
+            let simd_end = len - len % 16;
+            for i in (0..simd_end).step_by(16) {
+                let v = simd::load_rgb16(bytes, i * C);
+                for c in 0..C {
+                    let out = weighted16(&v, &m[c]).map(|v| simd::add(v, offsets[c]));
+                    simd::store_u8x16(&mut planes, c * len + i, &out, Rounding::Nearest);
+                }
+            }
+            simd_end
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let start = 0;
+        for (i, p) in bytes.chunks_exact(C).enumerate().skip(start) {
+            let v = [p[0], p[1], p[2]].map(|v| v as f32);
+            for c in 0..C {
+                let t = mix(v, RGB_TO_YCBCR[c]) + OFFSETS[c];
+                planes[c * len + i] = Rounding::Nearest.to_u8(t);
+            }
+        }
+        Self {
+            planes,
+            height: src.height,
+            width: src.width,
+        }
+    }
+
+    /// Converts every pixel back, rounded to the nearest integer and saturated.
+    pub fn to_rgb(&self) -> RgbImage {
+        let mut dst = vec![0; self.planes.len()];
+        self.to_rgb_bytes(&mut dst);
+        RgbImage::from_raw_unchecked(dst, self.height, self.width)
+    }
+
+    // `to_rgb` into the interleaved bytes `dst`
+    fn to_rgb_bytes(&self, dst: &mut [u8]) {
+        let len = self.height * self.width;
+        // pixels from `start` are left to the serial loop
+        #[cfg(all(any(target_arch = "aarch64"), target_feature = "neon"))]
+        let start = {
+            let m = YCBCR_TO_RGB.map(|row| row.map(simd::splat));
+            let offsets = OFFSETS.map(simd::splat);
+            let simd_end = len - len % 16;
+            for i in (0..simd_end).step_by(16) {
+                let v: simd::Rgb16 = [0, 1, 2].map(|c| {
+                    simd::load_u8x16(&self.planes, c * len + i)
+                        .map(|v| unsafe { vsubq_f32(v, offsets[c]) })
+                });
+                let out = [0, 1, 2].map(|c| weighted16(&v, &m[c]));
+                simd::store_rgb16(dst, i * C, &out, Rounding::Nearest);
+            }
+            simd_end
+        };
+        #[cfg(not(all(any(target_arch = "aarch64"), target_feature = "neon")))]
+        let start = 0;
+        for (i, p) in dst.chunks_exact_mut(C).enumerate().skip(start) {
+            let v = [0, 1, 2].map(|c| self.planes[c * len + i] as f32 - OFFSETS[c]);
+            for (c, p) in p.iter_mut().enumerate() {
+                *p = Rounding::Nearest.to_u8(mix(v, YCBCR_TO_RGB[c]));
+            }
+        }
+    }
+
+    /// Height in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Width in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Plane `c` (0 for Y, 1 for Cb and 2 for Cr), row by row.
+    ///
+    /// # Panics
+    ///
+    /// If `c` is 3 or more.
+    pub fn plane(&self, c: usize) -> &[u8] {
+        let len = self.height * self.width;
+        &self.planes[c * len..(c + 1) * len]
+    }
+
+    /// Mutable plane `c`, see [`plane`](Self::plane).
+    pub fn plane_mut(&mut self, c: usize) -> &mut [u8] {
+        let len = self.height * self.width;
+        &mut self.planes[c * len..(c + 1) * len]
+    }
+}
+
+// `m[0] * v[0] + m[1] * v[1] + m[2] * v[2]` in the order of `weighted16`
+fn mix(v: [f32; C], m: [f32; C]) -> f32 {
+    fma(v[2], m[2], fma(v[1], m[1], v[0] * m[0]))
+}
+
+impl<const K: usize> ConvProcessor<K> {
+    // `run` on the luma only, keeping the chroma of `src`
+    pub(crate) fn convolve_luma(&self, src: &RgbImage, dst: &mut RgbImage) -> Result<()> {
+        let (h, w) = (src.height, src.width);
+        let mut ycbcr = YCbCrImage::from_rgb(src);
+        let luma = self.convolve_channels(ycbcr.plane(0), h, w, 1, u8::MAX)?;
+        ycbcr.plane_mut(0).copy_from_slice(&luma);
+        ycbcr.to_rgb_bytes(&mut dst.inner);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{processor::BorderMode, test_util::photo_image};
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        // vectors of 16 pixels with some left over
+        let img = photo_image(7, 19, 52);
+        let ycbcr = YCbCrImage::from_rgb(&img);
+        assert_eq!((ycbcr.height(), ycbcr.width()), (7, 19));
+        for (i, p) in img.content().chunks_exact(C).enumerate() {
+            let luma = 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32;
+            assert!((ycbcr.plane(0)[i] as f32 - luma).abs() <= 0.5 + 1e-3);
+        }
+        let back = ycbcr.to_rgb();
+        assert!(back.approx_eq(&img, 1, 0));
+
+        // primaries and grays
+        let pixels = RgbImage::from_raw(
+            vec![
+                255, 0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 128, 128, 128, 255, 255, 255,
+            ],
+            1,
+            6,
+        )?;
+        let ycbcr = YCbCrImage::from_rgb(&pixels);
+        assert_eq!(ycbcr.plane(0), [76, 150, 29, 0, 128, 255]);
+        assert_eq!(ycbcr.plane(1), [85, 44, 255, 128, 128, 128]);
+        assert_eq!(ycbcr.plane(2), [255, 21, 107, 128, 128, 128]);
+        assert_eq!(ycbcr.to_rgb().content()[9..], pixels.content()[9..]);
+        Ok(())
+    }
+
+    #[test]
+    fn luma_only() -> Result<()> {
+        // grays are convolved like every channel
+        let gray: Vec<u8> = photo_image(9, 21, 53)
+            .content()
+            .chunks_exact(C)
+            .flat_map(|p| [p[1]; C])
+            .collect();
+        let gray = RgbImage::from_raw(gray, 9, 21)?;
+        for border in [BorderMode::Zero, BorderMode::Reflect] {
+            let blur = ConvProcessor::<3>::builder()
+                .kernel(&[1., 2., 1., 2., 4., 2., 1., 2., 1.])
+                .normalize(true)
+                .border(border)
+                .luma_only(true)
+                .build()?;
+            assert!(blur.luma_only());
+            let expected = blur.convolve_samples(gray.content(), 9, 21, u8::MAX)?;
+            assert_eq!(blur.convolve(&gray)?.content(), &expected[..]);
+        }
+
+        // colors keep their chroma, up to rounding
+        let img = photo_image(9, 21, 54);
+        let blur = ConvProcessor::<3>::builder()
+            .kernel(&[1.; 9])
+            .normalize(true)
+            .border(BorderMode::Replicate)
+            .luma_only(true)
+            .build()?;
+        let (before, after) = (
+            YCbCrImage::from_rgb(&img),
+            YCbCrImage::from_rgb(&blur.convolve(&img)?),
+        );
+        for c in 1..C {
+            for (&a, &b) in after.plane(c).iter().zip(before.plane(c)) {
+                assert!((a as i16 - b as i16).abs() <= 2, "{} {}", a, b);
+            }
+        }
+        assert_ne!(after.plane(0), before.plane(0));
+        Ok(())
+    }
+}